@@ -1,19 +1,20 @@
 use std::{
     fmt::{Debug, Display},
-    fs::{self, File},
-    io::{BufRead, BufReader, Write},
-    path::{Path, PathBuf},
+    fs,
+    io::Write,
+    os::unix::io::RawFd,
+    path::{Component, Path, PathBuf},
     time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
 use nix::{
-    sys::statfs::{statfs, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC},
+    sys::statfs::{statfs, FsType, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC},
     unistd::Pid,
 };
 use oci_spec::runtime::{
     LinuxDevice, LinuxDeviceBuilder, LinuxDeviceCgroup, LinuxDeviceCgroupBuilder, LinuxDeviceType,
-    LinuxResources,
+    LinuxRdma, LinuxResources,
 };
 #[cfg(feature = "systemd_cgroups")]
 use systemd::daemon::booted;
@@ -28,12 +29,48 @@ use super::v2;
 use super::stats::Stats;
 
 pub const CGROUP_PROCS: &str = "cgroup.procs";
+pub const CGROUP_THREADS: &str = "cgroup.threads";
 pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Parent cgroup of containers which do not specify an absolute cgroups path
+pub const DEFAULT_CGROUP_PARENT: &str = "/youki";
 
 pub trait CgroupManager {
-    /// Adds a task specified by its pid to the cgroup
+    /// Adds a task specified by its pid to the cgroup. The process is moved together with
+    /// all of its threads, which also makes it possible to move an existing process into
+    /// the cgroup of a container. Adding a task which is already part of the cgroup
+    /// succeeds without changes.
     fn add_task(&self, pid: Pid) -> Result<()>;
 
+    /// Creates the cgroup and returns the directory in which new processes are placed, so
+    /// that a child process can be created directly inside of it with clone3 and
+    /// CLONE_INTO_CGROUP. Returns None if processes can only be moved with add_task.
+    fn prepare_task_dir(&self) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Adds a single thread specified by its tid to the cgroup, while the other threads
+    /// of its process stay where they are. This is only supported for threaded cgroups
+    /// of cgroup v2.
+    fn add_thread(&self, tid: Pid) -> Result<()> {
+        bail!(
+            "cannot add thread {}, this cgroup manager does not support thread granular placement",
+            tid
+        )
+    }
+
+    /// Adds every thread of the process specified by its pid to the cgroup individually
+    fn add_all_threads(&self, pid: Pid) -> Result<()> {
+        let tasks = procfs::process::Process::new(pid.as_raw())
+            .and_then(|process| process.tasks())
+            .with_context(|| format!("failed to list threads of process {}", pid))?;
+        // threads which exited in the meantime are skipped
+        for task in tasks.flatten() {
+            self.add_thread(Pid::from_raw(task.tid))?;
+        }
+
+        Ok(())
+    }
+
     /// Applies resource restrictions to the cgroup
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<()>;
 
@@ -48,9 +85,22 @@ pub trait CgroupManager {
 
     // Gets the PIDs inside the cgroup
     fn get_all_pids(&self) -> Result<Vec<Pid>>;
+
+    /// Registers for notifications about invocations of the OOM killer in the cgroup.
+    /// Returns an eventfd which becomes readable on every oom event or None, if oom
+    /// events can only be detected through the oom_kill counter of the memory stats.
+    fn register_oom_event(&self) -> Result<Option<RawFd>> {
+        Ok(None)
+    }
+
+    /// Checks that the resource restrictions could be applied, without creating the
+    /// cgroup or writing any of the restrictions
+    fn validate(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        validate_resources(controller_opt.resources)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CgroupSetup {
     Hybrid,
     Legacy,
@@ -91,40 +141,97 @@ pub struct ControllerOpt<'a> {
     pub oom_score_adj: Option<i32>,
     /// FreezerState is given to freezer contoller for suspending process.
     pub freezer_state: Option<FreezerState>,
+    /// Marks the cgroup as idle priority on cgroup v2 (cpu.idle). This is not yet part
+    /// of LinuxCpu in oci-spec.
+    pub cpu_idle: Option<u64>,
+}
+
+/// Thaws the cgroup when it goes out of scope, unless it has been disarmed. This
+/// makes sure that a cgroup which has been frozen while resources are applied is
+/// not left frozen if applying one of the controllers fails.
+pub struct FreezerGuard<'a, C: CgroupManager + ?Sized> {
+    manager: &'a C,
+    armed: bool,
+}
+
+impl<'a, C: CgroupManager + ?Sized> FreezerGuard<'a, C> {
+    /// Creates a guard, which only thaws the cgroup if `armed` is true
+    pub fn new(manager: &'a C, armed: bool) -> Self {
+        Self { manager, armed }
+    }
+
+    /// Keeps the cgroup in its current state when the guard is dropped
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl<C: CgroupManager + ?Sized> Drop for FreezerGuard<'_, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            if let Err(e) = self.manager.freeze(FreezerState::Thawed) {
+                log::warn!("failed to thaw cgroup: {:?}", e);
+            }
+        }
+    }
 }
 
 #[inline]
 pub fn write_cgroup_file_str<P: AsRef<Path>>(path: P, data: &str) -> Result<()> {
+    let path = path.as_ref();
     fs::OpenOptions::new()
         .create(false)
         .write(true)
         .truncate(false)
-        .open(path.as_ref())
-        .with_context(|| format!("failed to open {:?}", path.as_ref()))?
-        .write_all(data.as_bytes())
-        .with_context(|| format!("failed to write to {:?}", path.as_ref()))?;
+        .open(path)
+        .and_then(|mut file| file.write_all(data.as_bytes()))
+        .with_context(|| format!("failed to write {} to {}", data, path.display()))?;
 
     Ok(())
 }
 
 #[inline]
 pub fn write_cgroup_file<P: AsRef<Path>, T: ToString>(path: P, data: T) -> Result<()> {
-    fs::OpenOptions::new()
-        .create(false)
-        .write(true)
-        .truncate(false)
-        .open(path.as_ref())
-        .with_context(|| format!("failed to open {:?}", path.as_ref()))?
-        .write_all(data.to_string().as_bytes())
-        .with_context(|| format!("failed to write to {:?}", path.as_ref()))?;
-
-    Ok(())
+    write_cgroup_file_str(path, &data.to_string())
 }
 
 #[inline]
 pub fn read_cgroup_file<P: AsRef<Path>>(path: P) -> Result<String> {
     let path = path.as_ref();
-    fs::read_to_string(path).with_context(|| format!("failed to open {:?}", path))
+    fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Reads a cgroup file and strips the trailing newline the kernel appends to most values
+#[inline]
+pub fn read_cgroup_file_trimmed<P: AsRef<Path>>(path: P) -> Result<String> {
+    read_cgroup_file(path).map(|content| content.trim().to_owned())
+}
+
+/// Parses the numeric part of a hugepage size like 2MB and checks that it is a
+/// power of two
+pub fn parse_hugepage_size(page_size: &str) -> Result<u64> {
+    let size: String = page_size.chars().take_while(|c| c.is_digit(10)).collect();
+    let size: u64 = size
+        .parse()
+        .with_context(|| format!("invalid hugetlb page size {:?}", page_size))?;
+    if !size.is_power_of_two() {
+        bail!(
+            "page size {} must be in the format of 2^(integer)",
+            page_size
+        );
+    }
+
+    Ok(size)
+}
+
+/// Formats a limit for the cgroup v2 interface files, which use max instead of
+/// a negative value to remove a limit
+pub fn limit_or_max(limit: i64) -> String {
+    if limit < 0 {
+        "max".to_owned()
+    } else {
+        limit.to_string()
+    }
 }
 
 /// Determines the cgroup setup of the system. Systems typically have one of
@@ -137,39 +244,51 @@ pub fn read_cgroup_file<P: AsRef<Path>>(path: P) -> Result<String> {
 ///   through the cgroup v1 hierarchy, not through the cgroup v2 hierarchy.
 pub fn get_cgroup_setup() -> Result<CgroupSetup> {
     let default_root = Path::new(DEFAULT_CGROUP_ROOT);
-    match default_root.exists() {
-        true => {
-            // If the filesystem is of type cgroup2, the system is in unified mode.
-            // If the filesystem is tmpfs instead the system is either in legacy or
-            // hybrid mode. If a cgroup2 filesystem has been mounted under the "unified"
-            // folder we are in hybrid mode, otherwise we are in legacy mode.
-            let stat = statfs(default_root).with_context(|| {
-                format!(
-                    "failed to stat default cgroup root {}",
-                    &default_root.display()
-                )
-            })?;
-            if stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
-                return Ok(CgroupSetup::Unified);
-            }
+    if !default_root.exists() {
+        bail!("non default cgroup root not supported");
+    }
 
-            if stat.filesystem_type() == TMPFS_MAGIC {
-                let unified = Path::new("/sys/fs/cgroup/unified");
-                if Path::new(unified).exists() {
-                    let stat = statfs(unified)
-                        .with_context(|| format!("failed to stat {}", unified.display()))?;
-                    if stat.filesystem_type() == CGROUP2_SUPER_MAGIC {
-                        return Ok(CgroupSetup::Hybrid);
-                    }
-                }
+    let root_fs = statfs(default_root)
+        .with_context(|| {
+            format!(
+                "failed to stat default cgroup root {}",
+                &default_root.display()
+            )
+        })?
+        .filesystem_type();
+
+    let unified = default_root.join("unified");
+    let unified_fs = if root_fs == TMPFS_MAGIC && unified.exists() {
+        let stat =
+            statfs(&unified).with_context(|| format!("failed to stat {}", unified.display()))?;
+        Some(stat.filesystem_type())
+    } else {
+        None
+    };
+
+    detect_cgroup_setup(root_fs, unified_fs).context("failed to detect cgroup setup")
+}
 
-                return Ok(CgroupSetup::Legacy);
-            }
+/// Derives the cgroup setup from the filesystem types mounted at the cgroup root
+/// and, if present, at the "unified" folder below it.
+fn detect_cgroup_setup(root_fs: FsType, unified_fs: Option<FsType>) -> Option<CgroupSetup> {
+    // If the filesystem is of type cgroup2, the system is in unified mode.
+    // If the filesystem is tmpfs instead the system is either in legacy or
+    // hybrid mode. If a cgroup2 filesystem has been mounted under the "unified"
+    // folder we are in hybrid mode, otherwise we are in legacy mode.
+    if root_fs == CGROUP2_SUPER_MAGIC {
+        return Some(CgroupSetup::Unified);
+    }
+
+    if root_fs == TMPFS_MAGIC {
+        if unified_fs == Some(CGROUP2_SUPER_MAGIC) {
+            return Some(CgroupSetup::Hybrid);
         }
-        false => bail!("non default cgroup root not supported"),
+
+        return Some(CgroupSetup::Legacy);
     }
 
-    bail!("failed to detect cgroup setup");
+    None
 }
 
 pub fn create_cgroup_manager<P: Into<PathBuf>>(
@@ -203,22 +322,165 @@ pub fn create_cgroup_manager<P: Into<PathBuf>>(
     }
 }
 
+/// Computes the cgroup of a container from the linux.cgroupsPath field of its spec.
+/// The returned path is absolute and relative to the cgroup mount point. Absolute
+/// paths are used as they are, relative paths are placed below the default parent
+/// and if no path has been specified the container id is used as the cgroup name.
+pub fn resolve_cgroup_path(cgroups_path: Option<&Path>, container_id: &str) -> Result<PathBuf> {
+    let cgroups_path = match cgroups_path {
+        Some(path) if !path.as_os_str().is_empty() => path,
+        _ => {
+            if container_id.is_empty() || container_id.contains('/') {
+                bail!(
+                    "container id {:?} cannot be used as cgroup name",
+                    container_id
+                );
+            }
+            Path::new(container_id)
+        }
+    };
+
+    let mut resolved = if cgroups_path.is_absolute() {
+        PathBuf::from("/")
+    } else {
+        PathBuf::from(DEFAULT_CGROUP_PARENT)
+    };
+    for component in cgroups_path.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::RootDir | Component::CurDir => continue,
+            Component::ParentDir | Component::Prefix(_) => bail!(
+                "cgroups path {} must not escape the cgroup hierarchy",
+                cgroups_path.display()
+            ),
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Validates the values of the resource restrictions which are independent of the
+/// cgroup version, so that malformed values are reported before anything is written.
+pub fn validate_resources(resources: &LinuxResources) -> Result<()> {
+    if let Some(memory) = resources.memory() {
+        for (name, value) in [
+            ("limit", memory.limit()),
+            ("reservation", memory.reservation()),
+            ("swap", memory.swap()),
+            ("kernel", memory.kernel()),
+            ("kernelTCP", memory.kernel_tcp()),
+        ] {
+            if let Some(value) = value {
+                if value < -1 {
+                    bail!("invalid memory {} {}", name, value);
+                }
+            }
+        }
+
+        if let (Some(limit), Some(swap)) = (memory.limit(), memory.swap()) {
+            if limit > 0 && swap > 0 && swap < limit {
+                bail!(
+                    "memory swap {} must not be lower than the memory limit {}",
+                    swap,
+                    limit
+                );
+            }
+        }
+
+        if let Some(swappiness) = memory.swappiness() {
+            if swappiness > 100 {
+                bail!("invalid memory swappiness {}", swappiness);
+            }
+        }
+    }
+
+    if let Some(cpu) = resources.cpu() {
+        if let Some(quota) = cpu.quota() {
+            if quota == 0 || quota < -1 {
+                bail!("invalid cpu quota {}", quota);
+            }
+        }
+        if let Some(0) = cpu.period() {
+            bail!("invalid cpu period 0");
+        }
+        if let Some(cpus) = cpu.cpus() {
+            validate_cpuset_list(cpus).context("invalid cpuset cpus")?;
+        }
+        if let Some(mems) = cpu.mems() {
+            validate_cpuset_list(mems).context("invalid cpuset mems")?;
+        }
+    }
+
+    if let Some(block_io) = resources.block_io() {
+        for (name, weight) in [
+            ("weight", block_io.weight()),
+            ("leaf weight", block_io.leaf_weight()),
+        ] {
+            if let Some(weight) = weight {
+                if !(10..=1000).contains(&weight) {
+                    bail!("blkio {} {} is not in the range [10, 1000]", name, weight);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates a list of cpus or memory nodes like "0-3,5"
+fn validate_cpuset_list(list: &str) -> Result<()> {
+    if list.is_empty() {
+        return Ok(());
+    }
+
+    for range in list.split(',') {
+        let mut bounds = range.splitn(2, '-');
+        let start: u32 = bounds
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .with_context(|| format!("{:?} is not a valid range", range))?;
+        if let Some(end) = bounds.next() {
+            let end: u32 = end
+                .parse()
+                .with_context(|| format!("{:?} is not a valid range", range))?;
+            if start > end {
+                bail!("{:?} is not a valid range", range);
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn get_all_pids(path: &Path) -> Result<Vec<Pid>> {
     log::debug!("scan pids in folder: {:?}", path);
     let mut result = vec![];
     walk_dir(path, &mut |p| {
         let file_path = p.join(CGROUP_PROCS);
         if file_path.exists() {
-            let file = File::open(file_path)?;
-            for line in BufReader::new(file).lines().flatten() {
-                result.push(Pid::from_raw(line.parse::<i32>()?))
-            }
+            result.append(&mut read_cgroup_procs(&file_path)?);
         }
         Ok(())
     })?;
     Ok(result)
 }
 
+/// Reads the pids listed in a cgroup.procs or tasks file. An empty file
+/// yields an empty list as the cgroup has no running processes.
+pub fn read_cgroup_procs(path: &Path) -> Result<Vec<Pid>> {
+    read_cgroup_file(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse::<i32>()
+                .map(Pid::from_raw)
+                .with_context(|| format!("failed to parse pid {} from {}", line, path.display()))
+        })
+        .collect()
+}
+
 fn walk_dir<F>(path: &Path, c: &mut F) -> Result<()>
 where
     F: FnMut(&Path) -> Result<()>,
@@ -357,6 +619,28 @@ pub(crate) fn default_devices() -> Vec<LinuxDevice> {
     ]
 }
 
+/// Formats the rdma limits of a device in the format expected by rdma.max, which is
+/// the same for cgroup v1 and v2. Returns None if no limit is set for the device.
+pub(crate) fn format_rdma_limit(device: &str, rdma: &LinuxRdma) -> Option<String> {
+    if rdma.hca_handles().is_none() && rdma.hca_objects().is_none() {
+        return None;
+    }
+
+    let hca_handles = rdma
+        .hca_handles()
+        .map(|h| h.to_string())
+        .unwrap_or_else(|| "max".to_owned());
+    let hca_objects = rdma
+        .hca_objects()
+        .map(|o| o.to_string())
+        .unwrap_or_else(|| "max".to_owned());
+
+    Some(format!(
+        "{} hca_handle={} hca_object={}",
+        device, hca_handles, hca_objects
+    ))
+}
+
 /// Attempts to delete the path the requested number of times.
 pub(crate) fn delete_with_retry<P: AsRef<Path>, L: Into<Option<Duration>>>(
     path: P,
@@ -374,7 +658,7 @@ pub(crate) fn delete_with_retry<P: AsRef<Path>, L: Into<Option<Duration>>>(
         }
 
         std::thread::sleep(delay);
-        attempts += attempts;
+        attempts += 1;
         delay *= attempts;
         if delay > limit {
             delay = limit;
@@ -383,3 +667,289 @@ pub(crate) fn delete_with_retry<P: AsRef<Path>, L: Into<Option<Duration>>>(
 
     bail!("could not delete {:?}", path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use crate::test_manager::TestManager;
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxRdmaBuilder, LinuxResourcesBuilder,
+    };
+
+    fn apply_with_guard(manager: &TestManager, frozen: bool, fail: bool) -> Result<()> {
+        let guard = FreezerGuard::new(manager, frozen);
+        if fail {
+            bail!("injected controller error");
+        }
+        guard.disarm();
+        Ok(())
+    }
+
+    #[test]
+    fn test_freezer_guard_thaws_on_error() {
+        let manager = TestManager::default();
+        assert!(apply_with_guard(&manager, true, true).is_err());
+        assert_eq!(manager.get_freeze_args(), vec![FreezerState::Thawed]);
+    }
+
+    #[test]
+    fn test_freezer_guard_disarmed() {
+        let manager = TestManager::default();
+        assert!(apply_with_guard(&manager, true, false).is_ok());
+        assert!(manager.get_freeze_args().is_empty());
+    }
+
+    #[test]
+    fn test_freezer_guard_not_armed() {
+        let manager = TestManager::default();
+        assert!(apply_with_guard(&manager, false, true).is_err());
+        assert!(manager.get_freeze_args().is_empty());
+    }
+
+    #[test]
+    fn test_parse_hugepage_size() {
+        assert_eq!(parse_hugepage_size("2MB").unwrap(), 2);
+        assert_eq!(parse_hugepage_size("1GB").unwrap(), 1);
+        assert_eq!(parse_hugepage_size("64KB").unwrap(), 64);
+        assert!(parse_hugepage_size("3MB").is_err());
+        assert!(parse_hugepage_size("0MB").is_err());
+        assert!(parse_hugepage_size("MB").is_err());
+    }
+
+    #[test]
+    fn test_limit_or_max() {
+        assert_eq!(limit_or_max(4096), "4096");
+        assert_eq!(limit_or_max(0), "0");
+        assert_eq!(limit_or_max(-1), "max");
+    }
+
+    #[test]
+    fn test_read_cgroup_procs() {
+        let tmp = create_temp_dir("test_read_cgroup_procs").expect("create temp dir");
+        let procs = set_fixture(&tmp, CGROUP_PROCS, "1\n42\n1337\n").expect("set fixture");
+
+        let pids = read_cgroup_procs(&procs).expect("read cgroup procs");
+        assert_eq!(
+            pids,
+            vec![Pid::from_raw(1), Pid::from_raw(42), Pid::from_raw(1337)]
+        );
+    }
+
+    #[test]
+    fn test_read_empty_cgroup_procs() {
+        let tmp = create_temp_dir("test_read_empty_cgroup_procs").expect("create temp dir");
+        let procs = set_fixture(&tmp, CGROUP_PROCS, "").expect("set fixture");
+
+        let pids = read_cgroup_procs(&procs).expect("read cgroup procs");
+        assert!(pids.is_empty());
+    }
+
+    #[test]
+    fn test_read_malformed_cgroup_procs() {
+        let tmp = create_temp_dir("test_read_malformed_cgroup_procs").expect("create temp dir");
+        let procs = set_fixture(&tmp, CGROUP_PROCS, "1\nabc\n").expect("set fixture");
+
+        assert!(read_cgroup_procs(&procs).is_err());
+    }
+
+    #[test]
+    fn test_validate_resources() {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024)
+                    .swap(2048)
+                    .build()
+                    .unwrap(),
+            )
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .quota(50000)
+                    .period(100000u64)
+                    .cpus("0-3,5")
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(validate_resources(&resources).is_ok());
+    }
+
+    #[test]
+    fn test_validate_malformed_resources() {
+        let cpus = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().cpus("3-1").build().unwrap())
+            .build()
+            .unwrap();
+        assert!(validate_resources(&cpus).is_err());
+
+        let quota = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().quota(-5).build().unwrap())
+            .build()
+            .unwrap();
+        assert!(validate_resources(&quota).is_err());
+
+        let swap = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(2048)
+                    .swap(1024)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        assert!(validate_resources(&swap).is_err());
+    }
+
+    #[test]
+    fn test_validate_does_not_apply() {
+        let manager = TestManager::default();
+        let resources = LinuxResources::default();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle: None,
+        };
+
+        manager.validate(&controller_opt).unwrap();
+
+        assert!(manager.validate_called());
+        assert!(!manager.apply_called());
+        assert!(manager.get_add_task_args().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_absolute_cgroup_path() {
+        assert_eq!(
+            resolve_cgroup_path(Some(Path::new("/machine/container")), "id").unwrap(),
+            PathBuf::from("/machine/container")
+        );
+    }
+
+    #[test]
+    fn test_resolve_relative_cgroup_path() {
+        assert_eq!(
+            resolve_cgroup_path(Some(Path::new("pod/./container")), "id").unwrap(),
+            PathBuf::from("/youki/pod/container")
+        );
+    }
+
+    #[test]
+    fn test_resolve_empty_cgroup_path() {
+        assert_eq!(
+            resolve_cgroup_path(None, "container_id").unwrap(),
+            PathBuf::from("/youki/container_id")
+        );
+        assert_eq!(
+            resolve_cgroup_path(Some(Path::new("")), "container_id").unwrap(),
+            PathBuf::from("/youki/container_id")
+        );
+        assert!(resolve_cgroup_path(None, "a/b").is_err());
+        assert!(resolve_cgroup_path(None, "").is_err());
+    }
+
+    #[test]
+    fn test_resolve_cgroup_path_rejects_traversal() {
+        assert!(resolve_cgroup_path(Some(Path::new("/youki/../../etc")), "id").is_err());
+        assert!(resolve_cgroup_path(Some(Path::new("../host")), "id").is_err());
+    }
+
+    #[test]
+    fn test_write_cgroup_file_reports_path() {
+        let tmp = create_temp_dir("test_write_cgroup_file_reports_path")
+            .expect("create temp directory for test");
+        let path = tmp.join("does").join("not").join("exist");
+
+        let err = write_cgroup_file(&path, 42).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains(&path.display().to_string()), "{}", msg);
+        assert!(msg.contains("42"), "{}", msg);
+    }
+
+    #[test]
+    fn test_read_cgroup_file_trimmed() {
+        let tmp = create_temp_dir("test_read_cgroup_file_trimmed")
+            .expect("create temp directory for test");
+        let path = set_fixture(&tmp, "pids.max", "").expect("set fixture for pids.max");
+
+        write_cgroup_file(&path, 1000).expect("write pids.max");
+        let content = read_cgroup_file_trimmed(&path).expect("read pids.max");
+        assert_eq!(content, "1000");
+
+        set_fixture(&tmp, "pids.current", "42\n").expect("set fixture for pids.current");
+        let content =
+            read_cgroup_file_trimmed(tmp.join("pids.current")).expect("read pids.current");
+        assert_eq!(content, "42");
+    }
+
+    #[test]
+    fn test_read_cgroup_file_missing() {
+        let tmp = create_temp_dir("test_read_cgroup_file_missing")
+            .expect("create temp directory for test");
+        let path = tmp.join("memory.usage_in_bytes");
+
+        let err = read_cgroup_file(&path).unwrap_err();
+        assert!(err.to_string().contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_format_rdma_limit() {
+        let rdma = LinuxRdmaBuilder::default()
+            .hca_handles(2u32)
+            .hca_objects(2000u32)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            format_rdma_limit("mlx5_1", &rdma),
+            Some("mlx5_1 hca_handle=2 hca_object=2000".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_format_rdma_limit_partial() {
+        let rdma = LinuxRdmaBuilder::default()
+            .hca_objects(2000u32)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            format_rdma_limit("mlx5_1", &rdma),
+            Some("mlx5_1 hca_handle=max hca_object=2000".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_format_rdma_limit_unset() {
+        let rdma = LinuxRdmaBuilder::default().build().unwrap();
+        assert_eq!(format_rdma_limit("mlx5_1", &rdma), None);
+    }
+
+    #[test]
+    fn test_detect_cgroup_setup() {
+        assert_eq!(
+            detect_cgroup_setup(CGROUP2_SUPER_MAGIC, None),
+            Some(CgroupSetup::Unified)
+        );
+        assert_eq!(
+            detect_cgroup_setup(TMPFS_MAGIC, Some(CGROUP2_SUPER_MAGIC)),
+            Some(CgroupSetup::Hybrid)
+        );
+        assert_eq!(
+            detect_cgroup_setup(TMPFS_MAGIC, None),
+            Some(CgroupSetup::Legacy)
+        );
+        assert_eq!(
+            detect_cgroup_setup(TMPFS_MAGIC, Some(TMPFS_MAGIC)),
+            Some(CgroupSetup::Legacy)
+        );
+        assert_eq!(
+            detect_cgroup_setup(nix::sys::statfs::EXT4_SUPER_MAGIC, None),
+            None
+        );
+    }
+}
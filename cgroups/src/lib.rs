@@ -7,6 +7,7 @@ extern crate quickcheck;
 mod test;
 
 pub mod common;
+pub mod noop_manager;
 pub mod stats;
 pub mod test_manager;
 pub mod v1;
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use nix::unistd::Pid;
+
+use crate::{
+    common::{CgroupManager, ControllerOpt, FreezerState},
+    stats::Stats,
+};
+
+/// Cgroup manager which does not manage any cgroup. It is used if cgroups have been
+/// disabled, so that the container process can be created without placing it into a
+/// cgroup or applying resource restrictions.
+#[derive(Debug, Default)]
+pub struct NoopCgroupManager {}
+
+impl CgroupManager for NoopCgroupManager {
+    fn add_task(&self, _pid: Pid) -> Result<()> {
+        Ok(())
+    }
+
+    fn apply(&self, _controller_opt: &ControllerOpt) -> Result<()> {
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn freeze(&self, _state: FreezerState) -> Result<()> {
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        Ok(Stats::default())
+    }
+
+    fn get_all_pids(&self) -> Result<Vec<Pid>> {
+        Ok(Vec::new())
+    }
+
+    fn validate(&self, _controller_opt: &ControllerOpt) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::LinuxResources;
+
+    #[test]
+    fn test_noop_manager() -> Result<()> {
+        let manager = NoopCgroupManager::default();
+        let resources = LinuxResources::default();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: Some(FreezerState::Frozen),
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            cpu_idle: None,
+        };
+
+        manager.add_task(Pid::from_raw(42))?;
+        manager.validate(&controller_opt)?;
+        manager.apply(&controller_opt)?;
+        manager.freeze(FreezerState::Thawed)?;
+        assert!(manager.get_all_pids()?.is_empty());
+        assert_eq!(manager.stats()?.pids.current, 0);
+        assert_eq!(manager.register_oom_event()?, None);
+        manager.remove()?;
+        Ok(())
+    }
+}
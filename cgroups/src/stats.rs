@@ -123,6 +123,8 @@ pub struct MemoryStats {
     pub hierarchy: bool,
     /// Various memory statistics
     pub stats: HashMap<String, u64>,
+    /// Number of processes that have been killed by the OOM killer
+    pub oom_kill: u64,
 }
 
 impl Default for MemoryStats {
@@ -135,6 +137,7 @@ impl Default for MemoryStats {
             cache: 0,
             hierarchy: false,
             stats: HashMap::default(),
+            oom_kill: 0,
         }
     }
 }
@@ -415,14 +418,10 @@ pub fn parse_device_number(device: &str) -> Result<(u64, u64)> {
 pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats> {
     let mut stats = PidStats::default();
 
-    let current = common::read_cgroup_file(cgroup_path.join("pids.current"))?;
-    stats.current = current
-        .trim()
-        .parse()
-        .context("failed to parse current pids")?;
+    let current = common::read_cgroup_file_trimmed(cgroup_path.join("pids.current"))?;
+    stats.current = current.parse().context("failed to parse current pids")?;
 
-    let limit =
-        common::read_cgroup_file(cgroup_path.join("pids.max")).map(|l| l.trim().to_owned())?;
+    let limit = common::read_cgroup_file_trimmed(cgroup_path.join("pids.max"))?;
     if limit != "max" {
         stats.limit = limit.parse().context("failed to parse pids limit")?;
     }
@@ -436,6 +435,26 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_pid_stats() {
+        let tmp = create_temp_dir("test_pid_stats").expect("create temp dir for test");
+        set_fixture(&tmp, "pids.current", "42\n").expect("set fixture for pids.current");
+        set_fixture(&tmp, "pids.max", "max\n").expect("set fixture for pids.max");
+
+        let stats = pid_stats(&tmp).expect("get pid stats");
+        assert_eq!(stats.current, 42);
+        assert_eq!(stats.limit, 0);
+    }
+
+    #[test]
+    fn test_pid_stats_missing_current() {
+        let tmp =
+            create_temp_dir("test_pid_stats_missing_current").expect("create temp dir for test");
+        set_fixture(&tmp, "pids.max", "10\n").expect("set fixture for pids.max");
+
+        assert!(pid_stats(&tmp).is_err());
+    }
+
     #[test]
     fn test_supported_page_sizes_gigabyte() {
         let page_size = extract_page_size("hugepages-1048576kB").unwrap();
@@ -6,6 +6,7 @@ use std::{
     io::Write,
     ops::Deref,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 pub struct TempDir {
@@ -53,8 +54,14 @@ impl Deref for TempDir {
     }
 }
 
+static TEMP_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a temporary directory named after the test. The name is made unique by
+/// appending the pid and a counter, so tests using the same name do not collide.
 pub fn create_temp_dir(test_name: &str) -> Result<TempDir> {
-    let dir = TempDir::new(std::env::temp_dir().join(test_name))?;
+    let id = TEMP_DIR_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir_name = format!("{}-{}-{}", test_name, std::process::id(), id);
+    let dir = TempDir::new(std::env::temp_dir().join(dir_name))?;
     Ok(dir)
 }
 
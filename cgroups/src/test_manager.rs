@@ -1,6 +1,6 @@
 use std::cell::RefCell;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use nix::unistd::Pid;
 
 use crate::{
@@ -12,6 +12,11 @@ use crate::{
 pub struct TestManager {
     add_task_args: RefCell<Vec<Pid>>,
     pub apply_called: RefCell<bool>,
+    remove_called: RefCell<bool>,
+    validate_called: RefCell<bool>,
+    freeze_args: RefCell<Vec<FreezerState>>,
+    cpu_idle: RefCell<Option<u64>>,
+    pids: RefCell<Option<Vec<Pid>>>,
 }
 
 impl Default for TestManager {
@@ -19,28 +24,40 @@ impl Default for TestManager {
         Self {
             add_task_args: RefCell::new(vec![]),
             apply_called: RefCell::new(false),
+            remove_called: RefCell::new(false),
+            validate_called: RefCell::new(false),
+            freeze_args: RefCell::new(vec![]),
+            cpu_idle: RefCell::new(None),
+            pids: RefCell::new(None),
         }
     }
 }
 
 impl CgroupManager for TestManager {
     fn add_task(&self, pid: Pid) -> Result<()> {
-        self.add_task_args.borrow_mut().push(pid);
+        // like the real managers, adding a task that is already present is a no-op
+        let mut add_task_args = self.add_task_args.borrow_mut();
+        if !add_task_args.contains(&pid) {
+            add_task_args.push(pid);
+        }
         Ok(())
     }
 
     // NOTE: The argument cannot be stored due to lifetime.
-    fn apply(&self, _controller_opt: &ControllerOpt) -> Result<()> {
+    fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
         *self.apply_called.borrow_mut() = true;
+        *self.cpu_idle.borrow_mut() = controller_opt.cpu_idle;
         Ok(())
     }
 
     fn remove(&self) -> Result<()> {
-        unimplemented!()
+        *self.remove_called.borrow_mut() = true;
+        Ok(())
     }
 
-    fn freeze(&self, _state: FreezerState) -> Result<()> {
-        unimplemented!()
+    fn freeze(&self, state: FreezerState) -> Result<()> {
+        self.freeze_args.borrow_mut().push(state);
+        Ok(())
     }
 
     fn stats(&self) -> anyhow::Result<Stats> {
@@ -48,7 +65,15 @@ impl CgroupManager for TestManager {
     }
 
     fn get_all_pids(&self) -> Result<Vec<Pid>> {
-        unimplemented!()
+        self.pids
+            .borrow()
+            .clone()
+            .ok_or_else(|| anyhow!("no pids have been set"))
+    }
+
+    fn validate(&self, _controller_opt: &ControllerOpt) -> Result<()> {
+        *self.validate_called.borrow_mut() = true;
+        Ok(())
     }
 }
 
@@ -60,4 +85,26 @@ impl TestManager {
     pub fn apply_called(&self) -> bool {
         *self.apply_called.borrow_mut()
     }
+
+    pub fn remove_called(&self) -> bool {
+        *self.remove_called.borrow_mut()
+    }
+
+    pub fn validate_called(&self) -> bool {
+        *self.validate_called.borrow_mut()
+    }
+
+    pub fn get_freeze_args(&self) -> Vec<FreezerState> {
+        self.freeze_args.borrow_mut().clone()
+    }
+
+    /// Sets the pids returned by get_all_pids, which fails as long as none are set
+    pub fn set_pids(&self, pids: Vec<Pid>) {
+        *self.pids.borrow_mut() = Some(pids);
+    }
+
+    /// Returns the cpu idle value of the last apply
+    pub fn get_cpu_idle(&self) -> Option<u64> {
+        *self.cpu_idle.borrow()
+    }
 }
@@ -38,7 +38,6 @@ const BLKIO_WEIGHT: &str = "blkio.weight";
 // Specifies the relative proportion of block I/O access for specific devices available
 // to the cgroup. This overrides the the blkio.weight value for the specified device
 // Format: Major:Minor weight (weight can range from 100 to 1000)
-#[allow(dead_code)]
 const BLKIO_WEIGHT_DEVICE: &str = "blkio.weight_device";
 
 // Common parameters which may be used for either policy but seem to be used only for
@@ -103,6 +102,21 @@ impl StatsProvider for Blkio {
 
 impl Blkio {
     fn apply(root_path: &Path, blkio: &LinuxBlockIo) -> Result<()> {
+        if let Some(weight) = blkio.weight() {
+            common::write_cgroup_file(root_path.join(BLKIO_WEIGHT), weight)?;
+        }
+
+        if let Some(weight_device) = blkio.weight_device().as_ref() {
+            for wd in weight_device {
+                if let Some(weight) = wd.weight() {
+                    common::write_cgroup_file_str(
+                        &root_path.join(BLKIO_WEIGHT_DEVICE),
+                        &format!("{}:{} {}", wd.major(), wd.minor(), weight),
+                    )?;
+                }
+            }
+        }
+
         if let Some(throttle_read_bps_device) = blkio.throttle_read_bps_device().as_ref() {
             for trbd in throttle_read_bps_device {
                 common::write_cgroup_file_str(
@@ -224,7 +238,75 @@ mod tests {
     use crate::test::{create_temp_dir, set_fixture, setup};
 
     use anyhow::Result;
-    use oci_spec::runtime::{LinuxBlockIoBuilder, LinuxThrottleDeviceBuilder};
+    use oci_spec::runtime::{
+        LinuxBlockIoBuilder, LinuxThrottleDeviceBuilder, LinuxWeightDeviceBuilder,
+    };
+
+    #[test]
+    fn test_set_blkio_weight() {
+        let (tmp, weight) = setup("test_set_blkio_weight", BLKIO_WEIGHT);
+
+        let blkio = LinuxBlockIoBuilder::default()
+            .weight(500u16)
+            .build()
+            .unwrap();
+
+        Blkio::apply(&tmp, &blkio).expect("apply blkio");
+        let content =
+            fs::read_to_string(weight).unwrap_or_else(|_| panic!("read {} content", BLKIO_WEIGHT));
+
+        assert_eq!("500", content);
+    }
+
+    #[test]
+    fn test_set_blkio_weight_unset() {
+        let (tmp, weight) = setup("test_set_blkio_weight_unset", BLKIO_WEIGHT);
+
+        let blkio = LinuxBlockIoBuilder::default().build().unwrap();
+
+        Blkio::apply(&tmp, &blkio).expect("apply blkio");
+        let content =
+            fs::read_to_string(weight).unwrap_or_else(|_| panic!("read {} content", BLKIO_WEIGHT));
+
+        assert_eq!("", content);
+    }
+
+    #[test]
+    fn test_set_blkio_weight_device() {
+        let (tmp, weight_device) = setup("test_set_blkio_weight_device", BLKIO_WEIGHT_DEVICE);
+
+        let blkio = LinuxBlockIoBuilder::default()
+            .weight_device(vec![LinuxWeightDeviceBuilder::default()
+                .major(8)
+                .minor(0)
+                .weight(300u16)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+
+        Blkio::apply(&tmp, &blkio).expect("apply blkio");
+        let content = fs::read_to_string(weight_device)
+            .unwrap_or_else(|_| panic!("read {} content", BLKIO_WEIGHT_DEVICE));
+
+        assert_eq!("8:0 300", content);
+    }
+
+    #[test]
+    fn test_set_blkio_empty_throttle() {
+        let (tmp, throttle) = setup("test_set_blkio_empty_throttle", BLKIO_THROTTLE_READ_BPS);
+
+        let blkio = LinuxBlockIoBuilder::default()
+            .throttle_read_bps_device(vec![])
+            .build()
+            .unwrap();
+
+        Blkio::apply(&tmp, &blkio).expect("apply blkio");
+        let content = fs::read_to_string(throttle)
+            .unwrap_or_else(|_| panic!("read {} content", BLKIO_THROTTLE_READ_BPS));
+
+        assert_eq!("", content);
+    }
 
     #[test]
     fn test_set_blkio_read_bps() {
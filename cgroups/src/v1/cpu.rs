@@ -113,20 +113,62 @@ impl Cpu {
             }
         }
 
-        if let Some(rt_runtime) = cpu.realtime_runtime() {
-            if rt_runtime != 0 {
-                common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_RUNTIME), rt_runtime)?;
+        let rt_runtime = cpu.realtime_runtime().filter(|r| *r != 0);
+        let rt_period = cpu.realtime_period().filter(|p| *p != 0);
+        match (rt_runtime, rt_period) {
+            (Some(rt_runtime), Some(rt_period)) => {
+                Self::apply_rt_bandwidth(root_path, rt_runtime, rt_period)?
             }
+            (Some(rt_runtime), None) => {
+                common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_RUNTIME), rt_runtime)?
+            }
+            (None, Some(rt_period)) => {
+                common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_PERIOD), rt_period)?
+            }
+            (None, None) => {}
         }
 
-        if let Some(rt_period) = cpu.realtime_period() {
-            if rt_period != 0 {
-                common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_PERIOD), rt_period)?;
-            }
+        Ok(())
+    }
+
+    // The kernel validates every intermediate state of the rt bandwidth, so the runtime
+    // must never exceed the period. When the period is lowered the runtime has to be
+    // written first, when it is raised the period has to be written first.
+    fn apply_rt_bandwidth(root_path: &Path, rt_runtime: i64, rt_period: u64) -> Result<()> {
+        Self::write_rt_bandwidth(root_path, rt_runtime, rt_period, |path, value| {
+            common::write_cgroup_file_str(path, value)
+        })
+    }
+
+    fn write_rt_bandwidth<W: FnMut(&Path, &str) -> Result<()>>(
+        root_path: &Path,
+        rt_runtime: i64,
+        rt_period: u64,
+        mut write: W,
+    ) -> Result<()> {
+        let current_period = common::read_cgroup_file_trimmed(root_path.join(CGROUP_CPU_RT_PERIOD))
+            .ok()
+            .and_then(|p| p.parse::<u64>().ok());
+
+        let runtime_path = root_path.join(CGROUP_CPU_RT_RUNTIME);
+        let period_path = root_path.join(CGROUP_CPU_RT_PERIOD);
+        if Self::rt_runtime_first(current_period, rt_period) {
+            write(&runtime_path, &rt_runtime.to_string())?;
+            write(&period_path, &rt_period.to_string())?;
+        } else {
+            write(&period_path, &rt_period.to_string())?;
+            write(&runtime_path, &rt_runtime.to_string())?;
         }
 
         Ok(())
     }
+
+    fn rt_runtime_first(current_period: Option<u64>, new_period: u64) -> bool {
+        match current_period {
+            Some(current_period) => new_period < current_period,
+            None => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -223,6 +265,82 @@ mod tests {
         assert_eq!(content, PERIOD.to_string());
     }
 
+    /// Returns the file names and values in the order in which they are written
+    fn record_rt_writes(
+        root_path: &Path,
+        rt_runtime: i64,
+        rt_period: u64,
+    ) -> Vec<(String, String)> {
+        let mut writes = Vec::new();
+        Cpu::write_rt_bandwidth(root_path, rt_runtime, rt_period, |path, value| {
+            let file = path.file_name().unwrap().to_string_lossy().into_owned();
+            writes.push((file, value.to_owned()));
+            Ok(())
+        })
+        .expect("write rt bandwidth");
+        writes
+    }
+
+    #[test]
+    fn test_set_rt_lower_period() {
+        // arrange
+        let tmp = create_temp_dir("test_set_rt_lower_period").expect("create test directory");
+        set_fixture(&tmp, CGROUP_CPU_RT_PERIOD, "950000").expect("set rt period fixture");
+        set_fixture(&tmp, CGROUP_CPU_RT_RUNTIME, "950000").expect("set rt runtime fixture");
+        let cpu = LinuxCpuBuilder::default()
+            .realtime_runtime(400000i64)
+            .realtime_period(500000u64)
+            .build()
+            .unwrap();
+
+        // act
+        let writes = record_rt_writes(&tmp, 400000, 500000);
+        Cpu::apply(&tmp, &cpu).expect("apply cpu");
+
+        // assert
+        assert_eq!(
+            writes,
+            vec![
+                (CGROUP_CPU_RT_RUNTIME.to_owned(), "400000".to_owned()),
+                (CGROUP_CPU_RT_PERIOD.to_owned(), "500000".to_owned()),
+            ]
+        );
+        let period = fs::read_to_string(tmp.join(CGROUP_CPU_RT_PERIOD)).expect("read rt period");
+        let runtime = fs::read_to_string(tmp.join(CGROUP_CPU_RT_RUNTIME)).expect("read rt runtime");
+        assert_eq!(period, "500000");
+        assert_eq!(runtime, "400000");
+    }
+
+    #[test]
+    fn test_set_rt_raise_period() {
+        // arrange
+        let tmp = create_temp_dir("test_set_rt_raise_period").expect("create test directory");
+        set_fixture(&tmp, CGROUP_CPU_RT_PERIOD, "500000").expect("set rt period fixture");
+        set_fixture(&tmp, CGROUP_CPU_RT_RUNTIME, "400000").expect("set rt runtime fixture");
+        let cpu = LinuxCpuBuilder::default()
+            .realtime_runtime(900000i64)
+            .realtime_period(950000u64)
+            .build()
+            .unwrap();
+
+        // act
+        let writes = record_rt_writes(&tmp, 900000, 950000);
+        Cpu::apply(&tmp, &cpu).expect("apply cpu");
+
+        // assert
+        assert_eq!(
+            writes,
+            vec![
+                (CGROUP_CPU_RT_PERIOD.to_owned(), "950000".to_owned()),
+                (CGROUP_CPU_RT_RUNTIME.to_owned(), "900000".to_owned()),
+            ]
+        );
+        let period = fs::read_to_string(tmp.join(CGROUP_CPU_RT_PERIOD)).expect("read rt period");
+        let runtime = fs::read_to_string(tmp.join(CGROUP_CPU_RT_RUNTIME)).expect("read rt runtime");
+        assert_eq!(period, "950000");
+        assert_eq!(runtime, "900000");
+    }
+
     #[test]
     fn test_stat_cpu_throttling() {
         let tmp = create_temp_dir("test_stat_cpu_throttling").expect("create test directory");
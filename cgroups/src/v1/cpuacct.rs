@@ -82,11 +82,8 @@ impl CpuAcct {
             .parse()
             .context("failed to parse kernel mode cpu usage")?;
 
-        let total = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_USAGE))?;
-        stats.usage_total = total
-            .trim()
-            .parse()
-            .context("failed to parse total cpu usage")?;
+        let total = common::read_cgroup_file_trimmed(cgroup_path.join(CGROUP_CPUACCT_USAGE))?;
+        stats.usage_total = total.parse().context("failed to parse total cpu usage")?;
 
         Ok(())
     }
@@ -65,8 +65,20 @@ impl CpuSet {
     // if a task is moved into the cgroup and a value has not been set for cpus and mems
     // Errno 28 (no space left on device) will be returned. Therefore we set the value from the parent if required.
     fn ensure_not_empty(cgroup_path: &Path, interface_file: &str) -> Result<()> {
-        let mut current = util::get_subsystem_mount_point(&ControllerType::CpuSet)?;
-        let relative_cgroup_path = cgroup_path.strip_prefix(&current)?;
+        let mount_point = util::get_subsystem_mount_point(&ControllerType::CpuSet)?;
+        Self::inherit_from_parent(&mount_point, cgroup_path, interface_file)
+    }
+
+    // walks from the subsystem root down to the cgroup and copies the parent value of the
+    // interface file into every level where it is still empty
+    fn inherit_from_parent(root: &Path, cgroup_path: &Path, interface_file: &str) -> Result<()> {
+        let mut current = root.to_path_buf();
+        let relative_cgroup_path = cgroup_path.strip_prefix(root).with_context(|| {
+            format!(
+                "cgroup path {:?} is not below the cpuset root {:?}",
+                cgroup_path, root
+            )
+        })?;
 
         for component in relative_cgroup_path.components() {
             let parent_value = fs::read_to_string(current.join(interface_file))?;
@@ -93,9 +105,65 @@ mod tests {
     use std::fs;
 
     use super::*;
-    use crate::test::setup;
+    use crate::test::{create_temp_dir, set_fixture, setup};
     use oci_spec::runtime::LinuxCpuBuilder;
 
+    #[test]
+    fn test_set_cpus_range() {
+        // arrange
+        let (tmp, cpus) = setup("test_set_cpus_range", CGROUP_CPUSET_CPUS);
+        let cpuset = LinuxCpuBuilder::default()
+            .cpus("0-3".to_owned())
+            .build()
+            .unwrap();
+
+        // act
+        CpuSet::apply(&tmp, &cpuset).expect("apply cpuset");
+
+        // assert
+        let content = fs::read_to_string(&cpus)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_CPUS));
+        assert_eq!(content, "0-3");
+    }
+
+    #[test]
+    fn test_inherit_cpus_from_parent() {
+        // arrange
+        let tmp = create_temp_dir("test_inherit_cpus_from_parent")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_CPUSET_CPUS, "0-7\n").expect("set parent cpus");
+        let child = tmp.join("youki");
+        fs::create_dir_all(&child).expect("create child cgroup");
+        let child_cpus = set_fixture(&child, CGROUP_CPUSET_CPUS, "\n").expect("set child cpus");
+
+        // act
+        CpuSet::inherit_from_parent(&tmp, &child, CGROUP_CPUSET_CPUS).expect("inherit cpus");
+
+        // assert
+        let content = fs::read_to_string(&child_cpus)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_CPUS));
+        assert_eq!(content, "0-7\n");
+    }
+
+    #[test]
+    fn test_inherit_keeps_existing_cpus() {
+        // arrange
+        let tmp = create_temp_dir("test_inherit_keeps_existing_cpus")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_CPUSET_CPUS, "0-7").expect("set parent cpus");
+        let child = tmp.join("youki");
+        fs::create_dir_all(&child).expect("create child cgroup");
+        let child_cpus = set_fixture(&child, CGROUP_CPUSET_CPUS, "2").expect("set child cpus");
+
+        // act
+        CpuSet::inherit_from_parent(&tmp, &child, CGROUP_CPUSET_CPUS).expect("inherit cpus");
+
+        // assert
+        let content = fs::read_to_string(&child_cpus)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_CPUS));
+        assert_eq!(content, "2");
+    }
+
     #[test]
     fn test_set_cpus() {
         // arrange
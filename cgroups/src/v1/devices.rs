@@ -6,6 +6,8 @@ use super::controller::Controller;
 use crate::common::{self, default_allow_devices, default_devices, ControllerOpt};
 use oci_spec::runtime::LinuxDeviceCgroup;
 
+const DENY_ALL: &str = "a *:* rwm";
+
 pub struct Devices {}
 
 impl Controller for Devices {
@@ -15,6 +17,11 @@ impl Controller for Devices {
         log::debug!("Apply Devices cgroup config");
 
         if let Some(devices) = controller_opt.resources.devices().as_ref() {
+            // explicit rules are expected to be applied on top of a deny all policy
+            if !devices.is_empty() {
+                Self::deny_all(cgroup_root)?;
+            }
+
             for d in devices {
                 Self::apply_device(d, cgroup_root)?;
             }
@@ -49,6 +56,10 @@ impl Devices {
         common::write_cgroup_file_str(path, &device.to_string())?;
         Ok(())
     }
+
+    fn deny_all(cgroup_root: &Path) -> Result<()> {
+        common::write_cgroup_file_str(cgroup_root.join("devices.deny"), DENY_ALL)
+    }
 }
 
 #[cfg(test)]
@@ -56,9 +67,60 @@ mod tests {
     use super::*;
     use crate::test::create_temp_dir;
     use crate::test::set_fixture;
-    use oci_spec::runtime::{LinuxDeviceCgroupBuilder, LinuxDeviceType};
+    use oci_spec::runtime::{LinuxDeviceCgroupBuilder, LinuxDeviceType, LinuxResourcesBuilder};
     use std::fs::read_to_string;
 
+    #[test]
+    fn test_set_wildcard_device() {
+        let tmp =
+            create_temp_dir("test_set_wildcard_device").expect("create temp directory for test");
+        set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
+        set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
+
+        let device = LinuxDeviceCgroupBuilder::default()
+            .allow(true)
+            .typ(LinuxDeviceType::C)
+            .access("rwm")
+            .build()
+            .unwrap();
+
+        Devices::apply_device(&device, &tmp).expect("Apply wildcard device");
+        let allowed_content = read_to_string(tmp.join("devices.allow")).expect("read to string");
+        assert_eq!(allowed_content, "c *:* rwm");
+    }
+
+    #[test]
+    fn test_deny_all_before_explicit_rules() {
+        let tmp = create_temp_dir("test_deny_all_before_explicit_rules")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
+        set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
+
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![LinuxDeviceCgroupBuilder::default()
+                .allow(true)
+                .typ(LinuxDeviceType::C)
+                .major(1)
+                .minor(3)
+                .access("rwm")
+                .build()
+                .unwrap()])
+            .hugepage_limits(vec![])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle: None,
+        };
+
+        <Devices as Controller>::apply(&controller_opt, &tmp).expect("apply devices");
+        let denied_content = read_to_string(tmp.join("devices.deny")).expect("read to string");
+        assert_eq!(denied_content, DENY_ALL);
+    }
+
     #[test]
     fn test_set_default_devices() {
         let tmp =
@@ -1,9 +1,4 @@
-use std::io::prelude::*;
-use std::{
-    fs::{create_dir_all, OpenOptions},
-    path::Path,
-    thread, time,
-};
+use std::{fs::create_dir_all, path::Path, thread, time};
 
 use anyhow::{Result, *};
 
@@ -15,6 +10,7 @@ const CGROUP_FREEZER_STATE: &str = "freezer.state";
 const FREEZER_STATE_THAWED: &str = "THAWED";
 const FREEZER_STATE_FROZEN: &str = "FROZEN";
 const FREEZER_STATE_FREEZING: &str = "FREEZING";
+const THAW_RETRIES: u32 = 100;
 
 pub struct Freezer {}
 
@@ -42,10 +38,25 @@ impl Freezer {
         match freezer_state {
             FreezerState::Undefined => {}
             FreezerState::Thawed => {
-                common::write_cgroup_file(
-                    cgroup_root.join(CGROUP_FREEZER_STATE),
-                    FREEZER_STATE_THAWED,
-                )?;
+                // The kernel may take a moment to wake up all tasks, so we wait until
+                // THAWED is reported back instead of trusting the write alone.
+                for i in 0..THAW_RETRIES {
+                    common::write_cgroup_file(
+                        cgroup_root.join(CGROUP_FREEZER_STATE),
+                        FREEZER_STATE_THAWED,
+                    )?;
+
+                    let r = Self::read_freezer_state(cgroup_root)?;
+                    if r.trim() == FREEZER_STATE_THAWED {
+                        if i > 0 {
+                            log::debug!("thawed after {} retries", i)
+                        }
+                        return Ok(());
+                    }
+
+                    thread::sleep(time::Duration::from_millis(10));
+                }
+                bail!("unable to thaw after {} retries", THAW_RETRIES);
             }
             FreezerState::Frozen => {
                 let r = || -> Result<()> {
@@ -88,7 +99,7 @@ impl Freezer {
                             }
                         }
                     }
-                    bail!("unbale to freeze");
+                    bail!("unable to freeze");
                 }();
 
                 if r.is_err() {
@@ -106,14 +117,7 @@ impl Freezer {
     }
 
     fn read_freezer_state(cgroup_root: &Path) -> Result<String> {
-        let path = cgroup_root.join(CGROUP_FREEZER_STATE);
-        let mut content = String::new();
-        OpenOptions::new()
-            .create(false)
-            .read(true)
-            .open(path)?
-            .read_to_string(&mut content)?;
-        Ok(content)
+        common::read_cgroup_file(cgroup_root.join(CGROUP_FREEZER_STATE))
     }
 }
 
@@ -164,6 +168,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_apply_without_freezer_state() {
+        let tmp = create_temp_dir("test_apply_without_freezer_state")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_FREEZER_STATE, FREEZER_STATE_THAWED)
+            .expect("set fixure for freezer state");
+
+        let linux_resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &linux_resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            cpu_idle: None,
+        };
+
+        <Freezer as Controller>::apply(&controller_opt, &tmp).expect("freezer apply");
+        let state_content =
+            std::fs::read_to_string(tmp.join(CGROUP_FREEZER_STATE)).expect("read to string");
+        assert_eq!(FREEZER_STATE_THAWED, state_content);
+    }
+
     #[test]
     fn test_add_and_apply() {
         let tmp = create_temp_dir("test_add_task").expect("create temp directory for test");
@@ -184,6 +214,7 @@ mod tests {
                 freezer_state: Some(state),
                 oom_score_adj: None,
                 disable_oom_killer: false,
+                cpu_idle: None,
             };
 
             let pid = Pid::from_raw(1000);
@@ -211,6 +242,7 @@ mod tests {
                 freezer_state: Some(state),
                 oom_score_adj: None,
                 disable_oom_killer: false,
+                cpu_idle: None,
             };
 
             let pid = Pid::from_raw(1001);
@@ -239,6 +271,7 @@ mod tests {
                 freezer_state: Some(state),
                 oom_score_adj: None,
                 disable_oom_killer: false,
+                cpu_idle: None,
             };
 
             let pid = Pid::from_raw(1002);
@@ -57,41 +57,34 @@ impl StatsProvider for HugeTlb {
 
 impl HugeTlb {
     fn apply(root_path: &Path, hugetlb: &LinuxHugepageLimit) -> Result<()> {
-        let page_size: String = hugetlb
-            .page_size()
-            .chars()
-            .take_while(|c| c.is_digit(10))
-            .collect();
-        let page_size: u64 = page_size.parse()?;
-        if !Self::is_power_of_two(page_size) {
-            bail!("page size must be in the format of 2^(integer)");
+        common::parse_hugepage_size(hugetlb.page_size())?;
+
+        let limit_file = root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size()));
+        if !limit_file.exists() {
+            bail!(
+                "hugetlb page size {} is not supported by the host",
+                hugetlb.page_size()
+            );
         }
 
-        common::write_cgroup_file(
-            root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size())),
-            hugetlb.limit(),
-        )?;
+        common::write_cgroup_file(limit_file, hugetlb.limit())?;
         Ok(())
     }
 
-    fn is_power_of_two(number: u64) -> bool {
-        (number != 0) && (number & (number - 1)) == 0
-    }
-
     fn stats_for_page_size(cgroup_path: &Path, page_size: &str) -> Result<HugeTlbStats> {
         let mut stats = HugeTlbStats::default();
 
         let usage_file = format!("hugetlb.{}.usage_in_bytes", page_size);
-        let usage_content = common::read_cgroup_file(cgroup_path.join(usage_file))?;
-        stats.usage = usage_content.trim().parse()?;
+        let usage_content = common::read_cgroup_file_trimmed(cgroup_path.join(usage_file))?;
+        stats.usage = usage_content.parse()?;
 
         let max_file = format!("hugetlb.{}.max_usage_in_bytes", page_size);
-        let max_content = common::read_cgroup_file(cgroup_path.join(max_file))?;
-        stats.max_usage = max_content.trim().parse()?;
+        let max_content = common::read_cgroup_file_trimmed(cgroup_path.join(max_file))?;
+        stats.max_usage = max_content.parse()?;
 
         let failcnt_file = format!("hugetlb.{}.failcnt", page_size);
-        let failcnt_content = common::read_cgroup_file(cgroup_path.join(failcnt_file))?;
-        stats.fail_count = failcnt_content.trim().parse()?;
+        let failcnt_content = common::read_cgroup_file_trimmed(cgroup_path.join(failcnt_file))?;
+        stats.fail_count = failcnt_content.parse()?;
 
         Ok(stats)
     }
@@ -139,6 +132,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_hugetlb_with_unsupported_page_size() {
+        let tmp = create_temp_dir("test_set_hugetlb_with_unsupported_page_size")
+            .expect("create temp directory for test");
+
+        let hugetlb = LinuxHugepageLimitBuilder::default()
+            .page_size("4MB")
+            .limit(16384)
+            .build()
+            .unwrap();
+
+        let err = HugeTlb::apply(&tmp, &hugetlb).unwrap_err();
+        assert!(err.to_string().contains("4MB"));
+    }
+
     quickcheck! {
         fn property_test_set_hugetlb(hugetlb: LinuxHugepageLimit) -> bool {
             let page_file_name = format!("hugetlb.{:?}.limit_in_bytes", hugetlb.page_size());
@@ -154,7 +162,7 @@ mod tests {
             .collect();
             let page_size: u64 = page_size.parse().expect("parse page size");
 
-            if HugeTlb::is_power_of_two(page_size) && page_size != 1 {
+            if page_size.is_power_of_two() && page_size != 1 {
                 let content =
                     read_to_string(tmp.join(page_file_name)).expect("Read hugetlb file content");
                 hugetlb.limit().to_string() == content
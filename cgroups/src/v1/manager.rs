@@ -1,10 +1,16 @@
 use std::fs;
+use std::os::unix::io::RawFd;
 use std::path::Path;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
-use anyhow::bail;
-use anyhow::Result;
+use anyhow::{anyhow, bail};
+use anyhow::{Context, Result};
 use nix::unistd::Pid;
 
 use procfs::process::Process;
@@ -14,14 +20,18 @@ use super::{
     blkio::Blkio, controller_type::CONTROLLERS, cpu::Cpu, cpuacct::CpuAcct, cpuset::CpuSet,
     devices::Devices, freezer::Freezer, hugetlb::HugeTlb, memory::Memory,
     network_classifier::NetworkClassifier, network_priority::NetworkPriority,
-    perf_event::PerfEvent, pids::Pids, util, Controller,
+    perf_event::PerfEvent, pids::Pids, rdma::Rdma, util, Controller,
 };
 
-use crate::common::{self, CgroupManager, ControllerOpt, FreezerState, PathBufExt, CGROUP_PROCS};
+use crate::common::{
+    self, CgroupManager, ControllerOpt, FreezerGuard, FreezerState, PathBufExt, CGROUP_PROCS,
+};
 use crate::stats::{Stats, StatsProvider};
 
 pub struct Manager {
     subsystems: HashMap<CtrlType, PathBuf>,
+    /// Subsystems which are mounted according to /proc/self/mountinfo
+    mounted_subsystems: HashSet<String>,
 }
 
 impl Manager {
@@ -36,7 +46,11 @@ impl Manager {
             }
         }
 
-        Ok(Manager { subsystems })
+        let mounted_subsystems = util::list_mounted_subsystems()?;
+        Ok(Manager {
+            subsystems,
+            mounted_subsystems,
+        })
     }
 
     fn get_subsystem_path(cgroup_path: &Path, subsystem: &CtrlType) -> Result<PathBuf> {
@@ -82,22 +96,163 @@ impl Manager {
                     NetworkClassifier::needs_to_handle(controller_opt).is_some()
                 }
                 CtrlType::Freezer => Freezer::needs_to_handle(controller_opt).is_some(),
+                CtrlType::Rdma => Rdma::needs_to_handle(controller_opt).is_some(),
             };
 
             if required {
-                if let Some(subsystem_path) = self.subsystems.get(controller) {
-                    required_controllers.insert(controller, subsystem_path);
-                } else {
-                    bail!("Cgroup {} is required to fullfill the request, but is not supported by this system", controller);
+                if !self.mounted_subsystems.contains(&controller.to_string()) {
+                    bail!("cgroup subsystem {} is required to fulfill the request, but is not mounted on this system", controller);
+                }
+
+                match self.subsystems.get(controller) {
+                    Some(subsystem_path) => {
+                        required_controllers.insert(controller, subsystem_path);
+                    }
+                    None => bail!(
+                        "cgroup subsystem {} is mounted, but its cgroup could not be found",
+                        controller
+                    ),
                 }
             }
         }
 
         Ok(required_controllers)
     }
+
+    fn apply_controllers(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        let required_controllers = self.get_required_controllers(controller_opt)?;
+
+        // Controllers which are mounted in the same hierarchy (e.g. cpu,cpuacct) write
+        // to the same directory and therefore have to be applied in order. Different
+        // hierarchies are independent of each other and can be applied concurrently.
+        let mut groups: Vec<(&PathBuf, Vec<&CtrlType>)> = Vec::new();
+        for controller in CONTROLLERS {
+            if let Some(subsystem_path) = required_controllers.get(controller) {
+                match groups.iter_mut().find(|(path, _)| path == subsystem_path) {
+                    Some((_, controllers)) => controllers.push(controller),
+                    None => groups.push((*subsystem_path, vec![controller])),
+                }
+            }
+        }
+
+        if groups.len() <= 1 {
+            return Self::apply_sequentially(&groups, controller_opt);
+        }
+
+        // the threads cannot borrow from this function, so every thread receives its own
+        // copy of the subsystem path and a shared copy of the resources
+        let resources = Arc::new(controller_opt.resources.clone());
+        let mut handles = Vec::with_capacity(groups.len());
+        let mut fallback = Vec::new();
+        for (subsystem_path, controllers) in &groups {
+            let thread_path = (*subsystem_path).clone();
+            let thread_controllers: Vec<CtrlType> =
+                controllers.iter().map(|c| (*c).clone()).collect();
+            let thread_resources = Arc::clone(&resources);
+            let disable_oom_killer = controller_opt.disable_oom_killer;
+            let oom_score_adj = controller_opt.oom_score_adj;
+            let freezer_state = controller_opt.freezer_state;
+            let cpu_idle = controller_opt.cpu_idle;
+
+            let spawned = thread::Builder::new()
+                .name(format!("cgroup-{}", controllers[0]))
+                .spawn(move || {
+                    let controller_opt = ControllerOpt {
+                        resources: &thread_resources,
+                        disable_oom_killer,
+                        oom_score_adj,
+                        freezer_state,
+                        cpu_idle,
+                    };
+                    let controllers: Vec<&CtrlType> = thread_controllers.iter().collect();
+                    Self::apply_group(&controllers, &controller_opt, &thread_path)
+                });
+
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    log::warn!("failed to spawn thread for {}: {}", controllers[0], e);
+                    fallback.push((*subsystem_path, controllers.clone()));
+                }
+            }
+        }
+
+        let mut errors = Vec::new();
+        if let Err(e) = Self::apply_sequentially(&fallback, controller_opt) {
+            errors.push(e);
+        }
+
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => errors.push(e),
+                Err(_) => errors.push(anyhow!("thread applying cgroup controller panicked")),
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.into_iter().next().unwrap()),
+            _ => bail!(
+                "failed to apply cgroup controllers: {}",
+                errors
+                    .iter()
+                    .map(|e| format!("{:#}", e))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+        }
+    }
+
+    fn apply_sequentially(
+        groups: &[(&PathBuf, Vec<&CtrlType>)],
+        controller_opt: &ControllerOpt,
+    ) -> Result<()> {
+        for (subsystem_path, controllers) in groups {
+            Self::apply_group(controllers, controller_opt, subsystem_path)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_group(
+        controllers: &[&CtrlType],
+        controller_opt: &ControllerOpt,
+        subsystem_path: &Path,
+    ) -> Result<()> {
+        for controller in controllers {
+            let result = match controller {
+                CtrlType::Cpu => Cpu::apply(controller_opt, subsystem_path),
+                CtrlType::CpuAcct => CpuAcct::apply(controller_opt, subsystem_path),
+                CtrlType::CpuSet => CpuSet::apply(controller_opt, subsystem_path),
+                CtrlType::Devices => Devices::apply(controller_opt, subsystem_path),
+                CtrlType::HugeTlb => HugeTlb::apply(controller_opt, subsystem_path),
+                CtrlType::Memory => Memory::apply(controller_opt, subsystem_path),
+                CtrlType::Pids => Pids::apply(controller_opt, subsystem_path),
+                CtrlType::PerfEvent => PerfEvent::apply(controller_opt, subsystem_path),
+                CtrlType::Blkio => Blkio::apply(controller_opt, subsystem_path),
+                CtrlType::NetworkPriority => NetworkPriority::apply(controller_opt, subsystem_path),
+                CtrlType::NetworkClassifier => {
+                    NetworkClassifier::apply(controller_opt, subsystem_path)
+                }
+                CtrlType::Freezer => Freezer::apply(controller_opt, subsystem_path),
+                CtrlType::Rdma => Rdma::apply(controller_opt, subsystem_path),
+            };
+
+            result.with_context(|| format!("failed to apply {} controller", controller))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl CgroupManager for Manager {
+    fn validate(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        common::validate_resources(controller_opt.resources)?;
+        self.get_required_controllers(controller_opt)?;
+        Ok(())
+    }
+
     fn get_all_pids(&self) -> Result<Vec<Pid>> {
         let devices = self.subsystems.get(&CtrlType::Devices);
         if let Some(p) = devices {
@@ -121,6 +276,7 @@ impl CgroupManager for Manager {
                 CtrlType::NetworkPriority => NetworkPriority::add_task(pid, subsys.1)?,
                 CtrlType::NetworkClassifier => NetworkClassifier::add_task(pid, subsys.1)?,
                 CtrlType::Freezer => Freezer::add_task(pid, subsys.1)?,
+                CtrlType::Rdma => Rdma::add_task(pid, subsys.1)?,
             }
         }
 
@@ -128,23 +284,14 @@ impl CgroupManager for Manager {
     }
 
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
-        for subsys in self.get_required_controllers(controller_opt)? {
-            match subsys.0 {
-                CtrlType::Cpu => Cpu::apply(controller_opt, subsys.1)?,
-                CtrlType::CpuAcct => CpuAcct::apply(controller_opt, subsys.1)?,
-                CtrlType::CpuSet => CpuSet::apply(controller_opt, subsys.1)?,
-                CtrlType::Devices => Devices::apply(controller_opt, subsys.1)?,
-                CtrlType::HugeTlb => HugeTlb::apply(controller_opt, subsys.1)?,
-                CtrlType::Memory => Memory::apply(controller_opt, subsys.1)?,
-                CtrlType::Pids => Pids::apply(controller_opt, subsys.1)?,
-                CtrlType::PerfEvent => PerfEvent::apply(controller_opt, subsys.1)?,
-                CtrlType::Blkio => Blkio::apply(controller_opt, subsys.1)?,
-                CtrlType::NetworkPriority => NetworkPriority::apply(controller_opt, subsys.1)?,
-                CtrlType::NetworkClassifier => NetworkClassifier::apply(controller_opt, subsys.1)?,
-                CtrlType::Freezer => Freezer::apply(controller_opt, subsys.1)?,
-            }
-        }
-
+        // The freezer is applied alongside the other controllers, so the cgroup may
+        // already be frozen when another controller fails
+        let guard = FreezerGuard::new(
+            self,
+            controller_opt.freezer_state == Some(FreezerState::Frozen),
+        );
+        self.apply_controllers(controller_opt)?;
+        guard.disarm();
         Ok(())
     }
 
@@ -173,11 +320,20 @@ impl CgroupManager for Manager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpu_idle: None,
+        };
+        let freezer_path = match self.subsystems.get(&CtrlType::Freezer) {
+            Some(path) => path,
+            None => bail!("freezer cgroup is not supported by this system"),
         };
-        Freezer::apply(
-            &controller_opt,
-            self.subsystems.get(&CtrlType::Freezer).unwrap(),
-        )
+        Freezer::apply(&controller_opt, freezer_path)
+    }
+
+    fn register_oom_event(&self) -> Result<Option<RawFd>> {
+        self.subsystems
+            .get(&CtrlType::Memory)
+            .map(|memory_path| Memory::register_oom_event(memory_path))
+            .transpose()
     }
 
     fn stats(&self) -> Result<Stats> {
@@ -198,3 +354,221 @@ impl CgroupManager for Manager {
         Ok(stats)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxHugepageLimitBuilder, LinuxPidsBuilder, LinuxResources,
+        LinuxResourcesBuilder,
+    };
+
+    fn setup_manager(tmp: &Path) -> Manager {
+        let subsystems: HashMap<CtrlType, PathBuf> =
+            [CtrlType::Pids, CtrlType::CpuSet, CtrlType::Devices]
+                .iter()
+                .map(|subsystem| {
+                    let path = tmp.join(subsystem.to_string());
+                    fs::create_dir_all(&path).expect("create subsystem directory");
+                    (subsystem.clone(), path)
+                })
+                .collect();
+
+        let devices = &subsystems[&CtrlType::Devices];
+        set_fixture(devices, "devices.allow", "").expect("set fixture for devices.allow");
+        set_fixture(devices, "devices.deny", "").expect("set fixture for devices.deny");
+
+        let mounted_subsystems = subsystems.keys().map(|s| s.to_string()).collect();
+        Manager {
+            subsystems,
+            mounted_subsystems,
+        }
+    }
+
+    fn resources() -> LinuxResources {
+        LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![])
+            .pids(LinuxPidsBuilder::default().limit(100).build().unwrap())
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .cpus("0-3".to_owned())
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    fn controller_opt(resources: &LinuxResources) -> ControllerOpt {
+        ControllerOpt {
+            resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_all_subsystems() {
+        let tmp = create_temp_dir("test_v1_manager_apply_all_subsystems")
+            .expect("create temp directory for test");
+        let manager = setup_manager(&tmp);
+        let pids = &manager.subsystems[&CtrlType::Pids];
+        let cpuset = &manager.subsystems[&CtrlType::CpuSet];
+        set_fixture(pids, "pids.max", "").expect("set fixture for pids.max");
+        set_fixture(cpuset, "cpuset.cpus", "").expect("set fixture for cpuset.cpus");
+
+        let resources = resources();
+        manager
+            .apply(&controller_opt(&resources))
+            .expect("apply cgroup controllers");
+
+        let pids_max = fs::read_to_string(pids.join("pids.max")).expect("read pids.max");
+        assert_eq!(pids_max, "100");
+        let cpus = fs::read_to_string(cpuset.join("cpuset.cpus")).expect("read cpuset.cpus");
+        assert_eq!(cpus, "0-3");
+    }
+
+    #[test]
+    fn test_apply_propagates_errors() {
+        let tmp = create_temp_dir("test_v1_manager_apply_propagates_errors")
+            .expect("create temp directory for test");
+        let manager = setup_manager(&tmp);
+        let pids = &manager.subsystems[&CtrlType::Pids];
+        set_fixture(pids, "pids.max", "").expect("set fixture for pids.max");
+
+        // cpuset.cpus does not exist, so applying the cpuset controller fails
+        let resources = resources();
+        let result = manager.apply(&controller_opt(&resources));
+
+        let err = result.expect_err("cpuset.cpus is missing");
+        assert!(err
+            .chain()
+            .any(|cause| cause.to_string() == "failed to apply cpuset controller"));
+        let pids_max = fs::read_to_string(pids.join("pids.max")).expect("read pids.max");
+        assert_eq!(pids_max, "100");
+    }
+
+    #[test]
+    fn test_apply_thaws_on_error() {
+        let tmp = create_temp_dir("test_v1_manager_apply_thaws_on_error")
+            .expect("create temp directory for test");
+        let mut manager = setup_manager(&tmp);
+        let freezer = tmp.join(CtrlType::Freezer.to_string());
+        fs::create_dir_all(&freezer).expect("create freezer directory");
+        set_fixture(&freezer, "freezer.state", "THAWED").expect("set fixture for freezer.state");
+        manager
+            .subsystems
+            .insert(CtrlType::Freezer, freezer.clone());
+        manager
+            .mounted_subsystems
+            .insert(CtrlType::Freezer.to_string());
+
+        // pids.max does not exist, so applying the pids controller fails, while the
+        // freezer controller freezes the cgroup
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(100).build().unwrap())
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            freezer_state: Some(FreezerState::Frozen),
+            ..controller_opt(&resources)
+        };
+        manager
+            .apply(&controller_opt)
+            .expect_err("pids.max is missing");
+
+        let state = fs::read_to_string(freezer.join("freezer.state")).expect("read freezer.state");
+        assert_eq!(state, "THAWED");
+    }
+
+    #[test]
+    fn test_apply_missing_subsystem() {
+        let tmp = create_temp_dir("test_v1_manager_apply_missing_subsystem")
+            .expect("create temp directory for test");
+        let manager = setup_manager(&tmp);
+        let pids = &manager.subsystems[&CtrlType::Pids];
+        set_fixture(pids, "pids.max", "").expect("set fixture for pids.max");
+
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .pids(LinuxPidsBuilder::default().limit(100).build().unwrap())
+            .hugepage_limits(vec![LinuxHugepageLimitBuilder::default()
+                .page_size("2MB")
+                .limit(16384)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+
+        let err = manager
+            .apply(&controller_opt(&resources))
+            .expect_err("hugetlb is not mounted");
+
+        assert!(err.to_string().contains("hugetlb"));
+        // the check happens before any controller is applied
+        let pids_max = fs::read_to_string(pids.join("pids.max")).expect("read pids.max");
+        assert_eq!(pids_max, "");
+    }
+
+    #[test]
+    fn test_apply_subsystem_missing_in_mountinfo() {
+        let tmp = create_temp_dir("test_v1_manager_apply_subsystem_missing_in_mountinfo")
+            .expect("create temp directory for test");
+        let mut manager = setup_manager(&tmp);
+        // the directory of the subsystem exists, but it is not mounted as cgroup
+        manager.mounted_subsystems.remove("pids");
+        let pids = &manager.subsystems[&CtrlType::Pids];
+        set_fixture(pids, "pids.max", "").expect("set fixture for pids.max");
+
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![])
+            .pids(LinuxPidsBuilder::default().limit(100).build().unwrap())
+            .build()
+            .unwrap();
+
+        let err = manager
+            .apply(&controller_opt(&resources))
+            .expect_err("pids is not mounted");
+
+        assert!(err.to_string().contains("pids"), "{}", err);
+        assert!(err.to_string().contains("not mounted"), "{}", err);
+        let pids_max = fs::read_to_string(pids.join("pids.max")).expect("read pids.max");
+        assert_eq!(pids_max, "");
+    }
+
+    #[test]
+    fn test_validate() {
+        let tmp =
+            create_temp_dir("test_v1_manager_validate").expect("create temp directory for test");
+        let manager = setup_manager(&tmp);
+        let pids = &manager.subsystems[&CtrlType::Pids];
+        set_fixture(pids, "pids.max", "").expect("set fixture for pids.max");
+
+        manager
+            .validate(&controller_opt(&resources()))
+            .expect("validate resources");
+
+        // nothing has been written
+        let pids_max = fs::read_to_string(pids.join("pids.max")).expect("read pids.max");
+        assert_eq!(pids_max, "");
+
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![LinuxHugepageLimitBuilder::default()
+                .page_size("2MB")
+                .limit(16384)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+        let err = manager
+            .validate(&controller_opt(&resources))
+            .expect_err("hugetlb is not mounted");
+        assert!(err.to_string().contains("hugetlb"));
+    }
+}
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 use std::io::{prelude::*, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::{fs::OpenOptions, path::Path};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use nix::errno::Errno;
+use nix::sys::eventfd::{eventfd, EfdFlags};
 
 use super::Controller;
 use crate::common::{self, ControllerOpt};
@@ -18,6 +20,8 @@ const CGROUP_MEMORY_MAX_USAGE: &str = "memory.max_usage_in_bytes";
 const CGROUP_MEMORY_SWAPPINESS: &str = "memory.swappiness";
 const CGROUP_MEMORY_RESERVATION: &str = "memory.soft_limit_in_bytes";
 const CGROUP_MEMORY_OOM_CONTROL: &str = "memory.oom_control";
+// Registers eventfds for notifications about events of the cgroup
+const CGROUP_EVENT_CONTROL: &str = "cgroup.event_control";
 
 const CGROUP_KERNEL_MEMORY_LIMIT: &str = "memory.kmem.limit_in_bytes";
 const CGROUP_KERNEL_TCP_MEMORY_LIMIT: &str = "memory.kmem.tcp.limit_in_bytes";
@@ -63,9 +67,9 @@ impl Controller for Memory {
                 )?;
             }
 
+            // writing 1 to oom_control disables the oom killer, tasks which hit the limit
+            // will then be paused until memory becomes available again
             if controller_opt.disable_oom_killer {
-                common::write_cgroup_file(cgroup_root.join(CGROUP_MEMORY_OOM_CONTROL), 0)?;
-            } else {
                 common::write_cgroup_file(cgroup_root.join(CGROUP_MEMORY_OOM_CONTROL), 1)?;
             }
 
@@ -88,12 +92,12 @@ impl Controller for Memory {
             // neither are implemented by runc. Tests pass without this, but
             // kept in per the spec.
             if let Some(kmem) = memory.kernel() {
-                common::write_cgroup_file(cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT), kmem)?;
+                Self::set_kernel_memory(kmem, &cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT))?;
             }
             if let Some(tcp_mem) = memory.kernel_tcp() {
-                common::write_cgroup_file(
-                    cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
+                Self::set_kernel_memory(
                     tcp_mem,
+                    &cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
                 )?;
             }
         }
@@ -130,6 +134,7 @@ impl StatsProvider for Memory {
             cache: stats["cache"],
             hierarchy,
             stats,
+            oom_kill: Self::get_oom_kill_count(cgroup_path)?,
         })
     }
 }
@@ -162,6 +167,43 @@ impl Memory {
         Ok(enabled)
     }
 
+    fn get_oom_kill_count(cgroup_path: &Path) -> Result<u64> {
+        // oom_kill is only reported by memory.oom_control since kernel 4.13
+        let oom_control = cgroup_path.join(CGROUP_MEMORY_OOM_CONTROL);
+        if !oom_control.exists() {
+            return Ok(0);
+        }
+
+        let oom_control = stats::parse_flat_keyed_data(&oom_control)?;
+        Ok(oom_control.get("oom_kill").copied().unwrap_or_default())
+    }
+
+    /// Registers an eventfd which the kernel signals whenever the OOM killer is
+    /// invoked in the cgroup. The returned fd becomes readable on every oom event
+    /// and is owned by the caller.
+    pub fn register_oom_event(cgroup_path: &Path) -> Result<RawFd> {
+        let oom_control_path = cgroup_path.join(CGROUP_MEMORY_OOM_CONTROL);
+        let oom_control = OpenOptions::new()
+            .read(true)
+            .open(&oom_control_path)
+            .with_context(|| format!("failed to open {:?}", oom_control_path))?;
+        let event_fd = eventfd(0, EfdFlags::EFD_CLOEXEC).context("failed to create eventfd")?;
+
+        // the kernel only needs the oom_control fd during registration
+        let line = Self::event_control_line(event_fd, oom_control.as_raw_fd());
+        let event_control = cgroup_path.join(CGROUP_EVENT_CONTROL);
+        if let Err(e) = common::write_cgroup_file_str(event_control, &line) {
+            let _ = nix::unistd::close(event_fd);
+            return Err(e.context("failed to register oom event"));
+        }
+
+        Ok(event_fd)
+    }
+
+    fn event_control_line(event_fd: RawFd, control_fd: RawFd) -> String {
+        format!("{} {}", event_fd, control_fd)
+    }
+
     fn get_stat_data(cgroup_path: &Path) -> Result<HashMap<String, u64>> {
         stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))
     }
@@ -263,6 +305,20 @@ impl Memory {
         }
     }
 
+    // Newer kernels have dropped kmem accounting and no longer provide the kmem files.
+    // Requesting no limit is what the kernel does anyway in that case, so a failure
+    // is only reported as a warning.
+    fn set_kernel_memory(limit: i64, path: &Path) -> Result<()> {
+        match common::write_cgroup_file(path, limit) {
+            Ok(()) => Ok(()),
+            Err(e) if limit == -1 => {
+                log::warn!("skipping unlimited kernel memory limit: {:#}", e);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     fn set_swap(swap: i64, cgroup_root: &Path) -> Result<()> {
         if swap == 0 {
             return Ok(());
@@ -410,6 +466,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_kernel_memory() {
+        let tmp =
+            create_temp_dir("test_set_kernel_memory").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_KERNEL_MEMORY_LIMIT, "0")
+            .expect("set fixture for kernel memory limit");
+
+        Memory::set_kernel_memory(1024, &tmp.join(CGROUP_KERNEL_MEMORY_LIMIT))
+            .expect("set kernel memory limit");
+
+        let content = std::fs::read_to_string(tmp.join(CGROUP_KERNEL_MEMORY_LIMIT))
+            .expect("read kernel memory limit");
+        assert_eq!(content, "1024");
+    }
+
+    #[test]
+    fn test_skip_unlimited_kernel_memory_without_kmem() {
+        let tmp = create_temp_dir("test_skip_unlimited_kernel_memory_without_kmem")
+            .expect("create temp directory for test");
+
+        Memory::set_kernel_memory(-1, &tmp.join(CGROUP_KERNEL_MEMORY_LIMIT))
+            .expect("unlimited kernel memory should be skipped");
+        assert!(!tmp.join(CGROUP_KERNEL_MEMORY_LIMIT).exists());
+
+        let result = Memory::set_kernel_memory(1024, &tmp.join(CGROUP_KERNEL_MEMORY_LIMIT));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_disable_oom_killer() {
+        for (disable_oom_killer, expected) in [(true, "1"), (false, "0")] {
+            let tmp =
+                create_temp_dir("test_disable_oom_killer").expect("create temp directory for test");
+            set_fixture(&tmp, CGROUP_MEMORY_LIMIT, "0").expect("set fixture for memory limit");
+            set_fixture(&tmp, CGROUP_MEMORY_SWAP_LIMIT, "0").expect("set fixture for swap limit");
+            set_fixture(&tmp, CGROUP_MEMORY_OOM_CONTROL, "0").expect("set fixture for oom control");
+
+            let linux_resources = LinuxResourcesBuilder::default()
+                .devices(vec![])
+                .memory(LinuxMemoryBuilder::default().build().unwrap())
+                .hugepage_limits(vec![])
+                .build()
+                .unwrap();
+            let controller_opt = ControllerOpt {
+                resources: &linux_resources,
+                disable_oom_killer,
+                oom_score_adj: None,
+                freezer_state: None,
+                cpu_idle: None,
+            };
+
+            <Memory as Controller>::apply(&controller_opt, &tmp).expect("apply memory");
+
+            let content = std::fs::read_to_string(tmp.join(CGROUP_MEMORY_OOM_CONTROL))
+                .expect("read oom control");
+            assert_eq!(content, expected);
+        }
+    }
+
     quickcheck! {
             fn property_test_set_memory(linux_memory: LinuxMemory, disable_oom_killer: bool) -> bool {
                 let tmp =
@@ -436,6 +551,7 @@ mod tests {
                     disable_oom_killer,
                     oom_score_adj: None,
                     freezer_state: None,
+                    cpu_idle: None,
                 };
 
                 let result = <Memory as Controller>::apply(&controller_opt, &tmp);
@@ -627,4 +743,45 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_stat_oom_kill_count() {
+        let tmp = create_temp_dir("test_stat_oom_kill_count").expect("create test directory");
+        assert_eq!(
+            Memory::get_oom_kill_count(&tmp).expect("get oom kill count"),
+            0
+        );
+
+        let content = ["oom_kill_disable 0", "under_oom 0", "oom_kill 3"].join("\n");
+        set_fixture(&tmp, CGROUP_MEMORY_OOM_CONTROL, &content).unwrap();
+
+        let actual = Memory::get_oom_kill_count(&tmp).expect("get oom kill count");
+        assert_eq!(actual, 3);
+    }
+
+    #[test]
+    fn test_event_control_line() {
+        assert_eq!(Memory::event_control_line(5, 7), "5 7");
+    }
+
+    #[test]
+    fn test_register_oom_event() -> Result<()> {
+        let tmp = create_temp_dir("test_register_oom_event")?;
+        assert!(Memory::register_oom_event(&tmp).is_err());
+
+        set_fixture(&tmp, CGROUP_MEMORY_OOM_CONTROL, "oom_kill_disable 0")?;
+        set_fixture(&tmp, CGROUP_EVENT_CONTROL, "")?;
+        let event_fd = Memory::register_oom_event(&tmp)?;
+
+        let content = std::fs::read_to_string(tmp.join(CGROUP_EVENT_CONTROL))?;
+        let fds: Vec<RawFd> = content
+            .split_whitespace()
+            .map(|fd| fd.parse())
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(fds.len(), 2);
+        assert_eq!(fds[0], event_fd);
+        assert_ne!(fds[1], event_fd);
+        nix::unistd::close(event_fd)?;
+        Ok(())
+    }
 }
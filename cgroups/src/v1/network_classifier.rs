@@ -41,7 +41,7 @@ impl NetworkClassifier {
 mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture};
-    use oci_spec::runtime::LinuxNetworkBuilder;
+    use oci_spec::runtime::{LinuxNetworkBuilder, LinuxResourcesBuilder};
 
     #[test]
     fn test_apply_network_classifier() {
@@ -62,4 +62,31 @@ mod tests {
             std::fs::read_to_string(tmp.join("net_cls.classid")).expect("Read classID contents");
         assert_eq!(id.to_string(), content);
     }
+
+    #[test]
+    fn test_network_classifier_without_network() {
+        let tmp = create_temp_dir("test_network_classifier_without_network")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, "net_cls.classid", "").expect("set fixture for net_cls.classid");
+
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle: None,
+        };
+
+        <NetworkClassifier as Controller>::apply(&controller_opt, &tmp)
+            .expect("apply network_classifier");
+
+        let content = std::fs::read_to_string(tmp.join("net_cls.classid"))
+            .expect("Read net_cls.classid contents");
+        assert_eq!("", content);
+    }
 }
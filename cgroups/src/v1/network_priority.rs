@@ -42,7 +42,9 @@ impl NetworkPriority {
 mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture};
-    use oci_spec::runtime::{LinuxInterfacePriorityBuilder, LinuxNetworkBuilder};
+    use oci_spec::runtime::{
+        LinuxInterfacePriorityBuilder, LinuxNetworkBuilder, LinuxResourcesBuilder,
+    };
 
     #[test]
     fn test_apply_network_priorites() {
@@ -73,4 +75,31 @@ mod tests {
             std::fs::read_to_string(tmp.join("net_prio.ifpriomap")).expect("Read classID contents");
         assert_eq!(priorities_string.trim(), content);
     }
+
+    #[test]
+    fn test_network_priority_without_network() {
+        let tmp = create_temp_dir("test_network_priority_without_network")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, "net_prio.ifpriomap", "").expect("set fixture for net_prio.ifpriomap");
+
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle: None,
+        };
+
+        <NetworkPriority as Controller>::apply(&controller_opt, &tmp)
+            .expect("apply network_priority");
+
+        let content = std::fs::read_to_string(tmp.join("net_prio.ifpriomap"))
+            .expect("Read net_prio.ifpriomap contents");
+        assert_eq!("", content);
+    }
 }
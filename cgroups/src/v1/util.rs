@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use anyhow::{anyhow, Context, Result};
 use procfs::process::Process;
@@ -17,6 +20,19 @@ pub fn list_subsystem_mount_points() -> Result<Vec<PathBuf>> {
         .collect())
 }
 
+/// Lists the names of all mounted cgroup subsystems. The subsystems of a hierarchy are
+/// taken from the super options of its mount, so that the result does not depend on
+/// the naming of the mount point.
+pub fn list_mounted_subsystems() -> Result<HashSet<String>> {
+    Ok(Process::myself()?
+        .mountinfo()
+        .context("failed to get mountinfo")?
+        .into_iter()
+        .filter(|m| m.fs_type == "cgroup")
+        .flat_map(|m| m.super_options.into_iter().map(|(option, _)| option))
+        .collect())
+}
+
 /// List the mount points of all currently supported cgroup subsystems.
 pub fn list_supported_mount_points() -> Result<HashMap<ControllerType, PathBuf>> {
     let mut mount_paths = HashMap::with_capacity(CONTROLLERS.len());
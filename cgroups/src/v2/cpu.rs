@@ -12,6 +12,10 @@ use super::controller::Controller;
 
 const CGROUP_CPU_WEIGHT: &str = "cpu.weight";
 const CGROUP_CPU_MAX: &str = "cpu.max";
+const CGROUP_CPU_IDLE: &str = "cpu.idle";
+// range of cpu.shares in cgroup v1
+const MIN_CPU_SHARES: u64 = 2;
+const MAX_CPU_SHARES: u64 = 262144;
 const DEFAULT_PERIOD: &str = "100000";
 const UNRESTRICTED_QUOTA: &str = "max";
 
@@ -25,6 +29,10 @@ impl Controller for Cpu {
             Self::apply(path, cpu).context("failed to apply cpu resource restrictions")?;
         }
 
+        if let Some(idle) = controller_opt.cpu_idle {
+            Self::set_idle(path, idle)?;
+        }
+
         Ok(())
     }
 }
@@ -94,12 +102,26 @@ impl Cpu {
         Ok(())
     }
 
-    fn convert_shares_to_cgroup2(shares: u64) -> u64 {
+    // cpu.idle is only available since Linux 5.15
+    fn set_idle(path: &Path, idle: u64) -> Result<()> {
+        let idle_path = path.join(CGROUP_CPU_IDLE);
+        if !idle_path.exists() {
+            log::warn!("cpu.idle is not supported by the kernel, skipping it");
+            return Ok(());
+        }
+
+        common::write_cgroup_file(idle_path, idle)
+    }
+
+    /// Maps cpu.shares of cgroup v1 in the range [2, 262144] linearly onto cpu.weight
+    /// in the range [1, 10000]. Shares outside of the range are clamped, 0 means unset.
+    pub(crate) fn convert_shares_to_cgroup2(shares: u64) -> u64 {
         if shares == 0 {
             return 0;
         }
 
-        1 + ((shares - 2) * 9999) / 262142
+        let shares = shares.clamp(MIN_CPU_SHARES, MAX_CPU_SHARES);
+        1 + ((shares - MIN_CPU_SHARES) * 9999) / (MAX_CPU_SHARES - MIN_CPU_SHARES)
     }
 
     fn is_realtime_requested(cpu: &LinuxCpu) -> bool {
@@ -119,9 +141,55 @@ impl Cpu {
 mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture, setup};
-    use oci_spec::runtime::LinuxCpuBuilder;
+    use oci_spec::runtime::{LinuxCpuBuilder, LinuxResources};
     use std::fs;
 
+    fn idle_controller_opt(resources: &LinuxResources, cpu_idle: Option<u64>) -> ControllerOpt {
+        ControllerOpt {
+            resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle,
+        }
+    }
+
+    #[test]
+    fn test_set_idle() {
+        let (tmp, idle) = setup("test_set_cpu_idle", CGROUP_CPU_IDLE);
+        let resources = LinuxResources::default();
+
+        <Cpu as Controller>::apply(&idle_controller_opt(&resources, Some(1)), &tmp)
+            .expect("apply cpu");
+
+        let content = fs::read_to_string(idle)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_IDLE));
+        assert_eq!(content, "1");
+    }
+
+    #[test]
+    fn test_unset_idle() {
+        let (tmp, idle) = setup("test_unset_cpu_idle", CGROUP_CPU_IDLE);
+        let resources = LinuxResources::default();
+
+        <Cpu as Controller>::apply(&idle_controller_opt(&resources, None), &tmp)
+            .expect("apply cpu");
+
+        let content = fs::read_to_string(idle)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_IDLE));
+        assert_eq!(content, "");
+    }
+
+    #[test]
+    fn test_idle_not_supported() {
+        let tmp = create_temp_dir("test_cpu_idle_not_supported").expect("create temp dir");
+        let resources = LinuxResources::default();
+
+        <Cpu as Controller>::apply(&idle_controller_opt(&resources, Some(1)), &tmp)
+            .expect("skip cpu.idle");
+        assert!(!tmp.join(CGROUP_CPU_IDLE).exists());
+    }
+
     #[test]
     fn test_set_shares() {
         // arrange
@@ -139,6 +207,17 @@ mod tests {
         assert_eq!(content, 840.to_string());
     }
 
+    #[test]
+    fn test_convert_shares_to_cgroup2() {
+        assert_eq!(Cpu::convert_shares_to_cgroup2(0), 0);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(2), 1);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(1024), 39);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(262144), 10000);
+        // out of range values are clamped
+        assert_eq!(Cpu::convert_shares_to_cgroup2(1), 1);
+        assert_eq!(Cpu::convert_shares_to_cgroup2(1_000_000), 10000);
+    }
+
     #[test]
     fn test_set_positive_quota() {
         // arrange
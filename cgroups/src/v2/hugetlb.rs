@@ -44,27 +44,20 @@ impl StatsProvider for HugeTlb {
 
 impl HugeTlb {
     fn apply(root_path: &Path, hugetlb: &LinuxHugepageLimit) -> Result<()> {
-        let page_size: String = hugetlb
-            .page_size()
-            .chars()
-            .take_while(|c| c.is_digit(10))
-            .collect();
-        let page_size: u64 = page_size.parse()?;
-        if !Self::is_power_of_two(page_size) {
-            bail!("page size must be in the format of 2^(integer)");
+        common::parse_hugepage_size(hugetlb.page_size())?;
+
+        let limit_file = root_path.join(format!("hugetlb.{}.max", hugetlb.page_size()));
+        if !limit_file.exists() {
+            bail!(
+                "hugetlb page size {} is not supported by the host",
+                hugetlb.page_size()
+            );
         }
 
-        common::write_cgroup_file(
-            root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size())),
-            hugetlb.limit(),
-        )?;
+        common::write_cgroup_file(limit_file, common::limit_or_max(hugetlb.limit()))?;
         Ok(())
     }
 
-    fn is_power_of_two(number: u64) -> bool {
-        (number != 0) && (number & (number - 1)) == 0
-    }
-
     fn stats_for_page_size(cgroup_path: &Path, page_size: &str) -> Result<HugeTlbStats> {
         let events_file = format!("hugetlb.{}.events", page_size);
         let events = common::read_cgroup_file(cgroup_path.join(&events_file))?;
@@ -93,7 +86,7 @@ mod tests {
 
     #[test]
     fn test_set_hugetlb() {
-        let page_file_name = "hugetlb.2MB.limit_in_bytes";
+        let page_file_name = "hugetlb.2MB.max";
         let tmp = create_temp_dir("test_set_hugetlbv2").expect("create temp directory for test");
         set_fixture(&tmp, page_file_name, "0").expect("Set fixture for 2 MB page size");
 
@@ -107,6 +100,37 @@ mod tests {
         assert_eq!(hugetlb.limit().to_string(), content);
     }
 
+    #[test]
+    fn test_set_hugetlb_unlimited() {
+        let page_file_name = "hugetlb.1GB.max";
+        let tmp = create_temp_dir("test_set_hugetlbv2_unlimited")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, page_file_name, "0").expect("Set fixture for 1 GB page size");
+
+        let hugetlb = LinuxHugepageLimitBuilder::default()
+            .page_size("1GB")
+            .limit(-1)
+            .build()
+            .unwrap();
+        HugeTlb::apply(&tmp, &hugetlb).expect("apply hugetlb");
+        let content = read_to_string(tmp.join(page_file_name)).expect("Read hugetlb file content");
+        assert_eq!("max", content);
+    }
+
+    #[test]
+    fn test_set_hugetlb_with_unsupported_page_size() {
+        let tmp = create_temp_dir("test_set_hugetlbv2_with_unsupported_page_size")
+            .expect("create temp directory for test");
+
+        let hugetlb = LinuxHugepageLimitBuilder::default()
+            .page_size("2MB")
+            .limit(16384)
+            .build()
+            .unwrap();
+
+        assert!(HugeTlb::apply(&tmp, &hugetlb).is_err());
+    }
+
     #[test]
     fn test_set_hugetlb_with_invalid_page_size() {
         let tmp = create_temp_dir("test_set_hugetlbv2_with_invalid_page_size")
@@ -127,7 +151,7 @@ mod tests {
 
     quickcheck! {
         fn property_test_set_hugetlb(hugetlb: LinuxHugepageLimit) -> bool {
-            let page_file_name = format!("hugetlb.{:?}.limit_in_bytes", hugetlb.page_size());
+            let page_file_name = format!("hugetlb.{:?}.max", hugetlb.page_size());
             let tmp = create_temp_dir("property_test_set_hugetlbv2").expect("create temp directory for test");
             set_fixture(&tmp, &page_file_name, "0").expect("Set fixture for page size");
             let result = HugeTlb::apply(&tmp, &hugetlb);
@@ -139,7 +163,7 @@ mod tests {
             .collect();
             let page_size: u64 = page_size.parse().expect("parse page size");
 
-            if HugeTlb::is_power_of_two(page_size) && page_size != 1 {
+            if page_size.is_power_of_two() && page_size != 1 {
                 let content =
                     read_to_string(tmp.join(page_file_name)).expect("Read hugetlb file content");
                 hugetlb.limit().to_string() == content
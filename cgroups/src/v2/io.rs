@@ -1,4 +1,7 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{bail, Context, Result};
 
@@ -78,7 +81,79 @@ impl StatsProvider for Io {
     }
 }
 
+/// Throttling limits of a single device in io.max, unset limits are left untouched
+#[derive(Debug, Default, PartialEq)]
+struct IoMax {
+    rbps: Option<u64>,
+    wbps: Option<u64>,
+    riops: Option<u64>,
+    wiops: Option<u64>,
+}
+
+impl IoMax {
+    fn format(&self, major: i64, minor: i64) -> String {
+        let limits = [
+            ("rbps", self.rbps),
+            ("wbps", self.wbps),
+            ("riops", self.riops),
+            ("wiops", self.wiops),
+        ];
+
+        let mut line = format!("{}:{}", major, minor);
+        for (key, value) in limits.iter() {
+            if let Some(value) = value {
+                line.push_str(&format!(" {}={}", key, value));
+            }
+        }
+        line
+    }
+}
+
 impl Io {
+    /// Converts the blkio weight of cgroup v1 (10-1000) into the io weight of
+    /// cgroup v2 (1-10000)
+    fn convert_blkio_weight(weight: u16) -> Result<u64> {
+        if !(10..=1000).contains(&weight) {
+            bail!("blkio weight {} must be within [10, 1000]", weight);
+        }
+
+        Ok(1 + (u64::from(weight) - 10) * 9999 / 990)
+    }
+
+    /// Combines all throttling limits of a device into a single io.max line
+    fn io_max_lines(blkio: &LinuxBlockIo) -> Vec<String> {
+        let mut devices: BTreeMap<(i64, i64), IoMax> = BTreeMap::new();
+        for throttle in blkio.throttle_read_bps_device().iter().flatten() {
+            devices
+                .entry((throttle.major(), throttle.minor()))
+                .or_default()
+                .rbps = Some(throttle.rate());
+        }
+        for throttle in blkio.throttle_write_bps_device().iter().flatten() {
+            devices
+                .entry((throttle.major(), throttle.minor()))
+                .or_default()
+                .wbps = Some(throttle.rate());
+        }
+        for throttle in blkio.throttle_read_iops_device().iter().flatten() {
+            devices
+                .entry((throttle.major(), throttle.minor()))
+                .or_default()
+                .riops = Some(throttle.rate());
+        }
+        for throttle in blkio.throttle_write_iops_device().iter().flatten() {
+            devices
+                .entry((throttle.major(), throttle.minor()))
+                .or_default()
+                .wiops = Some(throttle.rate());
+        }
+
+        devices
+            .iter()
+            .map(|((major, minor), max)| max.format(*major, *minor))
+            .collect()
+    }
+
     fn io_max_path(path: &Path) -> PathBuf {
         path.join("io.max")
     }
@@ -102,45 +177,14 @@ impl Io {
             if io_weight > 0 {
                 common::write_cgroup_file(
                     root_path.join(CGROUP_IO_WEIGHT),
-                    format!("{}", io_weight),
-                )?;
-            }
-        }
-
-        if let Some(throttle_read_bps_device) = blkio.throttle_read_bps_device() {
-            for trbd in throttle_read_bps_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    &format!("{}:{} rbps={}", trbd.major(), trbd.minor(), trbd.rate()),
-                )?;
-            }
-        }
-
-        if let Some(throttle_write_bps_device) = blkio.throttle_write_bps_device() {
-            for twbd in throttle_write_bps_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    format!("{}:{} wbps={}", twbd.major(), twbd.minor(), twbd.rate()),
-                )?;
-            }
-        }
-
-        if let Some(throttle_read_iops_device) = blkio.throttle_read_iops_device() {
-            for trid in throttle_read_iops_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    format!("{}:{} riops={}", trid.major(), trid.minor(), trid.rate()),
+                    Self::convert_blkio_weight(io_weight)?,
                 )?;
             }
         }
 
-        if let Some(throttle_write_iops_device) = blkio.throttle_write_iops_device() {
-            for twid in throttle_write_iops_device {
-                common::write_cgroup_file(
-                    Self::io_max_path(root_path),
-                    format!("{}:{} wiops={}", twid.major(), twid.minor(), twid.rate()),
-                )?;
-            }
+        // the kernel applies each written line separately, so every device gets its own write
+        for line in Self::io_max_lines(blkio) {
+            common::write_cgroup_file(Self::io_max_path(root_path), line)?;
         }
 
         Ok(())
@@ -269,7 +313,55 @@ mod test {
         let content =
             fs::read_to_string(throttle).unwrap_or_else(|_| panic!("read bfq_io_weight content"));
 
-        assert_eq!("100", content);
+        assert_eq!("910", content);
+    }
+
+    #[test]
+    fn test_convert_blkio_weight() {
+        assert_eq!(Io::convert_blkio_weight(10).unwrap(), 1);
+        assert_eq!(Io::convert_blkio_weight(500).unwrap(), 4950);
+        assert_eq!(Io::convert_blkio_weight(1000).unwrap(), 10000);
+        assert!(Io::convert_blkio_weight(5).is_err());
+        assert!(Io::convert_blkio_weight(1001).is_err());
+    }
+
+    #[test]
+    fn test_io_max_lines() {
+        let throttle = |major: i64, minor: i64, rate: u64| {
+            LinuxThrottleDeviceBuilder::default()
+                .major(major)
+                .minor(minor)
+                .rate(rate)
+                .build()
+                .unwrap()
+        };
+        let blkio = LinuxBlockIoBuilder::default()
+            .throttle_read_bps_device(vec![throttle(8, 0, 1048576), throttle(8, 16, 2048)])
+            .throttle_write_iops_device(vec![throttle(8, 0, 100)])
+            .throttle_read_iops_device(vec![throttle(252, 1, 50)])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Io::io_max_lines(&blkio),
+            vec![
+                "8:0 rbps=1048576 wiops=100",
+                "8:16 rbps=2048",
+                "252:1 riops=50"
+            ]
+        );
+        assert!(Io::io_max_lines(&LinuxBlockIoBuilder::default().build().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_io_max_format() {
+        let max = IoMax {
+            wbps: Some(4096),
+            riops: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(max.format(8, 0), "8:0 wbps=4096 riops=10");
+        assert_eq!(IoMax::default().format(8, 0), "8:0");
     }
 
     #[test]
@@ -5,7 +5,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
 use nix::unistd::Pid;
 
@@ -23,13 +23,19 @@ use super::{
     io::Io,
     memory::Memory,
     pids::Pids,
+    rdma::Rdma,
     unified::Unified,
     util::{self, CGROUP_SUBTREE_CONTROL},
 };
 use crate::{
-    common::{self, CgroupManager, ControllerOpt, FreezerState, PathBufExt, CGROUP_PROCS},
+    common::{
+        self, CgroupManager, ControllerOpt, FreezerState, PathBufExt, CGROUP_PROCS, CGROUP_THREADS,
+    },
     stats::{Stats, StatsProvider},
 };
+/// Leaf cgroup that receives the processes of a cgroup which has children
+const INIT_LEAF: &str = "init";
+
 pub struct Manager {
     root_path: PathBuf,
     cgroup_path: PathBuf,
@@ -50,12 +56,134 @@ impl Manager {
     }
 
     fn create_unified_cgroup(&self, pid: Pid) -> Result<()> {
-        let controllers: Vec<String> = util::get_available_controllers(&self.root_path)?
+        let leaf_path = self.create_task_dir()?;
+        common::write_cgroup_file(Self::task_file(&leaf_path, false), pid)?;
+        Ok(())
+    }
+
+    /// Creates the cgroup of the container and returns the cgroup in which its processes
+    /// are placed
+    fn create_task_dir(&self) -> Result<PathBuf> {
+        let mut current_path = self.root_path.clone();
+        for component in self.cgroup_path.components().filter(|c| c.ne(&RootDir)) {
+            current_path = current_path.join(component);
+            if !current_path.exists() {
+                fs::create_dir(&current_path)?;
+                fs::metadata(&current_path)?.permissions().set_mode(0o755);
+            }
+        }
+
+        // processes are only allowed in leaf cgroups, so if the container cgroup already
+        // has children (e.g. created by the container itself) the init leaf is used
+        let leaf_path = Self::leaf_path(&self.full_path)?;
+        if !leaf_path.exists() {
+            fs::create_dir(&leaf_path)?;
+        }
+
+        Ok(leaf_path)
+    }
+
+    /// Returns the file through which tasks are moved into the cgroup at `path`. Processes
+    /// are moved with all their threads through cgroup.procs, single threads through
+    /// cgroup.threads.
+    fn task_file(path: &Path, thread: bool) -> PathBuf {
+        if thread {
+            path.join(CGROUP_THREADS)
+        } else {
+            path.join(CGROUP_PROCS)
+        }
+    }
+
+    /// Returns the cgroup in which processes of the cgroup at `path` can be placed without
+    /// violating the no internal processes rule, which is the cgroup itself as long as it
+    /// has no children and its init leaf otherwise
+    fn leaf_path(path: &Path) -> Result<PathBuf> {
+        let mut has_children = false;
+        for entry in fs::read_dir(path)? {
+            if entry?.file_type()?.is_dir() {
+                has_children = true;
+                break;
+            }
+        }
+
+        if has_children {
+            Ok(path.join(INIT_LEAF))
+        } else {
+            Ok(path.to_path_buf())
+        }
+    }
+
+    /// Returns if the files from which the statistics of the controller are read exist for
+    /// the cgroup at `path`, which is the case if the controller is enabled for it. Cpu
+    /// usage statistics are always available.
+    fn has_stats(path: &Path, controller: &ControllerType) -> Result<bool> {
+        let stats_file = match controller {
+            ControllerType::Cpu => "cpu.stat",
+            ControllerType::Io => "io.stat",
+            ControllerType::Memory => "memory.stat",
+            ControllerType::Pids => "pids.current",
+            ControllerType::HugeTlb => {
+                for entry in fs::read_dir(path)? {
+                    let name = entry?.file_name();
+                    let name = name.to_string_lossy();
+                    if name.starts_with("hugetlb.") && name.ends_with(".current") {
+                        return Ok(true);
+                    }
+                }
+                return Ok(false);
+            }
+            _ => return Ok(false),
+        };
+
+        Ok(path.join(stats_file).exists())
+    }
+
+    /// Returns the cgroup at `path` together with all of its descendants, ordered so
+    /// that every cgroup comes after its children. This is the order in which they have
+    /// to be removed, as a cgroup with children cannot be removed.
+    fn descendants_first(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut cgroups = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                cgroups.extend(Self::descendants_first(&entry.path())?);
+            }
+        }
+
+        cgroups.push(path.to_path_buf());
+        Ok(cgroups)
+    }
+
+    /// Kills the processes of the cgroup at `path`
+    fn kill_processes(path: &Path) -> Result<()> {
+        let procs_path = path.join(CGROUP_PROCS);
+        // there is nothing to kill in a cgroup which has been removed in the meantime
+        if !procs_path.exists() {
+            return Ok(());
+        }
+
+        for pid in common::read_cgroup_procs(&procs_path)? {
+            let _ = nix::sys::signal::kill(pid, nix::sys::signal::SIGKILL);
+        }
+
+        Ok(())
+    }
+
+    /// Enables the controllers in the cgroup.subtree_control of the root and every
+    /// ancestor of the container cgroup, so that the container cgroup can use them
+    fn enable_controllers(&self, controllers: &[ControllerType]) -> Result<()> {
+        let available = util::get_available_controllers(&self.root_path)?;
+        let controllers: Vec<String> = controllers
             .iter()
-            .map(|c| format!("{}{}", "+", c.to_string()))
+            .filter(|c| available.contains(c))
+            .map(|c| format!("+{}", c))
             .collect();
+        if controllers.is_empty() {
+            return Ok(());
+        }
+        let controllers = controllers.join(" ");
 
-        Self::write_controllers(&self.root_path, &controllers)?;
+        common::write_cgroup_file_str(self.root_path.join(CGROUP_SUBTREE_CONTROL), &controllers)?;
 
         let mut current_path = self.root_path.clone();
         let mut components = self
@@ -64,39 +192,123 @@ impl Manager {
             .filter(|c| c.ne(&RootDir))
             .peekable();
         while let Some(component) = components.next() {
-            current_path = current_path.join(component);
-            if !current_path.exists() {
-                fs::create_dir(&current_path)?;
-                fs::metadata(&current_path)?.permissions().set_mode(0o755);
-            }
-
             // last component cannot have subtree_control enabled due to internal process constraint
             // if this were set, writing to the cgroups.procs file will fail with Erno 16 (device or resource busy)
-            if components.peek().is_some() {
-                Self::write_controllers(&current_path, &controllers)?;
+            if components.peek().is_none() {
+                break;
             }
+
+            current_path = current_path.join(component);
+            // processes of other cgroups are never moved, so this fails with EBUSY if an
+            // ancestor contains processes
+            common::write_cgroup_file_str(current_path.join(CGROUP_SUBTREE_CONTROL), &controllers)
+                .with_context(|| {
+                    format!("failed to enable {} in {:?}", controllers, current_path)
+                })?;
         }
 
-        common::write_cgroup_file(&self.full_path.join(CGROUP_PROCS), pid)?;
         Ok(())
     }
+}
 
-    fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
-        for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
-        }
+/// Determines the controllers which are required to apply the resource restrictions
+pub(super) fn needed_controllers(controller_opt: &ControllerOpt) -> Vec<ControllerType> {
+    let resources = controller_opt.resources;
+    let mut needed = Vec::new();
 
-        Ok(())
+    if let Some(cpu) = resources.cpu() {
+        if cpu.shares().is_some()
+            || cpu.quota().is_some()
+            || cpu.period().is_some()
+            || cpu.realtime_runtime().is_some()
+            || cpu.realtime_period().is_some()
+        {
+            needed.push(ControllerType::Cpu);
+        }
+        if cpu.cpus().is_some() || cpu.mems().is_some() {
+            needed.push(ControllerType::CpuSet);
+        }
+    }
+    if controller_opt.cpu_idle.is_some() {
+        needed.push(ControllerType::Cpu);
+    }
+    if resources.memory().is_some() {
+        needed.push(ControllerType::Memory);
+    }
+    if resources.pids().is_some() {
+        needed.push(ControllerType::Pids);
+    }
+    if resources.block_io().is_some() {
+        needed.push(ControllerType::Io);
+    }
+    if matches!(resources.hugepage_limits(), Some(limits) if !limits.is_empty()) {
+        needed.push(ControllerType::HugeTlb);
+    }
+    if matches!(resources.rdma(), Some(rdma) if !rdma.is_empty()) {
+        needed.push(ControllerType::Rdma);
+    }
+    // unified resources are named after the controller they belong to
+    for cgroup_file in resources.unified().iter().flat_map(|u| u.keys()) {
+        let subsystem = cgroup_file.split('.').next().unwrap_or_default();
+        if let Some(controller) = CONTROLLER_TYPES.iter().find(|c| c.to_string() == subsystem) {
+            needed.push(*controller);
+        }
     }
+
+    CONTROLLER_TYPES
+        .iter()
+        .filter(|c| needed.contains(c))
+        .copied()
+        .collect()
 }
 
 impl CgroupManager for Manager {
+    fn validate(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        common::validate_resources(controller_opt.resources)?;
+
+        let needed = needed_controllers(controller_opt);
+        if needed.is_empty() {
+            return Ok(());
+        }
+
+        let available = util::get_available_controllers(&self.root_path)?;
+        for controller in needed {
+            if !available.contains(&controller) {
+                bail!(
+                    "{} controller is not available in {:?}",
+                    controller,
+                    self.root_path
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     fn add_task(&self, pid: Pid) -> Result<()> {
         self.create_unified_cgroup(pid)?;
         Ok(())
     }
 
+    fn prepare_task_dir(&self) -> Result<Option<PathBuf>> {
+        Ok(Some(self.create_task_dir()?))
+    }
+
+    fn add_thread(&self, tid: Pid) -> Result<()> {
+        // threads can only be placed individually within a threaded subtree, the kernel
+        // rejects the write otherwise
+        common::write_cgroup_file(Self::task_file(&self.full_path, true), tid).with_context(|| {
+            format!(
+                "failed to add thread {} to {:?}, which has to be a threaded cgroup",
+                tid, self.full_path
+            )
+        })
+    }
+
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        self.enable_controllers(&needed_controllers(controller_opt))
+            .context("failed to enable controllers")?;
+
         for controller in CONTROLLER_TYPES {
             match controller {
                 ControllerType::Cpu => Cpu::apply(controller_opt, &self.full_path)?,
@@ -105,6 +317,7 @@ impl CgroupManager for Manager {
                 ControllerType::Io => Io::apply(controller_opt, &self.full_path)?,
                 ControllerType::Memory => Memory::apply(controller_opt, &self.full_path)?,
                 ControllerType::Pids => Pids::apply(controller_opt, &self.full_path)?,
+                ControllerType::Rdma => Rdma::apply(controller_opt, &self.full_path)?,
             }
         }
 
@@ -126,16 +339,13 @@ impl CgroupManager for Manager {
 
     fn remove(&self) -> Result<()> {
         if self.full_path.exists() {
-            log::debug!("remove cgroup {:?}", self.full_path);
-            let procs_path = self.full_path.join(CGROUP_PROCS);
-            let procs = fs::read_to_string(&procs_path)?;
-
-            for line in procs.lines() {
-                let pid: i32 = line.parse()?;
-                let _ = nix::sys::signal::kill(Pid::from_raw(pid), nix::sys::signal::SIGKILL);
+            // the processes may have been placed into the init leaf and the container may
+            // have created child cgroups of its own, which all have to be removed first
+            for cgroup in Self::descendants_first(&self.full_path)? {
+                log::debug!("remove cgroup {:?}", cgroup);
+                Self::kill_processes(&cgroup)?;
+                common::delete_with_retry(&cgroup, 4, Duration::from_millis(100))?;
             }
-
-            common::delete_with_retry(&self.full_path, 4, Duration::from_millis(100))?;
         }
 
         Ok(())
@@ -147,6 +357,7 @@ impl CgroupManager for Manager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpu_idle: None,
         };
         Freezer::apply(&controller_opt, &self.full_path)
     }
@@ -154,7 +365,13 @@ impl CgroupManager for Manager {
     fn stats(&self) -> Result<Stats> {
         let mut stats = Stats::default();
 
+        // statistics are collected for every controller whose files exist for the cgroup,
+        // regardless of whether resource restrictions have been applied for it
         for subsystem in CONTROLLER_TYPES {
+            if !Self::has_stats(&self.full_path, subsystem)? {
+                continue;
+            }
+
             match subsystem {
                 ControllerType::Cpu => stats.cpu.usage = Cpu::stats(&self.full_path)?,
                 ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats(&self.full_path)?,
@@ -172,3 +389,249 @@ impl CgroupManager for Manager {
         common::get_all_pids(&self.full_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResources,
+        LinuxResourcesBuilder,
+    };
+    use std::collections::HashMap;
+
+    fn controller_opt(resources: &LinuxResources) -> ControllerOpt {
+        ControllerOpt {
+            resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            cpu_idle: None,
+        }
+    }
+
+    #[test]
+    fn test_needed_controllers() -> Result<()> {
+        let mut unified = HashMap::new();
+        unified.insert("io.weight".to_owned(), "100".to_owned());
+        unified.insert("misc.max".to_owned(), "res_a 1".to_owned());
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().quota(20000i64).build()?)
+            .memory(LinuxMemoryBuilder::default().limit(1024i64).build()?)
+            .pids(LinuxPidsBuilder::default().limit(10).build()?)
+            .unified(unified)
+            .build()?;
+
+        assert_eq!(
+            needed_controllers(&controller_opt(&resources)),
+            vec![
+                ControllerType::Cpu,
+                ControllerType::Io,
+                ControllerType::Memory,
+                ControllerType::Pids
+            ]
+        );
+
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().cpus("0-1").build()?)
+            .build()?;
+        assert_eq!(
+            needed_controllers(&controller_opt(&resources)),
+            vec![ControllerType::CpuSet]
+        );
+
+        let resources = LinuxResources::default();
+        assert!(needed_controllers(&controller_opt(&resources)).is_empty());
+        let opt = ControllerOpt {
+            cpu_idle: Some(1),
+            ..controller_opt(&resources)
+        };
+        assert_eq!(needed_controllers(&opt), vec![ControllerType::Cpu]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_leaf_path() -> Result<()> {
+        let tmp = create_temp_dir("test_leaf_path")?;
+        set_fixture(&tmp, CGROUP_PROCS, "")?;
+        assert_eq!(Manager::leaf_path(&tmp)?, tmp.to_path_buf());
+
+        fs::create_dir(tmp.join("child"))?;
+        assert_eq!(Manager::leaf_path(&tmp)?, tmp.join(INIT_LEAF));
+
+        let leaf = tmp.join("child");
+        assert_eq!(Manager::leaf_path(&leaf)?, leaf);
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_file() {
+        let path = Path::new("/sys/fs/cgroup/youki");
+        assert_eq!(Manager::task_file(path, false), path.join("cgroup.procs"));
+        assert_eq!(Manager::task_file(path, true), path.join("cgroup.threads"));
+    }
+
+    #[test]
+    fn test_add_thread() -> Result<()> {
+        let tmp = create_temp_dir("test_add_thread")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki"))?;
+        assert!(manager.add_thread(Pid::from_raw(1234)).is_err());
+
+        fs::create_dir(tmp.join("youki"))?;
+        set_fixture(&tmp.join("youki"), CGROUP_THREADS, "")?;
+        set_fixture(&tmp.join("youki"), CGROUP_PROCS, "")?;
+        manager.add_thread(Pid::from_raw(1234))?;
+
+        assert_eq!(
+            fs::read_to_string(tmp.join("youki").join(CGROUP_THREADS))?,
+            "1234"
+        );
+        assert_eq!(
+            fs::read_to_string(tmp.join("youki").join(CGROUP_PROCS))?,
+            ""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_task_dir() -> Result<()> {
+        let tmp = create_temp_dir("test_prepare_task_dir")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki/container"))?;
+        let container = tmp.join("youki").join("container");
+
+        assert_eq!(manager.prepare_task_dir()?, Some(container.clone()));
+        assert!(container.is_dir());
+
+        fs::create_dir(container.join("child"))?;
+        assert_eq!(manager.prepare_task_dir()?, Some(container.join(INIT_LEAF)));
+        assert!(container.join(INIT_LEAF).is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn test_descendants_first() -> Result<()> {
+        let tmp = create_temp_dir("test_descendants_first")?;
+        let leaf = tmp.join(INIT_LEAF);
+        let nested = tmp.join("nested");
+        fs::create_dir_all(&leaf)?;
+        fs::create_dir_all(nested.join("child"))?;
+        set_fixture(&tmp, CGROUP_PROCS, "")?;
+
+        let cgroups = Manager::descendants_first(&tmp)?;
+        assert_eq!(cgroups.len(), 4);
+        assert_eq!(cgroups.last(), Some(&tmp.to_path_buf()));
+        let position = |path: &Path| cgroups.iter().position(|c| c == path).unwrap();
+        assert!(position(&leaf) < position(&tmp));
+        assert!(position(&nested.join("child")) < position(&nested));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_with_leaf() -> Result<()> {
+        let tmp = create_temp_dir("test_remove_with_leaf")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki/container"))?;
+        let container = tmp.join("youki").join("container");
+        fs::create_dir_all(container.join(INIT_LEAF))?;
+
+        manager.remove()?;
+        assert!(!container.exists());
+        assert!(tmp.join("youki").exists());
+        // removing a cgroup which does not exist succeeds
+        manager.remove()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_enable_controllers() -> Result<()> {
+        let tmp = create_temp_dir("test_enable_controllers")?;
+        set_fixture(&tmp, util::CGROUP_CONTROLLERS, "cpu io memory pids")?;
+        set_fixture(&tmp, CGROUP_SUBTREE_CONTROL, "")?;
+        let parent = tmp.join("youki");
+        let container = parent.join("container");
+        fs::create_dir_all(&container)?;
+        set_fixture(&parent, CGROUP_SUBTREE_CONTROL, "")?;
+        set_fixture(&container, CGROUP_SUBTREE_CONTROL, "")?;
+
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki/container"))?;
+        manager.enable_controllers(&[
+            ControllerType::CpuSet,
+            ControllerType::Memory,
+            ControllerType::Pids,
+        ])?;
+
+        // cpuset is not available and the container cgroup itself is left untouched
+        for path in &[tmp.to_path_buf(), parent] {
+            let content = fs::read_to_string(path.join(CGROUP_SUBTREE_CONTROL))?;
+            assert_eq!(content, "+memory +pids");
+        }
+        assert_eq!(
+            fs::read_to_string(container.join(CGROUP_SUBTREE_CONTROL))?,
+            ""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let tmp = create_temp_dir("test_v2_manager_validate")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki"))?;
+
+        // nothing has to be checked without resource restrictions
+        manager.validate(&controller_opt(&LinuxResources::default()))?;
+
+        set_fixture(&tmp, util::CGROUP_CONTROLLERS, "cpu memory")?;
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(1024i64).build()?)
+            .build()?;
+        manager.validate(&controller_opt(&resources))?;
+        assert!(!tmp.join("youki").exists());
+
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(10).build()?)
+            .build()?;
+        let err = manager
+            .validate(&controller_opt(&resources))
+            .expect_err("pids is not available");
+        assert!(err.to_string().contains("pids"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_of_existing_controllers() -> Result<()> {
+        let tmp = create_temp_dir("test_stats_of_existing_controllers")?;
+        let container = tmp.join("youki");
+        fs::create_dir(&container)?;
+        // no resource restrictions have been applied and cgroup.controllers is missing
+        set_fixture(
+            &container,
+            "cpu.stat",
+            "usage_usec 100\nuser_usec 60\nsystem_usec 40\n",
+        )?;
+        set_fixture(&container, "pids.current", "5\n")?;
+        set_fixture(&container, "pids.max", "max\n")?;
+
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/youki"))?;
+        let stats = manager.stats()?;
+
+        assert_eq!(stats.cpu.usage.usage_total, 100);
+        assert_eq!(stats.cpu.usage.usage_user, 60);
+        assert_eq!(stats.pids.current, 5);
+        assert_eq!(stats.pids.limit, 0);
+        assert!(stats.hugetlb.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_stats() -> Result<()> {
+        let tmp = create_temp_dir("test_has_stats")?;
+        assert!(!Manager::has_stats(&tmp, &ControllerType::Memory)?);
+        assert!(!Manager::has_stats(&tmp, &ControllerType::HugeTlb)?);
+
+        set_fixture(&tmp, "memory.stat", "")?;
+        set_fixture(&tmp, "hugetlb.2MB.current", "0")?;
+        assert!(Manager::has_stats(&tmp, &ControllerType::Memory)?);
+        assert!(Manager::has_stats(&tmp, &ControllerType::HugeTlb)?);
+        assert!(!Manager::has_stats(&tmp, &ControllerType::CpuSet)?);
+        Ok(())
+    }
+}
@@ -14,11 +14,18 @@ const CGROUP_MEMORY_SWAP: &str = "memory.swap.max";
 const CGROUP_MEMORY_MAX: &str = "memory.max";
 const CGROUP_MEMORY_LOW: &str = "memory.low";
 const MEMORY_STAT: &str = "memory.stat";
+const MEMORY_EVENTS: &str = "memory.events";
 
 pub struct Memory {}
 
 impl Controller for Memory {
     fn apply(controller_opt: &ControllerOpt, cgroup_path: &Path) -> Result<()> {
+        // cgroup v2 has no equivalent of memory.oom_control, like runc the setting is
+        // ignored instead of failing the creation of the container
+        if controller_opt.disable_oom_killer {
+            log::warn!("disabling the oom killer is not supported by cgroup v2, ignoring it");
+        }
+
         if let Some(memory) = &controller_opt.resources.memory() {
             Self::apply(cgroup_path, memory)
                 .context("failed to apply memory resource restrictions")?;
@@ -37,6 +44,7 @@ impl StatsProvider for Memory {
             memswap: Self::get_memory_data(cgroup_path, "memory.swap", "fail")?,
             hierarchy: true,
             stats: stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))?,
+            oom_kill: Self::get_oom_kill_count(cgroup_path)?,
             ..Default::default()
         };
 
@@ -72,6 +80,11 @@ impl Memory {
         })
     }
 
+    fn get_oom_kill_count(cgroup_path: &Path) -> Result<u64> {
+        let events = stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_EVENTS))?;
+        Ok(events.get("oom_kill").copied().unwrap_or_default())
+    }
+
     fn set<P: AsRef<Path>>(path: P, val: i64) -> Result<()> {
         if val == 0 {
             Ok(())
@@ -112,8 +125,9 @@ impl Memory {
                         // In cgroup v1 swap is memory+swap, but in cgroup v2 swap is
                         // a separate value, so the swap value in the runtime spec needs
                         // to be converted from the cgroup v1 value to the cgroup v2 value
-                        // by subtracting limit from swap
-                        Memory::set(path.join(CGROUP_MEMORY_SWAP), swap - limit)?;
+                        // by subtracting limit from swap. A result of zero disables swap
+                        // and has to be written explicitly, unlike the other zero values.
+                        common::write_cgroup_file(path.join(CGROUP_MEMORY_SWAP), swap - limit)?;
                     }
                     Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
                 }
@@ -146,9 +160,31 @@ impl Memory {
 mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture};
-    use oci_spec::runtime::LinuxMemoryBuilder;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
     use std::fs::read_to_string;
 
+    #[test]
+    fn test_ignore_disable_oom_killer() {
+        let tmp = create_temp_dir("test_ignore_disable_oom_killer_v2")
+            .expect("create temp directory for test");
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .hugepage_limits(vec![])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: true,
+            oom_score_adj: None,
+            freezer_state: None,
+            cpu_idle: None,
+        };
+
+        let result = <Memory as Controller>::apply(&controller_opt, &tmp);
+
+        assert!(result.is_ok());
+    }
+
     #[test]
     fn test_set_memory() {
         let tmp = create_temp_dir("test_set_memory_v2").expect("create temp directory for test");
@@ -199,6 +235,80 @@ mod tests {
         assert_eq!(swap_content, "max");
     }
 
+    #[test]
+    fn test_set_swap_only_value() {
+        let tmp =
+            create_temp_dir("test_set_swap_only_value_v2").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MAX, "0").expect("set fixture for memory limit");
+        set_fixture(&tmp, CGROUP_MEMORY_SWAP, "").expect("set fixture for swap limit");
+
+        let limit = 100 * 1024 * 1024;
+        let swap = 200 * 1024 * 1024;
+        let memory_limits = LinuxMemoryBuilder::default()
+            .limit(limit)
+            .swap(swap)
+            .build()
+            .unwrap();
+
+        Memory::apply(&tmp, &memory_limits).expect("apply memory limits");
+
+        let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
+        assert_eq!(swap_content, (100 * 1024 * 1024).to_string());
+    }
+
+    #[test]
+    fn test_set_swap_unlimited() {
+        let tmp =
+            create_temp_dir("test_set_swap_unlimited_v2").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MAX, "0").expect("set fixture for memory limit");
+        set_fixture(&tmp, CGROUP_MEMORY_SWAP, "").expect("set fixture for swap limit");
+
+        let memory_limits = LinuxMemoryBuilder::default()
+            .limit(1024)
+            .swap(-1)
+            .build()
+            .unwrap();
+
+        Memory::apply(&tmp, &memory_limits).expect("apply memory limits");
+
+        let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
+        assert_eq!(swap_content, "max");
+    }
+
+    #[test]
+    fn test_set_swap_equal_to_limit() {
+        let tmp = create_temp_dir("test_set_swap_equal_to_limit_v2")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MAX, "0").expect("set fixture for memory limit");
+        set_fixture(&tmp, CGROUP_MEMORY_SWAP, "").expect("set fixture for swap limit");
+
+        let memory_limits = LinuxMemoryBuilder::default()
+            .limit(1024)
+            .swap(1024)
+            .build()
+            .unwrap();
+
+        Memory::apply(&tmp, &memory_limits).expect("apply memory limits");
+
+        let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
+        assert_eq!(swap_content, "0");
+    }
+
+    #[test]
+    fn test_limit_without_swap_leaves_swap_untouched() {
+        let tmp = create_temp_dir("test_limit_without_swap_leaves_swap_untouched_v2")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MAX, "0").expect("set fixture for memory limit");
+        set_fixture(&tmp, CGROUP_MEMORY_SWAP, "").expect("set fixture for swap limit");
+
+        let memory_limits = LinuxMemoryBuilder::default().limit(1024).build().unwrap();
+
+        Memory::apply(&tmp, &memory_limits).expect("apply memory limits");
+
+        let swap_content = read_to_string(tmp.join(CGROUP_MEMORY_SWAP)).expect("read swap limit");
+        assert_eq!(swap_content, "");
+    }
+
     #[test]
     fn test_err_swap_no_memory() {
         let tmp =
@@ -348,4 +458,14 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_get_oom_kill_count() {
+        let tmp = create_temp_dir("test_get_oom_kill_count").expect("create test directory");
+        let events = ["low 0", "high 0", "max 4", "oom 2", "oom_kill 1"].join("\n");
+        set_fixture(&tmp, MEMORY_EVENTS, &events).unwrap();
+
+        let actual = Memory::get_oom_kill_count(&tmp).expect("get oom kill count");
+        assert_eq!(actual, 1);
+    }
 }
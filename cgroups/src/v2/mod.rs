@@ -8,6 +8,8 @@ mod io;
 pub mod manager;
 mod memory;
 mod pids;
+mod rdma;
+pub mod systemd_client;
 pub mod systemd_manager;
 mod unified;
 pub mod util;
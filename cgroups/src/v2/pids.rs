@@ -10,6 +10,9 @@ use crate::{
 use super::controller::Controller;
 use oci_spec::runtime::LinuxPids;
 
+// Contains the maximum allowed number of active pids
+const CGROUP_PIDS_MAX: &str = "pids.max";
+
 pub struct Pids {}
 
 impl Controller for Pids {
@@ -37,7 +40,7 @@ impl Pids {
         } else {
             "max".to_string()
         };
-        common::write_cgroup_file(&root_path.join("pids.max"), &limit)
+        common::write_cgroup_file(&root_path.join(CGROUP_PIDS_MAX), &limit)
     }
 }
 
@@ -49,7 +52,7 @@ mod tests {
 
     #[test]
     fn test_set_pids() {
-        let pids_file_name = "pids.max";
+        let pids_file_name = CGROUP_PIDS_MAX;
         let tmp = create_temp_dir("v2_test_set_pids").expect("create temp directory for test");
         set_fixture(&tmp, pids_file_name, "1000").expect("Set fixture for 1000 pids");
 
@@ -63,7 +66,7 @@ mod tests {
 
     #[test]
     fn test_set_pids_max() {
-        let pids_file_name = "pids.max";
+        let pids_file_name = CGROUP_PIDS_MAX;
         let tmp = create_temp_dir("v2_test_set_pids_max").expect("create temp directory for test");
         set_fixture(&tmp, pids_file_name, "0").expect("set fixture for 0 pids");
 
@@ -75,4 +78,18 @@ mod tests {
             std::fs::read_to_string(tmp.join(pids_file_name)).expect("Read pids contents");
         assert_eq!("max".to_string(), content);
     }
+
+    #[test]
+    fn test_set_pids_unlimited() {
+        let tmp =
+            create_temp_dir("v2_test_set_pids_unlimited").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_PIDS_MAX, "1000").expect("set fixture for 1000 pids");
+
+        let pids = LinuxPidsBuilder::default().limit(-1).build().unwrap();
+
+        Pids::apply(&tmp, &pids).expect("apply pids");
+        let content =
+            std::fs::read_to_string(tmp.join(CGROUP_PIDS_MAX)).expect("Read pids contents");
+        assert_eq!("max", content);
+    }
 }
@@ -0,0 +1,60 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+
+use super::controller::Controller;
+use crate::common::{self, ControllerOpt};
+use oci_spec::runtime::LinuxRdma;
+
+const CGROUP_RDMA_MAX: &str = "rdma.max";
+
+pub struct Rdma {}
+
+impl Controller for Rdma {
+    fn apply(controller_opt: &ControllerOpt, cgroup_path: &Path) -> Result<()> {
+        log::debug!("Apply rdma cgroup v2 config");
+        if let Some(rdma) = &controller_opt.resources.rdma() {
+            Self::apply(cgroup_path, rdma).context("failed to apply rdma resource restrictions")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Rdma {
+    fn apply(root_path: &Path, rdma: &HashMap<String, LinuxRdma>) -> Result<()> {
+        for (device, limits) in rdma {
+            if let Some(limit) = common::format_rdma_limit(device, limits) {
+                common::write_cgroup_file_str(root_path.join(CGROUP_RDMA_MAX), &limit)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::setup;
+    use oci_spec::runtime::LinuxRdmaBuilder;
+    use std::fs::read_to_string;
+
+    #[test]
+    fn test_set_rdma() {
+        let (tmp, max) = setup("v2_test_set_rdma", CGROUP_RDMA_MAX);
+        let mut rdma = HashMap::new();
+        rdma.insert(
+            "mlx5_1".to_owned(),
+            LinuxRdmaBuilder::default()
+                .hca_handles(2u32)
+                .build()
+                .unwrap(),
+        );
+
+        Rdma::apply(&tmp, &rdma).expect("apply rdma");
+
+        let content = read_to_string(max).expect("read rdma.max");
+        assert_eq!(content, "mlx5_1 hca_handle=2 hca_object=max");
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use dbus::{
+    arg::{RefArg, Variant},
+    blocking::Connection,
+};
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const SYSTEMD_MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const DBUS_TIMEOUT: Duration = Duration::from_millis(5000);
+
+/// Value of a systemd unit property
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyValue {
+    Bool(bool),
+    U64(u64),
+    String(String),
+    U32Array(Vec<u32>),
+}
+
+impl PropertyValue {
+    fn to_variant(&self) -> Variant<Box<dyn RefArg>> {
+        match self {
+            PropertyValue::Bool(value) => Variant(Box::new(*value)),
+            PropertyValue::U64(value) => Variant(Box::new(*value)),
+            PropertyValue::String(value) => Variant(Box::new(value.clone())),
+            PropertyValue::U32Array(value) => Variant(Box::new(value.clone())),
+        }
+    }
+}
+
+/// Unit properties as they are passed to systemd, e.g. ("MemoryMax", U64(1024))
+pub type Properties = Vec<(&'static str, PropertyValue)>;
+
+/// Abstraction over the parts of the systemd manager API that are needed to manage
+/// the cgroup of a container. See https://www.freedesktop.org/wiki/Software/systemd/dbus/
+pub trait SystemdClient {
+    /// Creates and starts a transient unit with the given properties
+    fn start_transient_unit(
+        &self,
+        unit_name: &str,
+        properties: &[(&'static str, PropertyValue)],
+    ) -> Result<()>;
+
+    /// Changes the properties of an existing unit at runtime
+    fn set_unit_properties(
+        &self,
+        unit_name: &str,
+        properties: &[(&'static str, PropertyValue)],
+    ) -> Result<()>;
+
+    /// Stops the unit
+    fn stop_unit(&self, unit_name: &str) -> Result<()>;
+}
+
+/// Talks to systemd over the system bus. A new connection is established for every
+/// call as the client is used from processes which are forked after its creation.
+#[derive(Default)]
+pub struct DbusClient {}
+
+impl DbusClient {
+    fn connection() -> Result<Connection> {
+        Connection::new_system().context("failed to connect to the system bus")
+    }
+
+    fn to_dbus_properties(
+        properties: &[(&'static str, PropertyValue)],
+    ) -> Vec<(&'static str, Variant<Box<dyn RefArg>>)> {
+        properties
+            .iter()
+            .map(|(name, value)| (*name, value.to_variant()))
+            .collect()
+    }
+}
+
+impl SystemdClient for DbusClient {
+    fn start_transient_unit(
+        &self,
+        unit_name: &str,
+        properties: &[(&'static str, PropertyValue)],
+    ) -> Result<()> {
+        let conn = Self::connection()?;
+        let proxy = conn.with_proxy(SYSTEMD_DESTINATION, SYSTEMD_PATH, DBUS_TIMEOUT);
+        let aux: Vec<(&str, Vec<(&str, Variant<Box<dyn RefArg>>)>)> = Vec::new();
+
+        let (_job,): (dbus::Path<'static>,) = proxy
+            .method_call(
+                SYSTEMD_MANAGER_INTERFACE,
+                "StartTransientUnit",
+                (
+                    unit_name,
+                    "replace",
+                    Self::to_dbus_properties(properties),
+                    aux,
+                ),
+            )
+            .with_context(|| format!("failed to start transient unit {}", unit_name))?;
+        Ok(())
+    }
+
+    fn set_unit_properties(
+        &self,
+        unit_name: &str,
+        properties: &[(&'static str, PropertyValue)],
+    ) -> Result<()> {
+        let conn = Self::connection()?;
+        let proxy = conn.with_proxy(SYSTEMD_DESTINATION, SYSTEMD_PATH, DBUS_TIMEOUT);
+
+        let _: () = proxy
+            .method_call(
+                SYSTEMD_MANAGER_INTERFACE,
+                "SetUnitProperties",
+                (unit_name, true, Self::to_dbus_properties(properties)),
+            )
+            .with_context(|| format!("failed to set properties of unit {}", unit_name))?;
+        Ok(())
+    }
+
+    fn stop_unit(&self, unit_name: &str) -> Result<()> {
+        let conn = Self::connection()?;
+        let proxy = conn.with_proxy(SYSTEMD_DESTINATION, SYSTEMD_PATH, DBUS_TIMEOUT);
+
+        let (_job,): (dbus::Path<'static>,) = proxy
+            .method_call(
+                SYSTEMD_MANAGER_INTERFACE,
+                "StopUnit",
+                (unit_name, "replace"),
+            )
+            .with_context(|| format!("failed to stop unit {}", unit_name))?;
+        Ok(())
+    }
+}
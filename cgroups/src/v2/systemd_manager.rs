@@ -2,25 +2,34 @@ use std::{
     fs::{self},
     os::unix::fs::PermissionsExt,
     path::Component::RootDir,
+    time::Duration,
 };
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use nix::unistd::Pid;
 use std::path::{Path, PathBuf};
 
 #[cfg(feature = "cgroupsv2_devices")]
 use super::devices::Devices;
+use super::systemd_client::{DbusClient, Properties, PropertyValue, SystemdClient};
 use super::{
     controller::Controller, controller_type::ControllerType, cpu::Cpu, cpuset::CpuSet,
-    freezer::Freezer, hugetlb::HugeTlb, io::Io, memory::Memory, pids::Pids,
+    freezer::Freezer, hugetlb::HugeTlb, io::Io, manager::needed_controllers, memory::Memory,
+    pids::Pids, rdma::Rdma,
 };
 use crate::common::{self, CgroupManager, ControllerOpt, FreezerState, PathBufExt};
 use crate::stats::Stats;
+use oci_spec::runtime::LinuxResources;
 
 const CGROUP_PROCS: &str = "cgroup.procs";
 const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
 const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
 
+const DEFAULT_SLICE: &str = "machine.slice";
+const DEFAULT_CPU_PERIOD: u64 = 100000;
+// systemd only accepts cpu quotas with a granularity of 10ms per second
+const CPU_QUOTA_GRANULARITY: u64 = 10000;
+
 // v2 systemd only supports cpu, io, memory and pids.
 const CONTROLLER_TYPES: &[ControllerType] = &[
     ControllerType::Cpu,
@@ -34,6 +43,9 @@ pub struct SystemDCGroupManager {
     root_path: PathBuf,
     cgroups_path: PathBuf,
     full_path: PathBuf,
+    unit_name: String,
+    slice: String,
+    client: Box<dyn SystemdClient>,
 }
 
 /// Represents the systemd cgroups path:
@@ -48,8 +60,21 @@ struct CgroupsPath {
 
 impl SystemDCGroupManager {
     pub fn new(root_path: PathBuf, cgroups_path: PathBuf) -> Result<Self> {
-        // TODO: create the systemd unit using a dbus client.
+        Self::with_client(root_path, cgroups_path, Box::new(DbusClient::default()))
+    }
+
+    fn with_client(
+        root_path: PathBuf,
+        cgroups_path: PathBuf,
+        client: Box<dyn SystemdClient>,
+    ) -> Result<Self> {
         let destructured_path = Self::destructure_cgroups_path(cgroups_path)?;
+        let unit_name = Self::get_unit_name(&destructured_path);
+        let slice = if destructured_path.parent.is_empty() {
+            DEFAULT_SLICE.to_owned()
+        } else {
+            destructured_path.parent.clone()
+        };
         let cgroups_path = Self::construct_cgroups_path(destructured_path)?;
         let full_path = root_path.join_safely(&cgroups_path)?;
 
@@ -57,6 +82,9 @@ impl SystemDCGroupManager {
             root_path,
             cgroups_path,
             full_path,
+            unit_name,
+            slice,
+            client,
         })
     }
 
@@ -94,12 +122,12 @@ impl SystemDCGroupManager {
 
     /// get_unit_name returns the unit (scope) name from the path provided by the user
     /// for example: foo:docker:bar returns in '/docker-bar.scope'
-    fn get_unit_name(cgroups_path: CgroupsPath) -> String {
+    fn get_unit_name(cgroups_path: &CgroupsPath) -> String {
         // By default we create a scope unless specified explicitly.
         if !cgroups_path.name.ends_with(".slice") {
             return format!("{}-{}.scope", cgroups_path.scope, cgroups_path.name);
         }
-        cgroups_path.name
+        cgroups_path.name.clone()
     }
 
     // systemd represents slice hierarchy using `-`, so we need to follow suit when
@@ -141,7 +169,7 @@ impl SystemDCGroupManager {
         if !cgroups_path.parent.is_empty() {
             slice = Self::expand_slice(&cgroups_path.parent)?;
         }
-        let unit_name = Self::get_unit_name(cgroups_path);
+        let unit_name = Self::get_unit_name(&cgroups_path);
         let cgroups_path = slice.join(unit_name);
         Ok(cgroups_path)
     }
@@ -185,6 +213,109 @@ impl SystemDCGroupManager {
         common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)
     }
 
+    /// start_unit asks systemd to create a transient unit for the container and to place
+    /// the process into it. Delegation is requested so that controllers systemd does not
+    /// know about can still be configured by writing to the cgroup directly.
+    fn start_unit(&self, pid: Pid) -> Result<()> {
+        let is_slice = self.unit_name.ends_with(".slice");
+        let mut properties: Properties = vec![
+            (
+                "Description",
+                PropertyValue::String(format!("youki container {}", self.unit_name)),
+            ),
+            ("Delegate", PropertyValue::Bool(true)),
+            ("DefaultDependencies", PropertyValue::Bool(false)),
+        ];
+        if is_slice {
+            properties.push(("Wants", PropertyValue::String(self.slice.clone())));
+        } else {
+            properties.push(("Slice", PropertyValue::String(self.slice.clone())));
+            properties.push(("PIDs", PropertyValue::U32Array(vec![pid.as_raw() as u32])));
+        }
+
+        self.client
+            .start_transient_unit(&self.unit_name, &properties)?;
+
+        // slices cannot contain processes, so systemd does not move the process for us
+        if is_slice {
+            common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)?;
+        }
+
+        Ok(())
+    }
+
+    /// unit_properties translates the resource restrictions into the equivalent
+    /// systemd unit properties. Restrictions without a systemd equivalent are
+    /// skipped and have to be written to the cgroup directly.
+    fn unit_properties(resources: &LinuxResources) -> Properties {
+        let mut properties = Properties::new();
+
+        if let Some(memory) = resources.memory() {
+            match memory.limit() {
+                Some(limit) if limit > 0 => {
+                    properties.push(("MemoryMax", PropertyValue::U64(limit as u64)));
+                    match memory.swap() {
+                        Some(-1) => {
+                            properties.push(("MemorySwapMax", PropertyValue::U64(u64::MAX)))
+                        }
+                        Some(swap) if swap >= limit => properties
+                            .push(("MemorySwapMax", PropertyValue::U64((swap - limit) as u64))),
+                        _ => {}
+                    }
+                }
+                Some(-1) => properties.push(("MemoryMax", PropertyValue::U64(u64::MAX))),
+                _ => {}
+            }
+
+            if let Some(reservation) = memory.reservation() {
+                if reservation > 0 {
+                    properties.push(("MemoryLow", PropertyValue::U64(reservation as u64)));
+                }
+            }
+        }
+
+        if let Some(cpu) = resources.cpu() {
+            if let Some(shares) = cpu.shares() {
+                let weight = Cpu::convert_shares_to_cgroup2(shares);
+                if weight != 0 {
+                    properties.push(("CPUWeight", PropertyValue::U64(weight)));
+                }
+            }
+
+            let period = match cpu.period() {
+                Some(period) if period > 0 => {
+                    properties.push(("CPUQuotaPeriodUSec", PropertyValue::U64(period)));
+                    period
+                }
+                _ => DEFAULT_CPU_PERIOD,
+            };
+            if let Some(quota) = cpu.quota() {
+                let quota_per_sec = if quota > 0 {
+                    let mut quota_per_sec = quota as u64 * 1_000_000 / period;
+                    if quota_per_sec % CPU_QUOTA_GRANULARITY != 0 {
+                        quota_per_sec =
+                            (quota_per_sec / CPU_QUOTA_GRANULARITY + 1) * CPU_QUOTA_GRANULARITY;
+                    }
+                    quota_per_sec
+                } else {
+                    u64::MAX
+                };
+                properties.push(("CPUQuotaPerSecUSec", PropertyValue::U64(quota_per_sec)));
+            }
+        }
+
+        if let Some(pids) = resources.pids() {
+            let limit = if pids.limit() > 0 {
+                pids.limit() as u64
+            } else {
+                u64::MAX
+            };
+            properties.push(("TasksMax", PropertyValue::U64(limit)));
+        }
+
+        properties
+    }
+
     fn get_available_controllers<P: AsRef<Path>>(
         &self,
         cgroups_path: P,
@@ -211,6 +342,31 @@ impl SystemDCGroupManager {
         Ok(controllers)
     }
 
+    /// Moves the processes which are still left in the cgroup into the root cgroup,
+    /// because a cgroup can only be removed once it is empty. Stopping the unit
+    /// normally takes care of them, but the cgroup may have been created without
+    /// systemd.
+    fn move_processes_to_root(&self) -> Result<()> {
+        let procs_path = self.full_path.join(CGROUP_PROCS);
+        if !procs_path.exists() {
+            return Ok(());
+        }
+
+        let root_procs = self.root_path.join(CGROUP_PROCS);
+        for pid in common::read_cgroup_procs(&procs_path)? {
+            log::debug!("move process {} to the root cgroup", pid);
+            common::write_cgroup_file(&root_procs, pid).with_context(|| {
+                format!(
+                    "failed to move process {} out of {}",
+                    pid,
+                    self.full_path.display()
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
     fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
         for controller in controllers {
             common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
@@ -221,17 +377,69 @@ impl SystemDCGroupManager {
 }
 
 impl CgroupManager for SystemDCGroupManager {
+    fn validate(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        common::validate_resources(controller_opt.resources)?;
+
+        let needed = needed_controllers(controller_opt);
+        if needed.is_empty() {
+            return Ok(());
+        }
+
+        let available = self.get_available_controllers(&self.root_path)?;
+        for controller in needed {
+            if !CONTROLLER_TYPES.contains(&controller) {
+                bail!(
+                    "{} controller is not supported by the systemd cgroup manager",
+                    controller
+                );
+            }
+            if !available.contains(&controller) {
+                bail!(
+                    "{} controller is not available in {:?}",
+                    controller,
+                    self.root_path
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     fn add_task(&self, pid: Pid) -> Result<()> {
         // Dont attach any pid to the cgroup if -1 is specified as a pid
         if pid.as_raw() == -1 {
             return Ok(());
         }
 
-        self.create_unified_cgroup(pid)?;
+        if let Err(err) = self.start_unit(pid) {
+            log::warn!(
+                "failed to create systemd unit {}, falling back to managing the cgroup directly: {:?}",
+                self.unit_name,
+                err
+            );
+            self.create_unified_cgroup(pid)?;
+        }
+
         Ok(())
     }
 
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        let properties = Self::unit_properties(controller_opt.resources);
+        if !properties.is_empty() {
+            if let Err(err) = self
+                .client
+                .set_unit_properties(&self.unit_name, &properties)
+            {
+                log::warn!(
+                    "failed to set properties of systemd unit {}: {:?}",
+                    self.unit_name,
+                    err
+                );
+            }
+        }
+
+        // systemd does not cover every controller and the unit may not be managed by
+        // systemd at all, so the restrictions are also written to the delegated subtree
         for controller in CONTROLLER_TYPES {
             match controller {
                 ControllerType::Cpu => Cpu::apply(controller_opt, &self.full_path)?,
@@ -240,6 +448,7 @@ impl CgroupManager for SystemDCGroupManager {
                 ControllerType::Io => Io::apply(controller_opt, &self.full_path)?,
                 ControllerType::Memory => Memory::apply(controller_opt, &self.full_path)?,
                 ControllerType::Pids => Pids::apply(controller_opt, &self.full_path)?,
+                ControllerType::Rdma => Rdma::apply(controller_opt, &self.full_path)?,
             }
         }
 
@@ -249,6 +458,16 @@ impl CgroupManager for SystemDCGroupManager {
     }
 
     fn remove(&self) -> Result<()> {
+        if let Err(err) = self.client.stop_unit(&self.unit_name) {
+            log::debug!("failed to stop systemd unit {}: {:?}", self.unit_name, err);
+        }
+
+        if self.full_path.exists() {
+            log::debug!("remove cgroup {:?}", self.full_path);
+            self.move_processes_to_root()?;
+            common::delete_with_retry(&self.full_path, 4, Duration::from_millis(100))?;
+        }
+
         Ok(())
     }
 
@@ -258,6 +477,7 @@ impl CgroupManager for SystemDCGroupManager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpu_idle: None,
         };
         Freezer::apply(&controller_opt, &self.full_path)
     }
@@ -274,6 +494,291 @@ impl CgroupManager for SystemDCGroupManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use anyhow::anyhow;
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResourcesBuilder,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    type Calls = Rc<RefCell<Vec<(&'static str, String, Properties)>>>;
+
+    #[derive(Default)]
+    struct MockClient {
+        calls: Calls,
+        fail: bool,
+    }
+
+    impl MockClient {
+        fn record(
+            &self,
+            method: &'static str,
+            unit_name: &str,
+            properties: &[(&'static str, PropertyValue)],
+        ) -> Result<()> {
+            self.calls
+                .borrow_mut()
+                .push((method, unit_name.to_owned(), properties.to_vec()));
+            if self.fail {
+                return Err(anyhow!("systemd is not available"));
+            }
+            Ok(())
+        }
+    }
+
+    impl SystemdClient for MockClient {
+        fn start_transient_unit(
+            &self,
+            unit_name: &str,
+            properties: &[(&'static str, PropertyValue)],
+        ) -> Result<()> {
+            self.record("StartTransientUnit", unit_name, properties)
+        }
+
+        fn set_unit_properties(
+            &self,
+            unit_name: &str,
+            properties: &[(&'static str, PropertyValue)],
+        ) -> Result<()> {
+            self.record("SetUnitProperties", unit_name, properties)
+        }
+
+        fn stop_unit(&self, unit_name: &str) -> Result<()> {
+            self.record("StopUnit", unit_name, &[])
+        }
+    }
+
+    fn manager_with_mock(root_path: &Path, fail: bool) -> (SystemDCGroupManager, Calls) {
+        let calls = Calls::default();
+        let client = MockClient {
+            calls: calls.clone(),
+            fail,
+        };
+        let manager = SystemDCGroupManager::with_client(
+            root_path.to_path_buf(),
+            PathBuf::from("machine.slice:youki:test"),
+            Box::new(client),
+        )
+        .expect("create manager");
+        (manager, calls)
+    }
+
+    #[test]
+    fn test_unit_properties() {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024 * 1024 * 1024)
+                    .swap(2 * 1024 * 1024 * 1024)
+                    .reservation(512 * 1024 * 1024)
+                    .build()
+                    .unwrap(),
+            )
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .shares(1024u64)
+                    .quota(50000)
+                    .period(100000u64)
+                    .build()
+                    .unwrap(),
+            )
+            .pids(LinuxPidsBuilder::default().limit(100).build().unwrap())
+            .build()
+            .unwrap();
+
+        let properties = SystemDCGroupManager::unit_properties(&resources);
+        assert_eq!(
+            properties,
+            vec![
+                ("MemoryMax", PropertyValue::U64(1024 * 1024 * 1024)),
+                ("MemorySwapMax", PropertyValue::U64(1024 * 1024 * 1024)),
+                ("MemoryLow", PropertyValue::U64(512 * 1024 * 1024)),
+                ("CPUWeight", PropertyValue::U64(39)),
+                ("CPUQuotaPeriodUSec", PropertyValue::U64(100000)),
+                ("CPUQuotaPerSecUSec", PropertyValue::U64(500000)),
+                ("TasksMax", PropertyValue::U64(100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unit_properties_unlimited() {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(-1).build().unwrap())
+            .cpu(LinuxCpuBuilder::default().quota(-1).build().unwrap())
+            .pids(LinuxPidsBuilder::default().limit(-1).build().unwrap())
+            .build()
+            .unwrap();
+
+        let properties = SystemDCGroupManager::unit_properties(&resources);
+        assert_eq!(
+            properties,
+            vec![
+                ("MemoryMax", PropertyValue::U64(u64::MAX)),
+                ("CPUQuotaPerSecUSec", PropertyValue::U64(u64::MAX)),
+                ("TasksMax", PropertyValue::U64(u64::MAX)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cpu_quota_is_rounded_up() {
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().quota(12345).build().unwrap())
+            .build()
+            .unwrap();
+
+        let properties = SystemDCGroupManager::unit_properties(&resources);
+        assert_eq!(
+            properties,
+            vec![("CPUQuotaPerSecUSec", PropertyValue::U64(130000))]
+        );
+    }
+
+    #[test]
+    fn test_add_task_starts_transient_unit() {
+        let tmp = create_temp_dir("test_add_task_starts_transient_unit").expect("create temp dir");
+        let (manager, calls) = manager_with_mock(&tmp, false);
+
+        manager.add_task(Pid::from_raw(1000)).expect("add task");
+
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 1);
+        let (method, unit_name, properties) = &calls[0];
+        assert_eq!(*method, "StartTransientUnit");
+        assert_eq!(unit_name, "youki-test.scope");
+        assert!(properties.contains(&("Slice", PropertyValue::String("machine.slice".to_owned()))));
+        assert!(properties.contains(&("Delegate", PropertyValue::Bool(true))));
+        assert!(properties.contains(&("PIDs", PropertyValue::U32Array(vec![1000]))));
+        assert!(!tmp.join("machine.slice").exists());
+    }
+
+    #[test]
+    fn test_add_task_falls_back_to_cgroupfs() {
+        let tmp = create_temp_dir("test_add_task_falls_back_to_cgroupfs").expect("create temp dir");
+        set_fixture(&tmp, CGROUP_CONTROLLERS, "cpu memory").expect("set controllers");
+        set_fixture(&tmp, CGROUP_SUBTREE_CONTROL, "").expect("set subtree control");
+        let slice = tmp.join("machine.slice");
+        fs::create_dir_all(&slice).expect("create slice");
+        set_fixture(&slice, CGROUP_SUBTREE_CONTROL, "").expect("set subtree control");
+        let (manager, _) = manager_with_mock(&tmp, true);
+        fs::create_dir_all(&manager.full_path).expect("create cgroup");
+        set_fixture(&manager.full_path, CGROUP_PROCS, "").expect("set cgroup.procs");
+
+        manager.add_task(Pid::from_raw(1000)).expect("add task");
+
+        let procs =
+            fs::read_to_string(manager.full_path.join(CGROUP_PROCS)).expect("read cgroup.procs");
+        assert_eq!(procs, "1000");
+    }
+
+    #[test]
+    fn test_apply_sets_unit_properties() {
+        let tmp = create_temp_dir("test_apply_sets_unit_properties").expect("create temp dir");
+        let (manager, calls) = manager_with_mock(&tmp, false);
+        fs::create_dir_all(&manager.full_path).expect("create cgroup");
+        set_fixture(&manager.full_path, "pids.max", "").expect("set pids.max");
+
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(100).build().unwrap())
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            cpu_idle: None,
+        };
+        manager.apply(&controller_opt).expect("apply");
+
+        assert_eq!(
+            *calls.borrow(),
+            vec![(
+                "SetUnitProperties",
+                "youki-test.scope".to_owned(),
+                vec![("TasksMax", PropertyValue::U64(100))]
+            )]
+        );
+        let pids_max =
+            fs::read_to_string(manager.full_path.join("pids.max")).expect("read pids.max");
+        assert_eq!(pids_max, "100");
+    }
+
+    #[test]
+    fn test_validate() {
+        let tmp = create_temp_dir("test_systemd_manager_validate").expect("create temp dir");
+        set_fixture(&tmp, CGROUP_CONTROLLERS, "cpu cpuset memory").expect("set controllers");
+        let (manager, calls) = manager_with_mock(&tmp, false);
+        let validate = |resources: &LinuxResources| {
+            manager.validate(&ControllerOpt {
+                resources,
+                freezer_state: None,
+                oom_score_adj: None,
+                disable_oom_killer: false,
+                cpu_idle: None,
+            })
+        };
+
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024i64)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        validate(&resources).expect("validate memory limit");
+        assert!(calls.borrow().is_empty());
+        assert!(!manager.full_path.exists());
+
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(10).build().unwrap())
+            .build()
+            .unwrap();
+        let err = validate(&resources).expect_err("pids is not available");
+        assert!(err.to_string().contains("not available"));
+
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().cpus("0-1").build().unwrap())
+            .build()
+            .unwrap();
+        let err = validate(&resources).expect_err("cpuset is not supported");
+        assert!(err.to_string().contains("not supported"));
+    }
+
+    #[test]
+    fn test_remove_stops_unit_and_removes_cgroup() {
+        let tmp =
+            create_temp_dir("test_remove_stops_unit_and_removes_cgroup").expect("create temp dir");
+        let (manager, calls) = manager_with_mock(&tmp, false);
+        fs::create_dir_all(&manager.full_path).expect("create cgroup");
+
+        manager.remove().expect("remove cgroup");
+
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "StopUnit");
+        assert_eq!(calls[0].1, "youki-test.scope");
+        assert!(!manager.full_path.exists());
+    }
+
+    #[test]
+    fn test_remove_moves_processes_to_root() {
+        let tmp = create_temp_dir("test_remove_moves_processes_to_root").expect("create temp dir");
+        set_fixture(&tmp, CGROUP_PROCS, "").expect("set root cgroup.procs");
+        let (manager, _) = manager_with_mock(&tmp, false);
+        fs::create_dir_all(&manager.full_path).expect("create cgroup");
+        set_fixture(&manager.full_path, CGROUP_PROCS, "1234\n").expect("set cgroup.procs");
+
+        manager
+            .move_processes_to_root()
+            .expect("move processes to root");
+
+        let root_procs = fs::read_to_string(tmp.join(CGROUP_PROCS)).expect("read cgroup.procs");
+        assert_eq!(root_procs, "1234");
+    }
 
     #[test]
     fn expand_slice_works() -> Result<()> {
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, path::Path};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use super::controller_type::ControllerType;
 use crate::common::{self, ControllerOpt};
@@ -21,35 +21,43 @@ impl Unified {
         Ok(())
     }
 
+    /// Checks that the key names a file of the form <subsystem>.<name> directly in the
+    /// cgroup directory, so that the unified resources cannot write outside of it
+    fn validate_key(cgroup_file: &str) -> Result<()> {
+        if cgroup_file.contains('/') {
+            bail!("unified resource {:?} must not contain a '/'", cgroup_file);
+        }
+
+        match cgroup_file.split_once('.') {
+            Some((subsystem, name)) if !subsystem.is_empty() && !name.is_empty() => Ok(()),
+            _ => bail!(
+                "unified resource {:?} must have the format <subsystem>.<name>",
+                cgroup_file
+            ),
+        }
+    }
+
     fn apply_impl(
         unified: &HashMap<String, String>,
         cgroup_path: &Path,
         controllers: &[ControllerType],
     ) -> Result<()> {
-        {
-            log::debug!("Apply unified cgroup config");
-            for (cgroup_file, value) in unified {
-                common::write_cgroup_file_str(cgroup_path.join(cgroup_file), value).map_err(
-                    |e| {
-                        let (subsystem, _) = cgroup_file
-                            .split_once(".")
-                            .with_context(|| {
-                                format!("failed to split {} with {}", cgroup_file, ".")
-                            })
-                            .unwrap();
-                        let context = if !controllers.iter().any(|c| c.to_string() == subsystem) {
-                            format!(
-                                "failed to set {} to {}: subsystem {} is not available",
-                                cgroup_file, value, subsystem
-                            )
-                        } else {
-                            format!("failed to set {} to {}: {}", cgroup_file, value, e)
-                        };
-
-                        e.context(context)
-                    },
-                )?;
-            }
+        log::debug!("Apply unified cgroup config");
+        for (cgroup_file, value) in unified {
+            Self::validate_key(cgroup_file)?;
+            common::write_cgroup_file_str(cgroup_path.join(cgroup_file), value).map_err(|e| {
+                let subsystem = cgroup_file.split('.').next().unwrap_or_default();
+                let context = if !controllers.iter().any(|c| c.to_string() == subsystem) {
+                    format!(
+                        "failed to set {} to {}: subsystem {} is not available",
+                        cgroup_file, value, subsystem
+                    )
+                } else {
+                    format!("failed to set {} to {}: {}", cgroup_file, value, e)
+                };
+
+                e.context(context)
+            })?;
         }
 
         Ok(())
@@ -95,6 +103,7 @@ mod tests {
             freezer_state: None,
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpu_idle: None,
         };
 
         // act
@@ -133,6 +142,7 @@ mod tests {
             freezer_state: None,
             oom_score_adj: None,
             disable_oom_killer: false,
+            cpu_idle: None,
         };
 
         // act
@@ -167,6 +177,7 @@ mod tests {
             oom_score_adj: None,
             disable_oom_killer: false,
             freezer_state: None,
+            cpu_idle: None,
         };
 
         // act
@@ -179,4 +190,51 @@ mod tests {
         // assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_validate_key() {
+        assert!(Unified::validate_key("cpu.weight").is_ok());
+        assert!(Unified::validate_key("hugetlb.1GB.max").is_ok());
+        assert!(Unified::validate_key("misc.max").is_ok());
+
+        for key in &[
+            "",
+            "weight",
+            ".weight",
+            "cpu.",
+            "..",
+            "../cpu.weight",
+            "child/cpu.weight",
+            "/sys/fs/cgroup/cpu.weight",
+        ] {
+            assert!(Unified::validate_key(key).is_err(), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn test_set_unified_rejects_path_separator() {
+        let tmp = create_temp_dir("test_set_unified_rejects_path_separator").unwrap();
+        let child = tmp.join("child");
+        fs::create_dir(&child).unwrap();
+        let escaped_path = set_fixture(&tmp, "cpu.weight", "").unwrap();
+
+        let mut unified = HashMap::new();
+        unified.insert("../cpu.weight".to_owned(), "5000".to_owned());
+        let resources = LinuxResourcesBuilder::default()
+            .unified(unified)
+            .build()
+            .unwrap();
+
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            cpu_idle: None,
+        };
+
+        let result = Unified::apply(&controller_opt, &child, vec![ControllerType::Cpu]);
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(escaped_path).unwrap(), "");
+    }
 }
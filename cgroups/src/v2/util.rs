@@ -37,6 +37,7 @@ pub fn get_available_controllers(root_path: &Path) -> Result<Vec<ControllerType>
             "io" => controllers.push(ControllerType::Io),
             "memory" => controllers.push(ControllerType::Memory),
             "pids" => controllers.push(ControllerType::Pids),
+            "rdma" => controllers.push(ControllerType::Rdma),
             tpe => log::warn!("Controller {} is not yet implemented.", tpe),
         }
     }
@@ -0,0 +1,244 @@
+//! Typed access to the annotations of the runtime spec, which youki uses to
+//! toggle behavior per container
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::Spec;
+
+use crate::namespaces::TimeOffset;
+
+/// Use the systemd cgroup manager instead of the cgroupfs one
+pub const SYSTEMD_CGROUP: &str = "org.youki.systemd";
+/// Create the container as rootless container, even if youki runs as root
+pub const ROOTLESS: &str = "org.youki.rootless";
+/// Value for cpu.idle of cgroup v2, which is not yet part of the runtime spec
+pub const CPU_IDLE: &str = "org.youki.cpu.idle";
+/// Create a new time namespace for the container
+pub const TIME_NAMESPACE: &str = "org.youki.timens";
+/// Offset of the monotonic clock in the time namespace as "<secs> <nanosecs>"
+pub const TIME_OFFSET_MONOTONIC: &str = "org.youki.timens.monotonic";
+/// Offset of the boottime clock in the time namespace as "<secs> <nanosecs>"
+pub const TIME_OFFSET_BOOTTIME: &str = "org.youki.timens.boottime";
+
+pub struct Annotations<'a> {
+    annotations: Option<&'a HashMap<String, String>>,
+}
+
+impl<'a> Annotations<'a> {
+    pub fn new(spec: &'a Spec) -> Self {
+        Self {
+            annotations: spec.annotations().as_ref(),
+        }
+    }
+
+    /// Returns the raw value of the annotation
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.annotations
+            .and_then(|annotations| annotations.get(key))
+            .map(String::as_str)
+    }
+
+    /// Returns the value of a boolean annotation or None if the annotation is not
+    /// set. Values other than true and false are an error.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get(key)
+            .map(|value| {
+                value.parse::<bool>().with_context(|| {
+                    format!(
+                        "invalid value {:?} for annotation {}, expected true or false",
+                        value, key
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns if the systemd cgroup manager should be used, None leaves the
+    /// decision to the caller
+    pub fn systemd_cgroup(&self) -> Result<Option<bool>> {
+        self.get_bool(SYSTEMD_CGROUP)
+    }
+
+    /// Returns if the container must be created as rootless container
+    pub fn rootless(&self) -> Result<bool> {
+        Ok(self.get_bool(ROOTLESS)?.unwrap_or(false))
+    }
+
+    /// Returns the value for cpu.idle or None if the annotation is not set
+    pub fn cpu_idle(&self) -> Result<Option<u64>> {
+        self.get(CPU_IDLE)
+            .map(|value| {
+                value.parse::<u64>().with_context(|| {
+                    format!("invalid value {:?} for annotation {}", value, CPU_IDLE)
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns if a new time namespace should be created for the container, which
+    /// is not part of the runtime spec yet
+    pub fn time_namespace(&self) -> Result<bool> {
+        Ok(self.get_bool(TIME_NAMESPACE)?.unwrap_or(false))
+    }
+
+    /// Returns the offset of the monotonic clock in the time namespace
+    pub fn monotonic_offset(&self) -> Result<Option<TimeOffset>> {
+        self.get_time_offset(TIME_OFFSET_MONOTONIC)
+    }
+
+    /// Returns the offset of the boottime clock in the time namespace
+    pub fn boottime_offset(&self) -> Result<Option<TimeOffset>> {
+        self.get_time_offset(TIME_OFFSET_BOOTTIME)
+    }
+
+    fn get_time_offset(&self, key: &str) -> Result<Option<TimeOffset>> {
+        self.get(key)
+            .map(|value| {
+                parse_time_offset(value).with_context(|| {
+                    format!(
+                        "invalid value {:?} for annotation {}, expected <secs> <nanosecs>",
+                        value, key
+                    )
+                })
+            })
+            .transpose()
+    }
+}
+
+fn parse_time_offset(value: &str) -> Result<TimeOffset> {
+    let mut parts = value.split_whitespace();
+    let (secs, nanosecs) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(secs), Some(nanosecs), None) => (secs, nanosecs),
+        _ => bail!("expected two values"),
+    };
+
+    let offset = TimeOffset {
+        secs: secs.parse()?,
+        nanosecs: nanosecs.parse()?,
+    };
+    if offset.nanosecs >= 1_000_000_000 {
+        bail!("nanoseconds must be less than a second");
+    }
+
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(annotations: &[(&str, &str)]) -> Spec {
+        let mut spec = Spec::default();
+        if !annotations.is_empty() {
+            spec.set_annotations(Some(
+                annotations
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ));
+        }
+        spec
+    }
+
+    #[test]
+    fn test_absent_annotations() -> Result<()> {
+        let mut spec = spec(&[]);
+        spec.set_annotations(None);
+        let annotations = Annotations::new(&spec);
+        assert_eq!(annotations.get(SYSTEMD_CGROUP), None);
+        assert_eq!(annotations.systemd_cgroup()?, None);
+        assert!(!annotations.rootless()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_absent_annotation() -> Result<()> {
+        let spec = spec(&[("org.opencontainers.image.os", "linux")]);
+        let annotations = Annotations::new(&spec);
+        assert_eq!(
+            annotations.get("org.opencontainers.image.os"),
+            Some("linux")
+        );
+        assert_eq!(annotations.systemd_cgroup()?, None);
+        assert!(!annotations.rootless()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_present_annotations() -> Result<()> {
+        let spec = spec(&[(SYSTEMD_CGROUP, "false"), (ROOTLESS, "true")]);
+        let annotations = Annotations::new(&spec);
+        assert_eq!(annotations.systemd_cgroup()?, Some(false));
+        assert!(annotations.rootless()?);
+
+        let spec = self::spec(&[(SYSTEMD_CGROUP, "true"), (ROOTLESS, "false")]);
+        let annotations = Annotations::new(&spec);
+        assert_eq!(annotations.systemd_cgroup()?, Some(true));
+        assert!(!annotations.rootless()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpu_idle_annotation() -> Result<()> {
+        assert_eq!(Annotations::new(&spec(&[])).cpu_idle()?, None);
+        assert_eq!(
+            Annotations::new(&spec(&[(CPU_IDLE, "1")])).cpu_idle()?,
+            Some(1)
+        );
+        assert!(Annotations::new(&spec(&[(CPU_IDLE, "idle")]))
+            .cpu_idle()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_namespace_annotations() -> Result<()> {
+        let empty = spec(&[]);
+        let annotations = Annotations::new(&empty);
+        assert!(!annotations.time_namespace()?);
+        assert_eq!(annotations.monotonic_offset()?, None);
+        assert_eq!(annotations.boottime_offset()?, None);
+
+        let timens = spec(&[
+            (TIME_NAMESPACE, "true"),
+            (TIME_OFFSET_MONOTONIC, "-3600 0"),
+            (TIME_OFFSET_BOOTTIME, "86400 500"),
+        ]);
+        let annotations = Annotations::new(&timens);
+        assert!(annotations.time_namespace()?);
+        assert_eq!(
+            annotations.monotonic_offset()?,
+            Some(TimeOffset {
+                secs: -3600,
+                nanosecs: 0
+            })
+        );
+        assert_eq!(
+            annotations.boottime_offset()?,
+            Some(TimeOffset {
+                secs: 86400,
+                nanosecs: 500
+            })
+        );
+
+        for value in &["", "1", "1 2 3", "a 0", "0 -1", "0 1000000000"] {
+            let spec = spec(&[(TIME_OFFSET_MONOTONIC, *value)]);
+            assert!(
+                Annotations::new(&spec).monotonic_offset().is_err(),
+                "{:?}",
+                value
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_annotations() {
+        for value in &["", "yes", "1", "TRUE", " true"] {
+            let spec = spec(&[(SYSTEMD_CGROUP, value), (ROOTLESS, value)]);
+            let annotations = Annotations::new(&spec);
+            assert!(annotations.systemd_cgroup().is_err(), "{:?}", value);
+            assert!(annotations.rootless().is_err(), "{:?}", value);
+        }
+    }
+}
@@ -1,37 +1,92 @@
 use anyhow::{Context, Result};
 use std::{
     fs::{self},
-    path::Path,
+    io::ErrorKind,
+    path::{Path, PathBuf},
 };
 
 use crate::utils;
 
 const ENABLED_PARAMETER_PATH: &str = "/sys/module/apparmor/parameters/enabled";
+const PROC_ATTR_PATH: &str = "/proc/self/attr";
 
 /// Checks if AppArmor has been enabled on the system.
 pub fn is_enabled() -> Result<bool> {
-    let aa_enabled = fs::read_to_string(ENABLED_PARAMETER_PATH)
-        .with_context(|| format!("could not read {}", ENABLED_PARAMETER_PATH))?;
-    Ok(aa_enabled.starts_with('Y'))
+    is_enabled_at(Path::new(ENABLED_PARAMETER_PATH))
 }
 
-/// Applies an AppArmor profile to the container.
+fn is_enabled_at(path: &Path) -> Result<bool> {
+    match fs::read_to_string(path) {
+        Ok(aa_enabled) => Ok(aa_enabled.starts_with('Y')),
+        // the parameter only exists if the apparmor module is loaded
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err).with_context(|| format!("could not read {}", path.display())),
+    }
+}
+
+/// Applies an AppArmor profile to the container. The profile is skipped with a
+/// warning if AppArmor is not enabled on the system.
 pub fn apply_profile(profile: &str) -> Result<()> {
     if profile.is_empty() {
         return Ok(());
     }
 
-    // Try the module specific subdirectory. This is the recommended way to configure
-    // LSMs since Linux 5.1. AppArmor has such a directory since Linux 5.8.
-    if activate_profile(Path::new("/proc/self/attr/apparmor/exec"), profile).is_ok() {
+    if !is_enabled()? {
+        log::warn!(
+            "apparmor is not enabled on this system, skipping profile {}",
+            profile
+        );
         return Ok(());
     }
 
-    // try the legacy interface
-    activate_profile(Path::new("/proc/self/attr/exec"), profile)
+    activate_profile(&exec_attr_path(Path::new(PROC_ATTR_PATH)), profile)
+}
+
+// Use the module specific subdirectory if it exists. This is the recommended way to
+// configure LSMs since Linux 5.1. AppArmor has such a directory since Linux 5.8.
+// Otherwise fall back to the legacy interface.
+fn exec_attr_path(attr_dir: &Path) -> PathBuf {
+    let apparmor_exec = attr_dir.join("apparmor/exec");
+    if apparmor_exec.exists() {
+        return apparmor_exec;
+    }
+
+    attr_dir.join("exec")
 }
 
 fn activate_profile(path: &Path, profile: &str) -> Result<()> {
     utils::ensure_procfs(path)?;
     utils::write_file(path, format!("exec {}", profile))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_exec_attr_path() -> Result<()> {
+        let tmp = create_temp_dir("test_apparmor_exec_attr_path")?;
+        fs::write(tmp.join("exec"), "")?;
+        assert_eq!(exec_attr_path(&tmp), tmp.join("exec"));
+
+        fs::create_dir(tmp.join("apparmor"))?;
+        fs::write(tmp.join("apparmor/exec"), "")?;
+        assert_eq!(exec_attr_path(&tmp), tmp.join("apparmor/exec"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_enabled_at() -> Result<()> {
+        let tmp = create_temp_dir("test_apparmor_is_enabled_at")?;
+        let enabled = tmp.join("enabled");
+        assert!(!is_enabled_at(&enabled)?);
+
+        fs::write(&enabled, "N\n")?;
+        assert!(!is_enabled_at(&enabled)?);
+
+        fs::write(&enabled, "Y\n")?;
+        assert!(is_enabled_at(&enabled)?);
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use crate::syscall::Syscall;
 use caps::Capability as CapsCapability;
 use caps::*;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use oci_spec::runtime::{Capabilities, Capability as SpecCapability, LinuxCapabilities};
 
 /// Converts a list of capability types to capabilities has set
@@ -130,8 +130,39 @@ pub fn reset_effective<S: Syscall + ?Sized>(syscall: &S) -> Result<()> {
     Ok(())
 }
 
+/// Ambient capabilities can only be raised if they are both permitted and inheritable,
+/// otherwise the kernel rejects them
+fn validate_ambient(cs: &LinuxCapabilities) -> Result<()> {
+    let ambient = match cs.ambient() {
+        Some(ambient) => ambient,
+        None => return Ok(()),
+    };
+
+    let mut missing: Vec<_> = ambient
+        .iter()
+        .filter(|cap| {
+            let permitted = cs.permitted().as_ref().map_or(true, |p| p.contains(*cap));
+            let inheritable = cs.inheritable().as_ref().map_or(true, |i| i.contains(*cap));
+            !(permitted && inheritable)
+        })
+        .map(|cap| format!("{:?}", cap.to_cap()))
+        .collect();
+
+    if !missing.is_empty() {
+        missing.sort();
+        bail!(
+            "ambient capabilities must be permitted and inheritable: {}",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
 /// Drop any extra granted capabilities, and reset to defaults which are in oci specification
 pub fn drop_privileges<S: Syscall + ?Sized>(cs: &LinuxCapabilities, syscall: &S) -> Result<()> {
+    validate_ambient(cs)?;
+
     log::debug!("dropping bounding capabilities to {:?}", cs.bounding());
     if let Some(bounding) = cs.bounding() {
         syscall.set_capability(CapSet::Bounding, &to_set(bounding))?;
@@ -662,4 +693,38 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_validate_ambient() {
+        let caps = |c: &[SpecCapability]| c.iter().cloned().collect::<Capabilities>();
+
+        let subset = LinuxCapabilitiesBuilder::default()
+            .permitted(caps(&[SpecCapability::Kill, SpecCapability::NetRaw]))
+            .inheritable(caps(&[SpecCapability::Kill, SpecCapability::Chown]))
+            .ambient(caps(&[SpecCapability::Kill]))
+            .build()
+            .unwrap();
+        assert!(validate_ambient(&subset).is_ok());
+
+        let not_inheritable = LinuxCapabilitiesBuilder::default()
+            .permitted(caps(&[SpecCapability::Kill, SpecCapability::NetRaw]))
+            .inheritable(caps(&[SpecCapability::Kill]))
+            .ambient(caps(&[SpecCapability::Kill, SpecCapability::NetRaw]))
+            .build()
+            .unwrap();
+        let err = validate_ambient(&not_inheritable).unwrap_err();
+        assert!(err.to_string().contains("CAP_NET_RAW"), "{}", err);
+
+        let not_permitted = LinuxCapabilitiesBuilder::default()
+            .permitted(caps(&[SpecCapability::Kill]))
+            .inheritable(caps(&[SpecCapability::Kill, SpecCapability::Chown]))
+            .ambient(caps(&[SpecCapability::Chown]))
+            .build()
+            .unwrap();
+        assert!(validate_ambient(&not_permitted).is_err());
+
+        let test_command = TestHelperSyscall::default();
+        assert!(drop_privileges(&not_permitted, &test_command).is_err());
+        assert!(test_command.get_set_capability_args().is_empty());
+    }
 }
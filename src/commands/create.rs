@@ -1,7 +1,7 @@
 //! Handles the creation of a new container
 use anyhow::Result;
 use clap::Clap;
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use crate::{container::builder::ContainerBuilder, syscall::syscall::create_syscall};
 
@@ -21,6 +21,12 @@ pub struct Create {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     preserve_fds: i32,
+    /// Fail instead of warning if the runtime spec version is not supported
+    #[clap(long)]
+    strict: bool,
+    /// Seconds to wait for the uid/gid mappings of the user namespace to be written
+    #[clap(long, default_value = "5")]
+    mapping_timeout: u64,
     /// name of the container instance to be started
     #[clap(forbid_empty_values = true, required = true)]
     pub container_id: String,
@@ -32,15 +38,18 @@ pub struct Create {
 // it is running, it is just another process, and has attributes such as pid, file descriptors, etc.
 // associated with it like any other process.
 impl Create {
-    pub fn exec(&self, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+    pub fn exec(&self, root_path: PathBuf, systemd_cgroup: bool, use_cgroups: bool) -> Result<()> {
         let syscall = create_syscall();
         ContainerBuilder::new(self.container_id.clone(), syscall.as_ref())
             .with_pid_file(self.pid_file.as_ref())
             .with_console_socket(self.console_socket.as_ref())
             .with_root_path(root_path)
             .with_preserved_fds(self.preserve_fds)
+            .with_cgroups(use_cgroups)
+            .with_mapping_timeout(Duration::from_secs(self.mapping_timeout))
             .as_init(&self.bundle)
             .with_systemd(systemd_cgroup)
+            .with_strict_version(self.strict)
             .build()?;
 
         Ok(())
@@ -39,12 +39,13 @@ pub struct Exec {
 }
 
 impl Exec {
-    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+    pub fn exec(&self, root_path: PathBuf, use_cgroups: bool) -> Result<()> {
         let syscall = create_syscall();
         ContainerBuilder::new(self.container_id.clone(), syscall.as_ref())
             .with_root_path(root_path)
             .with_console_socket(self.console_socket.as_ref())
             .with_pid_file(self.pid_file.as_ref())
+            .with_cgroups(use_cgroups)
             .as_tenant()
             .with_cwd(self.cwd.as_ref())
             .with_env(self.env.clone().into_iter().collect())
@@ -0,0 +1,334 @@
+//! Contains functionality of features command, which reports the features supported
+//! by youki and the host in the format defined by the runtime spec
+use std::{collections::BTreeMap, fs};
+
+use anyhow::{Context, Result};
+use clap::Clap;
+use serde::Serialize;
+
+use cgroups::common::CgroupSetup;
+
+use crate::{apparmor, selinux};
+
+/// Show the features supported by youki and the host
+#[derive(Clap, Debug)]
+pub struct Features {}
+
+impl Features {
+    pub fn exec(&self) -> Result<()> {
+        let host = HostFeatures::detect()?;
+        let features = FeaturesInfo::new(&host);
+        println!("{}", serde_json::to_string_pretty(&features)?);
+        Ok(())
+    }
+}
+
+const OCI_VERSION_MIN: &str = "1.0.0";
+const OCI_VERSION_MAX: &str = "1.0.2";
+
+const HOOKS: &[&str] = &[
+    "prestart",
+    "createRuntime",
+    "createContainer",
+    "startContainer",
+    "poststart",
+    "poststop",
+];
+
+// mount options recognized by rootfs::utils::parse_mount, all other options are passed
+// as data to the filesystem
+const MOUNT_OPTIONS: &[&str] = &[
+    "async",
+    "atime",
+    "bind",
+    "defaults",
+    "dev",
+    "diratime",
+    "dirsync",
+    "exec",
+    "idmap",
+    "mand",
+    "noatime",
+    "nodev",
+    "nodiratime",
+    "noexec",
+    "nomand",
+    "norelatime",
+    "nostrictatime",
+    "nosuid",
+    "private",
+    "rbind",
+    "relatime",
+    "remount",
+    "ro",
+    "rprivate",
+    "rshared",
+    "rslave",
+    "runbindable",
+    "rw",
+    "shared",
+    "slave",
+    "strictatime",
+    "suid",
+    "sync",
+    "unbindable",
+];
+
+const SECCOMP_ACTIONS: &[&str] = &[
+    "SCMP_ACT_ALLOW",
+    "SCMP_ACT_ERRNO",
+    "SCMP_ACT_KILL",
+    "SCMP_ACT_KILL_PROCESS",
+    "SCMP_ACT_LOG",
+    "SCMP_ACT_NOTIFY",
+    "SCMP_ACT_TRACE",
+    "SCMP_ACT_TRAP",
+];
+
+const SECCOMP_OPERATORS: &[&str] = &[
+    "SCMP_CMP_EQ",
+    "SCMP_CMP_GE",
+    "SCMP_CMP_GT",
+    "SCMP_CMP_LE",
+    "SCMP_CMP_LT",
+    "SCMP_CMP_MASKED_EQ",
+    "SCMP_CMP_NE",
+];
+
+const SECCOMP_ARCHS: &[&str] = &[
+    "SCMP_ARCH_AARCH64",
+    "SCMP_ARCH_ARM",
+    "SCMP_ARCH_MIPS",
+    "SCMP_ARCH_MIPS64",
+    "SCMP_ARCH_MIPS64N32",
+    "SCMP_ARCH_MIPSEL",
+    "SCMP_ARCH_MIPSEL64",
+    "SCMP_ARCH_MIPSEL64N32",
+    "SCMP_ARCH_PPC",
+    "SCMP_ARCH_PPC64",
+    "SCMP_ARCH_PPC64LE",
+    "SCMP_ARCH_S390",
+    "SCMP_ARCH_S390X",
+    "SCMP_ARCH_X32",
+    "SCMP_ARCH_X86",
+    "SCMP_ARCH_X86_64",
+];
+
+/// Features of the host which limit what youki is able to do
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostFeatures {
+    pub cgroup_setup: Option<CgroupSetup>,
+    pub namespaces: Vec<String>,
+    pub seccomp: bool,
+    pub apparmor: bool,
+    pub selinux: bool,
+}
+
+impl HostFeatures {
+    pub fn detect() -> Result<Self> {
+        let cgroup_setup = cgroups::common::get_cgroup_setup()
+            .map_err(|e| log::warn!("failed to detect cgroup setup: {:?}", e))
+            .ok();
+
+        let namespaces = fs::read_dir("/proc/self/ns")
+            .context("failed to read /proc/self/ns")?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok());
+        let namespaces = spec_namespaces(namespaces);
+
+        let seccomp = fs::read_to_string("/proc/self/status")
+            .map(|status| status.lines().any(|l| l.starts_with("Seccomp:")))
+            .unwrap_or(false);
+
+        Ok(Self {
+            cgroup_setup,
+            namespaces,
+            seccomp,
+            apparmor: apparmor::is_enabled().unwrap_or(false),
+            selinux: selinux::is_enabled(),
+        })
+    }
+}
+
+/// Maps the entries of /proc/<pid>/ns to the namespace types of the runtime spec
+fn spec_namespaces<I: Iterator<Item = String>>(entries: I) -> Vec<String> {
+    let mut namespaces: Vec<String> = entries
+        .filter_map(|entry| match entry.as_str() {
+            "cgroup" | "ipc" | "pid" | "user" | "uts" => Some(entry),
+            "mnt" => Some("mount".to_owned()),
+            "net" => Some("network".to_owned()),
+            _ => None,
+        })
+        .collect();
+    namespaces.sort();
+    namespaces
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FeaturesInfo {
+    oci_version_min: String,
+    oci_version_max: String,
+    hooks: Vec<String>,
+    mount_options: Vec<String>,
+    linux: LinuxFeatures,
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LinuxFeatures {
+    namespaces: Vec<String>,
+    capabilities: Vec<String>,
+    cgroup: CgroupFeatures,
+    seccomp: SeccompFeatures,
+    apparmor: SecurityModuleFeatures,
+    selinux: SecurityModuleFeatures,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CgroupFeatures {
+    v1: bool,
+    v2: bool,
+    systemd: bool,
+    systemd_user: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SeccompFeatures {
+    enabled: bool,
+    actions: Vec<String>,
+    operators: Vec<String>,
+    archs: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SecurityModuleFeatures {
+    enabled: bool,
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+impl FeaturesInfo {
+    pub fn new(host: &HostFeatures) -> Self {
+        let mut capabilities: Vec<String> = caps::all().iter().map(|c| c.to_string()).collect();
+        capabilities.sort();
+
+        let (v1, v2) = match host.cgroup_setup {
+            Some(CgroupSetup::Legacy) => (true, false),
+            Some(CgroupSetup::Hybrid) => (true, true),
+            Some(CgroupSetup::Unified) => (false, true),
+            None => (false, false),
+        };
+        let systemd = cfg!(feature = "systemd_cgroups");
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert(
+            "org.youki.version".to_owned(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+        );
+
+        Self {
+            oci_version_min: OCI_VERSION_MIN.to_owned(),
+            oci_version_max: OCI_VERSION_MAX.to_owned(),
+            hooks: to_strings(HOOKS),
+            mount_options: to_strings(MOUNT_OPTIONS),
+            linux: LinuxFeatures {
+                namespaces: host.namespaces.clone(),
+                capabilities,
+                cgroup: CgroupFeatures {
+                    v1,
+                    v2,
+                    systemd,
+                    systemd_user: systemd,
+                },
+                seccomp: SeccompFeatures {
+                    enabled: host.seccomp,
+                    actions: to_strings(SECCOMP_ACTIONS),
+                    operators: to_strings(SECCOMP_OPERATORS),
+                    archs: to_strings(SECCOMP_ARCHS),
+                },
+                apparmor: SecurityModuleFeatures {
+                    enabled: host.apparmor,
+                },
+                selinux: SecurityModuleFeatures {
+                    enabled: host.selinux,
+                },
+            },
+            annotations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_namespaces() {
+        let entries = [
+            "cgroup",
+            "ipc",
+            "mnt",
+            "net",
+            "pid",
+            "pid_for_children",
+            "time",
+            "time_for_children",
+            "user",
+            "uts",
+        ];
+        assert_eq!(
+            spec_namespaces(entries.iter().map(|e| e.to_string())),
+            vec!["cgroup", "ipc", "mount", "network", "pid", "user", "uts"]
+        );
+    }
+
+    #[test]
+    fn test_features_json() -> Result<()> {
+        let host = HostFeatures {
+            cgroup_setup: Some(CgroupSetup::Unified),
+            namespaces: vec!["mount".to_owned(), "pid".to_owned()],
+            seccomp: true,
+            apparmor: false,
+            selinux: true,
+        };
+
+        let json = serde_json::to_value(&FeaturesInfo::new(&host))?;
+        assert_eq!(json["ociVersionMin"], "1.0.0");
+        assert_eq!(json["ociVersionMax"], "1.0.2");
+        assert_eq!(json["hooks"][0], "prestart");
+        assert!(json["mountOptions"]
+            .as_array()
+            .unwrap()
+            .contains(&"nosuid".into()));
+
+        let linux = &json["linux"];
+        assert_eq!(linux["namespaces"], serde_json::json!(["mount", "pid"]));
+        assert!(linux["capabilities"]
+            .as_array()
+            .unwrap()
+            .contains(&"CAP_SYS_ADMIN".into()));
+        assert_eq!(linux["cgroup"]["v1"], false);
+        assert_eq!(linux["cgroup"]["v2"], true);
+        assert!(linux["cgroup"]["systemdUser"].is_boolean());
+        assert_eq!(linux["seccomp"]["enabled"], true);
+        assert!(linux["seccomp"]["actions"]
+            .as_array()
+            .unwrap()
+            .contains(&"SCMP_ACT_NOTIFY".into()));
+        assert_eq!(linux["seccomp"]["operators"].as_array().unwrap().len(), 7);
+        assert_eq!(linux["apparmor"]["enabled"], false);
+        assert_eq!(linux["selinux"]["enabled"], true);
+        assert_eq!(
+            json["annotations"]["org.youki.version"],
+            env!("CARGO_PKG_VERSION")
+        );
+        Ok(())
+    }
+}
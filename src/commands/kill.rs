@@ -12,12 +12,15 @@ pub struct Kill {
     #[clap(forbid_empty_values = true, required = true)]
     container_id: String,
     signal: String,
+    /// Send the signal to all processes in the container
+    #[clap(short, long)]
+    all: bool,
 }
 
 impl Kill {
     pub fn exec(&self, root_path: PathBuf) -> Result<()> {
         let mut container = load_container(root_path, &self.container_id)?;
         let signal: Signal = self.signal.as_str().try_into()?;
-        container.kill(signal)
+        container.kill(signal, self.all)
     }
 }
@@ -2,65 +2,184 @@
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use chrono::{DateTime, Local};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, Utc};
 use clap::Clap;
+use serde::Serialize;
 use tabwriter::TabWriter;
 
-use crate::container::{state::State, Container};
+use crate::container::{state::State, Container, ContainerStatus};
 
 /// List created containers
 #[derive(Clap, Debug)]
-pub struct List {}
+pub struct List {
+    /// format to display containers: table or json (default: "table")
+    #[clap(short, long, default_value = "table")]
+    format: String,
+}
+
+/// Information about a single container as printed by the list command
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ContainerInfo {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<i32>,
+    status: ContainerStatus,
+    bundle: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<DateTime<Utc>>,
+    owner: String,
+}
+
+impl From<&Container> for ContainerInfo {
+    fn from(container: &Container) -> Self {
+        Self {
+            id: container.id().to_owned(),
+            pid: container.pid().map(|pid| pid.as_raw()),
+            status: container.status(),
+            bundle: container.bundle().clone(),
+            created: container.created(),
+            owner: container
+                .creator()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+}
 
 impl List {
     /// lists all existing containers
     pub fn exec(&self, root_path: PathBuf) -> Result<()> {
-        let root_path = fs::canonicalize(root_path)?;
-        let mut content = String::new();
-        // all containers' data is stored in their respective dir in root directory
-        // so we iterate through each and print the various info
-        for container_dir in fs::read_dir(root_path)? {
-            let container_dir = container_dir?.path();
-            let state_file = State::file_path(&container_dir);
-            if !state_file.exists() {
+        let containers: Vec<ContainerInfo> = load_containers(&root_path)?
+            .iter()
+            .map(ContainerInfo::from)
+            .collect();
+
+        match self.format.as_str() {
+            "table" => print_table(&containers),
+            "json" => {
+                println!("{}", serde_json::to_string(&containers)?);
+                Ok(())
+            }
+            _ => bail!("unknown format {}, expected table or json", self.format),
+        }
+    }
+}
+
+/// Loads all containers in the root directory ordered by their creation time. Containers
+/// whose state cannot be loaded are skipped, so a single corrupted container does not
+/// prevent listing the others.
+fn load_containers(root_path: &Path) -> Result<Vec<Container>> {
+    let root_path = fs::canonicalize(root_path)
+        .with_context(|| format!("failed to canonicalize {:?}", root_path))?;
+    let mut containers = Vec::new();
+    // all containers' data is stored in their respective dir in root directory
+    for container_dir in fs::read_dir(&root_path)? {
+        let container_dir = match container_dir {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                log::warn!("failed to read entry of {:?}: {}", root_path, e);
                 continue;
             }
+        };
+
+        let state_file = State::file_path(&container_dir);
+        if !state_file.exists() {
+            continue;
+        }
 
-            let container = Container::load(container_dir)?;
-            let pid = if let Some(pid) = container.pid() {
-                pid.to_string()
-            } else {
-                "".to_owned()
-            };
+        match Container::load(container_dir.clone()) {
+            Ok(container) => containers.push(container),
+            Err(e) => log::warn!("skipping container in {:?}: {:?}", container_dir, e),
+        }
+    }
 
-            let user_name = container.creator().unwrap_or_default();
+    // containers without creation time are listed first, ties are ordered by id
+    containers.sort_by(|a, b| {
+        a.created()
+            .cmp(&b.created())
+            .then_with(|| a.id().cmp(b.id()))
+    });
+    Ok(containers)
+}
 
-            let created = if let Some(utc) = container.created() {
+fn print_table(containers: &[ContainerInfo]) -> Result<()> {
+    let mut tab_writer = TabWriter::new(io::stdout());
+    writeln!(&mut tab_writer, "ID\tPID\tSTATUS\tBUNDLE\tCREATED\tCREATOR")?;
+    for container in containers {
+        let pid = container.pid.map(|pid| pid.to_string()).unwrap_or_default();
+        let created = container
+            .created
+            .map(|utc| {
                 let local: DateTime<Local> = DateTime::from(utc);
                 local.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
-            } else {
-                "".to_owned()
-            };
-
-            content.push_str(&format!(
-                "{}\t{}\t{}\t{}\t{}\t{}\n",
-                container.id(),
-                pid,
-                container.status(),
-                container.bundle().to_string_lossy(),
-                created,
-                user_name.to_string_lossy()
-            ));
-        }
+            })
+            .unwrap_or_default();
+
+        writeln!(
+            &mut tab_writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            container.id,
+            pid,
+            container.status,
+            container.bundle.to_string_lossy(),
+            created,
+            container.owner
+        )?;
+    }
+    tab_writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use chrono::TimeZone;
+
+    fn save_state(root: &Path, id: &str, created: DateTime<Utc>) -> Result<()> {
+        let container_root = root.join(id);
+        fs::create_dir(&container_root)?;
+        let mut state = State::new(
+            id,
+            ContainerStatus::Stopped,
+            None,
+            PathBuf::from(format!("/run/bundle/{}", id)),
+        );
+        state.created = Some(created);
+        state.save(&container_root)
+    }
+
+    #[test]
+    fn test_list_ordered_by_creation_time() -> Result<()> {
+        let root = create_temp_dir("test_list_ordered_by_creation_time")?;
+        save_state(&root, "aaa", Utc.ymd(2021, 9, 2).and_hms(12, 0, 0))?;
+        save_state(&root, "bbb", Utc.ymd(2021, 9, 1).and_hms(12, 0, 0))?;
+        // directories without state file and corrupted state files are skipped
+        fs::create_dir(root.join("no-state"))?;
+        fs::create_dir(root.join("corrupted"))?;
+        fs::write(State::file_path(&root.join("corrupted")), "{\"id\":")?;
+
+        let containers: Vec<ContainerInfo> = load_containers(&root)?
+            .iter()
+            .map(ContainerInfo::from)
+            .collect();
 
-        let mut tab_writer = TabWriter::new(io::stdout());
-        writeln!(&mut tab_writer, "ID\tPID\tSTATUS\tBUNDLE\tCREATED\tCREATOR")?;
-        write!(&mut tab_writer, "{}", content)?;
-        tab_writer.flush()?;
+        assert_eq!(containers.len(), 2);
+        assert_eq!(containers[0].id, "bbb");
+        assert_eq!(containers[0].bundle, PathBuf::from("/run/bundle/bbb"));
+        assert_eq!(containers[1].id, "aaa");
+        assert_eq!(containers[1].status, ContainerStatus::Stopped);
 
+        let json = serde_json::to_value(&containers)?;
+        assert_eq!(json[0]["id"], "bbb");
+        assert_eq!(json[0]["status"], "stopped");
+        assert_eq!(json[1]["created"], "2021-09-02T12:00:00Z");
         Ok(())
     }
 }
@@ -1,6 +1,5 @@
-use crate::{container::Container, utils};
-use anyhow::{bail, Context, Result};
-use cgroups;
+use crate::container::Container;
+use anyhow::{bail, Result};
 use clap::{self, Clap};
 use std::{path::PathBuf, process::Command};
 
@@ -24,21 +23,7 @@ impl Ps {
         }
         let container = Container::load(container_root)?;
         if container.root.exists() {
-            let config_absolute_path = container.root.join("config.json");
-            log::debug!("load spec from {:?}", config_absolute_path);
-            let spec = oci_spec::runtime::Spec::load(config_absolute_path)?;
-            log::debug!("spec: {:?}", spec);
-            let cgroups_path = utils::get_cgroup_path(
-                spec.linux()
-                    .as_ref()
-                    .context("no linux in spec")?
-                    .cgroups_path(),
-                container.id(),
-            );
-            let systemd_cgroup = container
-                .systemd()
-                .context("could not determine cgroup manager")?;
-            let cmanager = cgroups::common::create_cgroup_manager(cgroups_path, systemd_cgroup)?;
+            let cmanager = container.cgroup_manager()?;
             let pids: Vec<i32> = cmanager
                 .get_all_pids()?
                 .iter()
@@ -48,6 +33,11 @@ impl Ps {
             if self.format == "json" {
                 println!("{}", serde_json::to_string(&pids)?);
             } else if self.format == "table" {
+                if pids.is_empty() {
+                    log::debug!("container {} has no running processes", container.id());
+                    return Ok(());
+                }
+
                 let default_ps_options = vec![String::from("-ef")];
                 let ps_options = if self.ps_options.is_empty() {
                     &default_ps_options
@@ -73,6 +63,8 @@ impl Ps {
                         }
                     }
                 }
+            } else {
+                bail!("unknown format {}, expected table or json", self.format);
             }
         }
         Ok(())
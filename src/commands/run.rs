@@ -1,9 +1,18 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use crate::container::builder::ContainerBuilder;
 use crate::syscall::syscall::create_syscall;
+use crate::utils;
 use anyhow::{Context, Result};
 use clap::Clap;
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{self, SigSet, Signal},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
 
 /// Create a container and immediately start it
 #[derive(Clap, Debug)]
@@ -21,25 +30,145 @@ pub struct Run {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     preserve_fds: i32,
+    /// Fail instead of warning if the runtime spec version is not supported
+    #[clap(long)]
+    strict: bool,
+    /// Seconds to wait for the uid/gid mappings of the user namespace to be written
+    #[clap(long, default_value = "5")]
+    mapping_timeout: u64,
+    /// Return after the container has been started instead of waiting for it to exit
+    #[clap(short, long)]
+    detach: bool,
     /// name of the container instance to be started
     #[clap(forbid_empty_values = true, required = true)]
     pub container_id: String,
 }
 
 impl Run {
-    pub fn exec(&self, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+    pub fn exec(&self, root_path: PathBuf, systemd_cgroup: bool, use_cgroups: bool) -> Result<()> {
+        if !self.detach {
+            // The container init process is forked by the intermediate process, which
+            // exits after the container has been created. Become the subreaper, so
+            // that the init process is reparented to us and can be waited for.
+            set_child_subreaper().context("failed to become child subreaper")?;
+        }
+
         let syscall = create_syscall();
         let mut container = ContainerBuilder::new(self.container_id.clone(), syscall.as_ref())
             .with_pid_file(self.pid_file.as_ref())
             .with_console_socket(self.console_socket.as_ref())
             .with_root_path(root_path)
             .with_preserved_fds(self.preserve_fds)
+            .with_cgroups(use_cgroups)
+            .with_mapping_timeout(Duration::from_secs(self.mapping_timeout))
             .as_init(&self.bundle)
             .with_systemd(systemd_cgroup)
+            .with_strict_version(self.strict)
             .build()?;
 
+        // The signals have to be blocked after the container process has been
+        // forked, as the signal mask is inherited, but before it is started, so
+        // that no signal or exit is missed.
+        let signals = forwarded_signals();
+        if !self.detach {
+            signals
+                .thread_block()
+                .context("failed to block forwarded signals")?;
+        }
+
         container
             .start()
-            .with_context(|| format!("failed to start container {}", self.container_id))
+            .with_context(|| format!("failed to start container {}", self.container_id))?;
+
+        if self.detach {
+            return Ok(());
+        }
+
+        let init_pid = container
+            .pid()
+            .context("container state does not contain the init pid")?;
+        let exit_code = wait_for_init(init_pid, &signals)?;
+        container
+            .delete(true)
+            .with_context(|| format!("failed to delete container {}", self.container_id))?;
+        std::process::exit(exit_code)
+    }
+}
+
+fn set_child_subreaper() -> Result<()> {
+    let res = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    Errno::result(res)?;
+    Ok(())
+}
+
+/// Signals that are forwarded to the container, and SIGCHLD which is used to
+/// notice that the container has exited
+fn forwarded_signals() -> SigSet {
+    let mut signals = SigSet::empty();
+    for signal in [
+        Signal::SIGCHLD,
+        Signal::SIGHUP,
+        Signal::SIGINT,
+        Signal::SIGQUIT,
+        Signal::SIGTERM,
+        Signal::SIGUSR1,
+        Signal::SIGUSR2,
+        Signal::SIGWINCH,
+    ] {
+        signals.add(signal);
+    }
+    signals
+}
+
+/// Forwards the received signals to the init process until it exits and returns
+/// its exit code
+fn wait_for_init(init_pid: Pid, signals: &SigSet) -> Result<i32> {
+    loop {
+        if let Some(exit_code) = reap(init_pid)? {
+            return Ok(exit_code);
+        }
+
+        match signals.wait().context("failed to wait for signals")? {
+            Signal::SIGCHLD => {}
+            signal => {
+                log::debug!("forwarding signal {} to {}", signal, init_pid);
+                match signal::kill(init_pid, signal) {
+                    Ok(_) | Err(Errno::ESRCH) => {}
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("failed to forward signal {}", signal))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reaps all exited children, which may include orphans reparented to us, and
+/// returns the exit code of the init process if it was among them
+fn reap(init_pid: Pid) -> Result<Option<i32>> {
+    loop {
+        match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => return Ok(None),
+            Ok(status) if status.pid() == Some(init_pid) => return Ok(utils::exit_code(status)),
+            Ok(_) => {}
+            Err(err) => return Err(err).context("failed to wait for children"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_signals() {
+        let signals = forwarded_signals();
+        for signal in [Signal::SIGCHLD, Signal::SIGINT, Signal::SIGTERM] {
+            assert!(signals.contains(signal), "{} is not forwarded", signal);
+        }
+        // these cannot be caught, so they are never forwarded
+        assert!(!signals.contains(Signal::SIGKILL));
+        assert!(!signals.contains(Signal::SIGSTOP));
     }
 }
@@ -1,13 +1,12 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Clap;
 use nix;
-use oci_spec::runtime::Mount;
 use oci_spec::runtime::{
-    LinuxBuilder, LinuxIdMappingBuilder, LinuxNamespace, LinuxNamespaceBuilder, LinuxNamespaceType,
-    Spec,
+    LinuxIdMappingBuilder, LinuxNamespace, LinuxNamespaceBuilder, LinuxNamespaceType, Spec,
 };
+use oci_spec::runtime::{Mount, MountBuilder};
 use serde_json::to_writer_pretty;
-use std::fs::File;
+use std::fs::OpenOptions;
 use std::path::Path;
 use std::path::PathBuf;
 /// Command generates a config.json
@@ -16,6 +15,9 @@ pub struct SpecJson {
     /// Generate a configuration for a rootless container
     #[clap(long)]
     pub rootless: bool,
+    /// Path to the bundle directory the config.json is written to
+    #[clap(short, long, default_value = ".")]
+    pub bundle: PathBuf,
 }
 
 pub fn get_default() -> Result<Spec> {
@@ -41,49 +43,56 @@ pub fn get_rootless() -> Result<Spec> {
     let uid = nix::unistd::geteuid().as_raw();
     let gid = nix::unistd::getegid().as_raw();
 
-    let linux = LinuxBuilder::default()
-        .namespaces(namespaces)
-        .uid_mappings(vec![LinuxIdMappingBuilder::default()
+    let mut spec = get_default()?;
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    linux
+        .set_namespaces(Some(namespaces))
+        .set_uid_mappings(Some(vec![LinuxIdMappingBuilder::default()
             .host_id(uid)
             .container_id(0_u32)
             .size(1_u32)
-            .build()?])
-        .gid_mappings(vec![LinuxIdMappingBuilder::default()
+            .build()?]))
+        .set_gid_mappings(Some(vec![LinuxIdMappingBuilder::default()
             .host_id(gid)
             .container_id(0_u32)
             .size(1_u32)
-            .build()?])
-        .build()?;
-
-    // Prepare the mounts
+            .build()?]))
+        // an unprivileged user cannot set up cgroups for the container
+        .set_resources(None);
 
-    let mut mounts: Vec<Mount> = oci_spec::runtime::get_default_mounts();
+    // Prepare the mounts. Mounting sysfs requires privileges, so it is bind mounted from the
+    // host instead and all other mounts below /sys are dropped.
+    let mut mounts: Vec<Mount> = oci_spec::runtime::get_default_mounts()
+        .into_iter()
+        .filter(|mount| !mount.destination().starts_with("/sys"))
+        .collect();
     for mount in &mut mounts {
-        if mount.destination().eq(Path::new("/sys")) {
-            mount
-                .set_source(Some(PathBuf::from("/sys")))
-                .set_typ(Some(String::from("none")))
-                .set_options(Some(vec![
-                    "rbind".to_string(),
-                    "nosuid".to_string(),
-                    "noexec".to_string(),
-                    "nodev".to_string(),
-                    "ro".to_string(),
-                ]));
-        } else {
-            let options: Vec<String> = mount
-                .options()
-                .as_ref()
-                .unwrap_or(&vec![])
-                .iter()
-                .filter(|&o| !o.starts_with("gid=") && !o.starts_with("uid="))
-                .map(|o| o.to_string())
-                .collect();
-            mount.set_options(Some(options));
-        }
+        // the ids of the options are not mapped into the user namespace
+        let options: Vec<String> = mount
+            .options()
+            .as_ref()
+            .unwrap_or(&vec![])
+            .iter()
+            .filter(|&o| !o.starts_with("gid=") && !o.starts_with("uid="))
+            .map(|o| o.to_string())
+            .collect();
+        mount.set_options(Some(options));
     }
+    mounts.push(
+        MountBuilder::default()
+            .destination("/sys")
+            .source("/sys")
+            .typ("none")
+            .options(vec![
+                "rbind".to_string(),
+                "nosuid".to_string(),
+                "noexec".to_string(),
+                "nodev".to_string(),
+                "ro".to_string(),
+            ])
+            .build()?,
+    );
 
-    let mut spec = get_default()?;
     spec.set_linux(Some(linux)).set_mounts(Some(mounts));
     Ok(spec)
 }
@@ -97,12 +106,29 @@ impl SpecJson {
             get_default()?
         };
 
-        // write data to config.json
-        to_writer_pretty(&File::create("config.json")?, &spec)?;
-        Ok(())
+        write_spec(&spec, &self.bundle)
     }
 }
 
+/// Writes the spec to the config.json of the bundle, an existing config.json is
+/// never overwritten
+fn write_spec(spec: &Spec, bundle: &Path) -> Result<()> {
+    let config_path = bundle.join("config.json");
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&config_path)
+        .with_context(|| {
+            format!(
+                "failed to create {:?}, remove an existing config.json first",
+                config_path
+            )
+        })?;
+    to_writer_pretty(&file, spec)
+        .with_context(|| format!("failed to write spec to {:?}", config_path))?;
+    Ok(())
+}
+
 #[cfg(test)]
 // Tests become unstable if not serial. The cause is not known.
 mod tests {
@@ -113,8 +139,54 @@ mod tests {
     fn test_spec_json() -> Result<()> {
         let spec = get_rootless()?;
         let tmpdir = create_temp_dir("test_spec_json").expect("failed to create temp dir");
-        let path = tmpdir.path().join("config.json");
-        to_writer_pretty(&File::create(path)?, &spec)?;
+        write_spec(&spec, tmpdir.path())?;
+
+        let loaded = Spec::load(tmpdir.path().join("config.json"))?;
+        assert_eq!(serde_json::to_value(&loaded)?, serde_json::to_value(&spec)?);
+        // an existing config.json is not overwritten
+        assert!(write_spec(&get_default()?, tmpdir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootless_spec_id_mappings() -> Result<()> {
+        let spec = get_rootless()?;
+        let linux = spec.linux().as_ref().unwrap();
+        let uid_mappings = linux.uid_mappings().as_ref().unwrap();
+        let gid_mappings = linux.gid_mappings().as_ref().unwrap();
+        assert_eq!(uid_mappings.len(), 1);
+        assert_eq!(uid_mappings[0].container_id(), 0);
+        assert_eq!(uid_mappings[0].host_id(), nix::unistd::geteuid().as_raw());
+        assert_eq!(gid_mappings.len(), 1);
+        assert_eq!(gid_mappings[0].host_id(), nix::unistd::getegid().as_raw());
+        assert!(linux
+            .namespaces()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|ns| ns.typ() == LinuxNamespaceType::User));
+        assert!(linux.resources().is_none());
+
+        let mounts = spec.mounts().as_ref().unwrap();
+        let sys_mounts: Vec<&Mount> = mounts
+            .iter()
+            .filter(|m| m.destination().starts_with("/sys"))
+            .collect();
+        assert_eq!(sys_mounts.len(), 1);
+        assert_eq!(sys_mounts[0].typ(), &Some("none".to_owned()));
+        assert!(mounts
+            .iter()
+            .flat_map(|m| m.options().iter().flatten())
+            .all(|o| !o.starts_with("uid=") && !o.starts_with("gid=")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_spec_id_mappings() -> Result<()> {
+        let spec = get_default()?;
+        let linux = spec.linux().as_ref().unwrap();
+        assert!(linux.uid_mappings().is_none());
+        assert!(linux.gid_mappings().is_none());
         Ok(())
     }
 }
@@ -0,0 +1,272 @@
+//! Contains functionality of update container command
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Clap;
+use oci_spec::runtime::{LinuxBlockIo, LinuxCpu, LinuxMemory, LinuxPids, LinuxResources};
+
+use crate::{commands::load_container, utils::parse_size};
+
+/// Update the resource restrictions of a container
+#[derive(Clap, Debug, Default)]
+pub struct Update {
+    #[clap(forbid_empty_values = true, required = true)]
+    pub container_id: String,
+    /// Path to a file containing the resources in the json format of the runtime spec, use - to read from stdin
+    #[clap(short, long)]
+    pub resources: Option<PathBuf>,
+    /// CPU shares (relative weight vs. other containers)
+    #[clap(long)]
+    pub cpu_shares: Option<u64>,
+    /// CPU CFS period to be used for hardcapping (in usecs)
+    #[clap(long)]
+    pub cpu_period: Option<u64>,
+    /// CPU CFS hardcap limit (in usecs), allowed cpu time in a given period
+    #[clap(long)]
+    pub cpu_quota: Option<i64>,
+    /// CPU realtime period to be used for hardcapping (in usecs)
+    #[clap(long)]
+    pub cpu_rt_period: Option<u64>,
+    /// CPU realtime hardcap limit (in usecs), allowed cpu time in a given period
+    #[clap(long)]
+    pub cpu_rt_runtime: Option<i64>,
+    /// CPU(s) to use
+    #[clap(long)]
+    pub cpuset_cpus: Option<String>,
+    /// Memory node(s) to use
+    #[clap(long)]
+    pub cpuset_mems: Option<String>,
+    /// Memory limit (e.g. 512Mi, 2G or as percentage of the host memory)
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_memory))]
+    pub memory: Option<i64>,
+    /// Memory reservation or soft limit (e.g. 512Mi, 2G or as percentage of the host memory)
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_memory))]
+    pub memory_reservation: Option<i64>,
+    /// Total memory usage (memory + swap), set -1 or max to enable unlimited swap
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_size))]
+    pub memory_swap: Option<i64>,
+    /// Kernel memory limit (e.g. 64Mi)
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_size))]
+    pub kernel_memory: Option<i64>,
+    /// Kernel memory limit for tcp buffer (e.g. 16Mi)
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_size))]
+    pub kernel_memory_tcp: Option<i64>,
+    /// Maximum number of pids allowed in the container, set -1 for unlimited
+    #[clap(long, allow_hyphen_values = true)]
+    pub pids_limit: Option<i64>,
+    /// Block IO weight, between 10 and 1000
+    #[clap(long)]
+    pub blkio_weight: Option<u16>,
+}
+
+impl Update {
+    pub fn exec(&self, root_path: PathBuf) -> Result<()> {
+        log::debug!("start updating container {}", self.container_id);
+        let resources = self.resources()?;
+        let mut container = load_container(root_path, &self.container_id)?;
+        container
+            .update(&resources)
+            .with_context(|| format!("failed to update container {}", self.container_id))
+    }
+
+    /// Returns the requested resource update, either read from the resources file
+    /// or assembled from the individual flags
+    fn resources(&self) -> Result<LinuxResources> {
+        let from_flags = self.resources_from_flags();
+        match &self.resources {
+            Some(_) if from_flags.is_some() => {
+                bail!("--resources cannot be combined with individual resource flags")
+            }
+            Some(path) => read_resources(path),
+            None => from_flags.context("no resources to update were specified"),
+        }
+    }
+
+    fn resources_from_flags(&self) -> Option<LinuxResources> {
+        let mut resources = LinuxResources::default();
+
+        let mut cpu = LinuxCpu::default();
+        cpu.set_shares(self.cpu_shares)
+            .set_period(self.cpu_period)
+            .set_quota(self.cpu_quota)
+            .set_realtime_period(self.cpu_rt_period)
+            .set_realtime_runtime(self.cpu_rt_runtime)
+            .set_cpus(self.cpuset_cpus.clone())
+            .set_mems(self.cpuset_mems.clone());
+        if cpu != LinuxCpu::default() {
+            resources.set_cpu(Some(cpu));
+        }
+
+        let mut memory = LinuxMemory::default();
+        memory
+            .set_limit(self.memory)
+            .set_reservation(self.memory_reservation)
+            .set_swap(self.memory_swap)
+            .set_kernel(self.kernel_memory)
+            .set_kernel_tcp(self.kernel_memory_tcp);
+        if memory != LinuxMemory::default() {
+            resources.set_memory(Some(memory));
+        }
+
+        if let Some(limit) = self.pids_limit {
+            let mut pids = LinuxPids::default();
+            pids.set_limit(limit);
+            resources.set_pids(Some(pids));
+        }
+
+        if let Some(weight) = self.blkio_weight {
+            let mut block_io = LinuxBlockIo::default();
+            block_io.set_weight(Some(weight));
+            resources.set_block_io(Some(block_io));
+        }
+
+        if resources == LinuxResources::default() {
+            None
+        } else {
+            Some(resources)
+        }
+    }
+}
+
+fn read_resources(path: &Path) -> Result<LinuxResources> {
+    let resources = if path.as_os_str() == "-" {
+        serde_json::from_reader(io::stdin()).context("failed to parse resources from stdin")?
+    } else {
+        let file = fs::File::open(path)
+            .with_context(|| format!("failed to open resources file {:?}", path))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("failed to parse resources file {:?}", path))?
+    };
+
+    Ok(resources)
+}
+
+/// Parses a memory value, which may also be given as percentage of the host memory
+fn parse_memory(value: &str) -> Result<i64> {
+    resolve_memory(value, host_memory)
+}
+
+fn host_memory() -> Result<u64> {
+    let meminfo = procfs::Meminfo::new().context("failed to read /proc/meminfo")?;
+    Ok(meminfo.mem_total)
+}
+
+fn resolve_memory<F: FnOnce() -> Result<u64>>(value: &str, total: F) -> Result<i64> {
+    let percentage = match value.strip_suffix('%') {
+        Some(percentage) => percentage,
+        None => return parse_size(value),
+    };
+
+    let percentage: f64 = percentage
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid memory percentage {}", value))?;
+    if !(percentage > 0.0 && percentage <= 100.0) {
+        bail!("memory percentage {} must be within (0, 100]", value);
+    }
+
+    let total = total().context("failed to determine the memory of the host")?;
+    Ok((total as f64 * percentage / 100.0) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    fn update() -> Update {
+        Update {
+            container_id: "test".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_no_flags() {
+        let update = update();
+        assert!(update.resources_from_flags().is_none());
+        assert!(update.resources().is_err());
+    }
+
+    #[test]
+    fn test_cpu_flags() -> Result<()> {
+        let update = Update {
+            cpu_quota: Some(50000),
+            cpu_period: Some(100000),
+            cpuset_cpus: Some("0-1".to_owned()),
+            ..update()
+        };
+
+        let resources = update.resources()?;
+        let cpu = resources.cpu().as_ref().unwrap();
+        assert_eq!(cpu.quota(), Some(50000));
+        assert_eq!(cpu.period(), Some(100000));
+        assert_eq!(cpu.cpus(), &Some("0-1".to_owned()));
+        assert_eq!(cpu.shares(), None);
+        assert!(resources.memory().is_none());
+        assert!(resources.pids().is_none());
+        assert!(resources.block_io().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_pids_blkio_flags() -> Result<()> {
+        let update = Update {
+            memory: Some(1024 * 1024),
+            memory_swap: Some(-1),
+            pids_limit: Some(100),
+            blkio_weight: Some(500),
+            ..update()
+        };
+
+        let resources = update.resources()?;
+        let memory = resources.memory().as_ref().unwrap();
+        assert_eq!(memory.limit(), Some(1024 * 1024));
+        assert_eq!(memory.swap(), Some(-1));
+        assert_eq!(memory.reservation(), None);
+        assert_eq!(resources.pids().as_ref().unwrap().limit(), 100);
+        assert_eq!(resources.block_io().as_ref().unwrap().weight(), Some(500));
+        assert!(resources.cpu().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_memory_percentage() -> Result<()> {
+        let total = || Ok(8 * 1024 * 1024 * 1024);
+        assert_eq!(resolve_memory("50%", total)?, 4 * 1024 * 1024 * 1024);
+        assert_eq!(resolve_memory("100%", total)?, 8 * 1024 * 1024 * 1024);
+        assert_eq!(resolve_memory("12.5%", total)?, 1024 * 1024 * 1024);
+        assert_eq!(resolve_memory("2Gi", total)?, 2 * 1024 * 1024 * 1024);
+        assert_eq!(resolve_memory("max", total)?, -1);
+        assert!(resolve_memory("0%", total).is_err());
+        assert!(resolve_memory("150%", total).is_err());
+        assert!(resolve_memory("half%", total).is_err());
+        assert!(resolve_memory("50%", || bail!("no meminfo")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resources_file() -> Result<()> {
+        let tmp = create_temp_dir("test_resources_file")?;
+        let path = tmp.join("resources.json");
+        fs::write(&path, r#"{"pids": {"limit": 42}}"#)?;
+
+        let update = Update {
+            resources: Some(path.clone()),
+            ..update()
+        };
+        let resources = update.resources()?;
+        assert_eq!(resources.pids().as_ref().unwrap().limit(), 42);
+
+        let conflicting = Update {
+            resources: Some(path),
+            pids_limit: Some(10),
+            ..update()
+        };
+        assert!(conflicting.resources().is_err());
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
-use crate::syscall::Syscall;
-use std::path::PathBuf;
+use crate::{process::channel, syscall::Syscall};
+use std::{path::PathBuf, time::Duration};
 
 use super::{init_builder::InitContainerBuilder, tenant_builder::TenantContainerBuilder};
 pub struct ContainerBuilder<'a> {
@@ -16,6 +16,11 @@ pub struct ContainerBuilder<'a> {
     pub(super) console_socket: Option<PathBuf>,
     /// File descriptors to be passed into the container process
     pub(super) preserve_fds: i32,
+    /// Flag indicating if the container process should be placed into cgroups
+    pub(super) use_cgroups: bool,
+    /// How long the container process waits for the main process to set up its
+    /// uid/gid mappings and idmapped mounts
+    pub(super) mapping_timeout: Duration,
 }
 
 /// Builder that can be used to configure the common properties of
@@ -56,6 +61,8 @@ impl<'a> ContainerBuilder<'a> {
             pid_file: None,
             console_socket: None,
             preserve_fds: 0,
+            use_cgroups: true,
+            mapping_timeout: channel::DEFAULT_MAPPING_TIMEOUT,
         }
     }
 
@@ -155,4 +162,39 @@ impl<'a> ContainerBuilder<'a> {
         self.preserve_fds = preserved_fds;
         self
     }
+
+    /// Sets if the container process should be placed into cgroups and have its
+    /// resource restrictions applied. Disabling this allows running containers in
+    /// environments where cgroups cannot be managed, e.g. nested containers.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use youki::container::builder::ContainerBuilder;
+    /// # use youki::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_cgroups(false);
+    /// ```
+    pub fn with_cgroups(mut self, should_use: bool) -> Self {
+        self.use_cgroups = should_use;
+        self
+    }
+
+    /// Sets how long the container process waits for the uid/gid mappings of its
+    /// user namespace to be written, before the creation of the container fails.
+    /// Defaults to 5 seconds.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use youki::container::builder::ContainerBuilder;
+    /// # use youki::syscall::syscall::create_syscall;
+    /// # use std::time::Duration;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_mapping_timeout(Duration::from_secs(30));
+    /// ```
+    pub fn with_mapping_timeout(mut self, timeout: Duration) -> Self {
+        self.mapping_timeout = timeout;
+        self
+    }
 }
@@ -1,15 +1,30 @@
 use crate::{
+    exec_fifo::ExecFifo,
     hooks,
     notify_socket::NotifyListener,
     process::{args::ContainerArgs, channel, fork, intermediate},
+    rootfs::utils as rootfs_utils,
     rootless::Rootless,
-    syscall::Syscall,
+    selinux,
+    syscall::{linux, Syscall},
     utils,
 };
 use anyhow::{bail, Context, Result};
-use nix::unistd::Pid;
-use oci_spec::runtime::Spec;
-use std::{fs, io::Write, os::unix::prelude::RawFd, path::PathBuf};
+use cgroups::{common::CgroupManager, noop_manager::NoopCgroupManager};
+use nix::{
+    fcntl::{self, OFlag},
+    mount::MsFlags,
+    sys::stat::Mode,
+    unistd::{self, Pid},
+};
+use oci_spec::runtime::{Mount as SpecMount, Spec};
+use std::{
+    fs,
+    io::Write,
+    os::unix::prelude::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use super::{Container, ContainerStatus};
 
@@ -35,10 +50,17 @@ pub(super) struct ContainerBuilderImpl<'a> {
     pub rootless: Option<Rootless<'a>>,
     /// Path to the Unix Domain Socket to communicate container start
     pub notify_path: PathBuf,
-    /// Container state
+    /// Path to the fifo the init process waits on until the container is started
+    pub exec_fifo: Option<PathBuf>,
+    /// Container state, which is only updated and removed by init containers. Tenant
+    /// containers receive the state of the container they join.
     pub container: Option<Container>,
     /// File descriptos preserved/passed to the container init process.
     pub preserve_fds: i32,
+    /// Time the container process waits for its uid/gid mappings and idmapped mounts
+    pub mapping_timeout: Duration,
+    /// Flag indicating if the container process should be placed into cgroups
+    pub use_cgroups: bool,
 }
 
 impl<'a> ContainerBuilderImpl<'a> {
@@ -56,16 +78,11 @@ impl<'a> ContainerBuilderImpl<'a> {
 
     fn run_container(&mut self) -> Result<()> {
         let linux = self.spec.linux().as_ref().context("no linux in spec")?;
-        let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
-        let cmanager = cgroups::common::create_cgroup_manager(&cgroups_path, self.use_systemd)?;
+        let cgroups_path =
+            utils::get_cgroup_path(linux.cgroups_path(), &self.container_id, self.use_systemd)?;
+        let cmanager = self.create_cgroup_manager(&cgroups_path)?;
         let process = self.spec.process().as_ref().context("No process in spec")?;
 
-        if self.init {
-            if let Some(hooks) = self.spec.hooks() {
-                hooks::run_hooks(hooks.create_runtime().as_ref(), self.container.as_ref())?
-            }
-        }
-
         // We use a set of channels to communicate between parent and child process. Each channel is uni-directional.
         let (main_sender, main_receiver) = &mut channel::main_channel()?;
         let (intermediate_sender, intermediate_receiver) = &mut channel::intermediate_channel()?;
@@ -75,6 +92,8 @@ impl<'a> ContainerBuilderImpl<'a> {
         // exec, need to create the socket before we enter into existing mount
         // namespace.
         let notify_socket: NotifyListener = NotifyListener::new(&self.notify_path)?;
+        // Same applies to the exec fifo, which is located in the container directory
+        let exec_fifo = self.exec_fifo.as_deref().map(ExecFifo::open).transpose()?;
 
         // If Out-of-memory score adjustment is set in specification.  set the score
         // value for the current process check
@@ -103,6 +122,17 @@ impl<'a> ContainerBuilderImpl<'a> {
             prctl::set_dumpable(false).unwrap();
         }
 
+        // Where the kernel supports it, the intermediate process is created directly inside
+        // of its cgroup, so that it never runs outside of it. Rootless containers are not
+        // placed into cgroups.
+        let cgroup_dir = if self.use_cgroups && self.rootless.is_none() {
+            cmanager
+                .prepare_task_dir()
+                .context("failed to prepare cgroup")?
+        } else {
+            None
+        };
+
         // This intermediate_args will be passed to the container intermediate process,
         // therefore we will have to move all the variable by value. Since self
         // is a shared reference, we have to clone these variables here.
@@ -113,25 +143,58 @@ impl<'a> ContainerBuilderImpl<'a> {
             rootfs: self.rootfs.clone(),
             console_socket: self.console_socket,
             notify_socket,
+            exec_fifo,
             preserve_fds: self.preserve_fds,
+            mapping_timeout: self.mapping_timeout,
             container: self.container.clone(),
             rootless: self.rootless.clone(),
             cgroup_manager: cmanager,
+            use_cgroups: self.use_cgroups,
+            in_cgroup: false,
+            idmapped_mounts: Default::default(),
+            selinux_enabled: selinux::is_enabled(),
         };
-        let intermediate_pid = fork::container_fork(|| {
+        let intermediate = |in_cgroup: bool| {
             // The fds in the channel is duplicated during fork, so we first close
             // the unused fds. Note, this already runs in the child process.
             main_receiver
                 .close()
                 .context("failed to close unused receiver")?;
 
-            intermediate::container_intermediate(
+            let mut intermediate_args = intermediate_args;
+            intermediate_args.in_cgroup = in_cgroup;
+            if let Err(err) = intermediate::container_intermediate(
                 intermediate_args,
                 intermediate_sender,
                 intermediate_receiver,
                 main_sender,
-            )
-        })?;
+            ) {
+                // let the main process report why the container could not be created
+                let _ = main_sender.send_error(&err);
+                return Err(err);
+            }
+
+            Ok(())
+        };
+        let intermediate_pid = match cgroup_dir {
+            Some(cgroup_dir) => {
+                let cgroup_fd = fcntl::open(
+                    &cgroup_dir,
+                    OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+                    Mode::empty(),
+                )
+                .with_context(|| format!("failed to open cgroup {:?}", cgroup_dir))?;
+                let result = fork::container_clone_into_cgroup(cgroup_fd, intermediate);
+                let _ = unistd::close(cgroup_fd);
+                let (pid, in_cgroup) = result?;
+                log::debug!(
+                    "intermediate process created inside of its cgroup: {}",
+                    in_cgroup
+                );
+                pid
+            }
+            None => fork::container_fork(|| intermediate(false))?,
+        };
         // Close down unused fds. The corresponding fds are duplicated to the
         // child process during fork.
         main_sender
@@ -147,42 +210,88 @@ impl<'a> ContainerBuilderImpl<'a> {
             intermediate_sender.mapping_written()?;
         }
 
+        let idmapped_mounts = rootfs_utils::idmapped_mounts(self.spec);
+        if self.init && !idmapped_mounts.is_empty() {
+            main_receiver.wait_for_idmapped_mounts_request()?;
+            let trees = create_idmapped_mounts(&idmapped_mounts, intermediate_pid)?;
+            let result = intermediate_sender.idmapped_mounts(&trees);
+            for tree in trees {
+                let _ = unistd::close(tree);
+            }
+            result.context("failed to send idmapped mounts")?;
+        }
+
         intermediate_sender
             .close()
             .context("failed to close unused sender")?;
 
         let init_pid = main_receiver.wait_for_intermediate_ready()?;
         log::debug!("init pid is {:?}", init_pid);
+        if init_pid == intermediate_pid {
+            bail!(
+                "intermediate process {} reported itself as the init process",
+                intermediate_pid
+            );
+        }
 
         // if file to write the pid to is specified, write pid of the child
         if let Some(pid_file) = &self.pid_file {
             fs::write(&pid_file, format!("{}", init_pid)).context("failed to write pid file")?;
         }
 
-        if let Some(container) = &mut self.container {
-            // update status and pid of the container process
-            container
-                .set_status(ContainerStatus::Created)
-                .set_creator(nix::unistd::geteuid().as_raw())
-                .set_pid(init_pid.as_raw())
-                .save()
-                .context("Failed to save container state")?;
+        // the state of a tenant container belongs to the container it joins
+        if self.init {
+            if let Some(container) = &mut self.container {
+                // update status and pid of the container process
+                container
+                    .record_creation()
+                    .set_status(ContainerStatus::Created)
+                    .set_pid(init_pid.as_raw())
+                    .set_cgroup_path(cgroups_path)
+                    .save()
+                    .context("Failed to save container state")?;
+            }
+        }
+
+        // The hooks are run once the init process is ready and its pid is part of the
+        // state, so that they are able to enter the namespaces of the container.
+        if self.init {
+            if let Some(hooks) = self.spec.hooks() {
+                // While prestart is marked as deprecated in the OCI spec, the docker and integration
+                // test still uses it. It is called as part of create, right before createRuntime.
+                #[allow(deprecated)]
+                hooks::run_hooks(hooks.prestart().as_ref(), self.container.as_ref())
+                    .context("failed to run prestart hooks")?;
+                hooks::run_hooks(hooks.create_runtime().as_ref(), self.container.as_ref())
+                    .context("failed to run create runtime hooks")?;
+            }
         }
 
         Ok(())
     }
 
+    /// Creates the cgroup manager of the container. Without cgroups the container is
+    /// managed by a manager which does nothing, so that no cgroup is created or removed.
+    fn create_cgroup_manager(&self, cgroups_path: &Path) -> Result<Box<dyn CgroupManager>> {
+        if !self.use_cgroups {
+            return Ok(Box::new(NoopCgroupManager::default()));
+        }
+
+        cgroups::common::create_cgroup_manager(cgroups_path, self.use_systemd)
+    }
+
     fn cleanup_container(&self) -> Result<()> {
         let linux = self.spec.linux().as_ref().context("no linux in spec")?;
-        let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
-        let cmanager = cgroups::common::create_cgroup_manager(&cgroups_path, self.use_systemd)?;
+        let cgroups_path =
+            utils::get_cgroup_path(linux.cgroups_path(), &self.container_id, self.use_systemd)?;
+        let cmanager = self.create_cgroup_manager(&cgroups_path)?;
 
         let mut errors = Vec::new();
         if let Err(e) = cmanager.remove().context("failed to remove cgroup") {
             errors.push(e.to_string());
         }
 
-        if let Some(container) = &self.container {
+        if let Some(container) = self.container.as_ref().filter(|_| self.init) {
             if container.root.exists() {
                 if let Err(e) = fs::remove_dir_all(&container.root)
                     .with_context(|| format!("could not delete {:?}", container.root))
@@ -202,10 +311,9 @@ impl<'a> ContainerBuilderImpl<'a> {
 
 fn setup_mapping(rootless: &Rootless, pid: Pid) -> Result<()> {
     log::debug!("write mapping for pid {:?}", pid);
-    if !rootless.privileged {
-        // The main process is running as an unprivileged user and cannot write the mapping
-        // until "deny" has been written to setgroups. See CVE-2014-8989.
-        utils::write_file(format!("/proc/{}/setgroups", pid), "deny")?;
+    if should_deny_setgroups(rootless) {
+        utils::write_file(format!("/proc/{}/setgroups", pid), "deny")
+            .with_context(|| format!("failed to deny setgroups for pid {}", pid))?;
     }
     rootless
         .write_uid_mapping(pid)
@@ -216,6 +324,53 @@ fn setup_mapping(rootless: &Rootless, pid: Pid) -> Result<()> {
     Ok(())
 }
 
+/// Creates the detached mount trees of the idmapped mounts, with the ids mapped through
+/// the user namespace of the intermediate process, which is the one of the container
+fn create_idmapped_mounts(mounts: &[&SpecMount], pid: Pid) -> Result<Vec<RawFd>> {
+    let userns_path = format!("/proc/{}/ns/user", pid);
+    let userns = fs::File::open(&userns_path)
+        .with_context(|| format!("failed to open user namespace {}", userns_path))?;
+
+    let mut trees = Vec::with_capacity(mounts.len());
+    for mount in mounts {
+        let result = mount
+            .source()
+            .as_ref()
+            .with_context(|| format!("no source in idmapped mount {:?}", mount.destination()))
+            .and_then(|source| {
+                let source = fs::canonicalize(source)
+                    .with_context(|| format!("failed to canonicalize {:?}", source))?;
+                let recursive = rootfs_utils::parse_mount(mount)
+                    .flags
+                    .contains(MsFlags::MS_REC);
+                linux::open_idmapped_tree(&source, userns.as_raw_fd(), recursive)
+            })
+            .with_context(|| format!("failed to create idmapped mount {:?}", mount.destination()));
+
+        match result {
+            Ok(tree) => trees.push(tree),
+            Err(err) => {
+                for tree in trees {
+                    let _ = unistd::close(tree);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(trees)
+}
+
+/// An unprivileged process lacks CAP_SETGID in the parent user namespace and is
+/// therefore only allowed to write the gid mapping after "deny" has been written
+/// to setgroups (see CVE-2014-8989). This does not apply if the mapping is
+/// written by the setuid newgidmap helper, which leaves setgroups allowed. The
+/// helper is only used for multiple gid mappings, even if it has been looked up
+/// because of multiple uid mappings.
+fn should_deny_setgroups(rootless: &Rootless) -> bool {
+    !rootless.privileged && rootless.gid_mappings.map_or(false, |m| m.len() == 1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -224,9 +379,73 @@ mod tests {
         sched::{unshare, CloneFlags},
         unistd::{self, getgid, getuid},
     };
-    use oci_spec::runtime::LinuxIdMappingBuilder;
+    use oci_spec::runtime::{LinuxIdMapping, LinuxIdMappingBuilder};
     use serial_test::serial;
 
+    fn id_mappings(count: u32) -> Vec<LinuxIdMapping> {
+        (0..count)
+            .map(|i| {
+                LinuxIdMappingBuilder::default()
+                    .host_id(1000 + i)
+                    .container_id(i)
+                    .size(1u32)
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn setgroups_denied_for_unprivileged_direct_mapping() {
+        let gid_mappings = id_mappings(1);
+        let rootless = Rootless {
+            privileged: false,
+            gid_mappings: Some(&gid_mappings),
+            ..Default::default()
+        };
+        assert!(should_deny_setgroups(&rootless));
+    }
+
+    #[test]
+    fn setgroups_allowed_for_privileged_user() {
+        let gid_mappings = id_mappings(1);
+        let rootless = Rootless {
+            privileged: true,
+            gid_mappings: Some(&gid_mappings),
+            ..Default::default()
+        };
+        assert!(!should_deny_setgroups(&rootless));
+    }
+
+    #[test]
+    fn setgroups_allowed_with_newgidmap() {
+        let gid_mappings = id_mappings(2);
+        let rootless = Rootless {
+            privileged: false,
+            newgidmap: Some(PathBuf::from("/usr/bin/newgidmap")),
+            gid_mappings: Some(&gid_mappings),
+            ..Default::default()
+        };
+        assert!(!should_deny_setgroups(&rootless));
+    }
+
+    #[test]
+    fn setgroups_denied_for_single_gid_mapping_with_helpers() {
+        // the helpers are looked up because of the uid mappings, but the single gid
+        // mapping is still written directly
+        let uid_mappings = id_mappings(3);
+        let gid_mappings = id_mappings(1);
+        let rootless = Rootless {
+            privileged: false,
+            newuidmap: Some(PathBuf::from("/usr/bin/newuidmap")),
+            newgidmap: Some(PathBuf::from("/usr/bin/newgidmap")),
+            uid_mappings: Some(&uid_mappings),
+            gid_mappings: Some(&gid_mappings),
+            ..Default::default()
+        };
+        assert!(should_deny_setgroups(&rootless));
+    }
+
     #[test]
     #[serial]
     fn setup_uid_mapping_should_succeed() -> Result<()> {
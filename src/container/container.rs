@@ -77,6 +77,10 @@ impl Container {
         self.state.status.can_resume()
     }
 
+    pub fn can_update(&self) -> bool {
+        self.state.status.can_update()
+    }
+
     pub fn bundle(&self) -> &PathBuf {
         &self.state.bundle
     }
@@ -116,6 +120,15 @@ impl Container {
         self
     }
 
+    /// Records the creation time and the effective user creating the container. The
+    /// creation time is captured only once and kept when called again.
+    pub fn record_creation(&mut self) -> &mut Self {
+        if self.state.created.is_none() {
+            self.state.created = Some(Utc::now());
+        }
+        self.set_creator(nix::unistd::geteuid().as_raw())
+    }
+
     pub fn systemd(&self) -> Option<bool> {
         self.state.use_systemd
     }
@@ -125,6 +138,25 @@ impl Container {
         self
     }
 
+    /// Returns if the container has been placed into cgroups
+    pub fn use_cgroups(&self) -> bool {
+        self.state.use_cgroups.unwrap_or(true)
+    }
+
+    pub fn set_use_cgroups(&mut self, should_use: bool) -> &mut Self {
+        self.state.use_cgroups = Some(should_use);
+        self
+    }
+
+    pub fn cgroup_path(&self) -> Option<&PathBuf> {
+        self.state.cgroup_path.as_ref()
+    }
+
+    pub fn set_cgroup_path(&mut self, cgroup_path: PathBuf) -> &mut Self {
+        self.state.cgroup_path = Some(cgroup_path);
+        self
+    }
+
     pub fn status(&self) -> ContainerStatus {
         self.state.status
     }
@@ -142,30 +174,21 @@ impl Container {
     }
 
     pub fn refresh_status(&mut self) -> Result<()> {
-        let new_status = match self.pid() {
-            Some(pid) => {
-                // Note that Process::new does not spawn a new process
-                // but instead creates a new Process structure, and fill
-                // it with information about the process with given pid
-                if let Ok(proc) = Process::new(pid.as_raw()) {
+        let init_alive = match self.pid() {
+            // Note that Process::new does not spawn a new process
+            // but instead creates a new Process structure, and fill
+            // it with information about the process with given pid
+            Some(pid) => match Process::new(pid.as_raw()) {
+                Ok(proc) => {
                     use procfs::process::ProcState;
-
-                    match proc.stat.state()? {
-                        ProcState::Zombie | ProcState::Dead => ContainerStatus::Stopped,
-                        _ => match self.status() {
-                            ContainerStatus::Creating
-                            | ContainerStatus::Created
-                            | ContainerStatus::Paused => self.status(),
-                            _ => ContainerStatus::Running,
-                        },
-                    }
-                } else {
-                    ContainerStatus::Stopped
+                    !matches!(proc.stat.state()?, ProcState::Zombie | ProcState::Dead)
                 }
-            }
-            None => ContainerStatus::Stopped,
+                Err(_) => false,
+            },
+            None => false,
         };
 
+        let new_status = self.status().derive(init_alive);
         self.set_status(new_status);
         Ok(())
     }
@@ -260,6 +283,16 @@ mod tests {
         assert_eq!(container.systemd(), Some(false));
     }
 
+    #[test]
+    fn test_get_set_use_cgroups() {
+        let mut container = Container::default();
+        assert!(container.use_cgroups());
+        container.set_use_cgroups(false);
+        assert!(!container.use_cgroups());
+        container.set_use_cgroups(true);
+        assert!(container.use_cgroups());
+    }
+
     #[test]
     fn test_get_set_creator() {
         let mut container = Container::default();
@@ -268,6 +301,39 @@ mod tests {
         assert_eq!(container.creator(), Some(OsString::from("youki")));
     }
 
+    #[test]
+    fn test_record_creation() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_record_creation")?;
+        let mut container = Container::new(
+            "container_id",
+            ContainerStatus::Creating,
+            None,
+            &PathBuf::from("."),
+            tmp_dir.path(),
+        )?;
+        assert_eq!(container.created(), None);
+
+        container.record_creation();
+        let created = container.created();
+        assert!(created.is_some());
+        assert_eq!(
+            container.state.creator,
+            Some(nix::unistd::geteuid().as_raw())
+        );
+        assert!(container.creator().is_some());
+
+        // the creation time is persisted and not recomputed
+        container
+            .record_creation()
+            .set_status(ContainerStatus::Created);
+        assert_eq!(container.created(), created);
+        container.save()?;
+        let loaded = Container::load(tmp_dir.path().to_path_buf())?;
+        assert_eq!(loaded.created(), created);
+        assert_eq!(loaded.state.creator, container.state.creator);
+        Ok(())
+    }
+
     #[test]
     fn test_refresh_load_save_state() -> Result<()> {
         let tmp_dir = create_temp_dir("test_refresh_load_save_state")?;
@@ -1,10 +1,20 @@
-use super::{Container, ContainerStatus};
+use super::{container_kill, Container, ContainerStatus};
 use crate::hooks;
-use crate::utils;
 use anyhow::{bail, Context, Result};
-use cgroups;
-use nix::sys::signal;
-use std::fs;
+use cgroups::{self, common::CgroupManager};
+use nix::{
+    errno::Errno,
+    sys::{signal, wait::waitpid},
+    unistd::Pid,
+};
+use procfs::process::{ProcState, Process};
+use std::{
+    fs, thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the init process to exit after it has been killed
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
 
 impl Container {
     /// Deletes the container
@@ -27,58 +37,158 @@ impl Container {
     pub fn delete(&mut self, force: bool) -> Result<()> {
         self.refresh_status()
             .context("failed to refresh container status")?;
-        if self.can_kill() && force {
-            let sig = signal::Signal::SIGKILL;
-            log::debug!("kill signal {} to {}", sig, self.pid().unwrap());
-            signal::kill(self.pid().unwrap(), sig)?;
-            self.set_status(ContainerStatus::Stopped).save()?;
-        }
         log::debug!("container status: {:?}", self.status());
-        if self.can_delete() {
-            if self.root.exists() {
-                let spec = self.spec().with_context(|| {
-                    format!("failed to load runtime spec for container {}", self.id())
-                })?;
-                log::debug!("spec: {:?}", spec);
-
-                // remove the directory storing container state
-                log::debug!("remove dir {:?}", self.root);
-                fs::remove_dir_all(&self.root).with_context(|| {
-                    format!("failed to remove container dir {}", self.root.display())
-                })?;
-
-                let cgroups_path = utils::get_cgroup_path(
-                    spec.linux()
-                        .as_ref()
-                        .context("no linux in spec")?
-                        .cgroups_path(),
-                    self.id(),
-                );
-
-                // remove the cgroup created for the container
-                // check https://man7.org/linux/man-pages/man7/cgroups.7.html
-                // creating and removing cgroups section for more information on cgroups
-                let use_systemd = self
-                    .systemd()
-                    .context("container state does not contain cgroup manager")?;
-                let cmanager = cgroups::common::create_cgroup_manager(&cgroups_path, use_systemd)
-                    .context("failed to create cgroup manager")?;
-                cmanager.remove().with_context(|| {
-                    format!("failed to remove cgroup {}", cgroups_path.display())
-                })?;
-
-                if let Some(hooks) = spec.hooks() {
-                    hooks::run_hooks(hooks.poststop().as_ref(), Some(self))
-                        .with_context(|| "failed to run post stop hooks")?;
-                }
-            }
-            std::process::exit(0)
-        } else {
-            bail!(
+        if should_kill(self.status(), force).with_context(|| {
+            format!(
                 "{} could not be deleted because it was {:?}",
                 self.id(),
                 self.status()
             )
+        })? {
+            let sig = signal::Signal::SIGKILL;
+            let pid = self.pid().context("container has no pid")?;
+            log::debug!("kill signal {} to all processes of {}", sig, self.id());
+            let cmanager = self.cgroup_manager()?;
+            kill_processes(cmanager.as_ref(), pid, sig)?;
+            wait_for_exit(pid, KILL_TIMEOUT)?;
+            self.set_status(ContainerStatus::Stopped).save()?;
         }
+
+        if self.root.exists() {
+            let spec = self.spec().with_context(|| {
+                format!("failed to load runtime spec for container {}", self.id())
+            })?;
+            log::debug!("spec: {:?}", spec);
+
+            // remove the cgroup created for the container before the state, so
+            // that the delete can be retried if the removal fails
+            // check https://man7.org/linux/man-pages/man7/cgroups.7.html
+            // creating and removing cgroups section for more information on cgroups
+            let cmanager = self
+                .cgroup_manager()
+                .context("failed to create cgroup manager")?;
+            cmanager
+                .remove()
+                .with_context(|| format!("failed to remove cgroup of container {}", self.id()))?;
+
+            // remove the directory storing container state
+            log::debug!("remove dir {:?}", self.root);
+            fs::remove_dir_all(&self.root).with_context(|| {
+                format!("failed to remove container dir {}", self.root.display())
+            })?;
+
+            if let Some(hooks) = spec.hooks() {
+                hooks::run_hooks(hooks.poststop().as_ref(), Some(self))
+                    .with_context(|| "failed to run post stop hooks")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decides whether the processes of the container have to be killed before it
+/// can be deleted. A stopped container can always be deleted, a container that
+/// is still alive only if the deletion is forced.
+fn should_kill(status: ContainerStatus, force: bool) -> Result<bool> {
+    match status {
+        ContainerStatus::Stopped => Ok(false),
+        _ if force && status.can_kill() => Ok(true),
+        _ if status.can_kill() => bail!("container is still alive, use --force to delete it"),
+        _ => bail!("container is {:?}", status),
+    }
+}
+
+/// Sends the signal to every process in the cgroup and to the init process. Rootless
+/// containers and containers created without cgroups have no cgroup which contains
+/// their processes, so the init process is always signaled directly and a missing
+/// cgroup is tolerated.
+fn kill_processes(cmanager: &dyn CgroupManager, init_pid: Pid, sig: signal::Signal) -> Result<()> {
+    if let Err(err) = container_kill::signal_all(cmanager, sig) {
+        log::warn!("failed to signal the processes in the cgroup: {:?}", err);
+    }
+
+    match signal::kill(init_pid, sig) {
+        // the init process has already exited
+        Ok(_) | Err(Errno::ESRCH) => Ok(()),
+        Err(err) => bail!("failed to send signal {} to {}: {}", sig, init_pid, err),
+    }
+}
+
+/// Waits until the init process has exited. The init process is usually not a
+/// child of this process, in which case it cannot be reaped and the process is
+/// polled until it is gone or has become a zombie.
+fn wait_for_exit(pid: Pid, timeout: Duration) -> Result<()> {
+    match waitpid(pid, None) {
+        Ok(_) => return Ok(()),
+        Err(Errno::ECHILD) => {}
+        Err(err) => bail!("failed to wait for {}: {}", pid, err),
+    }
+
+    let start = Instant::now();
+    while has_process(pid) {
+        if start.elapsed() > timeout {
+            bail!("timed out waiting for {} to exit", pid);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+fn has_process(pid: Pid) -> bool {
+    match Process::new(pid.as_raw()) {
+        Ok(process) => !matches!(
+            process.stat.state(),
+            Ok(ProcState::Zombie) | Ok(ProcState::Dead)
+        ),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgroups::noop_manager::NoopCgroupManager;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_should_kill() {
+        use ContainerStatus::*;
+        assert!(!should_kill(Stopped, false).unwrap());
+        assert!(!should_kill(Stopped, true).unwrap());
+
+        for status in [Created, Running, Paused] {
+            assert!(should_kill(status, true).unwrap());
+            let err = should_kill(status, false).unwrap_err();
+            assert!(format!("{}", err).contains("--force"), "{}", err);
+        }
+
+        assert!(should_kill(Creating, false).is_err());
+        assert!(should_kill(Creating, true).is_err());
+    }
+
+    #[test]
+    fn test_kill_processes_without_cgroup() -> Result<()> {
+        let mut child = Command::new("sleep").arg("10").spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+
+        // the noop manager does not know about any process of the container
+        kill_processes(&NoopCgroupManager::default(), pid, signal::Signal::SIGKILL)?;
+
+        let status = child.wait()?;
+        assert_eq!(status.signal(), Some(signal::Signal::SIGKILL as i32));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_exit() -> Result<()> {
+        let child = Command::new("sleep").arg("10").spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        signal::kill(pid, signal::Signal::SIGKILL)?;
+
+        wait_for_exit(pid, Duration::from_secs(5))?;
+        assert!(!has_process(pid));
+        Ok(())
     }
 }
@@ -1,13 +1,271 @@
-use std::{thread, time::Duration};
-
-use crate::utils;
+use std::{collections::HashMap, convert::TryFrom, os::unix::io::RawFd, thread, time::Duration};
 
 use super::{Container, ContainerStatus};
 use anyhow::{bail, Context, Result};
+use cgroups::stats::{BlkioDeviceStat, MemoryData, Stats};
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    unistd,
+};
+use serde::Serialize;
+
+/// Kind of a container event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    /// Resource statistics of the container
+    Stats,
+    /// A process in the container has been killed by the OOM killer
+    Oom,
+}
+
+/// Container event, serialized in the same format as runc events
+#[derive(Debug, Serialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<EventStats>,
+}
+
+/// Resource statistics of a stats event. The field names follow the stats of runc
+/// events, which differ from the names used by the cgroups crate. Like runc, zero
+/// values and empty lists are omitted for most fields.
+#[derive(Debug, Default, Serialize)]
+pub struct EventStats {
+    pub cpu: EventCpu,
+    pub memory: EventMemory,
+    pub pids: EventPids,
+    pub blkio: EventBlkio,
+    pub hugetlb: HashMap<String, EventHugetlb>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventCpu {
+    pub usage: EventCpuUsage,
+    pub throttling: EventThrottling,
+}
+
+/// Cpu usage in nanoseconds
+#[derive(Debug, Default, Serialize)]
+pub struct EventCpuUsage {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub total: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub percpu: Vec<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub percpu_kernel: Vec<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub percpu_user: Vec<u64>,
+    pub kernel: u64,
+    pub user: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventThrottling {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub periods: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub throttled_periods: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub throttled_time: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventMemory {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub cache: u64,
+    pub usage: EventMemoryEntry,
+    pub swap: EventMemoryEntry,
+    pub kernel: EventMemoryEntry,
+    #[serde(rename = "kernelTCP")]
+    pub kernel_tcp: EventMemoryEntry,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub raw: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventMemoryEntry {
+    pub limit: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub usage: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub max: u64,
+    pub failcnt: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventPids {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub current: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub limit: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventBlkio {
+    #[serde(
+        rename = "ioServiceBytesRecursive",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub io_service_bytes_recursive: Vec<EventBlkioEntry>,
+    #[serde(rename = "ioServicedRecursive", skip_serializing_if = "Vec::is_empty")]
+    pub io_serviced_recursive: Vec<EventBlkioEntry>,
+    #[serde(rename = "ioQueueRecursive", skip_serializing_if = "Vec::is_empty")]
+    pub io_queued_recursive: Vec<EventBlkioEntry>,
+    #[serde(
+        rename = "ioServiceTimeRecursive",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub io_service_time_recursive: Vec<EventBlkioEntry>,
+    #[serde(rename = "ioWaitTimeRecursive", skip_serializing_if = "Vec::is_empty")]
+    pub io_wait_time_recursive: Vec<EventBlkioEntry>,
+    #[serde(rename = "ioMergedRecursive", skip_serializing_if = "Vec::is_empty")]
+    pub io_merged_recursive: Vec<EventBlkioEntry>,
+    #[serde(rename = "ioTimeRecursive", skip_serializing_if = "Vec::is_empty")]
+    pub io_time_recursive: Vec<EventBlkioEntry>,
+    #[serde(rename = "sectorsRecursive", skip_serializing_if = "Vec::is_empty")]
+    pub sectors_recursive: Vec<EventBlkioEntry>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventBlkioEntry {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub major: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub minor: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub op: String,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub value: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct EventHugetlb {
+    #[serde(skip_serializing_if = "is_zero")]
+    pub usage: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub max: u64,
+    pub failcnt: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+impl From<Stats> for EventStats {
+    fn from(stats: Stats) -> Self {
+        let usage = stats.cpu.usage;
+        let throttling = stats.cpu.throttling;
+        let memory = stats.memory;
+        let blkio = stats.blkio;
+
+        Self {
+            cpu: EventCpu {
+                usage: EventCpuUsage {
+                    total: usage.usage_total,
+                    percpu: usage.per_core_usage_total,
+                    percpu_kernel: usage.per_core_usage_kernel,
+                    percpu_user: usage.per_core_usage_user,
+                    kernel: usage.usage_kernel,
+                    user: usage.usage_user,
+                },
+                throttling: EventThrottling {
+                    periods: throttling.periods,
+                    throttled_periods: throttling.throttled_periods,
+                    throttled_time: throttling.throttled_time,
+                },
+            },
+            memory: EventMemory {
+                cache: memory.cache,
+                usage: memory.memory.into(),
+                swap: memory.memswap.into(),
+                kernel: memory.kernel.into(),
+                kernel_tcp: memory.kernel_tcp.into(),
+                raw: memory.stats,
+            },
+            pids: EventPids {
+                current: stats.pids.current,
+                limit: stats.pids.limit,
+            },
+            blkio: EventBlkio {
+                io_service_bytes_recursive: convert_blkio(blkio.service_bytes),
+                io_serviced_recursive: convert_blkio(blkio.serviced),
+                io_queued_recursive: convert_blkio(blkio.queued),
+                io_service_time_recursive: convert_blkio(blkio.service_time),
+                io_wait_time_recursive: convert_blkio(blkio.wait_time),
+                io_merged_recursive: convert_blkio(blkio.merged),
+                io_time_recursive: convert_blkio(blkio.time),
+                sectors_recursive: convert_blkio(blkio.sectors),
+            },
+            hugetlb: stats
+                .hugetlb
+                .into_iter()
+                .map(|(size, hugetlb)| {
+                    let hugetlb = EventHugetlb {
+                        usage: hugetlb.usage,
+                        max: hugetlb.max_usage,
+                        failcnt: hugetlb.fail_count,
+                    };
+                    (size, hugetlb)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<MemoryData> for EventMemoryEntry {
+    fn from(data: MemoryData) -> Self {
+        Self {
+            limit: data.limit,
+            usage: data.usage,
+            max: data.max_usage,
+            failcnt: data.fail_count,
+        }
+    }
+}
+
+fn convert_blkio(stats: Vec<BlkioDeviceStat>) -> Vec<EventBlkioEntry> {
+    stats
+        .into_iter()
+        .map(|stat| EventBlkioEntry {
+            major: stat.major,
+            minor: stat.minor,
+            op: stat.op_type.unwrap_or_default(),
+            value: stat.value,
+        })
+        .collect()
+}
+
+impl Event {
+    pub fn stats(id: &str, stats: Stats) -> Self {
+        Self {
+            event_type: EventType::Stats,
+            id: id.to_owned(),
+            data: Some(stats.into()),
+        }
+    }
+
+    pub fn oom(id: &str) -> Self {
+        Self {
+            event_type: EventType::Oom,
+            id: id.to_owned(),
+            data: None,
+        }
+    }
+}
 
 impl Container {
     /// Displays container events
     ///
+    /// With `stats` set the resource statistics are printed once, otherwise they are
+    /// printed every `interval` seconds together with an oom event whenever the OOM killer
+    /// has been invoked in the container's cgroup. With cgroup v1 oom events are reported
+    /// as soon as the kernel signals them, with cgroup v2 they are detected through the
+    /// oom_kill counter once per interval.
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -30,31 +288,186 @@ impl Container {
             bail!("{} is not in running state", self.id());
         }
 
-        let cgroups_path = utils::get_cgroup_path(
-            self.spec()?
-                .linux()
-                .as_ref()
-                .context("no linux in spec")?
-                .cgroups_path(),
-            self.id(),
-        );
-        let use_systemd = self
-            .systemd()
-            .context("Could not determine cgroup manager")?;
-
-        let cgroup_manager = cgroups::common::create_cgroup_manager(cgroups_path, use_systemd)?;
+        let cgroup_manager = self.cgroup_manager()?;
         match stats {
             true => {
                 let stats = cgroup_manager.stats()?;
-                println!("{}", serde_json::to_string_pretty(&stats)?);
+                let event = Event::stats(self.id(), stats);
+                println!("{}", serde_json::to_string_pretty(&event)?);
             }
-            false => loop {
-                let stats = cgroup_manager.stats()?;
-                println!("{}", serde_json::to_string_pretty(&stats)?);
-                thread::sleep(Duration::from_secs(interval as u64));
+            false => {
+                let oom_event_fd = cgroup_manager
+                    .register_oom_event()
+                    .context("failed to register for oom events")?;
+                let mut last_oom_kill = None;
+                loop {
+                    let stats = cgroup_manager.stats()?;
+                    // without an eventfd oom events are detected by the growing oom_kill counter
+                    if oom_event_fd.is_none() {
+                        let oom_kill = stats.memory.oom_kill;
+                        if matches!(last_oom_kill, Some(last) if oom_kill > last) {
+                            println!("{}", serde_json::to_string(&Event::oom(self.id()))?);
+                        }
+                        last_oom_kill = Some(oom_kill);
+                    }
+
+                    let event = Event::stats(self.id(), stats);
+                    println!("{}", serde_json::to_string(&event)?);
+
+                    let interval = Duration::from_secs(interval as u64);
+                    match oom_event_fd {
+                        Some(fd) => {
+                            if wait_for_event(fd, interval)? {
+                                println!("{}", serde_json::to_string(&Event::oom(self.id()))?);
+                            }
+                        }
+                        None => thread::sleep(interval),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Waits up to timeout for the eventfd to be signaled and consumes the event.
+/// Returns if an event occurred.
+fn wait_for_event(fd: RawFd, timeout: Duration) -> Result<bool> {
+    let mut poll_fds = [PollFd::new(fd, PollFlags::POLLIN)];
+    let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    match poll(&mut poll_fds, timeout) {
+        Ok(0) | Err(nix::Error::EINTR) => return Ok(false),
+        Ok(_) => {}
+        Err(e) => bail!("failed to poll eventfd: {}", e),
+    }
+
+    // an eventfd is read as 8 byte counter, which is reset by the read
+    let mut counter = [0u8; 8];
+    unistd::read(fd, &mut counter).context("failed to read eventfd")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgroups::stats::{
+        BlkioStats, CpuStats, CpuThrottling, CpuUsage, HugeTlbStats, MemoryStats, PidStats,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_stats_event_json() -> Result<()> {
+        let mut hugetlb = HashMap::new();
+        hugetlb.insert(
+            "2MB".to_owned(),
+            HugeTlbStats {
+                usage: 2 * 1024 * 1024,
+                max_usage: 4 * 1024 * 1024,
+                fail_count: 0,
+            },
+        );
+        let mut raw = HashMap::new();
+        raw.insert("pgfault".to_owned(), 7);
+
+        let stats = Stats {
+            cpu: CpuStats {
+                usage: CpuUsage {
+                    usage_total: 100,
+                    usage_user: 70,
+                    usage_kernel: 30,
+                    per_core_usage_total: vec![60, 40],
+                    ..Default::default()
+                },
+                throttling: CpuThrottling {
+                    periods: 5,
+                    throttled_periods: 2,
+                    throttled_time: 1000,
+                },
+            },
+            pids: PidStats {
+                current: 3,
+                limit: 10,
+            },
+            hugetlb,
+            blkio: BlkioStats {
+                service_bytes: vec![BlkioDeviceStat {
+                    major: 8,
+                    minor: 1,
+                    op_type: Some("Read".to_owned()),
+                    value: 512,
+                }],
+                ..Default::default()
             },
+            memory: MemoryStats {
+                memory: MemoryData {
+                    usage: 4096,
+                    max_usage: 6144,
+                    fail_count: 1,
+                    limit: 8192,
+                },
+                stats: raw,
+                oom_kill: 1,
+                ..Default::default()
+            },
+        };
+
+        let event = serde_json::to_value(Event::stats("test", stats))?;
+        assert_eq!(event["type"], json!("stats"));
+        assert_eq!(event["id"], json!("test"));
+
+        let data = &event["data"];
+        for section in ["cpu", "memory", "pids", "blkio", "hugetlb"] {
+            assert!(data.get(section).is_some(), "missing {} section", section);
         }
+        assert_eq!(
+            data["cpu"]["usage"],
+            json!({"total": 100, "percpu": [60, 40], "kernel": 30, "user": 70})
+        );
+        assert_eq!(
+            data["cpu"]["throttling"],
+            json!({"periods": 5, "throttledPeriods": 2, "throttledTime": 1000})
+        );
+        assert_eq!(data["pids"], json!({"current": 3, "limit": 10}));
+        assert_eq!(
+            data["memory"]["usage"],
+            json!({"limit": 8192, "usage": 4096, "max": 6144, "failcnt": 1})
+        );
+        assert_eq!(data["memory"]["swap"], json!({"limit": 0, "failcnt": 0}));
+        assert!(data["memory"].get("kernelTCP").is_some());
+        assert_eq!(data["memory"]["raw"], json!({"pgfault": 7}));
+        assert_eq!(
+            data["blkio"],
+            json!({
+                "ioServiceBytesRecursive": [{"major": 8, "minor": 1, "op": "Read", "value": 512}]
+            })
+        );
+        assert_eq!(
+            data["hugetlb"],
+            json!({"2MB": {"usage": 2097152, "max": 4194304, "failcnt": 0}})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_oom_event_json() -> Result<()> {
+        let event = serde_json::to_value(Event::oom("test"))?;
+        assert_eq!(event, json!({"type": "oom", "id": "test"}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_event() -> Result<()> {
+        use nix::sys::eventfd::{eventfd, EfdFlags};
+
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC)?;
+        assert!(!wait_for_event(fd, Duration::from_millis(0))?);
 
+        unistd::write(fd, &1u64.to_ne_bytes())?;
+        assert!(wait_for_event(fd, Duration::from_millis(100))?);
+        // the event has been consumed
+        assert!(!wait_for_event(fd, Duration::from_millis(0))?);
+        unistd::close(fd)?;
         Ok(())
     }
 }
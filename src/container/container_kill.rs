@@ -1,10 +1,19 @@
 use super::{Container, ContainerStatus};
-use crate::signal::Signal;
+use crate::{signal::Signal, utils};
 use anyhow::{bail, Context, Result};
-use nix::sys::signal::{self};
+use cgroups::{
+    common::{CgroupManager, FreezerState},
+    noop_manager::NoopCgroupManager,
+};
+use nix::{
+    errno::Errno,
+    sys::signal::{self, Signal as NixSignal},
+};
 
 impl Container {
-    /// Sends the specified signal to the container init process
+    /// Sends the specified signal to the container init process. If `all` is set,
+    /// the signal is sent to every process in the cgroup of the container, unless it
+    /// has been created without cgroups.
     ///
     /// # Example
     ///
@@ -18,17 +27,31 @@ impl Container {
     /// .as_init("/var/run/docker/bundle")
     /// .build()?;
     ///
-    /// container.kill(Signal::SIGKILL)?;
+    /// container.kill(Signal::SIGKILL, false)?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn kill<S: Into<Signal>>(&mut self, signal: S) -> Result<()> {
+    pub fn kill<S: Into<Signal>>(&mut self, signal: S, all: bool) -> Result<()> {
         let signal = signal.into().into_raw();
         self.refresh_status()
             .context("failed to refresh container status")?;
         if self.can_kill() {
-            log::debug!("kill signal {} to {}", signal, self.pid().unwrap());
-            signal::kill(self.pid().unwrap(), signal)?;
+            if all && !self.use_cgroups() {
+                // there is no cgroup to find the other processes of the container
+                log::warn!(
+                    "{} has been created without cgroups, only its init process is signaled",
+                    self.id()
+                );
+            }
+
+            if all && self.use_cgroups() {
+                log::debug!("kill signal {} to all processes of {}", signal, self.id());
+                let cmanager = self.cgroup_manager()?;
+                signal_all(cmanager.as_ref(), signal)?;
+            } else {
+                log::debug!("kill signal {} to {}", signal, self.pid().unwrap());
+                signal::kill(self.pid().unwrap(), signal)?;
+            }
             self.set_status(ContainerStatus::Stopped).save()?;
             std::process::exit(0)
         } else {
@@ -39,4 +62,130 @@ impl Container {
             )
         }
     }
+
+    /// Creates the cgroup manager of the container. Containers which have been created
+    /// without cgroups get a manager that does nothing, since they have no cgroup.
+    /// The cgroup path is taken from the state and only recomputed for containers
+    /// whose state predates it.
+    pub fn cgroup_manager(&self) -> Result<Box<dyn CgroupManager>> {
+        if !self.use_cgroups() {
+            return Ok(Box::new(NoopCgroupManager::default()));
+        }
+
+        let use_systemd = self
+            .systemd()
+            .context("container state does not contain cgroup manager")?;
+        let cgroups_path = match self.cgroup_path() {
+            Some(cgroups_path) => cgroups_path.clone(),
+            None => utils::get_legacy_cgroup_path(
+                self.spec()?
+                    .linux()
+                    .as_ref()
+                    .context("no linux in spec")?
+                    .cgroups_path(),
+                self.id(),
+                use_systemd,
+            )?,
+        };
+
+        cgroups::common::create_cgroup_manager(cgroups_path, use_systemd)
+    }
+}
+
+/// Sends the signal to every process in the cgroup. The cgroup is frozen while the
+/// processes are enumerated and signaled, so that processes forked in the meantime
+/// are not missed.
+pub(super) fn signal_all(cmanager: &dyn CgroupManager, signal: NixSignal) -> Result<()> {
+    cmanager
+        .freeze(FreezerState::Frozen)
+        .context("failed to freeze container")?;
+
+    let result = cmanager.get_all_pids().and_then(|pids| {
+        for pid in pids {
+            match signal::kill(pid, signal) {
+                // the process has already exited
+                Ok(_) | Err(Errno::ESRCH) => {}
+                Err(err) => bail!("failed to send signal {} to {}: {}", signal, pid, err),
+            }
+        }
+        Ok(())
+    });
+
+    cmanager
+        .freeze(FreezerState::Thawed)
+        .context("failed to thaw container")?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgroups::test_manager::TestManager;
+    use nix::unistd::Pid;
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    #[test]
+    fn test_signal_all() -> Result<()> {
+        let mut children = vec![
+            Command::new("sleep").arg("10").spawn()?,
+            Command::new("sleep").arg("10").spawn()?,
+        ];
+        let cmanager = TestManager::default();
+        cmanager.set_pids(
+            children
+                .iter()
+                .map(|c| Pid::from_raw(c.id() as i32))
+                .collect(),
+        );
+
+        signal_all(&cmanager, NixSignal::SIGKILL)?;
+
+        for child in children.iter_mut() {
+            let status = child.wait()?;
+            assert_eq!(status.signal(), Some(NixSignal::SIGKILL as i32));
+        }
+        assert_eq!(
+            cmanager.get_freeze_args(),
+            vec![FreezerState::Frozen, FreezerState::Thawed]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_signal_all_thaws_on_error() {
+        // enumerating the pids fails, since none have been set
+        let cmanager = TestManager::default();
+
+        assert!(signal_all(&cmanager, NixSignal::SIGKILL).is_err());
+        assert_eq!(
+            cmanager.get_freeze_args(),
+            vec![FreezerState::Frozen, FreezerState::Thawed]
+        );
+    }
+
+    #[test]
+    fn test_cgroup_manager_without_cgroups() -> Result<()> {
+        // the spec is not needed, since there is no cgroup to resolve
+        let mut container = Container::default();
+        container.set_use_cgroups(false);
+
+        let cmanager = container.cgroup_manager()?;
+        assert!(cmanager.get_all_pids()?.is_empty());
+        cmanager.remove()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_manager_uses_cgroup_path_of_state() -> Result<()> {
+        // the container has no spec, so the cgroup path can not be recomputed
+        let mut container = Container::default();
+        container
+            .set_systemd(false)
+            .set_cgroup_path(PathBuf::from("/youki/test"));
+
+        assert!(container.cgroup_manager().is_ok());
+        Ok(())
+    }
 }
@@ -1,5 +1,3 @@
-use crate::utils;
-
 use super::{Container, ContainerStatus};
 
 use anyhow::{bail, Context, Result};
@@ -36,20 +34,14 @@ impl Container {
             );
         }
 
-        let spec = self.spec()?;
-        let cgroups_path = utils::get_cgroup_path(
-            spec.linux()
-                .as_ref()
-                .context("no linux in spec")?
-                .cgroups_path(),
-            self.id(),
-        );
+        if !self.use_cgroups() {
+            bail!(
+                "{} could not be resumed because it has been created without cgroups",
+                self.id()
+            );
+        }
 
-        // create cgroup manager structure from the config at the path
-        let use_systemd = self
-            .systemd()
-            .context("container state does not contain cgroup manager")?;
-        let cmanager = cgroups::common::create_cgroup_manager(cgroups_path, use_systemd)?;
+        let cmanager = self.cgroup_manager()?;
         // resume the frozen container
         cmanager.freeze(FreezerState::Thawed)?;
 
@@ -1,6 +1,6 @@
 use crate::{
+    exec_fifo::{self, EXEC_FIFO_FILE},
     hooks,
-    notify_socket::{NotifySocket, NOTIFY_FILE},
 };
 
 use super::{Container, ContainerStatus};
@@ -29,6 +29,13 @@ impl Container {
         self.refresh_status()
             .context("failed to refresh container status")?;
 
+        if matches!(
+            self.status(),
+            ContainerStatus::Running | ContainerStatus::Paused
+        ) {
+            bail!("container {} already started", self.id());
+        }
+
         if !self.can_start() {
             let err_msg = format!(
                 "{} could not be started because it was {:?}",
@@ -42,18 +49,11 @@ impl Container {
         let spec = self
             .spec()
             .with_context(|| format!("failed to load runtime spec for container {}", self.id()))?;
-        if let Some(hooks) = spec.hooks() {
-            // While prestart is marked as deprecated in the OCI spec, the docker and integration test still
-            // uses it.
-            #[allow(deprecated)]
-            hooks::run_hooks(hooks.prestart().as_ref(), Some(self))
-                .with_context(|| "failed to run pre start hooks")?;
-        }
-
         unistd::chdir(self.root.as_os_str())?;
 
-        let mut notify_socket = NotifySocket::new(&self.root.join(NOTIFY_FILE));
-        notify_socket.notify_container_start()?;
+        let init_pid = self.pid().context("container has no init process")?;
+        exec_fifo::release_exec_fifo(&self.root.join(EXEC_FIFO_FILE), init_pid)
+            .with_context(|| format!("failed to start container {}", self.id()))?;
         self.set_status(ContainerStatus::Running)
             .save()
             .with_context(|| format!("could not save state for container {}", self.id()))?;
@@ -68,3 +68,30 @@ impl Container {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_start_twice() -> Result<()> {
+        let tmp = create_temp_dir("test_start_twice")?;
+        let mut container = Container::new(
+            "container_id",
+            ContainerStatus::Running,
+            Some(std::process::id() as i32),
+            &PathBuf::from("."),
+            &tmp,
+        )?;
+
+        let err = container.start().unwrap_err();
+        assert!(
+            format!("{}", err).contains("already started"),
+            "unexpected error: {}",
+            err
+        );
+        Ok(())
+    }
+}
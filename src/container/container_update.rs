@@ -0,0 +1,216 @@
+use super::Container;
+use crate::annotations::Annotations;
+use anyhow::{bail, Context, Result};
+use cgroups::common::ControllerOpt;
+use oci_spec::runtime::{LinuxCpu, LinuxMemory, LinuxResources};
+
+impl Container {
+    /// Updates the resource restrictions of the container. Limits which are not
+    /// part of the update are kept as they are.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use youki::container::builder::ContainerBuilder;
+    /// use youki::syscall::syscall::create_syscall;
+    /// use oci_spec::runtime::{LinuxPidsBuilder, LinuxResourcesBuilder};
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut container = ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .as_init("/var/run/docker/bundle")
+    /// .build()?;
+    ///
+    /// let resources = LinuxResourcesBuilder::default()
+    ///     .pids(LinuxPidsBuilder::default().limit(100).build()?)
+    ///     .build()?;
+    /// container.update(&resources)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update(&mut self, resources: &LinuxResources) -> Result<()> {
+        self.refresh_status()
+            .context("failed to refresh container status")?;
+
+        if !self.can_update() {
+            bail!(
+                "{} could not be updated because it was {:?}",
+                self.id(),
+                self.status()
+            );
+        }
+
+        let mut spec = self.spec()?;
+        let mut linux = spec.linux().clone().context("no linux in spec")?;
+        let merged = merge_resources(linux.resources().as_ref(), resources);
+
+        let controller_opt = ControllerOpt {
+            resources: &merged,
+            disable_oom_killer: merged.disable_oom_killer().unwrap_or(false),
+            // the oom score belongs to the processes and not to the cgroup
+            oom_score_adj: None,
+            // keep the container paused or running as it is
+            freezer_state: None,
+            cpu_idle: Annotations::new(&spec).cpu_idle()?,
+        };
+
+        let cmanager = self.cgroup_manager()?;
+        cmanager
+            .validate(&controller_opt)
+            .context("invalid resource update")?;
+        cmanager
+            .apply(&controller_opt)
+            .with_context(|| format!("failed to update resources of container {}", self.id()))?;
+
+        // store the new limits, so that subsequent updates are merged with them
+        linux.set_resources(Some(merged));
+        spec.set_linux(Some(linux));
+        spec.save(self.root.join("config.json"))
+            .context("failed to save updated spec")?;
+
+        log::debug!("container {} updated", self.id());
+        Ok(())
+    }
+}
+
+/// Merges the update into the current resources. Fields which are set in the update
+/// take precedence, all other fields retain their current value.
+fn merge_resources(current: Option<&LinuxResources>, update: &LinuxResources) -> LinuxResources {
+    let mut merged = current.cloned().unwrap_or_default();
+
+    if let Some(cpu) = update.cpu() {
+        merged.set_cpu(Some(merge_cpu(merged.cpu().as_ref(), cpu)));
+    }
+
+    if let Some(memory) = update.memory() {
+        merged.set_memory(Some(merge_memory(merged.memory().as_ref(), memory)));
+    }
+
+    if update.pids().is_some() {
+        merged.set_pids(update.pids().clone());
+    }
+
+    if update.block_io().is_some() {
+        merged.set_block_io(update.block_io().clone());
+    }
+
+    if update.hugepage_limits().is_some() {
+        merged.set_hugepage_limits(update.hugepage_limits().clone());
+    }
+
+    if update.disable_oom_killer().is_some() {
+        merged.set_disable_oom_killer(update.disable_oom_killer());
+    }
+
+    merged
+}
+
+fn merge_cpu(current: Option<&LinuxCpu>, update: &LinuxCpu) -> LinuxCpu {
+    let current = current.cloned().unwrap_or_default();
+    let mut merged = current.clone();
+    merged
+        .set_shares(update.shares().or_else(|| current.shares()))
+        .set_quota(update.quota().or_else(|| current.quota()))
+        .set_period(update.period().or_else(|| current.period()))
+        .set_realtime_runtime(
+            update
+                .realtime_runtime()
+                .or_else(|| current.realtime_runtime()),
+        )
+        .set_realtime_period(
+            update
+                .realtime_period()
+                .or_else(|| current.realtime_period()),
+        )
+        .set_cpus(update.cpus().clone().or_else(|| current.cpus().clone()))
+        .set_mems(update.mems().clone().or_else(|| current.mems().clone()));
+    merged
+}
+
+fn merge_memory(current: Option<&LinuxMemory>, update: &LinuxMemory) -> LinuxMemory {
+    let current = current.cloned().unwrap_or_default();
+    let mut merged = current.clone();
+    merged
+        .set_limit(update.limit().or_else(|| current.limit()))
+        .set_reservation(update.reservation().or_else(|| current.reservation()))
+        .set_swap(update.swap().or_else(|| current.swap()))
+        .set_kernel(update.kernel().or_else(|| current.kernel()))
+        .set_kernel_tcp(update.kernel_tcp().or_else(|| current.kernel_tcp()))
+        .set_swappiness(update.swappiness().or_else(|| current.swappiness()));
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResourcesBuilder,
+    };
+
+    fn current() -> Result<LinuxResources> {
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .shares(1024u64)
+                    .quota(50000i64)
+                    .period(100000u64)
+                    .cpus("0-3")
+                    .build()?,
+            )
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024 * 1024 * 1024i64)
+                    .swap(2 * 1024 * 1024 * 1024i64)
+                    .build()?,
+            )
+            .pids(LinuxPidsBuilder::default().limit(100).build()?)
+            .build()?;
+        Ok(resources)
+    }
+
+    #[test]
+    fn test_merge_keeps_unspecified_limits() -> Result<()> {
+        let current = current()?;
+        let update = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().quota(20000i64).build()?)
+            .build()?;
+
+        let merged = merge_resources(Some(&current), &update);
+        let cpu = merged.cpu().as_ref().unwrap();
+        assert_eq!(cpu.quota(), Some(20000));
+        assert_eq!(cpu.shares(), Some(1024));
+        assert_eq!(cpu.period(), Some(100000));
+        assert_eq!(cpu.cpus(), &Some("0-3".to_owned()));
+        assert_eq!(merged.memory(), current.memory());
+        assert_eq!(merged.pids(), current.pids());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_overrides_specified_limits() -> Result<()> {
+        let current = current()?;
+        let update = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(512 * 1024 * 1024i64)
+                    .build()?,
+            )
+            .pids(LinuxPidsBuilder::default().limit(10).build()?)
+            .build()?;
+
+        let merged = merge_resources(Some(&current), &update);
+        let memory = merged.memory().as_ref().unwrap();
+        assert_eq!(memory.limit(), Some(512 * 1024 * 1024));
+        assert_eq!(memory.swap(), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(merged.pids().as_ref().unwrap().limit(), 10);
+        assert_eq!(merged.cpu(), current.cpu());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_without_current_limits() -> Result<()> {
+        let update = current()?;
+        let merged = merge_resources(None, &update);
+        assert_eq!(merged, update);
+        Ok(())
+    }
+}
@@ -1,13 +1,16 @@
 use anyhow::{bail, Context, Result};
-use nix::unistd;
-use oci_spec::runtime::Spec;
+use nix::unistd::{self, Gid, Uid};
+use oci_spec::runtime::{LinuxIdMapping, LinuxNamespaceType, Spec};
 use rootless::Rootless;
 use std::{
     fs,
     path::{Path, PathBuf},
 };
 
-use crate::{apparmor, notify_socket::NOTIFY_FILE, rootless, tty, utils};
+use crate::{
+    annotations::Annotations, apparmor, exec_fifo, namespaces::Namespaces,
+    notify_socket::NOTIFY_FILE, rootless, tty, utils,
+};
 
 use super::{
     builder::ContainerBuilder, builder_impl::ContainerBuilderImpl, Container, ContainerStatus,
@@ -18,8 +21,12 @@ pub struct InitContainerBuilder<'a> {
     base: ContainerBuilder<'a>,
     bundle: PathBuf,
     use_systemd: bool,
+    strict_version: bool,
 }
 
+/// Major and minor version of the runtime spec that is supported
+const SUPPORTED_SPEC_VERSION: (u64, u64) = (1, 0);
+
 impl<'a> InitContainerBuilder<'a> {
     /// Generates the base configuration for a new container from which
     /// configuration methods can be chained
@@ -28,6 +35,7 @@ impl<'a> InitContainerBuilder<'a> {
             base: builder,
             bundle,
             use_systemd: true,
+            strict_version: false,
         }
     }
 
@@ -37,19 +45,39 @@ impl<'a> InitContainerBuilder<'a> {
         self
     }
 
+    /// Sets if a runtime spec with an unsupported major version should be
+    /// rejected instead of only producing a warning
+    pub fn with_strict_version(mut self, strict: bool) -> Self {
+        self.strict_version = strict;
+        self
+    }
+
     /// Creates a new container
     pub fn build(self) -> Result<Container> {
-        let spec = self.load_spec()?;
+        let spec = Self::load_spec(&self.bundle, self.strict_version)?;
+        let terminal = spec
+            .process()
+            .as_ref()
+            .and_then(|p| p.terminal())
+            .unwrap_or(false);
+        tty::validate_console_socket(terminal, self.base.console_socket.as_deref())?;
+        // an annotation of the container takes precedence over the commandline
+        let use_systemd = Annotations::new(&spec)
+            .systemd_cgroup()?
+            .unwrap_or(self.use_systemd);
         let container_dir = self.create_container_dir()?;
         self.save_spec(&spec, &container_dir)?;
 
         let mut container = self.create_container_state(&container_dir)?;
         container
-            .set_systemd(self.use_systemd)
+            .set_systemd(use_systemd)
+            .set_use_cgroups(self.base.use_cgroups)
             .set_annotations(spec.annotations().clone());
 
         unistd::chdir(&container_dir)?;
         let notify_path = container_dir.join(NOTIFY_FILE);
+        let (root_uid, root_gid) = container_root_ids(&spec)?;
+        let exec_fifo = exec_fifo::create_exec_fifo(&container_dir, root_uid, root_gid)?;
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(&spec.root().as_ref().context("no root in spec")?.path())?;
 
@@ -72,13 +100,16 @@ impl<'a> InitContainerBuilder<'a> {
             container_id: self.base.container_id,
             pid_file: self.base.pid_file,
             console_socket: csocketfd,
-            use_systemd: self.use_systemd,
+            use_systemd,
             spec: &spec,
             rootfs,
             rootless,
             notify_path,
+            exec_fifo: Some(exec_fifo),
             container: Some(container.clone()),
             preserve_fds: self.base.preserve_fds,
+            mapping_timeout: self.base.mapping_timeout,
+            use_cgroups: self.base.use_cgroups,
         };
 
         builder_impl.create()?;
@@ -99,27 +130,29 @@ impl<'a> InitContainerBuilder<'a> {
         Ok(container_dir)
     }
 
-    fn load_spec(&self) -> Result<Spec> {
-        let source_spec_path = self.bundle.join("config.json");
-        let mut spec = Spec::load(&source_spec_path)?;
-        Self::validate_spec(&spec).context("failed to validate runtime spec")?;
+    /// Loads and validates the runtime spec from the config.json of the bundle
+    fn load_spec(bundle: &Path, strict_version: bool) -> Result<Spec> {
+        let source_spec_path = bundle.join("config.json");
+        if !source_spec_path.exists() {
+            bail!("runtime spec {:?} does not exist", source_spec_path);
+        }
+
+        let mut spec = Spec::load(&source_spec_path)
+            .with_context(|| format!("failed to parse runtime spec {:?}", source_spec_path))?;
+        Self::validate_spec(&spec, strict_version).context("failed to validate runtime spec")?;
 
-        spec.canonicalize_rootfs(&self.bundle)?;
+        spec.canonicalize_rootfs(bundle)
+            .with_context(|| format!("failed to find the root filesystem in {:?}", bundle))?;
         Ok(spec)
     }
 
-    fn validate_spec(spec: &Spec) -> Result<()> {
-        if !spec.version().starts_with("1.0") {
-            bail!(
-                "runtime spec has incompatible version '{}'. Only 1.0.X is supported",
-                spec.version()
-            );
-        }
+    fn validate_spec(spec: &Spec, strict_version: bool) -> Result<()> {
+        validate_version(spec.version(), strict_version)?;
 
         if let Some(process) = spec.process() {
             if let Some(profile) = process.apparmor_profile() {
                 if !apparmor::is_enabled()? {
-                    bail!(
+                    log::warn!(
                         "apparmor profile {} is specified in runtime spec, \
                     but apparmor is not activated on this system",
                         profile
@@ -128,6 +161,23 @@ impl<'a> InitContainerBuilder<'a> {
             }
         }
 
+        if spec.root().is_none() {
+            bail!("runtime spec does not specify root");
+        }
+
+        if spec.process().is_none() {
+            bail!("runtime spec does not specify process");
+        }
+
+        if let Some(linux) = spec.linux() {
+            if let Some(sysctl) = linux.sysctl() {
+                let namespaces = Namespaces::from(linux.namespaces().as_ref());
+                for key in sysctl.keys() {
+                    validate_sysctl(key, &namespaces)?;
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -149,3 +199,253 @@ impl<'a> InitContainerBuilder<'a> {
         Ok(container)
     }
 }
+
+/// Checks the version of a runtime spec against the supported version. A newer
+/// minor version is accepted with a warning, since it only adds optional fields.
+/// A different major version is rejected in strict mode and otherwise accepted
+/// with a warning.
+fn validate_version(version: &str, strict: bool) -> Result<()> {
+    if version.is_empty() {
+        bail!("runtime spec does not specify ociVersion");
+    }
+
+    let (major, minor) = parse_version(version)?;
+    if major != SUPPORTED_SPEC_VERSION.0 {
+        let msg = format!(
+            "runtime spec has incompatible version '{}'. Only {}.{}.X is supported",
+            version, SUPPORTED_SPEC_VERSION.0, SUPPORTED_SPEC_VERSION.1
+        );
+        if strict {
+            bail!(msg);
+        }
+        log::warn!("{}", msg);
+    } else if minor > SUPPORTED_SPEC_VERSION.1 {
+        log::warn!(
+            "runtime spec version '{}' is newer than the supported version {}.{}.X",
+            version,
+            SUPPORTED_SPEC_VERSION.0,
+            SUPPORTED_SPEC_VERSION.1
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that a sysctl is namespaced by one of the namespaces of the container,
+/// as it would otherwise change the setting of the host
+fn validate_sysctl(key: &str, namespaces: &Namespaces) -> Result<()> {
+    const IPC_SYSCTLS: &[&str] = &[
+        "kernel.msgmax",
+        "kernel.msgmnb",
+        "kernel.msgmni",
+        "kernel.sem",
+        "kernel.shmall",
+        "kernel.shmmax",
+        "kernel.shmmni",
+        "kernel.shm_rmid_forced",
+    ];
+
+    let required = if IPC_SYSCTLS.contains(&key) || key.starts_with("fs.mqueue.") {
+        LinuxNamespaceType::Ipc
+    } else if key.starts_with("net.") {
+        LinuxNamespaceType::Network
+    } else if key == "kernel.hostname" || key == "kernel.domainname" {
+        LinuxNamespaceType::Uts
+    } else {
+        bail!("sysctl {} is not in a separate kernel namespace", key);
+    };
+
+    if namespaces.get(required).is_none() {
+        bail!(
+            "sysctl {} requires a new {:?} namespace to be specified",
+            key,
+            required
+        );
+    }
+
+    Ok(())
+}
+
+/// Parses the major and minor version from a semver string like 1.0.2-dev
+/// Returns the ids on the host of the root user of the container, which are those of
+/// the runtime unless the container maps its root user to different ids
+fn container_root_ids(spec: &Spec) -> Result<(Uid, Gid)> {
+    let linux = spec.linux().as_ref().context("no linux in spec")?;
+    let host_id = |mappings: &Option<Vec<LinuxIdMapping>>| {
+        mappings
+            .as_ref()
+            .and_then(|m| m.iter().find(|m| m.container_id() == 0))
+            .map(|m| m.host_id())
+    };
+
+    let uid = host_id(linux.uid_mappings()).map_or_else(unistd::geteuid, Uid::from_raw);
+    let gid = host_id(linux.gid_mappings()).map_or_else(unistd::getegid, Gid::from_raw);
+    Ok((uid, gid))
+}
+
+fn parse_version(version: &str) -> Result<(u64, u64)> {
+    let core = version
+        .split(|c| c == '-' || c == '+')
+        .next()
+        .unwrap_or_default();
+    let parts = core
+        .split('.')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|parts| parts.len() == 3)
+        .with_context(|| format!("runtime spec version '{}' is not a valid semver", version))?;
+
+    Ok((parts[0], parts[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    fn create_bundle(test_name: &str, spec: serde_json::Value) -> Result<utils::TempDir> {
+        let bundle = create_temp_dir(test_name)?;
+        fs::create_dir(bundle.join("rootfs"))?;
+        fs::write(bundle.join("config.json"), serde_json::to_vec(&spec)?)?;
+        Ok(bundle)
+    }
+
+    #[test]
+    fn test_load_spec() -> Result<()> {
+        let bundle = create_bundle("test_load_spec", serde_json::to_value(Spec::default())?)?;
+
+        let spec = InitContainerBuilder::load_spec(&bundle, false)?;
+        assert_eq!(
+            spec.root().as_ref().unwrap().path(),
+            &fs::canonicalize(bundle.join("rootfs"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_spec_without_version() -> Result<()> {
+        let mut spec = serde_json::to_value(Spec::default())?;
+        spec.as_object_mut().unwrap().remove("ociVersion");
+        let bundle = create_bundle("test_load_spec_without_version", spec)?;
+
+        assert!(InitContainerBuilder::load_spec(&bundle, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_spec_incompatible_version() -> Result<()> {
+        let mut spec = serde_json::to_value(Spec::default())?;
+        spec["ociVersion"] = "2.0.0".into();
+        let bundle = create_bundle("test_load_spec_incompatible_version", spec)?;
+
+        assert!(InitContainerBuilder::load_spec(&bundle, false).is_ok());
+        assert!(InitContainerBuilder::load_spec(&bundle, true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_spec_missing_or_malformed() -> Result<()> {
+        let bundle = create_temp_dir("test_load_spec_missing_or_malformed")?;
+        let err = InitContainerBuilder::load_spec(&bundle, false).unwrap_err();
+        assert!(format!("{}", err).contains("does not exist"), "{}", err);
+
+        fs::write(bundle.join("config.json"), "{ not json")?;
+        let err = InitContainerBuilder::load_spec(&bundle, false).unwrap_err();
+        assert!(format!("{}", err).contains("failed to parse"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_version() {
+        assert!(validate_version("1.0.0", true).is_ok());
+        assert!(validate_version("1.0.2-dev", true).is_ok());
+        // a future minor version is accepted with a warning
+        assert!(validate_version("1.1.0", true).is_ok());
+        // a different major version is only rejected in strict mode
+        assert!(validate_version("2.0.0", false).is_ok());
+        assert!(validate_version("2.0.0", true).is_err());
+        assert!(validate_version("0.9.0", true).is_err());
+    }
+
+    #[test]
+    fn test_validate_version_invalid() {
+        assert!(validate_version("", false).is_err());
+        assert!(validate_version("latest", false).is_err());
+        assert!(validate_version("1.0", false).is_err());
+        assert!(validate_version("1.x.0", false).is_err());
+    }
+
+    #[test]
+    fn test_parse_version() -> Result<()> {
+        assert_eq!(parse_version("1.0.2")?, (1, 0));
+        assert_eq!(parse_version("1.2.0-rc.1")?, (1, 2));
+        assert_eq!(parse_version("1.0.2+build")?, (1, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_sysctl() -> Result<()> {
+        use oci_spec::runtime::LinuxNamespaceBuilder;
+
+        let namespaces = vec![
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Network)
+                .build()?,
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Ipc)
+                .build()?,
+        ];
+        let namespaces = Namespaces::from(Some(&namespaces));
+        assert!(validate_sysctl("net.ipv4.ip_forward", &namespaces).is_ok());
+        assert!(validate_sysctl("kernel.shmmax", &namespaces).is_ok());
+        assert!(validate_sysctl("fs.mqueue.msg_max", &namespaces).is_ok());
+        // there is no uts namespace
+        assert!(validate_sysctl("kernel.hostname", &namespaces).is_err());
+        // not namespaced at all
+        assert!(validate_sysctl("kernel.pid_max", &namespaces).is_err());
+
+        let err = validate_sysctl("net.ipv4.ip_forward", &Namespaces::from(None)).unwrap_err();
+        assert!(format!("{}", err).contains("Network"), "{}", err);
+        Ok(())
+    }
+    #[test]
+    fn test_container_root_ids() -> Result<()> {
+        use oci_spec::runtime::{LinuxBuilder, LinuxIdMappingBuilder, SpecBuilder};
+
+        let mapping = |container_id: u32, host_id: u32| {
+            LinuxIdMappingBuilder::default()
+                .container_id(container_id)
+                .host_id(host_id)
+                .size(1u32)
+                .build()
+        };
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .uid_mappings(vec![mapping(1, 1001)?, mapping(0, 1000)?])
+                    .gid_mappings(vec![mapping(0, 2000)?])
+                    .build()?,
+            )
+            .build()?;
+        assert_eq!(
+            container_root_ids(&spec)?,
+            (Uid::from_raw(1000), Gid::from_raw(2000))
+        );
+
+        // the root user of the container is not mapped
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .uid_mappings(vec![mapping(1, 1001)?])
+                    .gid_mappings(vec![])
+                    .build()?,
+            )
+            .build()?;
+        assert_eq!(
+            container_root_ids(&spec)?,
+            (unistd::geteuid(), unistd::getegid())
+        );
+        Ok(())
+    }
+}
@@ -14,8 +14,10 @@ mod container_kill;
 mod container_pause;
 mod container_resume;
 mod container_start;
+mod container_update;
 pub mod init_builder;
 pub mod state;
 pub mod tenant_builder;
 pub use container::Container;
+pub use container_events::{Event, EventType};
 pub use state::{ContainerStatus, State};
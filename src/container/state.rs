@@ -55,6 +55,30 @@ impl ContainerStatus {
     pub fn can_resume(&self) -> bool {
         matches!(self, ContainerStatus::Paused)
     }
+
+    pub fn can_update(&self) -> bool {
+        use ContainerStatus::*;
+        match self {
+            Creating | Stopped => false,
+            Created | Running | Paused => true,
+        }
+    }
+
+    /// Derives the current status from the last recorded status and whether the
+    /// init process of the container is still alive. A container whose init
+    /// process has exited is stopped. Otherwise it keeps a creating, created or
+    /// paused status, and is running once it has been started.
+    pub fn derive(self, init_alive: bool) -> ContainerStatus {
+        use ContainerStatus::*;
+        if !init_alive {
+            return Stopped;
+        }
+
+        match self {
+            Creating | Created | Paused => self,
+            Running | Stopped => Running,
+        }
+    }
 }
 
 impl Display for ContainerStatus {
@@ -97,6 +121,13 @@ pub struct State {
     pub creator: Option<u32>,
     // Specifies if systemd should be used to manage cgroups
     pub use_systemd: Option<bool>,
+    // Specifies if the container has been placed into cgroups, containers created
+    // without this field always have been
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_cgroups: Option<bool>,
+    // Path of the cgroup of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_path: Option<PathBuf>,
 }
 
 impl State {
@@ -118,20 +149,32 @@ impl State {
             created: None,
             creator: None,
             use_systemd: None,
+            use_cgroups: None,
+            cgroup_path: None,
         }
     }
 
+    /// Saves the state to the state file in `container_root`. The state is written to
+    /// a temporary file first and then renamed, so a concurrently running command never
+    /// observes a partially written state file.
     pub fn save(&self, container_root: &Path) -> Result<()> {
         let state_file_path = Self::file_path(container_root);
+        let tmp_file_path = container_root.join(format!(".{}.tmp", Self::STATE_FILE_PATH));
         let file = fs::OpenOptions::new()
-            .read(true)
             .write(true)
-            .append(false)
             .create(true)
             .truncate(true)
-            .open(&state_file_path)
-            .with_context(|| format!("failed to open {}", state_file_path.display()))?;
-        serde_json::to_writer(&file, self)?;
+            .open(&tmp_file_path)
+            .with_context(|| format!("failed to open {}", tmp_file_path.display()))?;
+        serde_json::to_writer(&file, self)
+            .with_context(|| format!("failed to write container state to {:?}", tmp_file_path))?;
+        file.sync_all()?;
+        fs::rename(&tmp_file_path, &state_file_path).with_context(|| {
+            format!(
+                "failed to move {:?} to {:?}",
+                tmp_file_path, state_file_path
+            )
+        })?;
         Ok(())
     }
 
@@ -140,7 +183,12 @@ impl State {
         let file = File::open(&state_file_path).with_context(|| {
             format!("failed to open container state file {:?}", state_file_path)
         })?;
-        let state: Self = serde_json::from_reader(&file)?;
+        let state: Self = serde_json::from_reader(&file).with_context(|| {
+            format!(
+                "failed to parse container state file {:?}, it may be corrupted",
+                state_file_path
+            )
+        })?;
         Ok(state)
     }
 
@@ -162,6 +210,131 @@ impl State {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_state_round_trip() -> Result<()> {
+        let tmp = create_temp_dir("test_state_round_trip")?;
+        let mut state = State::new(
+            "container_id",
+            ContainerStatus::Created,
+            Some(42),
+            PathBuf::from("/path/to/bundle"),
+        );
+        state.created = Some(Utc::now());
+        state.creator = Some(1000);
+        state.use_systemd = Some(false);
+        state.cgroup_path = Some(PathBuf::from("/youki/container_id"));
+
+        state.save(&tmp)?;
+        let loaded = State::load(&tmp)?;
+        assert_eq!(
+            serde_json::to_value(&loaded)?,
+            serde_json::to_value(&state)?
+        );
+        assert!(!tmp.join(".state.json.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_json_fields() -> Result<()> {
+        let state = State::new(
+            "container_id",
+            ContainerStatus::Running,
+            Some(42),
+            PathBuf::from("/path/to/bundle"),
+        );
+        let json = serde_json::to_value(&state)?;
+        assert_eq!(json["ociVersion"], "v1.0.2");
+        assert_eq!(json["id"], "container_id");
+        assert_eq!(json["status"], "running");
+        assert_eq!(json["pid"], 42);
+        assert_eq!(json["bundle"], "/path/to/bundle");
+        assert!(json.get("cgroupPath").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_created_and_creator() -> Result<()> {
+        let mut state = State::new(
+            "container_id",
+            ContainerStatus::Created,
+            None,
+            PathBuf::from("/path/to/bundle"),
+        );
+        state.created = Some(DateTime::parse_from_rfc3339("2021-09-01T10:20:30.5Z")?.into());
+        state.creator = Some(1000);
+
+        let json = serde_json::to_value(&state)?;
+        let created = json["created"].as_str().unwrap();
+        assert_eq!(
+            DateTime::parse_from_rfc3339(created)?,
+            state.created.unwrap()
+        );
+        assert_eq!(json["creator"], 1000);
+
+        let decoded: State = serde_json::from_value(json)?;
+        assert_eq!(decoded.created, state.created);
+        assert_eq!(decoded.creator, Some(1000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_corrupt_state() -> Result<()> {
+        let tmp = create_temp_dir("test_load_corrupt_state")?;
+        fs::write(State::file_path(&tmp), r#"{"ociVersion":"v1.0.2","id":"#)?;
+
+        let err = State::load(&tmp).unwrap_err();
+        assert!(format!("{}", err).contains("corrupted"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_missing_state() -> Result<()> {
+        let tmp = create_temp_dir("test_load_missing_state")?;
+        assert!(State::load(&tmp).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_status() {
+        use ContainerStatus::*;
+        let cases = [
+            (Creating, true, Creating),
+            (Created, true, Created),
+            (Running, true, Running),
+            (Paused, true, Paused),
+            (Creating, false, Stopped),
+            (Created, false, Stopped),
+            (Running, false, Stopped),
+            (Paused, false, Stopped),
+            (Stopped, false, Stopped),
+        ];
+
+        for (recorded, alive, expected) in cases {
+            assert_eq!(
+                recorded.derive(alive),
+                expected,
+                "recorded {:?}, alive {}",
+                recorded,
+                alive
+            );
+        }
+    }
+
+    #[test]
+    fn test_status_serialization() -> Result<()> {
+        use ContainerStatus::*;
+        for (status, expected) in [
+            (Creating, "creating"),
+            (Created, "created"),
+            (Running, "running"),
+            (Stopped, "stopped"),
+        ] {
+            assert_eq!(serde_json::to_value(status)?, expected);
+        }
+        Ok(())
+    }
 
     #[test]
     fn test_creating_status() {
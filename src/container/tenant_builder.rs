@@ -22,7 +22,7 @@ use crate::{notify_socket::NotifySocket, rootless::Rootless, tty, utils};
 
 use super::{builder::ContainerBuilder, Container};
 
-const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup"];
+const NAMESPACE_TYPES: &[&str] = &["user", "ipc", "uts", "net", "pid", "mnt", "cgroup"];
 const TENANT_NOTIFY: &str = "tenant-notify-";
 const TENANT_TTY: &str = "tenant-tty-";
 
@@ -126,8 +126,13 @@ impl<'a> TenantContainerBuilder<'a> {
             rootfs,
             rootless,
             notify_path: notify_path.clone(),
-            container: None,
+            exec_fifo: None,
+            // the state is passed on to the seccomp agent
+            container: Some(container.clone()),
             preserve_fds: self.base.preserve_fds,
+            mapping_timeout: self.base.mapping_timeout,
+            // a tenant cannot join the cgroup of a container created without cgroups
+            use_cgroups: self.base.use_cgroups && container.use_cgroups(),
         };
 
         builder_impl.create()?;
@@ -192,8 +197,18 @@ impl<'a> TenantContainerBuilder<'a> {
         }
 
         let init_process = procfs::process::Process::new(container.pid().unwrap().as_raw())?;
-        let ns = self.get_namespaces(init_process.namespaces()?)?;
-        let linux = LinuxBuilder::default().namespaces(ns).build()?;
+        let own_namespaces = procfs::process::Process::myself()?.namespaces()?;
+        let ns = Self::get_namespaces(init_process.namespaces()?, &own_namespaces)?;
+        let mut linux_builder = LinuxBuilder::default().namespaces(ns);
+        // the processes of the container are subject to the same seccomp profile
+        if let Some(seccomp) = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.seccomp().clone())
+        {
+            linux_builder = linux_builder.seccomp(seccomp);
+        }
+        let linux = linux_builder.build()?;
 
         spec.set_process(Some(process)).set_linux(Some(linux));
         Ok(())
@@ -317,11 +332,27 @@ impl<'a> TenantContainerBuilder<'a> {
         Ok(None)
     }
 
-    fn get_namespaces(&self, init_namespaces: Vec<Namespace>) -> Result<Vec<LinuxNamespace>> {
+    // Collects the namespaces of the container init process which the tenant has to
+    // join. The order in which they are joined is determined when they are applied,
+    // where the user namespace is always entered first. A user namespace can not be
+    // joined if the calling process is already a member of it, so it is skipped if
+    // the container shares the user namespace with us.
+    fn get_namespaces(
+        init_namespaces: Vec<Namespace>,
+        own_namespaces: &[Namespace],
+    ) -> Result<Vec<LinuxNamespace>> {
         let mut tenant_namespaces = Vec::with_capacity(init_namespaces.len());
 
         for &ns_type in NAMESPACE_TYPES {
             if let Some(init_ns) = init_namespaces.iter().find(|n| n.ns_type == ns_type) {
+                if ns_type == "user"
+                    && own_namespaces
+                        .iter()
+                        .any(|n| n.ns_type == ns_type && n.identifier == init_ns.identifier)
+                {
+                    continue;
+                }
+
                 let tenant_ns = LinuxNamespaceType::try_from(ns_type)?;
                 tenant_namespaces.push(
                     LinuxNamespaceBuilder::default()
@@ -375,3 +406,65 @@ impl<'a> TenantContainerBuilder<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::namespaces::Namespaces;
+    use std::ffi::OsString;
+
+    fn namespace(pid: i32, ns_type: &str, identifier: u64) -> Namespace {
+        Namespace {
+            ns_type: OsString::from(ns_type),
+            path: PathBuf::from(format!("/proc/{}/ns/{}", pid, ns_type)),
+            identifier,
+            device_id: 4,
+        }
+    }
+
+    #[test]
+    fn test_get_namespaces_join_order() -> Result<()> {
+        let init_namespaces = ["cgroup", "ipc", "mnt", "net", "pid", "user", "uts", "time"]
+            .iter()
+            .enumerate()
+            .map(|(i, ns_type)| namespace(42, ns_type, 100 + i as u64))
+            .collect();
+        let own_namespaces = vec![namespace(1, "user", 1)];
+
+        let tenant_namespaces =
+            TenantContainerBuilder::get_namespaces(init_namespaces, &own_namespaces)?;
+        assert_eq!(tenant_namespaces.len(), 7);
+        for ns in &tenant_namespaces {
+            let path = ns.path().as_ref().expect("namespace path");
+            assert!(path.starts_with("/proc/42/ns"), "{:?}", path);
+        }
+
+        let namespaces = Namespaces::from(Some(&tenant_namespaces));
+        let order: Vec<LinuxNamespaceType> = namespaces.iter().map(|ns| ns.typ()).collect();
+        assert_eq!(
+            order,
+            vec![
+                LinuxNamespaceType::User,
+                LinuxNamespaceType::Ipc,
+                LinuxNamespaceType::Uts,
+                LinuxNamespaceType::Network,
+                LinuxNamespaceType::Pid,
+                LinuxNamespaceType::Cgroup,
+                LinuxNamespaceType::Mount,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_namespaces_skips_shared_user_namespace() -> Result<()> {
+        let init_namespaces = vec![namespace(42, "user", 7), namespace(42, "pid", 8)];
+        let own_namespaces = vec![namespace(1, "user", 7), namespace(1, "pid", 9)];
+
+        let tenant_namespaces =
+            TenantContainerBuilder::get_namespaces(init_namespaces, &own_namespaces)?;
+        let types: Vec<LinuxNamespaceType> = tenant_namespaces.iter().map(|ns| ns.typ()).collect();
+        assert_eq!(types, vec![LinuxNamespaceType::Pid]);
+        Ok(())
+    }
+}
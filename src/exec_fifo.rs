@@ -0,0 +1,210 @@
+//! The exec fifo holds back the container process after the container has been
+//! created until it is started. The init process blocks opening the fifo for
+//! writing right before it executes the payload and start releases it by opening
+//! the fifo for reading and consuming the byte written by the init process.
+use std::{
+    fs::{self, Permissions},
+    os::unix::{fs::PermissionsExt, io::RawFd},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    poll::{self, PollFd, PollFlags},
+    sys::stat::Mode,
+    unistd::{self, Gid, Pid, Uid},
+};
+use procfs::process::{ProcState, Process};
+
+pub const EXEC_FIFO_FILE: &str = "exec.fifo";
+/// Interval in milliseconds in which is checked if the init process is still alive
+/// while waiting for it to open the exec fifo
+const POLL_INTERVAL: i32 = 100;
+
+/// Creates the exec fifo in the directory of the container. The fifo is owned by the
+/// root user of the container on the host and writable for everyone, so that an init
+/// process inside of a user namespace is able to open it.
+pub fn create_exec_fifo(container_dir: &Path, uid: Uid, gid: Gid) -> Result<PathBuf> {
+    let path = container_dir.join(EXEC_FIFO_FILE);
+    unistd::mkfifo(&path, Mode::from_bits_truncate(0o622))
+        .with_context(|| format!("failed to create exec fifo {:?}", path))?;
+    // the mode passed to mkfifo is restricted by the umask
+    fs::set_permissions(&path, Permissions::from_mode(0o622))
+        .with_context(|| format!("failed to set permissions of exec fifo {:?}", path))?;
+    unistd::chown(&path, Some(uid), Some(gid))
+        .with_context(|| format!("failed to change owner of exec fifo {:?}", path))?;
+    Ok(path)
+}
+
+/// Releases the init process waiting on the exec fifo. The fifo is removed afterwards,
+/// so that a container can only be started once. Fails if the init process exits
+/// before it has opened the fifo.
+pub fn release_exec_fifo(path: &Path, init_pid: Pid) -> Result<()> {
+    if !path.exists() {
+        bail!(
+            "exec fifo {:?} does not exist, container already started",
+            path
+        );
+    }
+
+    // opening the fifo without O_NONBLOCK would block until the init process has
+    // opened it for writing, which never happens if it has died in the meantime
+    let fd = fcntl::open(
+        path,
+        OFlag::O_RDONLY | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("failed to open exec fifo {:?}", path))?;
+    let result = read_exec_fifo(fd, init_pid);
+    let _ = unistd::close(fd);
+    if result.with_context(|| format!("failed to read exec fifo {:?}", path))? == 0 {
+        bail!("container already started");
+    }
+
+    fs::remove_file(path).with_context(|| format!("failed to remove exec fifo {:?}", path))
+}
+
+/// Waits until the init process has written to the fifo and returns the number of
+/// bytes read
+fn read_exec_fifo(fd: RawFd, init_pid: Pid) -> Result<usize> {
+    loop {
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll::poll(&mut fds, POLL_INTERVAL) {
+            Ok(0) => {
+                if !is_alive(init_pid) {
+                    bail!(
+                        "init process {} exited before the container was started",
+                        init_pid
+                    );
+                }
+            }
+            Ok(_) => break,
+            Err(Errno::EINTR) => continue,
+            Err(err) => bail!("failed to poll: {}", err),
+        }
+    }
+
+    let mut buf = [0u8; 1];
+    Ok(unistd::read(fd, &mut buf)?)
+}
+
+fn is_alive(pid: Pid) -> bool {
+    match Process::new(pid.as_raw()) {
+        Ok(process) => !matches!(
+            process.stat.state(),
+            Ok(ProcState::Zombie) | Ok(ProcState::Dead)
+        ),
+        Err(_) => false,
+    }
+}
+
+/// End of the exec fifo used by the init process
+pub struct ExecFifo {
+    fd: RawFd,
+}
+
+impl ExecFifo {
+    /// Opens the fifo without reading or writing, which does not block. The init
+    /// process loses access to the container directory with pivot_root, so the fifo
+    /// is opened beforehand and reopened through /proc/self/fd when waiting.
+    pub fn open(path: &Path) -> Result<Self> {
+        let fd = fcntl::open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())
+            .with_context(|| format!("failed to open exec fifo {:?}", path))?;
+        Ok(Self { fd })
+    }
+
+    /// Blocks until the container is started, i.e. the fifo is opened for reading,
+    /// and signals the start by writing a single byte
+    pub fn wait_for_start(&self) -> Result<()> {
+        let path = format!("/proc/self/fd/{}", self.fd);
+        let fd = fcntl::open(
+            path.as_str(),
+            OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .context("failed to open exec fifo for writing")?;
+        let result = unistd::write(fd, b"0").context("failed to write to exec fifo");
+        let _ = unistd::close(fd);
+        result?;
+        Ok(())
+    }
+
+    pub fn close(&self) -> Result<()> {
+        unistd::close(self.fd).context("failed to close exec fifo")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use nix::sys::stat;
+    use serial_test::serial;
+    use std::{os::unix::fs::MetadataExt, process::Command, sync::mpsc, thread, time::Duration};
+
+    #[test]
+    fn test_exec_fifo_sequencing() -> Result<()> {
+        let tmp = create_temp_dir("test_exec_fifo_sequencing")?;
+        let path = create_exec_fifo(&tmp, unistd::getuid(), unistd::getgid())?;
+        // opening the fifo in the init process must not block
+        let exec_fifo = ExecFifo::open(&path)?;
+
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let result = exec_fifo.wait_for_start();
+            tx.send(()).unwrap();
+            exec_fifo.close()?;
+            result
+        });
+
+        // the init process stays blocked until the container is started
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        release_exec_fifo(&path, Pid::this())?;
+        rx.recv_timeout(Duration::from_secs(5))
+            .context("init process was not released")?;
+        handle.join().unwrap()?;
+        assert!(!path.exists());
+
+        let err = release_exec_fifo(&path, Pid::this()).unwrap_err();
+        assert!(err.to_string().contains("already started"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_exec_fifo_of_exited_init() -> Result<()> {
+        let tmp = create_temp_dir("test_release_exec_fifo_of_exited_init")?;
+        let path = create_exec_fifo(&tmp, unistd::getuid(), unistd::getgid())?;
+        let mut child = Command::new("true").spawn()?;
+        child.wait()?;
+
+        let err = release_exec_fifo(&path, Pid::from_raw(child.id() as i32)).unwrap_err();
+        assert!(format!("{:?}", err).contains("exited"), "{:?}", err);
+        assert!(path.exists());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_create_exec_fifo_ignores_umask() -> Result<()> {
+        let tmp = create_temp_dir("test_create_exec_fifo_ignores_umask")?;
+        let old_umask = stat::umask(Mode::from_bits_truncate(0o077));
+        let result = create_exec_fifo(&tmp, unistd::getuid(), unistd::getgid());
+        stat::umask(old_umask);
+
+        let metadata = fs::metadata(result?)?;
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o622);
+        assert_eq!(metadata.uid(), unistd::getuid().as_raw());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_exec_fifo_twice() -> Result<()> {
+        let tmp = create_temp_dir("test_create_exec_fifo_twice")?;
+        create_exec_fifo(&tmp, unistd::getuid(), unistd::getgid())?;
+        assert!(create_exec_fifo(&tmp, unistd::getuid(), unistd::getgid()).is_err());
+        Ok(())
+    }
+}
@@ -97,9 +97,10 @@ pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Re
                 match r.recv_timeout(time::Duration::from_secs(timeout_sec as u64)) {
                     Ok(res) => res,
                     Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
-                        // Kill the process. There is no need to further clean
-                        // up because we will be error out.
+                        // Kill the process and wait for the wait thread to
+                        // reap it, so no zombie is left behind.
                         let _ = signal::kill(hook_process_pid, signal::Signal::SIGKILL);
+                        let _ = r.recv();
                         return Err(HookTimeoutError.into());
                     }
                     Err(_) => {
@@ -200,6 +201,31 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_run_hook_state_on_stdin() -> Result<()> {
+        let tmp = crate::utils::create_temp_dir("test_run_hook_state_on_stdin")?;
+        let state_file = tmp.join("state.json");
+        let default_container: Container = Default::default();
+        let hook = HookBuilder::default()
+            .path("bash")
+            .args(vec![
+                String::from("bash"),
+                String::from("-c"),
+                format!("cat > {}", state_file.display()),
+            ])
+            .build()?;
+        let hooks = Some(vec![hook]);
+        run_hooks(hooks.as_ref(), Some(&default_container)).context("Failed stdin test")?;
+
+        let written = fs::read_to_string(&state_file)?;
+        assert_eq!(written, serde_json::to_string(&default_container.state)?);
+        let state: serde_json::Value = serde_json::from_str(&written)?;
+        assert!(state.get("ociVersion").is_some());
+        assert!(state.get("status").is_some());
+        Ok(())
+    }
+
     #[test]
     #[serial]
     // This will test executing hook with a timeout. Since the timeout is set in
@@ -1,8 +1,10 @@
+pub mod annotations;
 pub mod apparmor;
 pub mod capabilities;
 pub mod commands;
 pub mod container;
 pub mod dbus;
+pub mod exec_fifo;
 pub mod hooks;
 pub mod logger;
 pub mod namespaces;
@@ -11,6 +13,7 @@ pub mod process;
 pub mod rootfs;
 pub mod rootless;
 pub mod seccomp;
+pub mod selinux;
 pub mod signal;
 pub mod syscall;
 pub mod tty;
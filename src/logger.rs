@@ -2,14 +2,16 @@
 
 use std::env;
 use std::io::{stderr, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::path::PathBuf;
 use std::{
     fs::{File, OpenOptions},
     str::FromStr,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use log::{LevelFilter, Log, Metadata, Record};
+use nix::fcntl::{fcntl, FcntlArg};
 use once_cell::sync::OnceCell;
 
 /// Public global variables to access logger and logfile
@@ -24,49 +26,142 @@ const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
 #[cfg(not(debug_assertions))]
 const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Warn;
 
+/// Format in which the log records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One json object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format {}, must be text or json", format),
+        }
+    }
+}
+
 /// Initialize the logger, must be called before accessing the logger
 /// Multiple parts might call this at once, but the actual initialization
-/// is done only once due to use of OnceCell
-pub fn init(log_file: Option<PathBuf>) -> Result<()> {
+/// is done only once due to use of OnceCell. Logs are written to the log file or
+/// the already opened log fd if one of them is given, otherwise to stderr.
+pub fn init(
+    log_file: Option<PathBuf>,
+    log_fd: Option<RawFd>,
+    log_level: Option<String>,
+    log_format: Option<String>,
+) -> Result<()> {
     // If file exists, ignore, else create and open the file
-    let _log_file = LOG_FILE.get_or_init(|| -> Option<File> {
-        // set the log level if specified in env variable or set to default
-        let level_filter = if let Ok(log_level_str) = env::var("YOUKI_LOG_LEVEL") {
-            LevelFilter::from_str(&log_level_str).unwrap_or(DEFAULT_LOG_LEVEL)
-        } else {
-            DEFAULT_LOG_LEVEL
+    let _log_file = LOG_FILE.get_or_try_init(|| -> Result<Option<File>> {
+        let level_filter = detect_log_level(log_level)?;
+        let format = match log_format {
+            Some(format) => LogFormat::from_str(&format)?,
+            None => LogFormat::Text,
         };
 
+        let log_file = open_log_target(log_file, log_fd)?;
+
         // Create a new logger, or get existing if already created
-        let logger = YOUKI_LOGGER.get_or_init(|| YoukiLogger::new(level_filter.to_level()));
+        let logger = YOUKI_LOGGER.get_or_init(|| YoukiLogger::new(level_filter.to_level(), format));
 
         log::set_logger(logger)
             .map(|()| log::set_max_level(level_filter))
-            .expect("set logger failed");
+            .context("failed to set logger")?;
 
-        // Create and open log file
-        log_file.as_ref().map(|log_file_path| {
-            OpenOptions::new()
+        Ok(log_file)
+    })?;
+    Ok(())
+}
+
+/// Opens the file the logs should be written to. A log fd is passed by the caller
+/// and used as is, it is never closed by youki.
+fn open_log_target(log_file: Option<PathBuf>, log_fd: Option<RawFd>) -> Result<Option<File>> {
+    match (log_file, log_fd) {
+        (Some(_), Some(_)) => bail!("only one of log file and log fd can be specified"),
+        (Some(log_file_path), None) => {
+            let file = OpenOptions::new()
                 .create(true)
                 .write(true)
-                .truncate(false)
-                .open(log_file_path)
-                .expect("failed opening log file ")
-        })
-    });
-    Ok(())
+                .append(true)
+                .open(&log_file_path)
+                .with_context(|| format!("failed to open log file {:?}", log_file_path))?;
+            Ok(Some(file))
+        }
+        (None, Some(fd)) => {
+            fcntl(fd, FcntlArg::F_GETFD)
+                .with_context(|| format!("log fd {} is not a valid file descriptor", fd))?;
+            // the file is stored in a static and therefore never dropped, so the fd
+            // stays open for the lifetime of the process
+            Ok(Some(unsafe { File::from_raw_fd(fd) }))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Determines the log level. A level given on the command line takes precedence over
+/// the YOUKI_LOG_LEVEL environment variable.
+fn detect_log_level(input: Option<String>) -> Result<LevelFilter> {
+    if let Some(level) = input {
+        return LevelFilter::from_str(&level)
+            .with_context(|| format!("invalid log level {}", level));
+    }
+
+    // set the log level if specified in env variable or set to default
+    let level_filter = if let Ok(log_level_str) = env::var("YOUKI_LOG_LEVEL") {
+        LevelFilter::from_str(&log_level_str).unwrap_or(DEFAULT_LOG_LEVEL)
+    } else {
+        DEFAULT_LOG_LEVEL
+    };
+
+    Ok(level_filter)
 }
 
 /// Youki's custom Logger
 pub struct YoukiLogger {
     /// Indicates level up to which logs are to be printed
     level: Option<log::Level>,
+    /// Format of the emitted log records
+    format: LogFormat,
 }
 
 impl YoukiLogger {
     /// Create new logger
-    pub fn new(level: Option<log::Level>) -> Self {
-        Self { level }
+    pub fn new(level: Option<log::Level>, format: LogFormat) -> Self {
+        Self { level, format }
+    }
+
+    /// Formats the record according to the configured log format
+    fn format(&self, record: &Record) -> String {
+        match self.format {
+            LogFormat::Text => match (record.file(), record.line()) {
+                (Some(file), Some(line)) => format!(
+                    "[{} {}:{}] {} {}\r",
+                    record.level(),
+                    file,
+                    line,
+                    chrono::Local::now().to_rfc3339(),
+                    record.args()
+                ),
+                (_, _) => format!(
+                    "[{}] {} {}\r",
+                    record.level(),
+                    chrono::Local::now().to_rfc3339(),
+                    record.args()
+                ),
+            },
+            LogFormat::Json => serde_json::json!({
+                "level": record.level().to_string(),
+                "timestamp": chrono::Local::now().to_rfc3339(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        }
     }
 }
 
@@ -84,25 +179,10 @@ impl Log for YoukiLogger {
     /// Function to carry out logging
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let log_msg = match (record.file(), record.line()) {
-                (Some(file), Some(line)) => format!(
-                    "[{} {}:{}] {} {}\r",
-                    record.level(),
-                    file,
-                    line,
-                    chrono::Local::now().to_rfc3339(),
-                    record.args()
-                ),
-                (_, _) => format!(
-                    "[{}] {} {}\r",
-                    record.level(),
-                    chrono::Local::now().to_rfc3339(),
-                    record.args()
-                ),
-            };
+            let log_msg = self.format(record);
 
             // if log file is set, write to it, else write to stderr
-            if let Some(mut log_file) = LOG_FILE.get().unwrap().as_ref() {
+            if let Some(mut log_file) = LOG_FILE.get().and_then(Option::as_ref) {
                 let _ = writeln!(log_file, "{}", log_msg);
             } else {
                 let _ = writeln!(stderr(), "{}", log_msg);
@@ -112,10 +192,121 @@ impl Log for YoukiLogger {
 
     /// Flush logs to file
     fn flush(&self) {
-        if let Some(mut log_file) = LOG_FILE.get().unwrap().as_ref() {
+        if let Some(mut log_file) = LOG_FILE.get().and_then(Option::as_ref) {
             log_file.flush().expect("Failed to flush");
         } else {
             stderr().flush().expect("Failed to flush");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{create_temp_dir, test_utils::test_in_child_process};
+    use log::Level;
+    use std::{fs, os::unix::io::AsRawFd};
+
+    fn log_record(logger: &YoukiLogger) -> String {
+        logger.format(
+            &Record::builder()
+                .level(Level::Warn)
+                .args(format_args!("test message"))
+                .file(Some("src/logger.rs"))
+                .line(Some(42))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn test_log_format_from_str() -> Result<()> {
+        assert_eq!(LogFormat::from_str("text")?, LogFormat::Text);
+        assert_eq!(LogFormat::from_str("json")?, LogFormat::Json);
+        assert!(LogFormat::from_str("xml").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_format() {
+        let logger = YoukiLogger::new(Some(Level::Debug), LogFormat::Text);
+        let msg = log_record(&logger);
+        assert!(msg.starts_with("[WARN src/logger.rs:42] "));
+        assert!(msg.ends_with("test message\r"));
+    }
+
+    #[test]
+    fn test_json_format() -> Result<()> {
+        let logger = YoukiLogger::new(Some(Level::Debug), LogFormat::Json);
+        let msg = log_record(&logger);
+        assert!(!msg.contains('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&msg)?;
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["message"], "test message");
+        assert!(chrono::DateTime::parse_from_rfc3339(value["timestamp"].as_str().unwrap()).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_to_file() -> Result<()> {
+        let tmp = create_temp_dir("test_log_to_file")?;
+        let log_path = tmp.join("youki.log");
+        test_in_child_process(|| {
+            init(Some(log_path.clone()), None, Some("debug".to_owned()), None)?;
+            log::debug!("debug message for the log file");
+            log::logger().flush();
+            Ok(())
+        })?;
+
+        let content = fs::read_to_string(&log_path)?;
+        assert!(content.contains("debug message for the log file"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_to_fd() -> Result<()> {
+        let tmp = create_temp_dir("test_log_to_fd")?;
+        let log_path = tmp.join("youki.log");
+        let log_file = File::create(&log_path)?;
+        test_in_child_process(|| {
+            init(
+                None,
+                Some(log_file.as_raw_fd()),
+                Some("debug".to_owned()),
+                Some("json".to_owned()),
+            )?;
+            log::debug!("debug message for the log fd");
+            log::logger().flush();
+            Ok(())
+        })?;
+
+        let content = fs::read_to_string(&log_path)?;
+        let record: serde_json::Value = serde_json::from_str(content.trim())?;
+        assert_eq!(record["message"], "debug message for the log fd");
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_log_target() -> Result<()> {
+        let tmp = create_temp_dir("test_open_log_target")?;
+        assert!(open_log_target(None, None)?.is_none());
+        assert!(open_log_target(Some(tmp.join("youki.log")), Some(2)).is_err());
+        assert!(open_log_target(None, Some(-1)).is_err());
+        assert!(open_log_target(Some(tmp.join("missing/youki.log")), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_log_level() -> Result<()> {
+        assert_eq!(
+            detect_log_level(Some("error".to_owned()))?,
+            LevelFilter::Error
+        );
+        assert_eq!(
+            detect_log_level(Some("TRACE".to_owned()))?,
+            LevelFilter::Trace
+        );
+        assert!(detect_log_level(Some("verbose".to_owned())).is_err());
+        Ok(())
+    }
+}
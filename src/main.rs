@@ -3,6 +3,7 @@
 //! This crate provides a container runtime which can be used by a high-level container runtime to run containers.
 
 use std::fs;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 use anyhow::bail;
@@ -16,6 +17,7 @@ use youki::commands::create;
 use youki::commands::delete;
 use youki::commands::events;
 use youki::commands::exec;
+use youki::commands::features;
 use youki::commands::info;
 use youki::commands::kill;
 use youki::commands::list;
@@ -26,6 +28,7 @@ use youki::commands::run;
 use youki::commands::spec_json;
 use youki::commands::start;
 use youki::commands::state;
+use youki::commands::update;
 use youki::rootless::rootless_required;
 use youki::utils::{self, create_dir_all_with_mode};
 
@@ -38,13 +41,25 @@ struct Opts {
     /// root directory to store container state
     #[clap(short, long)]
     root: Option<PathBuf>,
+    /// file to write the logs to, stderr is used if not set
     #[clap(short, long)]
     log: Option<PathBuf>,
+    /// already opened file descriptor to write the logs to
+    #[clap(long)]
+    log_fd: Option<RawFd>,
+    /// log level (error, warn, info, debug or trace)
+    #[clap(long)]
+    log_level: Option<String>,
+    /// log format (text or json)
     #[clap(long)]
     log_format: Option<String>,
     /// Enable systemd cgroup manager, rather then use the cgroupfs directly.
     #[clap(short, long)]
     systemd_cgroup: bool,
+    /// Do not place containers into cgroups or apply their resource restrictions,
+    /// e.g. for environments like nested containers in which cgroups cannot be managed
+    #[clap(long)]
+    no_cgroups: bool,
     /// command to actually manage container
     #[clap(subcommand)]
     subcmd: SubCommand,
@@ -71,6 +86,8 @@ enum SubCommand {
     #[clap(version = crate_version!(), author = "youki team")]
     Info(info::Info),
     #[clap(version = crate_version!(), author = "youki team")]
+    Features(features::Features),
+    #[clap(version = crate_version!(), author = "youki team")]
     Spec(spec_json::SpecJson),
     #[clap(version = crate_version!(), author = "youki team")]
     List(list::List),
@@ -82,6 +99,8 @@ enum SubCommand {
     Events(events::Events),
     #[clap(version = crate_version!(), author = "youki team", setting=clap::AppSettings::AllowLeadingHyphen)]
     Ps(ps::Ps),
+    #[clap(version = crate_version!(), author = "youki team")]
+    Update(update::Update),
 }
 
 /// This is the entry point in the container runtime. The binary is run by a high-level container runtime,
@@ -101,28 +120,31 @@ fn main() -> Result<()> {
 
     let opts = Opts::parse();
 
-    if let Err(e) = youki::logger::init(opts.log) {
+    if let Err(e) = youki::logger::init(opts.log, opts.log_fd, opts.log_level, opts.log_format) {
         eprintln!("log init failed: {:?}", e);
     }
 
     let root_path = determine_root_path(opts.root)?;
     let systemd_cgroup = opts.systemd_cgroup;
+    let use_cgroups = !opts.no_cgroups;
 
     match opts.subcmd {
-        SubCommand::Create(create) => create.exec(root_path, systemd_cgroup),
+        SubCommand::Create(create) => create.exec(root_path, systemd_cgroup, use_cgroups),
         SubCommand::Start(start) => start.exec(root_path),
-        SubCommand::Run(run) => run.exec(root_path, systemd_cgroup),
-        SubCommand::Exec(exec) => exec.exec(root_path),
+        SubCommand::Run(run) => run.exec(root_path, systemd_cgroup, use_cgroups),
+        SubCommand::Exec(exec) => exec.exec(root_path, use_cgroups),
         SubCommand::Kill(kill) => kill.exec(root_path),
         SubCommand::Delete(delete) => delete.exec(root_path),
         SubCommand::State(state) => state.exec(root_path),
         SubCommand::Info(info) => info.exec(),
+        SubCommand::Features(features) => features.exec(),
         SubCommand::List(list) => list.exec(root_path),
         SubCommand::Spec(spec) => spec.exec(),
         SubCommand::Pause(pause) => pause.exec(root_path),
         SubCommand::Resume(resume) => resume.exec(root_path),
         SubCommand::Events(events) => events.exec(root_path),
         SubCommand::Ps(ps) => ps.exec(root_path),
+        SubCommand::Update(update) => update.exec(root_path),
     }
 }
 
@@ -7,18 +7,57 @@
 //! UTS (hostname and domain information, processes will think they're running on servers with different names),
 //! Cgroup (Resource limits, execution priority etc.)
 
-use crate::syscall::{syscall::create_syscall, Syscall};
+use crate::{
+    syscall::{syscall::create_syscall, Syscall},
+    utils,
+};
 use anyhow::{Context, Result};
-use nix::{fcntl, sched::CloneFlags, sys::stat, unistd};
+use nix::{
+    fcntl,
+    sched::CloneFlags,
+    sys::stat,
+    unistd::{self, Pid},
+};
 use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType};
 use std::collections;
 
+/// Flag to create a new time namespace (since Linux 5.6), which is not known to nix yet
+pub const CLONE_NEWTIME: i32 = 0x80;
+
+/// Returns CLONE_NEWTIME as clone flag, see CLONE_NEWTIME
+pub fn time_clone_flag() -> CloneFlags {
+    // Safety: the flag is only passed on to unshare and setns
+    unsafe { CloneFlags::from_bits_unchecked(CLONE_NEWTIME) }
+}
+
+/// Offset of a clock in a time namespace relative to the clock of the
+/// initial time namespace, see time_namespaces(7)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeOffset {
+    pub secs: i64,
+    pub nanosecs: u32,
+}
+
 /// Holds information about namespaces
 pub struct Namespaces {
     command: Box<dyn Syscall>,
     namespace_map: collections::HashMap<CloneFlags, LinuxNamespace>,
 }
 
+/// Order in which namespaces are entered. The user namespace comes first so that
+/// the remaining namespaces are owned by it and the mount namespace comes last,
+/// because entering it earlier can make the paths of the other namespaces and the
+/// files required for uid/gid mapping inaccessible.
+const NAMESPACE_ORDER: &[LinuxNamespaceType] = &[
+    LinuxNamespaceType::User,
+    LinuxNamespaceType::Ipc,
+    LinuxNamespaceType::Uts,
+    LinuxNamespaceType::Network,
+    LinuxNamespaceType::Pid,
+    LinuxNamespaceType::Cgroup,
+    LinuxNamespaceType::Mount,
+];
+
 fn get_clone_flag(namespace_type: LinuxNamespaceType) -> CloneFlags {
     match namespace_type {
         LinuxNamespaceType::Pid => CloneFlags::CLONE_NEWPID,
@@ -31,6 +70,37 @@ fn get_clone_flag(namespace_type: LinuxNamespaceType) -> CloneFlags {
     }
 }
 
+/// Formats the clock offsets in the format expected by /proc/<pid>/timens_offsets
+pub fn format_timens_offsets(
+    monotonic: Option<TimeOffset>,
+    boottime: Option<TimeOffset>,
+) -> String {
+    let mut offsets = String::new();
+    for (clock, offset) in [("monotonic", monotonic), ("boottime", boottime)] {
+        if let Some(offset) = offset {
+            offsets.push_str(&format!("{} {} {}\n", clock, offset.secs, offset.nanosecs));
+        }
+    }
+    offsets
+}
+
+/// Writes the clock offsets of the time namespace that has been created by
+/// unsharing CLONE_NEWTIME. This has to be done before the first process
+/// enters the namespace, i.e. before the process that unshared it forks.
+pub fn write_timens_offsets(
+    pid: Pid,
+    monotonic: Option<TimeOffset>,
+    boottime: Option<TimeOffset>,
+) -> Result<()> {
+    let offsets = format_timens_offsets(monotonic, boottime);
+    if offsets.is_empty() {
+        return Ok(());
+    }
+
+    utils::write_file(format!("/proc/{}/timens_offsets", pid), offsets)
+        .context("failed to write time namespace offsets")
+}
+
 impl From<Option<&Vec<LinuxNamespace>>> for Namespaces {
     fn from(namespaces: Option<&Vec<LinuxNamespace>>) -> Self {
         let command: Box<dyn Syscall> = create_syscall();
@@ -49,18 +119,19 @@ impl From<Option<&Vec<LinuxNamespace>>> for Namespaces {
 
 impl Namespaces {
     pub fn apply_namespaces<F: Fn(CloneFlags) -> bool>(&self, filter: F) -> Result<()> {
-        let to_enter: collections::HashMap<&CloneFlags, &LinuxNamespace> = self
-            .namespace_map
-            .iter()
-            .filter(|(k, _)| filter(**k))
-            .collect();
-        for (ns_type, ns) in to_enter {
+        for ns in self.iter().filter(|ns| filter(get_clone_flag(ns.typ()))) {
             self.unshare_or_setns(ns)
-                .with_context(|| format!("Failed to enter {:?} namespace: {:?}", ns_type, ns))?;
+                .with_context(|| format!("Failed to enter {:?} namespace: {:?}", ns.typ(), ns))?;
         }
         Ok(())
     }
 
+    /// Iterates over the requested namespaces in the order in which they have to be
+    /// entered, see NAMESPACE_ORDER. Namespace types that were not requested are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = &LinuxNamespace> {
+        NAMESPACE_ORDER.iter().filter_map(move |typ| self.get(*typ))
+    }
+
     pub fn unshare_or_setns(&self, namespace: &LinuxNamespace) -> Result<()> {
         log::debug!("unshare or setns: {:?}", namespace);
         if namespace.path().is_none() {
@@ -69,18 +140,50 @@ impl Namespaces {
             let ns_path = namespace.path().as_ref().unwrap();
             let fd = fcntl::open(ns_path, fcntl::OFlag::empty(), stat::Mode::empty())
                 .with_context(|| format!("Failed to open namespace fd: {:?}", ns_path))?;
-            self.command
+            // setns verifies that the fd refers to a namespace of the requested type
+            // and fails with EINVAL otherwise
+            let result = self
+                .command
                 .set_ns(fd, get_clone_flag(namespace.typ()))
-                .with_context(|| "Failed to set namespace")?;
+                .with_context(|| {
+                    format!(
+                        "Failed to join namespace {:?}, is it a {:?} namespace?",
+                        ns_path,
+                        namespace.typ()
+                    )
+                });
             unistd::close(fd).with_context(|| "Failed to close namespace fd")?;
+            result?;
         }
 
         Ok(())
     }
 
+    /// Creates a new time namespace, which the children of the calling process are
+    /// going to be members of, and sets the offsets of its clocks. The time namespace
+    /// is not part of the runtime spec yet and therefore not handled by NAMESPACE_ORDER.
+    /// Offsets can only be written before the first process enters the namespace, so
+    /// this must be called before the init process is forked.
+    pub fn unshare_time(
+        &self,
+        monotonic: Option<TimeOffset>,
+        boottime: Option<TimeOffset>,
+    ) -> Result<()> {
+        log::debug!("unshare time namespace");
+        self.command
+            .unshare(time_clone_flag())
+            .context("failed to create time namespace")?;
+        write_timens_offsets(Pid::this(), monotonic, boottime)
+    }
+
     pub fn get(&self, k: LinuxNamespaceType) -> Option<&LinuxNamespace> {
         self.namespace_map.get(&get_clone_flag(k))
     }
+
+    #[cfg(test)]
+    pub(crate) fn syscall(&self) -> &dyn Syscall {
+        self.command.as_ref()
+    }
 }
 
 #[cfg(test)]
@@ -143,4 +246,107 @@ mod tests {
         expect.sort();
         assert_eq!(unshare_args, expect)
     }
+
+    #[test]
+    fn test_get_clone_flag() {
+        let cases = [
+            (LinuxNamespaceType::Pid, CloneFlags::CLONE_NEWPID),
+            (LinuxNamespaceType::User, CloneFlags::CLONE_NEWUSER),
+            (LinuxNamespaceType::Uts, CloneFlags::CLONE_NEWUTS),
+            (LinuxNamespaceType::Cgroup, CloneFlags::CLONE_NEWCGROUP),
+            (LinuxNamespaceType::Ipc, CloneFlags::CLONE_NEWIPC),
+            (LinuxNamespaceType::Network, CloneFlags::CLONE_NEWNET),
+            (LinuxNamespaceType::Mount, CloneFlags::CLONE_NEWNS),
+        ];
+        for (typ, flag) in cases {
+            assert_eq!(get_clone_flag(typ), flag, "{:?}", typ);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_setns_with_path() {
+        let namespaces = Namespaces::from(None);
+        let test_command: &TestHelperSyscall = namespaces.command.as_any().downcast_ref().unwrap();
+        let network = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Network)
+            .path("/dev/null")
+            .build()
+            .unwrap();
+
+        namespaces.unshare_or_setns(&network).unwrap();
+
+        let setns_args: Vec<_> = test_command
+            .get_setns_args()
+            .into_iter()
+            .map(|(_fd, cf)| cf)
+            .collect();
+        assert_eq!(setns_args, vec![CloneFlags::CLONE_NEWNET]);
+        assert!(test_command.get_unshare_args().is_empty());
+    }
+
+    #[test]
+    fn test_setns_with_missing_path() {
+        let namespaces = Namespaces::from(None);
+        let network = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Network)
+            .path("/does/not/exist")
+            .build()
+            .unwrap();
+
+        assert!(namespaces.unshare_or_setns(&network).is_err());
+    }
+
+    #[test]
+    fn test_format_timens_offsets() {
+        let monotonic = TimeOffset {
+            secs: -3600,
+            nanosecs: 0,
+        };
+        let boottime = TimeOffset {
+            secs: 86400,
+            nanosecs: 500,
+        };
+
+        assert_eq!(format_timens_offsets(None, None), "");
+        assert_eq!(
+            format_timens_offsets(Some(monotonic), None),
+            "monotonic -3600 0\n"
+        );
+        assert_eq!(
+            format_timens_offsets(Some(monotonic), Some(boottime)),
+            "monotonic -3600 0\nboottime 86400 500\n"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_unshare_time() -> Result<()> {
+        let namespaces = Namespaces::from(None);
+        let test_command: &TestHelperSyscall = namespaces.command.as_any().downcast_ref().unwrap();
+
+        namespaces.unshare_time(None, None)?;
+
+        assert_eq!(test_command.get_unshare_args(), vec![time_clone_flag()]);
+        assert_eq!(time_clone_flag().bits(), CLONE_NEWTIME);
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_order() {
+        let sample_linux_namespaces = gen_sample_linux_namespaces();
+        let namespaces = Namespaces::from(Some(&sample_linux_namespaces));
+
+        let order: Vec<LinuxNamespaceType> = namespaces.iter().map(|ns| ns.typ()).collect();
+        assert_eq!(
+            order,
+            vec![
+                LinuxNamespaceType::User,
+                LinuxNamespaceType::Ipc,
+                LinuxNamespaceType::Network,
+                LinuxNamespaceType::Pid,
+                LinuxNamespaceType::Mount,
+            ]
+        );
+    }
 }
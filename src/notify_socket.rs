@@ -69,10 +69,13 @@ impl NotifySocket {
         log::debug!("notify container start");
         let cwd = env::current_dir()?;
         unistd::chdir(self.path.parent().unwrap())?;
-        let mut stream = UnixStream::connect(&self.path.file_name().unwrap())?;
-        stream.write_all(b"start container")?;
-        log::debug!("notify finished");
+        // change back to the original directory even if the notification failed
+        let result = UnixStream::connect(&self.path.file_name().unwrap())
+            .and_then(|mut stream| stream.write_all(b"start container"))
+            .with_context(|| format!("failed to notify {:?}", self.path));
         unistd::chdir(&cwd)?;
+        result?;
+        log::debug!("notify finished");
         Ok(())
     }
 
@@ -81,3 +84,46 @@ impl NotifySocket {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use serial_test::serial;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    #[serial]
+    fn test_notify_releases_listener() -> Result<()> {
+        let tmp = create_temp_dir("test_notify_releases_listener")?;
+        let socket_path = tmp.join(NOTIFY_FILE);
+        let listener = NotifyListener::new(&socket_path)?;
+
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let result = listener.wait_for_container_start();
+            tx.send(()).unwrap();
+            result
+        });
+
+        // the listener must stay blocked until start is notified
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        NotifySocket::new(&socket_path).notify_container_start()?;
+        rx.recv_timeout(Duration::from_secs(5))
+            .context("listener was not released")?;
+        handle.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_notify_without_listener() -> Result<()> {
+        let tmp = create_temp_dir("test_notify_without_listener")?;
+        let mut notify_socket = NotifySocket::new(tmp.join(NOTIFY_FILE));
+        assert!(notify_socket.notify_container_start().is_err());
+        Ok(())
+    }
+}
@@ -2,9 +2,13 @@ use cgroups::common::CgroupManager;
 use oci_spec::runtime::Spec;
 use std::os::unix::prelude::RawFd;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::rootfs::mount::IdmappedMounts;
 use crate::rootless::Rootless;
-use crate::{container::Container, notify_socket::NotifyListener, syscall::Syscall};
+use crate::{
+    container::Container, exec_fifo::ExecFifo, notify_socket::NotifyListener, syscall::Syscall,
+};
 
 pub struct ContainerArgs<'a> {
     /// Flag indicating if an init or a tenant container should be created
@@ -19,12 +23,28 @@ pub struct ContainerArgs<'a> {
     pub console_socket: Option<RawFd>,
     /// The Unix Domain Socket to communicate container start
     pub notify_socket: NotifyListener,
+    /// Fifo the init process of an init container waits on for the container start,
+    /// tenant containers are started through the notify socket instead
+    pub exec_fifo: Option<ExecFifo>,
     /// File descriptos preserved/passed to the container init process.
     pub preserve_fds: i32,
+    /// Time the intermediate process waits for its uid/gid mappings and idmapped mounts
+    pub mapping_timeout: Duration,
     /// Container state
     pub container: Option<Container>,
     /// Options for rootless containers
     pub rootless: Option<Rootless<'a>>,
     /// Cgroup Manager
     pub cgroup_manager: Box<dyn CgroupManager>,
+    /// Flag indicating if the container process should be placed into cgroups
+    pub use_cgroups: bool,
+    /// Flag indicating if the intermediate process has been created inside of its cgroup
+    /// already, in which case it does not have to be added to the cgroup anymore
+    pub in_cgroup: bool,
+    /// Detached mount trees of the idmapped mounts, which are created by the main process
+    /// once the user namespace of the container exists
+    pub idmapped_mounts: IdmappedMounts,
+    /// Flag indicating if SELinux is enabled on the host, which is detected before the
+    /// init process enters the rootfs of the container
+    pub selinux_enabled: bool,
 }
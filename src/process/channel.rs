@@ -1,14 +1,24 @@
 use crate::process::message::Message;
+use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
-use mio::unix::pipe;
-use mio::unix::pipe::{Receiver, Sender};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{self, AddressFamily, MsgFlags, SockFlag, SockType};
+use nix::sys::uio;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
 use nix::unistd;
 use nix::unistd::Pid;
-use std::io::Read;
-use std::io::Write;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+// interval in milliseconds in which the exit of a child is checked if pidfd is not available
+const WAITPID_INTERVAL: i32 = 100;
+const FRAME_HEADER_SIZE: usize = 4;
+/// Default time the intermediate process waits for the main process to write
+/// the uid/gid mappings
+pub const DEFAULT_MAPPING_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Channel Design
 ///
@@ -20,23 +30,186 @@ use std::os::unix::io::AsRawFd;
 /// receiver to receive all message sent to the main process. The other
 /// processes will share the main_sender and use it to send message to the main
 /// process.
+///
+/// A channel is a SOCK_SEQPACKET socket pair, which preserves message
+/// boundaries. Messages are serialized into a frame that is prefixed with the
+/// length of the serialized message. Every frame is sent as a single packet,
+/// which the kernel delivers atomically, so that frames of processes sharing
+/// a sender cannot be interleaved. The receiver uses the length to allocate a
+/// buffer that fits the whole packet.
 
-trait SenderExt {
-    fn write_message(&mut self, msg: Message) -> Result<()>;
+struct Sender {
+    fd: RawFd,
 }
 
-impl SenderExt for Sender {
-    #[inline]
-    fn write_message(&mut self, msg: Message) -> Result<()> {
-        let bytes = (msg as u8).to_be_bytes();
-        self.write_all(&bytes)
-            .with_context(|| format!("Failed to write message {:?} to the pipe", bytes))?;
+impl Sender {
+    fn send(&mut self, msg: Message) -> Result<()> {
+        let frame = encode_frame(&msg)?;
+        self.send_frame(&frame)
+            .with_context(|| format!("failed to send message {:?}", msg))
+    }
+
+    /// Sends the fd as SCM_RIGHTS ancillary data of a single byte packet
+    fn send_fd(&mut self, fd: RawFd) -> Result<()> {
+        let iov = [uio::IoVec::from_slice(&[0u8])];
+        let fds = [fd];
+        let cmsg = socket::ControlMessage::ScmRights(&fds);
+        socket::sendmsg(
+            self.fd,
+            &iov,
+            &[cmsg],
+            MsgFlags::from_bits_truncate(libc::MSG_NOSIGNAL),
+            None,
+        )
+        .context("failed to send fd to channel")?;
         Ok(())
     }
+
+    /// Sends the frame as a single packet. Splitting it up would allow the packets
+    /// of processes sharing the sender to interleave, so frames which do not fit
+    /// into the socket buffer fail with EMSGSIZE instead.
+    fn send_frame(&mut self, frame: &[u8]) -> Result<()> {
+        send_packet(self.fd, frame)
+    }
+}
+
+impl AsRawFd for Sender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+struct Receiver {
+    fd: RawFd,
+}
+
+impl Receiver {
+    fn recv(&mut self) -> Result<Message> {
+        let payload = self.recv_frame()?;
+        decode_message(&payload)
+    }
+
+    /// Receives a fd that has been sent with send_fd
+    fn recv_fd(&mut self) -> Result<RawFd> {
+        let mut buf = [0u8; 1];
+        let iov = [uio::IoVec::from_mut_slice(&mut buf)];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let msg = socket::recvmsg(
+            self.fd,
+            &iov,
+            Some(&mut cmsg_buf),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .context("failed to receive fd from channel")?;
+
+        msg.cmsgs()
+            .filter_map(|cmsg| match cmsg {
+                socket::ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+                _ => None,
+            })
+            .next()
+            .context("no fd was received from the channel")
+    }
+
+    /// Receives a frame and returns its payload
+    fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        // the header is only peeked, as the rest of a packet is discarded once
+        // part of it has been received
+        let mut header = [0; FRAME_HEADER_SIZE];
+        let len = recv_packet(self.fd, &mut header, MsgFlags::MSG_PEEK)?;
+        if len == 0 {
+            bail!("channel has been closed by the sender");
+        }
+        if len < FRAME_HEADER_SIZE {
+            bail!("received truncated frame of {} bytes", len);
+        }
+
+        let frame_len = u32::from_be_bytes(header) as usize;
+        // one more byte than expected is received to detect oversized frames
+        let mut packet = vec![0; FRAME_HEADER_SIZE + frame_len + 1];
+        let len = recv_packet(self.fd, &mut packet, MsgFlags::empty())?;
+        if len != FRAME_HEADER_SIZE + frame_len {
+            bail!(
+                "received {} bytes for a message of {} bytes",
+                len.saturating_sub(FRAME_HEADER_SIZE),
+                frame_len
+            );
+        }
+
+        packet.truncate(len);
+        Ok(packet.split_off(FRAME_HEADER_SIZE))
+    }
+}
+
+impl AsRawFd for Receiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+/// Blocks until fd becomes readable or fails once the timeout has expired
+fn wait_readable_timeout(fd: RawFd, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll(&mut fds, remaining.as_millis() as i32) {
+            // hang ups are reported by the subsequent read
+            Ok(0) => bail!("timed out after {:?}", timeout),
+            Ok(_) => return Ok(()),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).context("failed to poll channel"),
+        }
+    }
+}
+
+fn encode_frame(msg: &Message) -> Result<Vec<u8>> {
+    let payload = serde_json::to_vec(msg).context("failed to serialize message")?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+fn decode_message(payload: &[u8]) -> Result<Message> {
+    serde_json::from_slice(payload).context("failed to deserialize message")
+}
+
+fn send_packet(fd: RawFd, packet: &[u8]) -> Result<()> {
+    loop {
+        // the receiver may already be gone if the sending process fails, which must
+        // not kill the process with SIGPIPE
+        match socket::send(fd, packet, MsgFlags::from_bits_truncate(libc::MSG_NOSIGNAL)) {
+            // a packet of a SOCK_SEQPACKET socket is either sent as a whole or not at all
+            Ok(_) => return Ok(()),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).context("failed to send to channel"),
+        }
+    }
+}
+
+fn recv_packet(fd: RawFd, packet: &mut [u8], flags: MsgFlags) -> Result<usize> {
+    loop {
+        match socket::recv(fd, packet, flags) {
+            Ok(len) => return Ok(len),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).context("failed to receive from channel"),
+        }
+    }
+}
+
+/// Validates a pid received over a channel. The pid has to be the one that is
+/// visible from the pid namespace of the receiving process.
+fn validate_pid(pid: i32) -> Result<Pid> {
+    if pid <= 0 {
+        bail!("received invalid pid {}", pid);
+    }
+
+    Ok(Pid::from_raw(pid))
 }
 
 pub fn main_channel() -> Result<(MainSender, MainReceiver)> {
-    let (sender, receiver) = new_pipe()?;
+    let (sender, receiver) = new_channel()?;
     Ok((MainSender { sender }, MainReceiver { receiver }))
 }
 
@@ -49,15 +222,32 @@ impl MainSender {
     // this needs to be done from the parent see https://man7.org/linux/man-pages/man7/user_namespaces.7.html
     pub fn identifier_mapping_request(&mut self) -> Result<()> {
         log::debug!("send identifier mapping request");
-        self.sender.write_message(Message::WriteMapping)?;
+        self.sender.send(Message::WriteMapping)?;
         Ok(())
     }
 
+    /// Sends over the IntermediateReady carrying the pid of the init process.
+    /// The pid must be the one returned by fork in the intermediate process and not
+    /// the one seen by the init process itself, as only the former is valid in the
+    /// pid namespace of the main process.
     pub fn intermediate_ready(&mut self, pid: Pid) -> Result<()> {
-        // Send over the IntermediateReady follow by the pid.
         log::debug!("sending init pid ({:?})", pid);
-        self.sender.write_message(Message::IntermediateReady)?;
-        self.sender.write_all(&(pid.as_raw()).to_be_bytes())?;
+        self.sender.send(Message::IntermediateReady(pid.as_raw()))?;
+        Ok(())
+    }
+
+    /// Requests the main process to create the idmapped mounts, once the user namespace
+    /// of the container has been set up
+    pub fn idmapped_mounts_request(&mut self) -> Result<()> {
+        log::debug!("send idmapped mounts request");
+        self.sender.send(Message::IdmappedMountsRequest)?;
+        Ok(())
+    }
+
+    /// Sends the error that made the intermediate process fail, so that the main
+    /// process can report it
+    pub fn send_error(&mut self, err: &anyhow::Error) -> Result<()> {
+        self.sender.send(Message::Error(format!("{:#}", err)))?;
         Ok(())
     }
 
@@ -75,37 +265,33 @@ impl MainReceiver {
     /// Waits for associated intermediate process to send ready message
     /// and return the pid of init process which is forked by intermediate process
     pub fn wait_for_intermediate_ready(&mut self) -> Result<Pid> {
-        let mut buf = [0; 1];
-        self.receiver
-            .read_exact(&mut buf)
+        let msg = self
+            .receiver
+            .recv()
             .with_context(|| "failed to receive a message from the intermediate process")?;
 
-        match Message::from(u8::from_be_bytes(buf)) {
-            Message::IntermediateReady => {
+        match msg {
+            Message::IntermediateReady(pid) => {
                 log::debug!("received intermediate ready message");
-                // Read the Pid which will be i32 or 4 bytes.
-                let mut buf = [0; 4];
-                self.receiver
-                    .read_exact(&mut buf)
-                    .with_context(|| "failed to receive a message from the intermediate process")?;
-
-                Ok(Pid::from_raw(i32::from_be_bytes(buf)))
+                validate_pid(pid).context("invalid init pid from the intermediate process")
             }
+            Message::Error(err) => Err(anyhow!(err)),
             msg => bail!(
                 "receive unexpected message {:?} waiting for intermediate ready",
                 msg
             ),
         }
     }
+
     pub fn wait_for_mapping_request(&mut self) -> Result<()> {
-        let mut buf = [0; 1];
-        self.receiver
-            .read_exact(&mut buf)
+        let msg = self
+            .receiver
+            .recv()
             .with_context(|| "failed to receive a message from the child process")?;
 
-        // convert to Message wrapper
-        match Message::from(u8::from_be_bytes(buf)) {
+        match msg {
             Message::WriteMapping => Ok(()),
+            Message::Error(err) => Err(anyhow!(err)),
             msg => bail!(
                 "receive unexpected message {:?} waiting for mapping request",
                 msg
@@ -113,6 +299,22 @@ impl MainReceiver {
         }
     }
 
+    pub fn wait_for_idmapped_mounts_request(&mut self) -> Result<()> {
+        let msg = self
+            .receiver
+            .recv()
+            .with_context(|| "failed to receive a message from the child process")?;
+
+        match msg {
+            Message::IdmappedMountsRequest => Ok(()),
+            Message::Error(err) => Err(anyhow!(err)),
+            msg => bail!(
+                "receive unexpected message {:?} waiting for idmapped mounts request",
+                msg
+            ),
+        }
+    }
+
     pub fn close(&self) -> Result<()> {
         unistd::close(self.receiver.as_raw_fd())?;
         Ok(())
@@ -120,7 +322,7 @@ impl MainReceiver {
 }
 
 pub fn intermediate_channel() -> Result<(IntermediateSender, IntermediateReceiver)> {
-    let (sender, receiver) = new_pipe()?;
+    let (sender, receiver) = new_channel()?;
     Ok((
         IntermediateSender { sender },
         IntermediateReceiver { receiver },
@@ -134,13 +336,37 @@ pub struct IntermediateSender {
 impl IntermediateSender {
     pub fn mapping_written(&mut self) -> Result<()> {
         log::debug!("identifier mapping written");
-        self.sender
-            .write_all(&(Message::MappingWritten as u8).to_be_bytes())?;
+        self.sender.send(Message::MappingWritten)?;
+        Ok(())
+    }
+
+    /// Passes the detached mount trees of the idmapped mounts to the intermediate process
+    pub fn idmapped_mounts(&mut self, trees: &[RawFd]) -> Result<()> {
+        log::debug!("send {} idmapped mounts", trees.len());
+        self.sender.send(Message::IdmappedMounts(trees.len()))?;
+        for tree in trees {
+            self.sender.send_fd(*tree)?;
+        }
         Ok(())
     }
 
     pub fn init_ready(&mut self) -> Result<()> {
-        self.sender.write_message(Message::InitReady)?;
+        self.sender.send(Message::InitReady)?;
+        Ok(())
+    }
+
+    /// Passes the seccomp notify fd to the intermediate process, which forwards
+    /// it to the seccomp agent
+    pub fn seccomp_notify_request(&mut self, fd: RawFd) -> Result<()> {
+        self.sender.send(Message::SeccompNotify)?;
+        self.sender.send_fd(fd)?;
+        Ok(())
+    }
+
+    /// Sends the error that made the init process fail, so that the intermediate
+    /// process can pass it on to the main process
+    pub fn send_error(&mut self, err: &anyhow::Error) -> Result<()> {
+        self.sender.send(Message::Error(format!("{:#}", err)))?;
         Ok(())
     }
 
@@ -155,16 +381,20 @@ pub struct IntermediateReceiver {
 }
 
 impl IntermediateReceiver {
-    // wait until the parent process has finished writing the id mappings
-    pub fn wait_for_mapping_ack(&mut self) -> Result<()> {
+    // wait until the parent process has finished writing the id mappings. Fails if
+    // the ack does not arrive within the timeout, e.g. because the main process died.
+    pub fn wait_for_mapping_ack(&mut self, timeout: Duration) -> Result<()> {
         log::debug!("waiting for mapping ack");
-        let mut buf = [0; 1];
-        self.receiver
-            .read_exact(&mut buf)
+        wait_readable_timeout(self.receiver.as_raw_fd(), timeout)
+            .context("the main process did not write the uid/gid mappings")?;
+        let msg = self
+            .receiver
+            .recv()
             .with_context(|| "Failed to receive a message from the main process.")?;
 
-        match Message::from(u8::from_be_bytes(buf)) {
+        match msg {
             Message::MappingWritten => Ok(()),
+            Message::Error(err) => Err(anyhow!(err)),
             msg => bail!(
                 "receive unexpected message {:?} in waiting for mapping ack",
                 msg
@@ -172,16 +402,64 @@ impl IntermediateReceiver {
         }
     }
 
-    /// Waits for associated init process to send ready message
-    /// and return the pid of init process which is forked by init process
-    pub fn wait_for_init_ready(&mut self) -> Result<()> {
-        let mut buf = [0; 1];
-        self.receiver
-            .read_exact(&mut buf)
+    /// Waits for the main process to send the idmapped mount trees. Fails if they do
+    /// not arrive within the timeout, e.g. because the main process died.
+    pub fn wait_for_idmapped_mounts(&mut self, timeout: Duration) -> Result<Vec<RawFd>> {
+        log::debug!("waiting for idmapped mounts");
+        wait_readable_timeout(self.receiver.as_raw_fd(), timeout)
+            .context("the main process did not create the idmapped mounts")?;
+        let msg = self
+            .receiver
+            .recv()
+            .with_context(|| "Failed to receive a message from the main process.")?;
+
+        match msg {
+            Message::IdmappedMounts(count) => (0..count).map(|_| self.receiver.recv_fd()).collect(),
+            Message::Error(err) => Err(anyhow!(err)),
+            msg => bail!(
+                "receive unexpected message {:?} waiting for idmapped mounts",
+                msg
+            ),
+        }
+    }
+
+    /// Waits for the init process to send its seccomp notify fd. Fails if the init
+    /// process exits before sending it.
+    pub fn wait_for_seccomp_request(&mut self, init_pid: Pid) -> Result<RawFd> {
+        ChildWatcher::new(init_pid)?
+            .wait_readable(self.receiver.as_raw_fd())
+            .context("init process exited before it sent the seccomp notify fd")?;
+
+        let msg = self
+            .receiver
+            .recv()
+            .with_context(|| "Failed to receive a message from the init process.")?;
+
+        match msg {
+            Message::SeccompNotify => self.receiver.recv_fd(),
+            Message::Error(err) => Err(anyhow!(err)),
+            msg => bail!(
+                "receive unexpected message {:?} waiting for seccomp notify fd",
+                msg
+            ),
+        }
+    }
+
+    /// Waits for associated init process to send ready message. Fails if the init
+    /// process exits before sending it, instead of blocking forever.
+    pub fn wait_for_init_ready(&mut self, init_pid: Pid) -> Result<()> {
+        ChildWatcher::new(init_pid)?
+            .wait_readable(self.receiver.as_raw_fd())
+            .context("init process exited before it became ready")?;
+
+        let msg = self
+            .receiver
+            .recv()
             .with_context(|| "Failed to receive a message from the init process.")?;
 
-        match Message::from(u8::from_be_bytes(buf)) {
+        match msg {
             Message::InitReady => Ok(()),
+            Message::Error(err) => Err(anyhow!(err)),
             msg => bail!(
                 "receive unexpected message {:?} waiting for init ready",
                 msg
@@ -195,8 +473,112 @@ impl IntermediateReceiver {
     }
 }
 
+/// Detects the exit of a child process while waiting for a message from it
+#[derive(Debug, PartialEq)]
+enum ChildWatcher {
+    /// pidfd of the child, which becomes readable when the child exits
+    Pidfd { pidfd: RawFd, child: Pid },
+    /// Periodically checks the child with waitpid. Used on kernels older than
+    /// 5.3, which do not support pidfd_open.
+    Waitpid(Pid),
+}
+
+impl ChildWatcher {
+    fn new(child: Pid) -> Result<Self> {
+        Self::select(child, pidfd_open(child))
+    }
+
+    fn select(child: Pid, pidfd: nix::Result<RawFd>) -> Result<Self> {
+        match pidfd {
+            Ok(pidfd) => Ok(ChildWatcher::Pidfd { pidfd, child }),
+            // ESRCH means that the child has already been reaped, which waitpid will report
+            Err(Errno::ENOSYS) | Err(Errno::ESRCH) => Ok(ChildWatcher::Waitpid(child)),
+            Err(err) => Err(err).with_context(|| format!("failed to open pidfd of {}", child)),
+        }
+    }
+
+    /// Blocks until fd becomes readable or fails if the child exits before that.
+    /// A message that has been sent right before the child exited is still received.
+    fn wait_readable(&self, fd: RawFd) -> Result<()> {
+        loop {
+            let mut fds = vec![PollFd::new(fd, PollFlags::POLLIN)];
+            let timeout = match self {
+                ChildWatcher::Pidfd { pidfd, .. } => {
+                    fds.push(PollFd::new(*pidfd, PollFlags::POLLIN));
+                    -1
+                }
+                ChildWatcher::Waitpid(_) => WAITPID_INTERVAL,
+            };
+
+            match poll(&mut fds, timeout) {
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).context("failed to poll channel"),
+            }
+
+            // hang ups are reported by the subsequent read
+            if Self::has_events(&fds[0]) {
+                return Ok(());
+            }
+
+            match self {
+                ChildWatcher::Pidfd { child, .. } => {
+                    if Self::has_events(&fds[1]) {
+                        let status = wait::waitpid(*child, None)
+                            .context("failed to wait for child process")?;
+                        return Err(Self::exit_error(status));
+                    }
+                }
+                ChildWatcher::Waitpid(child) => {
+                    let status = match wait::waitpid(*child, Some(WaitPidFlag::WNOHANG)) {
+                        Ok(WaitStatus::StillAlive) => continue,
+                        Ok(status) => status,
+                        Err(Errno::ECHILD) => bail!("child process {} does not exist", child),
+                        Err(err) => return Err(err).context("failed to wait for child process"),
+                    };
+
+                    let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+                    if poll(&mut fds, 0)? > 0 && Self::has_events(&fds[0]) {
+                        return Ok(());
+                    }
+                    return Err(Self::exit_error(status));
+                }
+            }
+        }
+    }
+
+    fn has_events(fd: &PollFd) -> bool {
+        fd.revents().map_or(false, |events| !events.is_empty())
+    }
+
+    fn exit_error(status: WaitStatus) -> anyhow::Error {
+        match status {
+            WaitStatus::Exited(pid, code) => {
+                anyhow!("child process {} exited with status {}", pid, code)
+            }
+            WaitStatus::Signaled(pid, signal, _) => {
+                anyhow!("child process {} was killed by signal {}", pid, signal)
+            }
+            status => anyhow!("child process stopped unexpectedly: {:?}", status),
+        }
+    }
+}
+
+impl Drop for ChildWatcher {
+    fn drop(&mut self) {
+        if let ChildWatcher::Pidfd { pidfd, .. } = self {
+            let _ = unistd::close(*pidfd);
+        }
+    }
+}
+
+fn pidfd_open(pid: Pid) -> nix::Result<RawFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    Errno::result(fd).map(|fd| fd as RawFd)
+}
+
 pub fn init_channel() -> Result<(InitSender, InitReceiver)> {
-    let (sender, receiver) = new_pipe()?;
+    let (sender, receiver) = new_channel()?;
     Ok((InitSender { sender }, InitReceiver { receiver }))
 }
 
@@ -205,6 +587,11 @@ pub struct InitSender {
 }
 
 impl InitSender {
+    pub fn seccomp_notify_done(&mut self) -> Result<()> {
+        self.sender.send(Message::SeccompNotifyDone)?;
+        Ok(())
+    }
+
     pub fn close(&self) -> Result<()> {
         unistd::close(self.sender.as_raw_fd())?;
         Ok(())
@@ -216,38 +603,164 @@ pub struct InitReceiver {
 }
 
 impl InitReceiver {
+    /// Waits until the seccomp notify fd has been handed over to the seccomp agent
+    pub fn wait_for_seccomp_request_done(&mut self) -> Result<()> {
+        let msg = self
+            .receiver
+            .recv()
+            .with_context(|| "Failed to receive a message from the intermediate process.")?;
+
+        match msg {
+            Message::SeccompNotifyDone => Ok(()),
+            msg => bail!(
+                "receive unexpected message {:?} waiting for seccomp notify done",
+                msg
+            ),
+        }
+    }
+
     pub fn close(&self) -> Result<()> {
         unistd::close(self.receiver.as_raw_fd())?;
         Ok(())
     }
 }
 
-fn new_pipe() -> Result<(Sender, Receiver)> {
-    let (sender, receiver) = pipe::new()?;
+fn new_channel() -> Result<(Sender, Receiver)> {
     // Our use case is for the process to wait for the communication to come
-    // through, so we set nonblocking to false here (double negative). It is
-    // expected that the waiting process will block and wait.
-    receiver
-        .set_nonblocking(false)
-        .with_context(|| "Failed to set channel receiver to blocking")?;
-    Ok((sender, receiver))
+    // through, so the socket is blocking. It is expected that the waiting
+    // process will block and wait.
+    let (sender, receiver) = socket::socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )
+    .with_context(|| "Failed to create channel")?;
+    Ok((Sender { fd: sender }, Receiver { fd: receiver }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow::Context;
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
     use nix::sys::wait;
     use nix::unistd;
     use serial_test::serial;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
 
     // Note: due to cargo test by default runs tests in parallel using a single
     // process, these tests should not be running in parallel with other tests.
     // Because we run tests in the same process, other tests may decide to close
     // down file descriptors or saturate the IOs in the OS.  The channel uses
-    // pipe to communicate and can potentially become flaky as a result. There
+    // a socket to communicate and can potentially become flaky as a result. There
     // is not much else we can do other than to run the tests in serial.
 
+    #[test]
+    fn test_validate_pid() -> Result<()> {
+        for raw in [1, 42, 65536, i32::MAX] {
+            assert_eq!(validate_pid(raw)?, Pid::from_raw(raw));
+        }
+        assert!(validate_pid(0).is_err());
+        assert!(validate_pid(-1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_fds_cloexec() -> Result<()> {
+        let (sender, receiver) = new_channel()?;
+        for fd in [sender.as_raw_fd(), receiver.as_raw_fd()] {
+            let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD)?);
+            assert!(
+                flags.contains(FdFlag::FD_CLOEXEC),
+                "fd {} is not CLOEXEC",
+                fd
+            );
+        }
+
+        unistd::close(sender.as_raw_fd())?;
+        unistd::close(receiver.as_raw_fd())?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_message_round_trip() -> Result<()> {
+        let (sender, receiver) = &mut new_channel()?;
+        let messages = vec![
+            Message::IntermediateReady(42),
+            Message::InitReady,
+            Message::WriteMapping,
+            Message::MappingWritten,
+            Message::Error("failed to mount /proc".to_owned()),
+            // larger than a page
+            Message::Error("x".repeat(3 * 4096)),
+        ];
+        for msg in messages {
+            sender.send(msg.clone())?;
+            assert_eq!(receiver.recv()?, msg);
+        }
+        unistd::close(sender.as_raw_fd())?;
+        unistd::close(receiver.as_raw_fd())?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_large_frame() -> Result<()> {
+        let (sender, receiver) = &mut new_channel()?;
+        let payload: Vec<u8> = (0..(3 * 4096 + 123)).map(|i| (i % 251) as u8).collect();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&payload);
+
+        sender.send_frame(&frame)?;
+        sender.send(Message::InitReady)?;
+        assert_eq!(receiver.recv_frame()?, payload);
+        // the following message is not torn by the large one
+        assert_eq!(receiver.recv()?, Message::InitReady);
+        unistd::close(sender.as_raw_fd())?;
+        unistd::close(receiver.as_raw_fd())?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_concurrent_senders() -> Result<()> {
+        let (sender, receiver) = &mut new_channel()?;
+        let fd = sender.as_raw_fd();
+        let handles: Vec<_> = (b'a'..=b'd')
+            .map(|c| {
+                std::thread::spawn(move || {
+                    let mut sender = Sender { fd };
+                    sender.send(Message::Error((c as char).to_string().repeat(3 * 4096)))
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("sender thread panicked")?;
+        }
+
+        // every message arrives in one piece, regardless of the order of the senders
+        for _ in 0..4 {
+            match receiver.recv()? {
+                Message::Error(text) => {
+                    assert_eq!(text.len(), 3 * 4096);
+                    assert!(text.chars().all(|c| text.starts_with(c)));
+                }
+                msg => bail!("unexpected message {:?}", msg),
+            }
+        }
+        unistd::close(sender.as_raw_fd())?;
+        unistd::close(receiver.as_raw_fd())?;
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_intermadiate_ready() -> Result<()> {
@@ -272,6 +785,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_intermediate_error() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                let err = receiver.wait_for_intermediate_ready().unwrap_err();
+                receiver.close()?;
+                assert_eq!(
+                    err.to_string(),
+                    "failed to prepare rootfs: failed to mount /proc"
+                );
+            }
+            unistd::ForkResult::Child => {
+                let err = anyhow!("failed to mount /proc").context("failed to prepare rootfs");
+                sender.send_error(&err)?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_id_mapping_request() -> Result<()> {
@@ -301,7 +839,7 @@ mod tests {
         match unsafe { unistd::fork()? } {
             unistd::ForkResult::Parent { child } => {
                 wait::waitpid(child, None)?;
-                receiver.wait_for_mapping_ack()?;
+                receiver.wait_for_mapping_ack(DEFAULT_MAPPING_TIMEOUT)?;
             }
             unistd::ForkResult::Child => {
                 sender
@@ -314,6 +852,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_id_mapping_ack_timeout() -> Result<()> {
+        let (sender, receiver) = &mut intermediate_channel()?;
+        // the sender stays open, but the ack never arrives
+        let err = receiver
+            .wait_for_mapping_ack(Duration::from_millis(100))
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("timed out after 100ms"));
+        sender.close()?;
+        receiver.close()?;
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_init_ready() -> Result<()> {
@@ -321,7 +874,7 @@ mod tests {
         match unsafe { unistd::fork()? } {
             unistd::ForkResult::Parent { child } => {
                 wait::waitpid(child, None)?;
-                receiver.wait_for_init_ready()?;
+                receiver.wait_for_init_ready(child)?;
                 receiver.close()?;
             }
             unistd::ForkResult::Child => {
@@ -336,6 +889,72 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_seccomp_notify() -> Result<()> {
+        let (sender, receiver) = &mut intermediate_channel()?;
+        let (init_sender, init_receiver) = &mut init_channel()?;
+        let tmp = crate::utils::create_temp_dir("test_channel_seccomp_notify")?;
+        let path = tmp.join("notify");
+        std::fs::write(&path, "seccomp")?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let fd = receiver.wait_for_seccomp_request(child)?;
+                let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD)?);
+                assert!(flags.contains(FdFlag::FD_CLOEXEC));
+                let mut content = String::new();
+                let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+                file.read_to_string(&mut content)?;
+                assert_eq!(content, "seccomp");
+                init_sender.seccomp_notify_done()?;
+                wait::waitpid(child, None)?;
+            }
+            unistd::ForkResult::Child => {
+                let file = std::fs::File::open(&path)?;
+                sender
+                    .seccomp_notify_request(file.as_raw_fd())
+                    .with_context(|| "Failed to send seccomp notify fd")?;
+                init_receiver.wait_for_seccomp_request_done()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_idmapped_mounts() -> Result<()> {
+        let (main_sender, main_receiver) = &mut main_channel()?;
+        let (sender, receiver) = &mut intermediate_channel()?;
+        let tmp = crate::utils::create_temp_dir("test_channel_idmapped_mounts")?;
+        let files = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let path = tmp.join(name);
+                std::fs::write(&path, name)?;
+                std::fs::File::open(&path)
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let fds: Vec<RawFd> = files.iter().map(|f| f.as_raw_fd()).collect();
+
+        main_sender.idmapped_mounts_request()?;
+        main_receiver.wait_for_idmapped_mounts_request()?;
+        sender.idmapped_mounts(&fds)?;
+        let received = receiver.wait_for_idmapped_mounts(DEFAULT_MAPPING_TIMEOUT)?;
+
+        let contents = received
+            .into_iter()
+            .map(|fd| {
+                let mut content = String::new();
+                unsafe { std::fs::File::from_raw_fd(fd) }.read_to_string(&mut content)?;
+                Ok(content)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(contents, vec!["first", "second"]);
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_main_graceful_exit() -> Result<()> {
@@ -359,6 +978,51 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_init_exits_without_ready() -> Result<()> {
+        let (sender, receiver) = &mut intermediate_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                // The sender is kept open, so without watching the child
+                // wait_for_init_ready would block forever.
+                let err = receiver.wait_for_init_ready(child).unwrap_err();
+                assert!(format!("{:?}", err).contains("exited with status 3"));
+                sender.close()?;
+                receiver.close()?;
+            }
+            unistd::ForkResult::Child => {
+                receiver.close()?;
+                std::process::exit(3);
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_child_watcher_selection() -> Result<()> {
+        let child = Pid::from_raw(42);
+        assert_eq!(
+            ChildWatcher::select(child, Err(Errno::ENOSYS))?,
+            ChildWatcher::Waitpid(child)
+        );
+        assert_eq!(
+            ChildWatcher::select(child, Err(Errno::ESRCH))?,
+            ChildWatcher::Waitpid(child)
+        );
+        assert!(ChildWatcher::select(child, Err(Errno::EMFILE)).is_err());
+
+        // the pidfd is preferred if available and closed on drop
+        let (pidfd, write_end) = unistd::pipe()?;
+        let watcher = ChildWatcher::select(child, Ok(pidfd))?;
+        assert_eq!(watcher, ChildWatcher::Pidfd { pidfd, child });
+        drop(watcher);
+        unistd::close(write_end)?;
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_intermediate_graceful_exit() -> Result<()> {
@@ -369,7 +1033,7 @@ mod tests {
                 // The child process will exit without send the init ready
                 // message. This should cause the wait_for_init_ready to error
                 // out, instead of keep blocking.
-                let ret = receiver.wait_for_init_ready();
+                let ret = receiver.wait_for_init_ready(child);
                 assert!(ret.is_err());
                 wait::waitpid(child, None)?;
             }
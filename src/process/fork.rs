@@ -1,6 +1,14 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use nix::sys::utsname;
 use nix::unistd;
 use nix::unistd::Pid;
+use std::os::unix::io::RawFd;
+
+// Flag of clone3 to place the child into the cgroup referred to by clone_args.cgroup.
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+// CLONE_INTO_CGROUP is supported since Linux 5.7
+const CLONE_INTO_CGROUP_MIN_KERNEL: (u32, u32) = (5, 7);
 
 // Execute the cb in another process. Make the fork works more like thread_spawn
 // or clone, so it is easier to reason. Compared to clone call, fork is easier
@@ -11,14 +19,137 @@ use nix::unistd::Pid;
 pub fn container_fork<F: FnOnce() -> Result<()>>(cb: F) -> Result<Pid> {
     match unsafe { unistd::fork()? } {
         unistd::ForkResult::Parent { child } => Ok(child),
-        unistd::ForkResult::Child => {
-            let ret = if let Err(error) = cb() {
-                log::debug!("failed to run fork: {:?}", error);
-                -1
-            } else {
-                0
-            };
-            std::process::exit(ret);
+        unistd::ForkResult::Child => run_child(cb),
+    }
+}
+
+// Same as container_fork, but the child is created inside of the cgroup referred
+// to by cgroup_fd, so that there is no window in which it runs outside of its
+// cgroup. Returns the pid of the child and whether it has been placed into the
+// cgroup, which is passed to the cb as well. If clone3 with CLONE_INTO_CGROUP is
+// not supported, the child is created by fork instead and has to be added to the
+// cgroup afterwards.
+pub fn container_clone_into_cgroup<F: FnOnce(bool) -> Result<()>>(
+    cgroup_fd: RawFd,
+    cb: F,
+) -> Result<(Pid, bool)> {
+    if !clone_into_cgroup_supported(kernel_version()) {
+        log::debug!("clone3 with CLONE_INTO_CGROUP is not supported, falling back to fork");
+        return Ok((container_fork(|| cb(false))?, false));
+    }
+
+    match clone3_into_cgroup(cgroup_fd) {
+        Ok(Some(child)) => Ok((child, true)),
+        Ok(None) => run_child(|| cb(true)),
+        Err(err) => {
+            log::debug!("clone3 into cgroup failed, falling back to fork: {}", err);
+            Ok((container_fork(|| cb(false))?, false))
         }
     }
 }
+
+fn run_child<F: FnOnce() -> Result<()>>(cb: F) -> ! {
+    let ret = if let Err(error) = cb() {
+        log::debug!("failed to run fork: {:?}", error);
+        -1
+    } else {
+        0
+    };
+    std::process::exit(ret);
+}
+
+// Mirrors struct clone_args of the kernel up to the cgroup field (CLONE_ARGS_SIZE_VER2)
+#[repr(C)]
+#[derive(Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
+
+// Returns the pid of the child in the parent and None in the child. Without a
+// stack clone3 behaves like fork, i.e. the child continues on a copy of the stack.
+fn clone3_into_cgroup(cgroup_fd: RawFd) -> Result<Option<Pid>> {
+    let mut args = CloneArgs {
+        flags: CLONE_INTO_CGROUP,
+        exit_signal: libc::SIGCHLD as u64,
+        cgroup: cgroup_fd as u64,
+        ..Default::default()
+    };
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_clone3,
+            &mut args as *mut CloneArgs,
+            std::mem::size_of::<CloneArgs>(),
+        )
+    };
+
+    match Errno::result(res) {
+        Ok(0) => Ok(None),
+        Ok(pid) => Ok(Some(Pid::from_raw(pid as i32))),
+        Err(err) => bail!("clone3 failed: {}", err),
+    }
+}
+
+fn kernel_version() -> Option<(u32, u32)> {
+    parse_kernel_version(utsname::uname().release())
+}
+
+// Parses the major and minor version from a kernel release, e.g. 5.10.0-8-amd64
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn clone_into_cgroup_supported(kernel_version: Option<(u32, u32)>) -> bool {
+    kernel_version.map_or(false, |version| version >= CLONE_INTO_CGROUP_MIN_KERNEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::wait::{self, WaitStatus};
+    use serial_test::serial;
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("5.10.0-8-amd64"), Some((5, 10)));
+        assert_eq!(parse_kernel_version("4.19.128"), Some((4, 19)));
+        assert_eq!(parse_kernel_version("6.1"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("5"), None);
+        assert_eq!(parse_kernel_version("unknown"), None);
+    }
+
+    #[test]
+    fn test_clone_into_cgroup_supported() {
+        assert!(clone_into_cgroup_supported(Some((5, 7))));
+        assert!(clone_into_cgroup_supported(Some((6, 0))));
+        assert!(!clone_into_cgroup_supported(Some((5, 6))));
+        assert!(!clone_into_cgroup_supported(Some((4, 19))));
+        assert!(!clone_into_cgroup_supported(None));
+    }
+
+    #[test]
+    #[serial]
+    fn test_clone_into_invalid_cgroup_falls_back_to_fork() -> Result<()> {
+        let (pid, in_cgroup) = container_clone_into_cgroup(-1, |in_cgroup| {
+            if in_cgroup {
+                bail!("child has been created inside of an invalid cgroup");
+            }
+            Ok(())
+        })?;
+        assert!(!in_cgroup);
+        assert_eq!(wait::waitpid(pid, None)?, WaitStatus::Exited(pid, 0));
+        Ok(())
+    }
+}
@@ -3,18 +3,21 @@ use crate::apparmor;
 use crate::syscall::Syscall;
 use crate::{
     capabilities, hooks, namespaces::Namespaces, process::channel, rootfs::RootFS,
-    rootless::Rootless, seccomp, tty, utils,
+    rootless::Rootless, seccomp, selinux, tty, utils,
 };
 use anyhow::{bail, Context, Result};
 use nix::mount::mount as nix_mount;
 use nix::mount::MsFlags;
 use nix::sched::CloneFlags;
+use nix::sys::statvfs::{self, FsFlags};
 use nix::{
     fcntl,
+    sys::stat::{self, Mode},
     unistd::{self, Gid, Uid},
 };
-use oci_spec::runtime::{LinuxNamespaceType, Spec, User};
+use oci_spec::runtime::{LinuxNamespaceType, LinuxSeccomp, Spec, User};
 use std::collections::HashMap;
+use std::os::unix::io::RawFd;
 use std::{
     env, fs,
     path::{Path, PathBuf},
@@ -56,26 +59,69 @@ fn get_open_fds() -> Result<Vec<i32>> {
 // starting the container.
 fn cleanup_file_descriptors(preserve_fds: i32) -> Result<()> {
     let open_fds = get_open_fds().with_context(|| "Failed to obtain opened fds")?;
+    fds_to_cleanup(&open_fds, preserve_fds)
+        .into_iter()
+        .for_each(|fd| {
+            // Intentionally ignore errors here -- the cases where this might fail
+            // are basically file descriptors that have already been closed.
+            let _ = fcntl::fcntl(fd, fcntl::F_SETFD(fcntl::FdFlag::FD_CLOEXEC));
+        });
+
+    Ok(())
+}
+
+// Determines which of the open fds must not be inherited by the container
+// payload. Only stdio and the fds explicitly preserved by the caller are
+// retained. Everything else, including the channel to the parent, the notify
+// socket and the console socket, is only needed until execve.
+fn fds_to_cleanup(open_fds: &[i32], preserve_fds: i32) -> Vec<i32> {
     // Include stdin, stdout, and stderr for fd 0, 1, and 2 respectively.
-    let min_fd = preserve_fds + 3;
-    let to_be_cleaned_up_fds: Vec<i32> = open_fds
+    let retained = 0..preserve_fds.max(0) + 3;
+    open_fds
         .iter()
-        .filter_map(|&fd| if fd >= min_fd { Some(fd) } else { None })
-        .collect();
+        .copied()
+        .filter(|fd| !retained.contains(fd))
+        .collect()
+}
+
+// Passes the seccomp notify fd to the seccomp agent. This is done by the
+// intermediate process, which still has access to the listener socket on the host
+// and knows the pid of the init process outside of the pid namespace.
+fn sync_seccomp(
+    seccomp: &LinuxSeccomp,
+    notify_fd: Option<RawFd>,
+    intermediate_sender: &mut channel::IntermediateSender,
+    init_receiver: &mut channel::InitReceiver,
+) -> Result<()> {
+    if !seccomp::is_notify(seccomp) {
+        return Ok(());
+    }
 
-    to_be_cleaned_up_fds.iter().for_each(|&fd| {
-        // Intentionally ignore errors here -- the cases where this might fail
-        // are basically file descriptors that have already been closed.
-        let _ = fcntl::fcntl(fd, fcntl::F_SETFD(fcntl::FdFlag::FD_CLOEXEC));
-    });
+    let fd = notify_fd.context("seccomp profile uses SCMP_ACT_NOTIFY, but no notify fd exists")?;
+    log::debug!("init process sends seccomp notify fd {}", fd);
+    intermediate_sender.seccomp_notify_request(fd)?;
+    init_receiver.wait_for_seccomp_request_done()?;
+    // the seccomp agent owns the fd from now on
+    unistd::close(fd).context("failed to close seccomp notify fd")?;
 
     Ok(())
 }
 
+// Translates a sysctl key like net.ipv4.ip_forward into its path below /proc/sys
+fn sysctl_path(kernel_param: &str) -> Result<PathBuf> {
+    if kernel_param.is_empty()
+        || kernel_param.contains('/')
+        || kernel_param.split('.').any(|part| part.is_empty())
+    {
+        bail!("invalid sysctl {:?}", kernel_param);
+    }
+
+    Ok(PathBuf::from("/proc/sys").join(kernel_param.replace(".", "/")))
+}
+
 fn sysctl(kernel_params: &HashMap<String, String>) -> Result<()> {
-    let sys = PathBuf::from("/proc/sys");
     for (kernel_param, value) in kernel_params {
-        let path = sys.join(kernel_param.replace(".", "/"));
+        let path = sysctl_path(kernel_param)?;
         log::debug!(
             "apply value {} to kernel parameter {}.",
             value,
@@ -88,6 +134,36 @@ fn sysctl(kernel_params: &HashMap<String, String>) -> Result<()> {
     Ok(())
 }
 
+// Flags used to remount a readonly path after it has been bind mounted.
+fn readonly_remount_flags() -> MsFlags {
+    MsFlags::MS_NOSUID
+        | MsFlags::MS_NODEV
+        | MsFlags::MS_NOEXEC
+        | MsFlags::MS_BIND
+        | MsFlags::MS_REMOUNT
+        | MsFlags::MS_RDONLY
+}
+
+// Flags used to remount the rootfs readonly. The flags the rootfs is currently
+// mounted with have to be kept, since the kernel refuses to clear flags that
+// are locked in a user namespace.
+fn readonly_rootfs_flags(current: FsFlags) -> MsFlags {
+    let mut flags = MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND;
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if current.contains(fs_flag) {
+            flags |= ms_flag;
+        }
+    }
+    flags
+}
+
 // make a read only path
 // The first time we bind mount, other flags are ignored,
 // so we need to mount it once and then remount it with the necessary flags specified.
@@ -102,7 +178,7 @@ fn readonly_path(path: &str) -> Result<()> {
     ) {
         // ignore error if path is not exist.
         Err(nix::errno::Errno::ENOENT) => {
-            log::warn!("readonly path {:?} not exist", path);
+            log::debug!("readonly path {:?} does not exist, skipping", path);
             return Ok(());
         }
         Err(err) => bail!(err),
@@ -113,52 +189,111 @@ fn readonly_path(path: &str) -> Result<()> {
         Some(path),
         path,
         None::<&str>,
-        MsFlags::MS_NOSUID
-            | MsFlags::MS_NODEV
-            | MsFlags::MS_NOEXEC
-            | MsFlags::MS_BIND
-            | MsFlags::MS_REMOUNT
-            | MsFlags::MS_RDONLY,
+        readonly_remount_flags(),
         None::<&str>,
-    )?;
+    )
+    .with_context(|| format!("failed to remount {} readonly", path))?;
     log::debug!("readonly path {:?} mounted", path);
     Ok(())
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum Mask {
+    // bind mount /dev/null over a file
+    DevNull,
+    // mount a read only tmpfs over a directory
+    Tmpfs,
+}
+
+// Decides how a masked path is hidden. Returns None if the path does not exist. Any
+// other error is returned, since the path would be left exposed otherwise.
+fn mask_for(path: &Path) -> Result<Option<Mask>> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(Some(Mask::Tmpfs)),
+        Ok(_) => Ok(Some(Mask::DevNull)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to stat masked path {:?}", path)),
+    }
+}
+
 // For files, bind mounts /dev/null over the top of the specified path.
 // For directories, mounts read-only tmpfs over the top of the specified path.
 fn masked_path(path: &str, mount_label: &Option<String>) -> Result<()> {
-    match nix_mount::<str, str, str, str>(
-        Some("/dev/null"),
-        path,
-        None::<&str>,
-        MsFlags::MS_BIND,
-        None::<&str>,
-    ) {
-        // ignore error if path is not exist.
-        Err(nix::errno::Errno::ENOENT) => {
-            log::warn!("masked path {:?} not exist", path);
-            return Ok(());
+    match mask_for(Path::new(path))? {
+        None => {
+            log::debug!("masked path {:?} does not exist, skipping", path);
+        }
+        Some(Mask::DevNull) => {
+            nix_mount::<str, str, str, str>(
+                Some("/dev/null"),
+                path,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+            .with_context(|| format!("failed to mask {}", path))?;
         }
-        Err(nix::errno::Errno::ENOTDIR) => {
+        Some(Mask::Tmpfs) => {
             let label = match mount_label {
                 Some(l) => format!("context={}", l),
                 None => "".to_string(),
             };
-            let _ = nix_mount(
+            nix_mount(
                 Some("tmpfs"),
                 path,
                 Some("tmpfs"),
                 MsFlags::MS_RDONLY,
                 Some(label.as_str()),
-            );
+            )
+            .with_context(|| format!("failed to mask {}", path))?;
         }
-        Err(err) => bail!(err),
-        Ok(_) => {}
-    };
+    }
     Ok(())
 }
 
+// The no new privileges bit is only set if it is explicitly enabled in the spec.
+fn should_set_no_new_privileges(no_new_privileges: Option<bool>) -> bool {
+    no_new_privileges.unwrap_or(false)
+}
+
+/// Creates the working directory of the container process if it does not exist
+/// yet, which the runtime spec allows. The created directory is owned by the
+/// container user, so that the process can use it after the uid/gid switch.
+fn ensure_cwd(cwd: &Path, user: &User, syscall: &dyn Syscall) -> Result<()> {
+    match fs::metadata(cwd) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => bail!("cwd {:?} exists but is not a directory", cwd),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            log::debug!("creating missing cwd {:?}", cwd);
+            fs::create_dir_all(cwd).with_context(|| format!("failed to create cwd {:?}", cwd))?;
+            syscall
+                .chown(
+                    cwd,
+                    Some(Uid::from_raw(user.uid())),
+                    Some(Gid::from_raw(user.gid())),
+                )
+                .with_context(|| format!("failed to chown cwd {:?}", cwd))
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to stat cwd {:?}", cwd)),
+    }
+}
+
+/// Enters into the rootfs jail. If a mount namespace is used, pivot_root is used
+/// and the old root is unmounted, but if we are on the host mount namespace, we
+/// use simple chroot. Scary things will happen if you try to pivot_root in the
+/// host mount namespace...
+fn enter_rootfs(syscall: &dyn Syscall, rootfs: &Path, mount_namespace: bool) -> Result<()> {
+    if mount_namespace {
+        syscall
+            .pivot_rootfs(rootfs)
+            .with_context(|| format!("Failed to pivot root to {:?}", rootfs))
+    } else {
+        syscall
+            .chroot(rootfs)
+            .with_context(|| format!("Failed to chroot to {:?}", rootfs))
+    }
+}
+
 // Enter into rest of namespace. Note, we already entered into user and pid
 // namespace. We also have to enter into mount namespace last since
 // namespace may be bind to /proc path. The /proc path will need to be
@@ -170,7 +305,11 @@ fn apply_rest_namespaces(
 ) -> Result<()> {
     namespaces
         .apply_namespaces(|ns_type| -> bool {
-            ns_type != CloneFlags::CLONE_NEWUSER && ns_type != CloneFlags::CLONE_NEWPID
+            // user, pid and cgroup namespaces have already been entered by the
+            // intermediate process
+            ns_type != CloneFlags::CLONE_NEWUSER
+                && ns_type != CloneFlags::CLONE_NEWPID
+                && ns_type != CloneFlags::CLONE_NEWCGROUP
         })
         .with_context(|| "failed to apply namespaces")?;
 
@@ -188,7 +327,7 @@ fn apply_rest_namespaces(
 pub fn container_init(
     args: ContainerArgs,
     intermediate_sender: &mut channel::IntermediateSender,
-    _init_receiver: &mut channel::InitReceiver,
+    init_receiver: &mut channel::InitReceiver,
 ) -> Result<()> {
     let syscall = args.syscall;
     let spec = &args.spec;
@@ -201,16 +340,16 @@ pub fn container_init(
     let namespaces = Namespaces::from(linux.namespaces().as_ref());
 
     // set up tty if specified
-    if let Some(csocketfd) = args.console_socket {
-        tty::setup_console(&csocketfd).with_context(|| "Failed to set up tty")?;
-    }
+    let console = match args.console_socket {
+        Some(csocketfd) => Some(
+            tty::setup_console(&csocketfd, proc.console_size().as_ref())
+                .with_context(|| "Failed to set up tty")?,
+        ),
+        None => None,
+    };
 
     apply_rest_namespaces(&namespaces, spec, syscall)?;
 
-    if let Some(true) = proc.no_new_privileges() {
-        let _ = prctl::set_no_new_privileges(true);
-    }
-
     if args.init {
         // create_container hook needs to be called after the namespace setup, but
         // before pivot_root is called. This runs in the container namespaces.
@@ -227,24 +366,23 @@ pub fn container_init(
                 rootfs_path,
                 bind_service,
                 namespaces.get(LinuxNamespaceType::Cgroup).is_some(),
+                args.rootless.is_some(),
+                &args.idmapped_mounts,
             )
             .with_context(|| "Failed to prepare rootfs")?;
 
-        // Entering into the rootfs jail. If mount namespace is specified, then
-        // we use pivot_root, but if we are on the host mount namespace, we will
-        // use simple chroot. Scary things will happen if you try to pivot_root
-        // in the host mount namespace...
-        if namespaces.get(LinuxNamespaceType::Mount).is_some() {
-            // change the root of filesystem of the process to the rootfs
-            syscall
-                .pivot_rootfs(rootfs_path)
-                .with_context(|| format!("Failed to pivot root to {:?}", rootfs_path))?;
-        } else {
-            syscall
-                .chroot(rootfs_path)
-                .with_context(|| format!("Failed to chroot to {:?}", rootfs_path))?;
+        if let Some(console) = &console {
+            rootfs
+                .mount_console(rootfs_path, console)
+                .context("failed to mount console")?;
         }
 
+        enter_rootfs(
+            syscall,
+            rootfs_path,
+            namespaces.get(LinuxNamespaceType::Mount).is_some(),
+        )?;
+
         rootfs
             .adjust_root_mount_propagation(linux)
             .context("Failed to set propagation type of root mount")?;
@@ -260,14 +398,24 @@ pub fn container_init(
             .with_context(|| format!("failed to apply apparmor profile {}", profile))?;
     }
 
+    if let Some(label) = proc.selinux_label() {
+        selinux::apply_label(label, args.selinux_enabled)
+            .with_context(|| format!("failed to apply selinux label {}", label))?;
+    }
+
+    // The rootfs is made readonly only after all mounts and devices have been
+    // set up, since they are created inside of it.
     if let Some(true) = spec.root().as_ref().map(|r| r.readonly().unwrap_or(false)) {
-        nix_mount(
-            None::<&str>,
-            "/",
-            None::<&str>,
-            MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND,
-            None::<&str>,
-        )?
+        let current = statvfs::statvfs("/").context("failed to stat the rootfs")?;
+        syscall
+            .mount(
+                None,
+                Path::new("/"),
+                None,
+                readonly_rootfs_flags(current.flags()),
+                None,
+            )
+            .context("failed to remount the rootfs readonly")?;
     }
 
     if let Some(paths) = linux.readonly_paths() {
@@ -288,6 +436,7 @@ pub fn container_init(
     let do_chdir = if cwd.is_empty() {
         false
     } else {
+        ensure_cwd(proc.cwd(), proc.user(), syscall)?;
         // This chdir must run before setting up the user.
         // This may allow the user running youki to access directories
         // that the container user cannot access.
@@ -308,12 +457,20 @@ pub fn container_init(
         )
         .context("Failed to configure uid and gid")?;
 
+    if let Some(umask) = umask_mode(proc.user().umask())? {
+        stat::umask(umask);
+    }
+
     // Without no new privileges, seccomp is a privileged operation. We have to
     // do this before dropping capabilities. Otherwise, we should do it later,
     // as close to exec as possible.
-    if linux.seccomp().is_some() && proc.no_new_privileges().is_none() {
-        seccomp::initialize_seccomp(linux.seccomp().as_ref().unwrap())
-            .context("Failed to execute seccomp")?;
+    let no_new_privileges = should_set_no_new_privileges(proc.no_new_privileges());
+    if let Some(seccomp) = linux.seccomp() {
+        if !no_new_privileges {
+            let notify_fd =
+                seccomp::initialize_seccomp(seccomp).context("Failed to execute seccomp")?;
+            sync_seccomp(seccomp, notify_fd, intermediate_sender, init_receiver)?;
+        }
     }
 
     capabilities::reset_effective(syscall).context("Failed to reset effective capabilities")?;
@@ -321,6 +478,14 @@ pub fn container_init(
         capabilities::drop_privileges(caps, syscall).context("Failed to drop capabilities")?;
     }
 
+    // Set no new privileges after the capabilities are in place, so that it
+    // only restricts the container payload and allows unprivileged seccomp.
+    if no_new_privileges {
+        if let Err(e) = prctl::set_no_new_privileges(true) {
+            bail!("failed to set no new privileges: {}", e);
+        }
+    }
+
     // Take care of LISTEN_FDS used for systemd-active-socket. If the value is
     // not 0, then we have to preserve those fds as well, and set up the correct
     // environment variables.
@@ -375,15 +540,40 @@ pub fn container_init(
         .iter()
         .for_each(|(key, value)| env::set_var(key, value));
 
+    // Resolve the executable against the PATH of the container before notifying
+    // the parent, so that a missing executable already fails the create.
+    let executable = match proc.args() {
+        Some(args) if !args.is_empty() => {
+            let path_var = env::var("PATH").unwrap_or_default();
+            utils::get_executable_path(&args[0], &path_var)
+                .with_context(|| format!("executable {:?} not found in $PATH", args[0]))?
+        }
+        _ => bail!("on non-Windows, at least one process arg entry is required"),
+    };
+
+    // The seccomp agent has to receive the notify fd while the container is
+    // created. In this case the profile is loaded before waiting for the start
+    // of the container, so the notify socket is subject to the profile as well.
+    if let Some(seccomp) = linux.seccomp() {
+        if no_new_privileges && seccomp::is_notify(seccomp) {
+            let notify_fd =
+                seccomp::initialize_seccomp(seccomp).context("Failed to execute seccomp")?;
+            sync_seccomp(seccomp, notify_fd, intermediate_sender, init_receiver)?;
+        }
+    }
+
     // notify parents that the init process is ready to execute the payload.
     // Note, we pass -1 here because we are already inside the pid namespace.
     // The pid outside the pid namespace should be recorded by the intermediate
     // process.
     intermediate_sender.init_ready()?;
 
-    // listing on the notify socket for container start command
-    let notify_socket = args.notify_socket;
-    notify_socket.wait_for_container_start()?;
+    // wait for the container start command, which releases init containers through
+    // the exec fifo and tenant containers through the notify socket
+    match &args.exec_fifo {
+        Some(exec_fifo) => exec_fifo.wait_for_start()?,
+        None => args.notify_socket.wait_for_container_start()?,
+    }
 
     // create_container hook needs to be called after the namespace setup, but
     // before pivot_root is called. This runs in the container namespaces.
@@ -394,7 +584,7 @@ pub fn container_init(
     }
 
     if let Some(seccomp) = linux.seccomp() {
-        if proc.no_new_privileges().is_some() {
+        if no_new_privileges && !seccomp::is_notify(seccomp) {
             // Initialize seccomp profile right before we are ready to execute the
             // payload. The notify socket will still need network related syscalls.
             seccomp::initialize_seccomp(seccomp).context("Failed to execute seccomp")?;
@@ -402,9 +592,7 @@ pub fn container_init(
     }
 
     if let Some(args) = proc.args() {
-        utils::do_exec(&args[0], args)?;
-    } else {
-        bail!("on non-Windows, at least one process arg entry is required")
+        utils::do_exec(&executable, args)?;
     }
 
     // After do_exec is called, the process is replaced with the container
@@ -448,10 +636,7 @@ fn set_supplementary_gids(user: &User, rootless: &Option<Rootless>) -> Result<()
             bail!("cannot set supplementary gids, setgroup is disabled");
         }
 
-        let gids: Vec<Gid> = additional_gids
-            .iter()
-            .map(|gid| Gid::from_raw(*gid))
-            .collect();
+        let gids = supplementary_gids(user.gid(), additional_gids);
 
         match rootless {
             Some(r) if r.privileged => {
@@ -470,11 +655,39 @@ fn set_supplementary_gids(user: &User, rootless: &Option<Rootless>) -> Result<()
     Ok(())
 }
 
+/// Converts the umask of the spec into a mode. None means that the umask inherited
+/// from the runtime is kept.
+fn umask_mode(umask: Option<u32>) -> Result<Option<Mode>> {
+    match umask {
+        Some(umask) => match Mode::from_bits(umask) {
+            Some(mode) if umask <= 0o777 => Ok(Some(mode)),
+            _ => bail!("invalid umask {:#o}, must be within 0 and 0o777", umask),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Assembles the supplementary groups of the container process, which consist of the
+/// primary gid followed by the additional gids without duplicates
+fn supplementary_gids(gid: u32, additional_gids: &[u32]) -> Vec<Gid> {
+    let mut gids = vec![Gid::from_raw(gid)];
+    for additional_gid in additional_gids {
+        let additional_gid = Gid::from_raw(*additional_gid);
+        if !gids.contains(&additional_gid) {
+            gids.push(additional_gid);
+        }
+    }
+
+    gids
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::syscall::test::TestHelperSyscall;
     use anyhow::{bail, Result};
     use nix::{fcntl, sys, unistd};
+    use oci_spec::runtime::UserBuilder;
     use serial_test::serial;
     use std::{fs, os::unix::prelude::AsRawFd};
 
@@ -523,4 +736,160 @@ mod tests {
         unistd::close(fd)?;
         Ok(())
     }
+
+    #[test]
+    fn test_fds_to_cleanup() {
+        let open_fds = [0, 1, 2, 3, 4, 5, 9];
+        assert_eq!(fds_to_cleanup(&open_fds, 0), vec![3, 4, 5, 9]);
+        assert_eq!(fds_to_cleanup(&open_fds, 2), vec![5, 9]);
+        assert_eq!(fds_to_cleanup(&open_fds, 10), Vec::<i32>::new());
+        assert_eq!(fds_to_cleanup(&open_fds, -1), vec![3, 4, 5, 9]);
+        assert_eq!(fds_to_cleanup(&[], 0), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_umask_mode() -> Result<()> {
+        assert_eq!(umask_mode(None)?, None);
+        assert_eq!(
+            umask_mode(Some(0o022))?,
+            Some(Mode::from_bits_truncate(0o022))
+        );
+        assert_eq!(umask_mode(Some(0))?, Some(Mode::empty()));
+        assert_eq!(
+            umask_mode(Some(0o777))?,
+            Some(Mode::from_bits_truncate(0o777))
+        );
+        assert!(umask_mode(Some(0o1000)).is_err());
+        assert!(umask_mode(Some(u32::MAX)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_supplementary_gids() {
+        let gids = |raw: &[u32]| raw.iter().map(|g| Gid::from_raw(*g)).collect::<Vec<_>>();
+        assert_eq!(supplementary_gids(1000, &[10, 20]), gids(&[1000, 10, 20]));
+        assert_eq!(
+            supplementary_gids(1000, &[10, 1000, 20, 10]),
+            gids(&[1000, 10, 20])
+        );
+        assert_eq!(supplementary_gids(0, &[]), gids(&[0]));
+    }
+
+    #[test]
+    fn test_should_set_no_new_privileges() {
+        assert!(should_set_no_new_privileges(Some(true)));
+        assert!(!should_set_no_new_privileges(Some(false)));
+        assert!(!should_set_no_new_privileges(None));
+    }
+
+    #[test]
+    fn test_mask_for() -> Result<()> {
+        let tmp = utils::create_temp_dir("test_mask_for")?;
+        let file = tmp.join("file");
+        fs::write(&file, "")?;
+
+        assert_eq!(mask_for(&tmp)?, Some(Mask::Tmpfs));
+        assert_eq!(mask_for(&file)?, Some(Mask::DevNull));
+        assert_eq!(mask_for(&tmp.join("missing"))?, None);
+
+        // a path that cannot be resolved must not be skipped
+        let looped = tmp.join("loop");
+        std::os::unix::fs::symlink(&looped, &looped)?;
+        assert!(mask_for(&looped).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ensure_cwd_creates_missing_dir() -> Result<()> {
+        let tmp = utils::create_temp_dir("test_ensure_cwd_creates_missing_dir")?;
+        let cwd = tmp.join("work/dir");
+        let user = UserBuilder::default().uid(1000u32).gid(1001u32).build()?;
+        let syscall = TestHelperSyscall::default();
+
+        ensure_cwd(&cwd, &user, &syscall)?;
+
+        assert!(cwd.is_dir());
+        let chown_args = syscall.get_chown_args();
+        assert_eq!(chown_args.len(), 1);
+        assert_eq!(chown_args[0].path, cwd);
+        assert_eq!(chown_args[0].owner, Some(Uid::from_raw(1000)));
+        assert_eq!(chown_args[0].group, Some(Gid::from_raw(1001)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ensure_cwd_existing_dir() -> Result<()> {
+        let tmp = utils::create_temp_dir("test_ensure_cwd_existing_dir")?;
+        let syscall = TestHelperSyscall::default();
+
+        ensure_cwd(&tmp, &User::default(), &syscall)?;
+
+        assert!(syscall.get_chown_args().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ensure_cwd_not_a_directory() -> Result<()> {
+        let tmp = utils::create_temp_dir("test_ensure_cwd_not_a_directory")?;
+        let file = tmp.join("file");
+        fs::write(&file, "")?;
+        let syscall = TestHelperSyscall::default();
+
+        let err = ensure_cwd(&file, &User::default(), &syscall).unwrap_err();
+        assert!(format!("{}", err).contains("not a directory"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_enter_rootfs() -> Result<()> {
+        let rootfs = Path::new("/run/youki/rootfs");
+
+        let syscall = TestHelperSyscall::default();
+        enter_rootfs(&syscall, rootfs, true)?;
+        assert_eq!(syscall.get_pivot_rootfs_args(), vec![rootfs.to_path_buf()]);
+        assert!(syscall.get_chroot_args().is_empty());
+
+        let syscall = TestHelperSyscall::default();
+        enter_rootfs(&syscall, rootfs, false)?;
+        assert!(syscall.get_pivot_rootfs_args().is_empty());
+        assert_eq!(syscall.get_chroot_args(), vec![rootfs.to_path_buf()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_readonly_rootfs_flags() {
+        let flags = readonly_rootfs_flags(FsFlags::empty());
+        assert_eq!(
+            flags,
+            MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND
+        );
+
+        let flags = readonly_rootfs_flags(FsFlags::ST_NOSUID | FsFlags::ST_NODEV);
+        assert!(flags.contains(MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RDONLY));
+        assert!(!flags.contains(MsFlags::MS_NOEXEC));
+    }
+
+    #[test]
+    fn test_sysctl_path() -> Result<()> {
+        assert_eq!(
+            sysctl_path("net.ipv4.ip_forward")?,
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+        assert_eq!(
+            sysctl_path("kernel.shmmax")?,
+            PathBuf::from("/proc/sys/kernel/shmmax")
+        );
+        assert!(sysctl_path("").is_err());
+        assert!(sysctl_path("net..ipv4").is_err());
+        assert!(sysctl_path("net/../../etc").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_readonly_remount_flags() {
+        let flags = readonly_remount_flags();
+        assert!(flags.contains(MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY));
+        assert!(flags.contains(MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC));
+        assert!(!flags.contains(MsFlags::MS_REC));
+    }
 }
@@ -1,16 +1,25 @@
-use crate::{namespaces::Namespaces, process::channel, process::fork};
-use anyhow::{Context, Error, Result};
+use crate::{
+    annotations::Annotations,
+    namespaces::Namespaces,
+    process::{channel, fork, seccomp_listener},
+    rootfs::utils as rootfs_utils,
+    seccomp,
+};
+use anyhow::{bail, Context, Error, Result};
 use cgroups::common::CgroupManager;
-use nix::unistd::{Gid, Pid, Uid};
-use oci_spec::runtime::{LinuxNamespaceType, LinuxResources};
+use nix::unistd::{self, Gid, Pid, Uid};
+use oci_spec::runtime::{LinuxNamespaceType, LinuxResources, LinuxRlimit};
 use procfs::process::Process;
-use std::convert::From;
+use std::{convert::From, fs};
 
 use super::args::ContainerArgs;
 use super::init::container_init;
 
+const OOM_SCORE_ADJ_MIN: i32 = -1000;
+const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
 pub fn container_intermediate(
-    args: ContainerArgs,
+    mut args: ContainerArgs,
     intermediate_sender: &mut channel::IntermediateSender,
     intermediate_receiver: &mut channel::IntermediateReceiver,
     main_sender: &mut channel::MainSender,
@@ -34,7 +43,7 @@ pub fn container_intermediate(
             // allowed to write the uid/gid maps
             prctl::set_dumpable(true).unwrap();
             main_sender.identifier_mapping_request()?;
-            intermediate_receiver.wait_for_mapping_ack()?;
+            intermediate_receiver.wait_for_mapping_ack(args.mapping_timeout)?;
             prctl::set_dumpable(false).unwrap();
         }
 
@@ -49,11 +58,33 @@ pub fn container_intermediate(
         )?;
     }
 
+    // Mapping the ids of a mount requires privileges in the user namespace of the host,
+    // so the idmapped mounts are created by the main process and passed on to the init
+    // process, which attaches them.
+    let idmapped_mounts = rootfs_utils::idmapped_mounts(spec);
+    if args.init && !idmapped_mounts.is_empty() {
+        if namespaces.get(LinuxNamespaceType::User).is_none() {
+            bail!("idmapped mounts require a user namespace");
+        }
+
+        main_sender.idmapped_mounts_request()?;
+        let trees = intermediate_receiver
+            .wait_for_idmapped_mounts(args.mapping_timeout)
+            .context("failed to receive idmapped mounts")?;
+        args.idmapped_mounts = idmapped_mounts
+            .iter()
+            .map(|mount| mount.destination().clone())
+            .zip(trees)
+            .collect();
+    }
+
     // set limits and namespaces to the process
     let proc = spec.process().as_ref().context("no process in spec")?;
     if let Some(rlimits) = proc.rlimits() {
-        for rlimit in rlimits {
-            command.set_rlimit(rlimit).context("failed to set rlimit")?;
+        for rlimit in prepare_rlimits(rlimits)? {
+            command
+                .set_rlimit(rlimit)
+                .with_context(|| format!("failed to set rlimit {:?}", rlimit.typ()))?;
         }
     }
 
@@ -64,16 +95,42 @@ pub fn container_intermediate(
             .with_context(|| format!("Failed to enter pid namespace: {:?}", pid_namespace))?;
     }
 
+    // The time namespace is only entered by the children of this process, which
+    // means the offsets have to be written before the init process is forked.
+    let annotations = Annotations::new(spec);
+    let monotonic_offset = annotations.monotonic_offset()?;
+    let boottime_offset = annotations.boottime_offset()?;
+    if args.init && annotations.time_namespace()? {
+        namespaces.unshare_time(monotonic_offset, boottime_offset)?;
+    } else if args.init && (monotonic_offset.is_some() || boottime_offset.is_some()) {
+        bail!("time offsets require a time namespace");
+    }
+
     // this needs to be done before we create the init process, so that the init
     // process will already be captured by the cgroup
-    if args.rootless.is_none() {
-        apply_cgroups(
-            args.cgroup_manager.as_ref(),
-            linux.resources().as_ref(),
-            args.init,
-        )
-        .context("failed to apply cgroups")?
-    }
+    setup_cgroups(
+        args.cgroup_manager.as_ref(),
+        &namespaces,
+        linux.resources().as_ref(),
+        proc.oom_score_adj(),
+        annotations.cpu_idle()?,
+        args.init,
+        args.rootless.is_some(),
+        args.use_cgroups,
+        args.in_cgroup,
+    )?;
+
+    // The seccomp notify fd is passed on to the seccomp agent by this process,
+    // since the init process has no access to the listener socket anymore.
+    let notify_seccomp = linux
+        .seccomp()
+        .as_ref()
+        .filter(|seccomp| seccomp::is_notify(seccomp))
+        .cloned();
+    let state = args
+        .container
+        .as_ref()
+        .map(|container| container.state.clone());
 
     // We only need for init process to send us the ChildReady.
     let (init_sender, init_receiver) = &mut channel::init_channel()?;
@@ -89,19 +146,38 @@ pub fn container_intermediate(
         main_sender
             .close()
             .context("failed to close unused sender")?;
-        container_init(args, intermediate_sender, init_receiver)
+        if let Err(err) = container_init(args, intermediate_sender, init_receiver) {
+            // passed on to the main process by the intermediate process
+            let _ = intermediate_sender.send_error(&err);
+            return Err(err);
+        }
+
+        Ok(())
     })?;
     // Close unused fds in the parent process.
     intermediate_sender
         .close()
         .context("failed to close sender in the intermediate process")?;
+    if let Some(seccomp) = &notify_seccomp {
+        let state = state.context("seccomp notify requires the state of the container")?;
+        let fd = intermediate_receiver
+            .wait_for_seccomp_request(pid)
+            .context("failed to receive seccomp notify fd")?;
+        let result = seccomp_listener::send_seccomp_fd(seccomp, &state, pid, fd);
+        let _ = unistd::close(fd);
+        result?;
+        init_sender
+            .seccomp_notify_done()
+            .context("failed to notify init process")?;
+    }
     init_sender
         .close()
         .context("failed to close unused init sender")?;
-    // There is no point using the pid returned here, since the child will be
-    // inside the pid namespace already.
+    // The pid is only used to notice if the child exits before it becomes
+    // ready, the ready message itself can't carry it since the child is inside
+    // the pid namespace already.
     intermediate_receiver
-        .wait_for_init_ready()
+        .wait_for_init_ready(pid)
         .context("failed to wait for the child")?;
     // After the child (the container init process) becomes ready, we can signal
     // the parent (the main process) that we are ready.
@@ -112,23 +188,80 @@ pub fn container_intermediate(
     Ok(())
 }
 
+/// Moves the process into its cgroup and afterwards enters the cgroup namespace if one
+/// has been requested. The order matters, because the root of a new cgroup namespace
+/// is the cgroup of the process at the time of the unshare. Doing it the other way
+/// around would expose the cgroup hierarchy of the host to the container.
+/// Cgroups are neither joined nor applied for rootless containers or if cgroups
+/// have been disabled.
+#[allow(clippy::too_many_arguments)]
+fn setup_cgroups<C: CgroupManager + ?Sized>(
+    cmanager: &C,
+    namespaces: &Namespaces,
+    resources: Option<&LinuxResources>,
+    oom_score_adj: Option<i32>,
+    cpu_idle: Option<u64>,
+    init: bool,
+    rootless: bool,
+    use_cgroups: bool,
+    in_cgroup: bool,
+) -> Result<()> {
+    // oom_score_adj is a property of the process rather than the cgroup and is applied
+    // in every mode. The container init process is forked from this process and
+    // inherits it.
+    if let Some(score) = oom_score_adj {
+        set_oom_score_adj(Pid::this(), score)?;
+    }
+
+    if !use_cgroups {
+        log::debug!("cgroups are disabled, skip applying cgroups");
+    } else if !rootless {
+        apply_cgroups(
+            cmanager,
+            resources,
+            oom_score_adj,
+            cpu_idle,
+            init,
+            in_cgroup,
+        )
+        .context("failed to apply cgroups")?;
+    }
+
+    if let Some(cgroup_namespace) = namespaces.get(LinuxNamespaceType::Cgroup) {
+        namespaces
+            .unshare_or_setns(cgroup_namespace)
+            .with_context(|| format!("Failed to enter cgroup namespace: {:?}", cgroup_namespace))?;
+    }
+
+    Ok(())
+}
+
 fn apply_cgroups<C: CgroupManager + ?Sized>(
     cmanager: &C,
     resources: Option<&LinuxResources>,
+    oom_score_adj: Option<i32>,
+    cpu_idle: Option<u64>,
     init: bool,
+    in_cgroup: bool,
 ) -> Result<(), Error> {
-    let pid = Pid::from_raw(Process::myself()?.pid());
-    cmanager
-        .add_task(pid)
-        .with_context(|| format!("failed to add task {} to cgroup manager", pid))?;
+    // the process has been created inside of its cgroup already, if the kernel supports
+    // clone3 with CLONE_INTO_CGROUP
+    if !in_cgroup {
+        let pid = Pid::from_raw(Process::myself()?.pid());
+        cmanager
+            .add_task(pid)
+            .with_context(|| format!("failed to add task {} to cgroup manager", pid))?;
+    }
 
     if let Some(resources) = resources {
         if init {
             let controller_opt = cgroups::common::ControllerOpt {
+                // not part of the spec yet, but can be set through an annotation
+                cpu_idle,
                 resources,
                 freezer_state: None,
-                oom_score_adj: None,
-                disable_oom_killer: false,
+                oom_score_adj,
+                disable_oom_killer: resources.disable_oom_killer().unwrap_or(false),
             };
 
             cmanager
@@ -140,14 +273,105 @@ fn apply_cgroups<C: CgroupManager + ?Sized>(
     Ok(())
 }
 
+/// Validates the rlimits from the spec and removes duplicated types, where the last
+/// occurrence of a type takes precedence
+fn prepare_rlimits(rlimits: &[LinuxRlimit]) -> Result<Vec<&LinuxRlimit>> {
+    let mut prepared: Vec<&LinuxRlimit> = Vec::with_capacity(rlimits.len());
+    for rlimit in rlimits {
+        if rlimit.soft() > rlimit.hard() {
+            bail!(
+                "soft limit {} of rlimit {:?} is greater than its hard limit {}",
+                rlimit.soft(),
+                rlimit.typ(),
+                rlimit.hard()
+            );
+        }
+
+        if let Some(pos) = prepared.iter().position(|r| r.typ() == rlimit.typ()) {
+            log::debug!("rlimit {:?} is specified more than once", rlimit.typ());
+            prepared.remove(pos);
+        }
+        prepared.push(rlimit);
+    }
+
+    Ok(prepared)
+}
+
+fn validate_oom_score_adj(score: i32) -> Result<()> {
+    if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&score) {
+        bail!(
+            "oom_score_adj {} is out of range [{}, {}]",
+            score,
+            OOM_SCORE_ADJ_MIN,
+            OOM_SCORE_ADJ_MAX
+        );
+    }
+
+    Ok(())
+}
+
+fn set_oom_score_adj(pid: Pid, score: i32) -> Result<()> {
+    validate_oom_score_adj(score)?;
+    let path = format!("/proc/{}/oom_score_adj", pid);
+    fs::write(&path, score.to_string())
+        .with_context(|| format!("failed to write oom_score_adj {} to {}", score, path))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::apply_cgroups;
+    use super::{apply_cgroups, prepare_rlimits, setup_cgroups, validate_oom_score_adj};
+    use crate::{namespaces::Namespaces, syscall::test::TestHelperSyscall};
     use anyhow::Result;
-    use cgroups::test_manager::TestManager;
-    use nix::unistd::Pid;
-    use oci_spec::runtime::LinuxResources;
+    use cgroups::{
+        common::{CgroupManager, ControllerOpt, FreezerState},
+        stats::Stats,
+        test_manager::TestManager,
+    };
+    use nix::{sched::CloneFlags, unistd::Pid};
+    use oci_spec::runtime::{
+        LinuxNamespaceBuilder, LinuxNamespaceType, LinuxResources, LinuxRlimit, LinuxRlimitBuilder,
+        LinuxRlimitType,
+    };
     use procfs::process::Process;
+    use std::cell::RefCell;
+
+    /// Records whether the cgroup namespace had already been unshared when the
+    /// process was added to its cgroup
+    struct OrderingManager<'a> {
+        syscall: &'a TestHelperSyscall,
+        unshared_before_add: RefCell<Option<bool>>,
+    }
+
+    impl CgroupManager for OrderingManager<'_> {
+        fn add_task(&self, _pid: Pid) -> Result<()> {
+            let unshared = self
+                .syscall
+                .get_unshare_args()
+                .contains(&CloneFlags::CLONE_NEWCGROUP);
+            self.unshared_before_add.replace(Some(unshared));
+            Ok(())
+        }
+
+        fn apply(&self, _controller_opt: &ControllerOpt) -> Result<()> {
+            Ok(())
+        }
+
+        fn remove(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn freeze(&self, _state: FreezerState) -> Result<()> {
+            Ok(())
+        }
+
+        fn stats(&self) -> Result<Stats> {
+            Ok(Stats::default())
+        }
+
+        fn get_all_pids(&self) -> Result<Vec<Pid>> {
+            Ok(Vec::new())
+        }
+    }
 
     #[test]
     fn apply_cgroup_init() -> Result<()> {
@@ -156,7 +380,7 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), true)?;
+        apply_cgroups(&cmanager, Some(&resources), None, None, true, false)?;
 
         // assert
         assert!(cmanager.get_add_task_args().len() == 1);
@@ -175,7 +399,7 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), false)?;
+        apply_cgroups(&cmanager, Some(&resources), None, None, false, false)?;
 
         // assert
         assert_eq!(
@@ -192,7 +416,7 @@ mod tests {
         let cmanager = TestManager::default();
 
         // act
-        apply_cgroups(&cmanager, None, true)?;
+        apply_cgroups(&cmanager, None, None, None, true, false)?;
         // assert
         assert_eq!(
             cmanager.get_add_task_args()[0],
@@ -201,4 +425,180 @@ mod tests {
         assert!(!cmanager.apply_called());
         Ok(())
     }
+
+    #[test]
+    fn apply_cgroup_created_in_cgroup() -> Result<()> {
+        // arrange
+        let cmanager = TestManager::default();
+        let resources = LinuxResources::default();
+
+        // act
+        apply_cgroups(&cmanager, Some(&resources), None, None, true, true)?;
+
+        // assert
+        assert!(cmanager.get_add_task_args().is_empty());
+        assert!(cmanager.apply_called());
+        Ok(())
+    }
+
+    #[test]
+    fn apply_cgroup_cpu_idle() -> Result<()> {
+        // arrange
+        let cmanager = TestManager::default();
+        let resources = LinuxResources::default();
+
+        // act
+        apply_cgroups(&cmanager, Some(&resources), None, Some(1), true, false)?;
+
+        // assert
+        assert!(cmanager.apply_called());
+        assert_eq!(cmanager.get_cpu_idle(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn apply_cgroup_twice() -> Result<()> {
+        // arrange
+        let cmanager = TestManager::default();
+        let resources = LinuxResources::default();
+
+        // act
+        apply_cgroups(&cmanager, Some(&resources), None, None, true, false)?;
+        apply_cgroups(&cmanager, Some(&resources), None, None, false, false)?;
+
+        // assert
+        assert_eq!(
+            cmanager.get_add_task_args(),
+            vec![Pid::from_raw(Process::myself()?.pid())]
+        );
+        assert!(cmanager.apply_called());
+        Ok(())
+    }
+
+    #[test]
+    fn cgroup_namespace_after_cgroup_apply() -> Result<()> {
+        // arrange
+        let linux_namespaces = vec![LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Cgroup)
+            .build()?];
+        let namespaces = Namespaces::from(Some(&linux_namespaces));
+        let syscall: &TestHelperSyscall = namespaces.syscall().as_any().downcast_ref().unwrap();
+        let cmanager = OrderingManager {
+            syscall,
+            unshared_before_add: RefCell::new(None),
+        };
+
+        // act
+        setup_cgroups(
+            &cmanager,
+            &namespaces,
+            None,
+            None,
+            None,
+            true,
+            false,
+            true,
+            false,
+        )?;
+
+        // assert
+        assert_eq!(*cmanager.unshared_before_add.borrow(), Some(false));
+        assert_eq!(
+            syscall.get_unshare_args(),
+            vec![CloneFlags::CLONE_NEWCGROUP]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn setup_cgroups_disabled() -> Result<()> {
+        // arrange
+        let namespaces = Namespaces::from(None);
+        let cmanager = TestManager::default();
+        let resources = LinuxResources::default();
+
+        // act
+        setup_cgroups(
+            &cmanager,
+            &namespaces,
+            Some(&resources),
+            None,
+            None,
+            true,
+            false,
+            false,
+            false,
+        )?;
+
+        // assert
+        assert!(cmanager.get_add_task_args().is_empty());
+        assert!(!cmanager.apply_called());
+        Ok(())
+    }
+
+    #[test]
+    fn setup_cgroups_oom_score_adj_without_cgroups() {
+        let namespaces = Namespaces::from(None);
+        let cmanager = TestManager::default();
+
+        // an out of range score shows that oom_score_adj is applied, even though the
+        // cgroups are skipped for rootless containers and with cgroups disabled
+        for (rootless, use_cgroups) in &[(true, true), (false, false)] {
+            let err = setup_cgroups(
+                &cmanager,
+                &namespaces,
+                None,
+                Some(1001),
+                None,
+                true,
+                *rootless,
+                *use_cgroups,
+                false,
+            )
+            .unwrap_err();
+            assert!(err.to_string().contains("oom_score_adj"), "{}", err);
+        }
+        assert!(cmanager.get_add_task_args().is_empty());
+    }
+
+    fn rlimit(typ: LinuxRlimitType, soft: u64, hard: u64) -> LinuxRlimit {
+        LinuxRlimitBuilder::default()
+            .typ(typ)
+            .soft(soft)
+            .hard(hard)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn rlimit_soft_greater_than_hard() {
+        let rlimits = vec![rlimit(LinuxRlimitType::RlimitNofile, 2048, 1024)];
+        let err = prepare_rlimits(&rlimits).unwrap_err();
+        assert!(err.to_string().contains("RlimitNofile"));
+    }
+
+    #[test]
+    fn rlimit_duplicates_keep_last() -> Result<()> {
+        let rlimits = vec![
+            rlimit(LinuxRlimitType::RlimitNofile, 1024, 1024),
+            rlimit(LinuxRlimitType::RlimitCore, 0, 0),
+            rlimit(LinuxRlimitType::RlimitNofile, 4096, 8192),
+        ];
+        let prepared = prepare_rlimits(&rlimits)?;
+        assert_eq!(prepared, vec![&rlimits[1], &rlimits[2]]);
+        Ok(())
+    }
+
+    #[test]
+    fn oom_score_adj_in_range() {
+        assert!(validate_oom_score_adj(-1000).is_ok());
+        assert!(validate_oom_score_adj(500).is_ok());
+        assert!(validate_oom_score_adj(1000).is_ok());
+    }
+
+    #[test]
+    fn oom_score_adj_out_of_range() {
+        assert!(validate_oom_score_adj(1001).is_err());
+        assert!(validate_oom_score_adj(-1001).is_err());
+    }
 }
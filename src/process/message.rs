@@ -1,20 +1,22 @@
+use serde::{Deserialize, Serialize};
+
 /// Used as a wrapper for messages to be sent between child and parent processes
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Message {
-    IntermediateReady = 0x00,
-    InitReady = 0x01,
-    WriteMapping = 0x02,
-    MappingWritten = 0x03,
-}
-
-impl From<u8> for Message {
-    fn from(from: u8) -> Self {
-        match from {
-            0x00 => Message::IntermediateReady,
-            0x01 => Message::InitReady,
-            0x02 => Message::WriteMapping,
-            0x03 => Message::MappingWritten,
-            _ => panic!("unknown message: {:?}.", from),
-        }
-    }
+    /// Carries the pid of the init process as seen from the main process
+    IntermediateReady(i32),
+    InitReady,
+    WriteMapping,
+    MappingWritten,
+    /// Announces the seccomp notify fd of the init process, which is sent as
+    /// ancillary data right after this message
+    SeccompNotify,
+    SeccompNotifyDone,
+    /// Requests the main process to create the idmapped mounts of the container
+    IdmappedMountsRequest,
+    /// Announces the given number of idmapped mount trees, which are sent as ancillary
+    /// data right after this message
+    IdmappedMounts(usize),
+    /// Carries the error chain of a child process that failed
+    Error(String),
 }
@@ -7,3 +7,4 @@ pub mod fork;
 pub(crate) mod init;
 pub mod intermediate;
 pub mod message;
+pub mod seccomp_listener;
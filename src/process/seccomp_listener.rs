@@ -0,0 +1,171 @@
+//! Hands the seccomp notify fd of the container process over to the seccomp
+//! agent listening on the listenerPath of the seccomp profile. Along with the fd,
+//! the agent receives the container process state defined by the runtime spec.
+use std::os::unix::{
+    io::{AsRawFd, RawFd},
+    net::UnixStream,
+};
+
+use anyhow::{Context, Result};
+use nix::{
+    sys::{socket, uio},
+    unistd::Pid,
+};
+use oci_spec::runtime::LinuxSeccomp;
+use serde::{Deserialize, Serialize};
+
+use crate::container::State;
+
+/// Name of the seccomp notify fd in the fds of the container process state
+pub const SECCOMP_FD_NAME: &str = "seccompFd";
+
+/// State that is sent to the seccomp agent together with the notify fd
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerProcessState {
+    /// Version of the runtime spec
+    pub oci_version: String,
+    /// Names of the fds passed as ancillary data, in the same order
+    pub fds: Vec<String>,
+    /// Pid of the container process as seen by the runtime
+    pub pid: i32,
+    /// Opaque metadata from listenerMetadata of the seccomp profile
+    pub metadata: String,
+    /// State of the container
+    pub state: State,
+}
+
+impl ContainerProcessState {
+    pub fn new(state: &State, pid: Pid, metadata: Option<&str>) -> Self {
+        let mut state = state.clone();
+        state.pid = Some(pid.as_raw());
+
+        Self {
+            oci_version: state.oci_version.clone(),
+            fds: vec![SECCOMP_FD_NAME.to_owned()],
+            pid: pid.as_raw(),
+            metadata: metadata.unwrap_or_default().to_owned(),
+            state,
+        }
+    }
+}
+
+/// Connects to the listener of the seccomp agent and sends it the notify fd
+pub fn send_seccomp_fd(seccomp: &LinuxSeccomp, state: &State, pid: Pid, fd: RawFd) -> Result<()> {
+    let listener_path = seccomp
+        .listener_path()
+        .as_ref()
+        .context("seccomp profile uses SCMP_ACT_NOTIFY, but has no listenerPath")?;
+    let process_state =
+        ContainerProcessState::new(state, pid, seccomp.listener_metadata().as_deref());
+    let payload =
+        serde_json::to_vec(&process_state).context("failed to serialize container state")?;
+
+    let stream = UnixStream::connect(listener_path)
+        .with_context(|| format!("failed to connect to seccomp listener {:?}", listener_path))?;
+    send_with_fd(stream.as_raw_fd(), &payload, fd)
+        .with_context(|| format!("failed to send seccomp fd to {:?}", listener_path))
+}
+
+fn send_with_fd(socket: RawFd, payload: &[u8], fd: RawFd) -> Result<()> {
+    let iov = [uio::IoVec::from_slice(payload)];
+    let fds = [fd];
+    let cmsg = socket::ControlMessage::ScmRights(&fds);
+    socket::sendmsg(socket, &iov, &[cmsg], socket::MsgFlags::empty(), None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{container::ContainerStatus, utils::create_temp_dir};
+    use oci_spec::runtime::{LinuxSeccompAction, LinuxSeccompBuilder};
+    use std::{fs::File, os::unix::net::UnixListener, path::PathBuf};
+
+    fn state() -> State {
+        State::new(
+            "container",
+            ContainerStatus::Creating,
+            None,
+            PathBuf::from("/run/bundle"),
+        )
+    }
+
+    #[test]
+    fn test_container_process_state() -> Result<()> {
+        let process_state =
+            ContainerProcessState::new(&state(), Pid::from_raw(4422), Some("agent metadata"));
+        let value = serde_json::to_value(&process_state)?;
+
+        assert_eq!(value["ociVersion"], "v1.0.2");
+        assert_eq!(value["fds"], serde_json::json!(["seccompFd"]));
+        assert_eq!(value["pid"], 4422);
+        assert_eq!(value["metadata"], "agent metadata");
+        assert_eq!(value["state"]["id"], "container");
+        assert_eq!(value["state"]["status"], "creating");
+        assert_eq!(value["state"]["pid"], 4422);
+        assert_eq!(value["state"]["bundle"], "/run/bundle");
+
+        let decoded: ContainerProcessState = serde_json::from_value(value)?;
+        assert_eq!(decoded.fds, process_state.fds);
+        assert_eq!(decoded.state.pid, Some(4422));
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_process_state_without_metadata() {
+        let process_state = ContainerProcessState::new(&state(), Pid::from_raw(1), None);
+        assert_eq!(process_state.metadata, "");
+    }
+
+    #[test]
+    fn test_send_seccomp_fd() -> Result<()> {
+        let tmp = create_temp_dir("test_send_seccomp_fd")?;
+        let listener_path = tmp.join("seccomp.sock");
+        let listener = UnixListener::bind(&listener_path)?;
+        let seccomp = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .listener_path(listener_path)
+            .listener_metadata("agent metadata")
+            .build()?;
+        let file = File::open("/dev/null")?;
+
+        send_seccomp_fd(&seccomp, &state(), Pid::from_raw(42), file.as_raw_fd())?;
+
+        let (stream, _) = listener.accept()?;
+        let mut buf = [0u8; 4096];
+        let iov = [uio::IoVec::from_mut_slice(&mut buf)];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let msg = socket::recvmsg(
+            stream.as_raw_fd(),
+            &iov,
+            Some(&mut cmsg_buf),
+            socket::MsgFlags::empty(),
+        )?;
+        let fds: Vec<RawFd> = msg
+            .cmsgs()
+            .filter_map(|cmsg| match cmsg {
+                socket::ControlMessageOwned::ScmRights(fds) => Some(fds),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let received: ContainerProcessState = serde_json::from_slice(&buf[..msg.bytes])?;
+
+        assert_eq!(fds.len(), 1);
+        assert_eq!(received.pid, 42);
+        assert_eq!(received.metadata, "agent metadata");
+        assert_eq!(received.fds, vec![SECCOMP_FD_NAME]);
+        nix::unistd::close(fds[0])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_seccomp_fd_without_listener_path() -> Result<()> {
+        let seccomp = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .build()?;
+        assert!(send_seccomp_fd(&seccomp, &state(), Pid::from_raw(42), 0).is_err());
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use super::utils::to_sflag;
 use crate::syscall::{syscall::create_syscall, Syscall};
 use crate::utils::PathBufExt;
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use nix::{
     fcntl::{open, OFlag},
     mount::MsFlags,
@@ -33,7 +33,7 @@ impl Device {
         I: IntoIterator<Item = &'a LinuxDevice>,
     {
         let old_mode = umask(Mode::from_bits_truncate(0o000));
-        devices
+        let result = devices
             .into_iter()
             .map(|dev| {
                 if !dev.path().starts_with("/dev") {
@@ -53,10 +53,11 @@ impl Device {
                     self.mknod_dev(rootfs, dev)
                 }
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Result<Vec<_>>>();
+        // restore the umask even if a device could not be created
         umask(old_mode);
 
-        Ok(())
+        result.map(|_| ())
     }
 
     fn bind_dev(&self, rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
@@ -79,14 +80,30 @@ impl Device {
         Ok(())
     }
 
-    fn mknod_dev(&self, rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
-        fn makedev(major: i64, minor: i64) -> u64 {
-            ((minor & 0xff)
-                | ((major & 0xfff) << 8)
-                | ((minor & !0xff) << 12)
-                | ((major & !0xfff) << 32)) as u64
-        }
+    /// Bind mounts the pty slave allocated for the container to /dev/console
+    pub fn bind_console(&self, rootfs: &Path, console: &Path) -> Result<()> {
+        let container_console = rootfs.join("dev/console");
+        let fd = open(
+            &container_console,
+            OFlag::O_RDWR | OFlag::O_CREAT,
+            Mode::from_bits_truncate(0o600),
+        )
+        .with_context(|| format!("failed to create {:?}", container_console))?;
+        close(fd)?;
+        self.syscall
+            .mount(
+                Some(console),
+                &container_console,
+                None,
+                MsFlags::MS_BIND,
+                None,
+            )
+            .with_context(|| format!("failed to bind mount {:?} to /dev/console", console))?;
 
+        Ok(())
+    }
+
+    fn mknod_dev(&self, rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
         let full_container_path = rootfs.join(dev.path().as_in_container()?);
         self.syscall.mknod(
             &full_container_path,
@@ -104,6 +121,23 @@ impl Device {
     }
 }
 
+/// Packs the major and minor number into a device number the same way as
+/// the makedev macro of glibc
+fn makedev(major: i64, minor: i64) -> u64 {
+    ((minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12) | ((major & !0xfff) << 32))
+        as u64
+}
+
+/// Returns the devices to create in the container. A device in the spec
+/// replaces the default device with the same path.
+pub fn merge_devices(defaults: Vec<LinuxDevice>, devices: &[LinuxDevice]) -> Vec<LinuxDevice> {
+    defaults
+        .into_iter()
+        .filter(|default| !devices.iter().any(|dev| dev.path() == default.path()))
+        .chain(devices.iter().cloned())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -146,6 +180,32 @@ mod tests {
         assert_eq!(want, *got);
     }
 
+    #[test]
+    fn test_bind_console() -> Result<()> {
+        let tmp_dir = TempDir::new("/tmp/test_bind_console")?;
+        std::fs::create_dir(tmp_dir.path().join("dev"))?;
+        let device = Device::new();
+
+        device.bind_console(tmp_dir.path(), Path::new("/dev/pts/3"))?;
+
+        assert!(tmp_dir.path().join("dev/console").exists());
+        let want = MountArgs {
+            source: Some(PathBuf::from("/dev/pts/3")),
+            target: tmp_dir.path().join("dev/console"),
+            fstype: None,
+            flags: MsFlags::MS_BIND,
+            data: None,
+        };
+        let got = &device
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args()[0];
+        assert_eq!(want, *got);
+        Ok(())
+    }
+
     #[test]
     fn test_mknod_dev() {
         let tmp_dir = TempDir::new("/tmp/test_mknod_dev").unwrap();
@@ -246,4 +306,64 @@ mod tests {
             .get_mknod_args()[0];
         assert_eq!(want, *got);
     }
+
+    #[test]
+    fn test_makedev() {
+        for (major, minor) in [
+            (1, 3),
+            (5, 0),
+            (259, 1),
+            (4095, 255),
+            (4096, 256),
+            (65535, 1 << 20),
+        ] {
+            assert_eq!(
+                makedev(major, minor),
+                libc::makedev(major as u32, minor as u32),
+                "{}:{}",
+                major,
+                minor
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_devices() {
+        let spec_null = LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/null"))
+            .typ(LinuxDeviceType::C)
+            .major(1)
+            .minor(3)
+            .file_mode(0o600u32)
+            .build()
+            .unwrap();
+        let fuse = LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/fuse"))
+            .typ(LinuxDeviceType::C)
+            .major(10)
+            .minor(229)
+            .build()
+            .unwrap();
+
+        let devices = merge_devices(
+            crate::rootfs::utils::default_devices(),
+            &[spec_null.clone(), fuse],
+        );
+
+        let paths: Vec<_> = devices.iter().map(|d| d.path().clone()).collect();
+        assert_eq!(
+            paths
+                .iter()
+                .filter(|p| *p == &PathBuf::from("/dev/null"))
+                .count(),
+            1
+        );
+        assert!(paths.contains(&PathBuf::from("/dev/fuse")));
+        assert!(paths.contains(&PathBuf::from("/dev/urandom")));
+        let null = devices
+            .iter()
+            .find(|d| d.path() == &PathBuf::from("/dev/null"))
+            .unwrap();
+        assert_eq!(null.file_mode(), Some(0o600));
+    }
 }
@@ -1,6 +1,6 @@
 use super::{
     symlink::Symlink,
-    utils::{find_parent_mount, parse_mount},
+    utils::{find_parent_mount, parse_mount, MountOptionConfig},
 };
 use crate::syscall::{syscall::create_syscall, Syscall};
 use crate::utils::PathBufExt;
@@ -13,6 +13,7 @@ use nix::{errno::Errno, mount::MsFlags};
 use oci_spec::runtime::{Mount as SpecMount, MountBuilder as SpecMountBuilder};
 use procfs::process::{MountOptFields, Process};
 use std::borrow::Cow;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::{
     collections::HashMap,
@@ -24,10 +25,15 @@ pub struct MountOptions<'a> {
     pub root: &'a Path,
     pub label: Option<&'a str>,
     pub cgroup_ns: bool,
+    pub rootless: bool,
 }
 
+/// Detached mount trees of idmapped mounts by the destination of the mount
+pub type IdmappedMounts = HashMap<PathBuf, RawFd>;
+
 pub struct Mount {
     syscall: Box<dyn Syscall>,
+    idmapped_mounts: IdmappedMounts,
 }
 
 impl Default for Mount {
@@ -40,12 +46,21 @@ impl Mount {
     pub fn new() -> Mount {
         Mount {
             syscall: create_syscall(),
+            idmapped_mounts: IdmappedMounts::new(),
         }
     }
 
+    /// Sets the mount trees which are attached for idmapped mounts. They are created by
+    /// the runtime, since creating them requires privileges in the user namespace of
+    /// the host.
+    pub fn with_idmapped_mounts(mut self, idmapped_mounts: IdmappedMounts) -> Self {
+        self.idmapped_mounts = idmapped_mounts;
+        self
+    }
+
     pub fn setup_mount(&self, mount: &SpecMount, options: &MountOptions) -> Result<()> {
         log::debug!("Mounting {:?}", mount);
-        let (flags, data) = parse_mount(mount);
+        let mount_option_config = parse_mount(mount);
 
         match mount.typ().as_deref() {
             Some("cgroup") => {
@@ -56,23 +71,57 @@ impl Mount {
                         .mount_cgroup_v1(mount, options)
                         .context("failed to mount cgroup v1")?,
                     Unified => self
-                        .mount_cgroup_v2(mount, options, flags, &data)
+                        .mount_cgroup_v2(mount, options, &mount_option_config)
                         .context("failed to mount cgroup v2")?,
                 }
             }
             _ => {
                 if *mount.destination() == PathBuf::from("/dev") {
+                    let dev_option_config = MountOptionConfig {
+                        flags: mount_option_config.flags & !MsFlags::MS_RDONLY,
+                        ..mount_option_config
+                    };
                     self.mount_into_container(
                         mount,
                         options.root,
-                        flags & !MsFlags::MS_RDONLY,
-                        &data,
+                        &dev_option_config,
                         options.label,
                     )
                     .with_context(|| format!("failed to mount /dev: {:?}", mount))?;
-                } else {
-                    self.mount_into_container(mount, options.root, flags, &data, options.label)
-                        .with_context(|| format!("failed to mount: {:?}", mount))?;
+                } else if let Err(err) = self.mount_into_container(
+                    mount,
+                    options.root,
+                    &mount_option_config,
+                    options.label,
+                ) {
+                    match rootless_fallback(mount, &err) {
+                        Some(bind_mount) if options.rootless => {
+                            log::warn!(
+                                "failed to mount {:?} in rootless container, bind mounting {:?} from the host instead: {:?}",
+                                mount.destination(),
+                                bind_mount.source(),
+                                err
+                            );
+                            let bind_option_config = MountOptionConfig {
+                                flags: mount_option_config.flags
+                                    | MsFlags::MS_BIND
+                                    | MsFlags::MS_REC,
+                                data: String::new(),
+                                ..mount_option_config
+                            };
+                            self.mount_into_container(
+                                &bind_mount,
+                                options.root,
+                                &bind_option_config,
+                                options.label,
+                            )
+                            .with_context(|| format!("failed to bind mount: {:?}", bind_mount))?;
+                        }
+                        _ => {
+                            return Err(err)
+                                .with_context(|| format!("failed to mount: {:?}", mount))
+                        }
+                    }
                 }
             }
         }
@@ -179,17 +228,23 @@ impl Mount {
             .build()
             .with_context(|| format!("failed to build {}", subsystem_name))?;
 
-        let data: Cow<str> = if named {
-            format!("name={}", subsystem_name).into()
+        let data = if named {
+            format!("name={}", subsystem_name)
         } else {
-            subsystem_name.into()
+            subsystem_name.to_owned()
+        };
+
+        let mount_option_config = MountOptionConfig {
+            flags: MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            data,
+            propagation: MsFlags::empty(),
+            idmap: false,
         };
 
         self.mount_into_container(
             &subsystem_mount,
             options.root,
-            MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
-            &data,
+            &mount_option_config,
             options.label,
         )
         .with_context(|| format!("failed to mount {:?}", subsystem_mount))
@@ -257,8 +312,7 @@ impl Mount {
         &self,
         cgroup_mount: &SpecMount,
         options: &MountOptions,
-        flags: MsFlags,
-        data: &str,
+        mount_option_config: &MountOptionConfig,
     ) -> Result<()> {
         log::debug!("Mounting cgroup v2 filesystem");
 
@@ -271,7 +325,12 @@ impl Mount {
         log::debug!("{:?}", cgroup_mount);
 
         if self
-            .mount_into_container(&cgroup_mount, options.root, flags, data, options.label)
+            .mount_into_container(
+                &cgroup_mount,
+                options.root,
+                mount_option_config,
+                options.label,
+            )
             .context("failed to mount into container")
             .is_err()
         {
@@ -295,11 +354,15 @@ impl Mount {
                 .context("failed to build cgroup bind mount")?;
             log::debug!("{:?}", bind_mount);
 
+            let bind_option_config = MountOptionConfig {
+                flags: mount_option_config.flags | MsFlags::MS_BIND,
+                ..mount_option_config.clone()
+            };
+
             self.mount_into_container(
                 &bind_mount,
                 options.root,
-                flags | MsFlags::MS_BIND,
-                data,
+                &bind_option_config,
                 options.label,
             )
             .context("failed to bind mount cgroup hierarchy")?;
@@ -336,11 +399,12 @@ impl Mount {
         &self,
         m: &SpecMount,
         rootfs: &Path,
-        flags: MsFlags,
-        data: &str,
+        mount_option_config: &MountOptionConfig,
         label: Option<&str>,
     ) -> Result<()> {
         let typ = m.typ().as_deref();
+        let flags = mount_option_config.flags;
+        let data = mount_option_config.data.as_str();
         let mut d = data.to_string();
 
         if let Some(l) = label {
@@ -390,10 +454,28 @@ impl Mount {
             PathBuf::from(source)
         };
 
-        if let Err(err) = self.syscall.mount(Some(&*src), dest, typ, flags, Some(&*d)) {
+        if mount_option_config.idmap {
+            if typ != Some("bind") {
+                bail!(
+                    "idmap is only supported for bind mounts: {:?}",
+                    m.destination()
+                );
+            }
+
+            let tree = self.idmapped_mounts.get(m.destination()).with_context(|| {
+                format!(
+                    "no idmapped mount has been created for {:?}",
+                    m.destination()
+                )
+            })?;
+            self.syscall
+                .mount_idmapped(*tree, dest)
+                .with_context(|| format!("failed to idmap mount {:?} to {:?}", src, dest))?;
+        } else if let Err(err) = self.syscall.mount(Some(&*src), dest, typ, flags, Some(&*d)) {
             if let Some(errno) = err.downcast_ref() {
                 if !matches!(errno, Errno::EINVAL) {
-                    bail!("mount of {:?} failed. {}", m.destination(), errno);
+                    return Err(err)
+                        .with_context(|| format!("mount of {:?} failed", m.destination()));
                 }
             }
 
@@ -403,30 +485,53 @@ impl Mount {
         }
 
         if typ == Some("bind")
-            && flags.intersects(
-                !(MsFlags::MS_REC
-                    | MsFlags::MS_REMOUNT
-                    | MsFlags::MS_BIND
-                    | MsFlags::MS_PRIVATE
-                    | MsFlags::MS_SHARED
-                    | MsFlags::MS_SLAVE),
-            )
+            && flags.intersects(!(MsFlags::MS_REC | MsFlags::MS_REMOUNT | MsFlags::MS_BIND))
         {
             self.syscall
                 .mount(Some(dest), dest, None, flags | MsFlags::MS_REMOUNT, None)
                 .with_context(|| format!("Failed to remount: {:?}", dest))?;
         }
 
+        if !mount_option_config.propagation.is_empty() {
+            self.syscall
+                .mount(None, dest, None, mount_option_config.propagation, None)
+                .with_context(|| format!("failed to change propagation of {:?}", dest))?;
+        }
+
         Ok(())
     }
 }
 
+/// Returns a recursive bind mount of the host equivalent of a mount that a
+/// rootless container is not permitted to perform, e.g. because the user
+/// namespace does not own the pid namespace that a new proc would belong to.
+fn rootless_fallback(mount: &SpecMount, err: &anyhow::Error) -> Option<SpecMount> {
+    if err.root_cause().downcast_ref::<Errno>() != Some(&Errno::EPERM) {
+        return None;
+    }
+
+    let host_source = match mount.typ().as_deref() {
+        Some("proc") => "/proc",
+        Some("sysfs") => "/sys",
+        Some("mqueue") => "/dev/mqueue",
+        _ => return None,
+    };
+
+    SpecMountBuilder::default()
+        .typ("bind")
+        .source(host_source)
+        .destination(mount.destination())
+        .options(vec!["rbind".to_string()])
+        .build()
+        .ok()
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
 
     use super::*;
-    use crate::syscall::test::{MountArgs, TestHelperSyscall};
+    use crate::syscall::test::{IdmappedMountArgs, MountArgs, TestHelperSyscall};
     use crate::utils::create_temp_dir;
     use anyhow::Result;
 
@@ -449,10 +554,15 @@ mod tests {
                 ])
                 .build()
                 .unwrap();
-            let (flags, data) = parse_mount(mount);
+            let mount_option_config = parse_mount(mount);
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), flags, &data, Some("defaults"))
+                .mount_into_container(
+                    mount,
+                    tmp_dir.path(),
+                    &mount_option_config,
+                    Some("defaults")
+                )
                 .is_ok());
 
             let want = vec![MountArgs {
@@ -482,7 +592,7 @@ mod tests {
                 .options(vec!["ro".to_string()])
                 .build()
                 .unwrap();
-            let (flags, data) = parse_mount(mount);
+            let mount_option_config = parse_mount(mount);
             OpenOptions::new()
                 .create(true)
                 .write(true)
@@ -490,7 +600,7 @@ mod tests {
                 .unwrap();
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), flags, &data, None)
+                .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
                 .is_ok());
 
             let want = vec![
@@ -521,6 +631,131 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_mount_tmpfs_with_data() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_mount_tmpfs_with_data")?;
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/tmp"))
+            .typ("tmpfs")
+            .source(PathBuf::from("tmpfs"))
+            .options(vec![
+                "nosuid".to_string(),
+                "size=64m".to_string(),
+                "mode=1777".to_string(),
+                "nr_inodes=4k".to_string(),
+            ])
+            .build()?;
+        let mount_option_config = parse_mount(mount);
+        assert_eq!(mount_option_config.flags, MsFlags::MS_NOSUID);
+        assert_eq!(mount_option_config.data, "size=64m,mode=1777,nr_inodes=4k");
+
+        m.mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)?;
+
+        let want = vec![MountArgs {
+            source: Some(PathBuf::from("tmpfs")),
+            target: tmp_dir.path().join("tmp"),
+            fstype: Some("tmpfs".to_string()),
+            flags: MsFlags::MS_NOSUID,
+            data: Some("size=64m,mode=1777,nr_inodes=4k".to_string()),
+        }];
+        let got = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(got, want);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_idmapped_with_propagation() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_mount_idmapped_with_propagation")?;
+        let source = tmp_dir.join("source");
+        fs::create_dir_all(&source)?;
+
+        let tree: RawFd = 42;
+        let m = Mount::new()
+            .with_idmapped_mounts(vec![(PathBuf::from("/data"), tree)].into_iter().collect());
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(&source)
+            .options(vec![
+                "rbind".to_string(),
+                "ro".to_string(),
+                "idmap".to_string(),
+                "rslave".to_string(),
+            ])
+            .build()?;
+        let mount_option_config = parse_mount(mount);
+
+        m.mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)?;
+
+        let syscall = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        let target = tmp_dir.path().join("data");
+        assert_eq!(
+            syscall.get_idmapped_mount_args(),
+            vec![IdmappedMountArgs {
+                tree,
+                target: target.clone(),
+            }]
+        );
+
+        let want = vec![
+            MountArgs {
+                source: Some(target.clone()),
+                target: target.clone(),
+                fstype: None,
+                flags: MsFlags::MS_BIND
+                    | MsFlags::MS_REC
+                    | MsFlags::MS_RDONLY
+                    | MsFlags::MS_REMOUNT,
+                data: None,
+            },
+            MountArgs {
+                source: None,
+                target,
+                fstype: None,
+                flags: MsFlags::MS_SLAVE | MsFlags::MS_REC,
+                data: None,
+            },
+        ];
+        assert_eq!(syscall.get_mount_args(), want);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_idmapped_without_tree() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_mount_idmapped_without_tree")?;
+        let source = tmp_dir.join("source");
+        fs::create_dir_all(&source)?;
+
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(&source)
+            .options(vec!["rbind".to_string(), "idmap".to_string()])
+            .build()?;
+        let mount_option_config = parse_mount(mount);
+
+        let err = m
+            .mount_into_container(mount, tmp_dir.path(), &mount_option_config, None)
+            .unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("no idmapped mount"),
+            "{:?}",
+            err
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_make_parent_mount_private() {
         let tmp_dir = create_temp_dir("test_make_parent_mount_private").unwrap();
@@ -563,6 +798,7 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            rootless: false,
         };
 
         let subsystem_name = "cpu";
@@ -614,6 +850,7 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: false,
+            rootless: false,
         };
 
         let subsystem_name = "cpu";
@@ -671,14 +908,20 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            rootless: false,
         };
 
         let mounter = Mount::new();
-        let flags = MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        let mount_option_config = MountOptionConfig {
+            flags: MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            data: String::new(),
+            propagation: MsFlags::empty(),
+            idmap: false,
+        };
 
         // act
         mounter
-            .mount_cgroup_v2(&spec_cgroup_mount, &mount_opts, flags, "")
+            .mount_cgroup_v2(&spec_cgroup_mount, &mount_opts, &mount_option_config)
             .context("failed to mount cgroup v2")?;
 
         // assert
@@ -702,4 +945,32 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_rootless_fallback() -> Result<()> {
+        let sysfs = SpecMountBuilder::default()
+            .destination(PathBuf::from("/sys"))
+            .typ("sysfs")
+            .source(PathBuf::from("sysfs"))
+            .build()?;
+        let eperm = anyhow::Error::new(Errno::EPERM).context("failed to mount");
+
+        let fallback = rootless_fallback(&sysfs, &eperm).context("expected a fallback")?;
+        assert_eq!(fallback.typ().as_deref(), Some("bind"));
+        assert_eq!(fallback.source(), &Some(PathBuf::from("/sys")));
+        assert_eq!(fallback.destination(), &PathBuf::from("/sys"));
+
+        // only a permission error can be worked around by bind mounting
+        let einval = anyhow::Error::new(Errno::EINVAL).context("failed to mount");
+        assert!(rootless_fallback(&sysfs, &einval).is_none());
+
+        // there is no host equivalent to bind mount for a tmpfs
+        let tmpfs = SpecMountBuilder::default()
+            .destination(PathBuf::from("/tmp"))
+            .typ("tmpfs")
+            .source(PathBuf::from("tmpfs"))
+            .build()?;
+        assert!(rootless_fallback(&tmpfs, &eperm).is_none());
+        Ok(())
+    }
 }
@@ -1,6 +1,6 @@
 use super::{
-    device::Device,
-    mount::{Mount, MountOptions},
+    device::{merge_devices, Device},
+    mount::{IdmappedMounts, Mount, MountOptions},
     symlink::Symlink,
     utils::default_devices,
 };
@@ -10,6 +10,21 @@ use nix::mount::MsFlags;
 use oci_spec::runtime::{Linux, Spec};
 use std::path::Path;
 
+/// Returns the recursive propagation flags applied to the mount tree before
+/// the rootfs is mounted. Unbindable mounts can only be made unbindable after
+/// pivot_root, so they start out as slave like the default (see
+/// https://github.com/opencontainers/runc/pull/1500).
+fn rootfs_propagation_flags(propagation: Option<&str>) -> Result<MsFlags> {
+    let flags = match propagation {
+        Some("shared") => MsFlags::MS_SHARED,
+        Some("private") => MsFlags::MS_PRIVATE,
+        Some("slave" | "unbindable") | None => MsFlags::MS_SLAVE,
+        Some(unknown) => bail!("unknown rootfs_propagation: {}", unknown),
+    };
+
+    Ok(flags | MsFlags::MS_REC)
+}
+
 /// Holds information about rootfs
 pub struct RootFS {
     syscall: Box<dyn Syscall>,
@@ -34,23 +49,18 @@ impl RootFS {
         rootfs: &Path,
         bind_devices: bool,
         cgroup_ns: bool,
+        rootless: bool,
+        idmapped_mounts: &IdmappedMounts,
     ) -> Result<()> {
         log::debug!("Prepare rootfs: {:?}", rootfs);
-        let mut flags = MsFlags::MS_REC;
         let linux = spec.linux().as_ref().context("no linux in spec")?;
-
-        match linux.rootfs_propagation().as_deref() {
-            Some("shared") => flags |= MsFlags::MS_SHARED,
-            Some("private") => flags |= MsFlags::MS_PRIVATE,
-            Some("slave" | "unbindable") | None => flags |= MsFlags::MS_SLAVE,
-            Some(uknown) => bail!("unknown rootfs_propagation: {}", uknown),
-        }
+        let flags = rootfs_propagation_flags(linux.rootfs_propagation().as_deref())?;
 
         self.syscall
             .mount(None, Path::new("/"), None, flags, None)
             .context("failed to mount rootfs")?;
 
-        let mounter = Mount::new();
+        let mounter = Mount::new().with_idmapped_mounts(idmapped_mounts.clone());
 
         mounter
             .make_parent_mount_private(rootfs)
@@ -69,6 +79,7 @@ impl RootFS {
             root: rootfs,
             label: linux.mount_label().as_deref(),
             cgroup_ns,
+            rootless,
         };
 
         if let Some(mounts) = spec.mounts() {
@@ -88,20 +99,23 @@ impl RootFS {
             .context("failed to setup default symlinks")?;
 
         let devicer = Device::new();
-        if let Some(added_devices) = linux.devices() {
-            devicer.create_devices(
-                rootfs,
-                default_devices().iter().chain(added_devices),
-                bind_devices,
-            )
-        } else {
-            devicer.create_devices(rootfs, &default_devices(), bind_devices)
-        }?;
+        let devices = merge_devices(
+            default_devices(),
+            linux.devices().as_deref().unwrap_or_default(),
+        );
+        devicer
+            .create_devices(rootfs, &devices, bind_devices)
+            .context("failed to create devices")?;
 
         symlinker.setup_ptmx(rootfs)?;
         Ok(())
     }
 
+    /// Bind mounts the console of the container to /dev/console
+    pub fn mount_console(&self, rootfs: &Path, console: &Path) -> Result<()> {
+        Device::new().bind_console(rootfs, console)
+    }
+
     /// Change propagation type of rootfs as specified in spec.
     pub fn adjust_root_mount_propagation(&self, linux: &Linux) -> Result<()> {
         let rootfs_propagation = linux.rootfs_propagation().as_deref();
@@ -120,3 +134,31 @@ impl RootFS {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rootfs_propagation_flags() -> Result<()> {
+        let cases = [
+            (Some("shared"), MsFlags::MS_SHARED),
+            (Some("private"), MsFlags::MS_PRIVATE),
+            (Some("slave"), MsFlags::MS_SLAVE),
+            (Some("unbindable"), MsFlags::MS_SLAVE),
+            (None, MsFlags::MS_SLAVE),
+        ];
+
+        for (propagation, expected) in cases {
+            let flags = rootfs_propagation_flags(propagation)?;
+            assert_eq!(flags, expected | MsFlags::MS_REC, "{:?}", propagation);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootfs_propagation_flags_unknown() {
+        assert!(rootfs_propagation_flags(Some("rshared")).is_err());
+        assert!(rootfs_propagation_flags(Some("")).is_err());
+    }
+}
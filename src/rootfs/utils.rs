@@ -1,6 +1,6 @@
 use anyhow::{anyhow, Result};
 use nix::{mount::MsFlags, sys::stat::SFlag, NixPath};
-use oci_spec::runtime::{LinuxDevice, LinuxDeviceBuilder, LinuxDeviceType, Mount};
+use oci_spec::runtime::{LinuxDevice, LinuxDeviceBuilder, LinuxDeviceType, Mount, Spec};
 use procfs::process::MountInfo;
 use std::path::{Path, PathBuf};
 
@@ -11,7 +11,7 @@ pub fn default_devices() -> Vec<LinuxDevice> {
             .typ(LinuxDeviceType::C)
             .major(1)
             .minor(3)
-            .file_mode(0o066u32)
+            .file_mode(0o666u32)
             .build()
             .unwrap(),
         LinuxDeviceBuilder::default()
@@ -19,7 +19,7 @@ pub fn default_devices() -> Vec<LinuxDevice> {
             .typ(LinuxDeviceType::C)
             .major(1)
             .minor(5)
-            .file_mode(0o066u32)
+            .file_mode(0o666u32)
             .build()
             .unwrap(),
         LinuxDeviceBuilder::default()
@@ -27,7 +27,7 @@ pub fn default_devices() -> Vec<LinuxDevice> {
             .typ(LinuxDeviceType::C)
             .major(1)
             .minor(7)
-            .file_mode(0o066u32)
+            .file_mode(0o666u32)
             .build()
             .unwrap(),
         LinuxDeviceBuilder::default()
@@ -35,7 +35,7 @@ pub fn default_devices() -> Vec<LinuxDevice> {
             .typ(LinuxDeviceType::C)
             .major(5)
             .minor(0)
-            .file_mode(0o066u32)
+            .file_mode(0o666u32)
             .build()
             .unwrap(),
         LinuxDeviceBuilder::default()
@@ -43,7 +43,7 @@ pub fn default_devices() -> Vec<LinuxDevice> {
             .typ(LinuxDeviceType::C)
             .major(1)
             .minor(9)
-            .file_mode(0o066u32)
+            .file_mode(0o666u32)
             .build()
             .unwrap(),
         LinuxDeviceBuilder::default()
@@ -51,7 +51,7 @@ pub fn default_devices() -> Vec<LinuxDevice> {
             .typ(LinuxDeviceType::C)
             .major(1)
             .minor(8)
-            .file_mode(0o066u32)
+            .file_mode(0o666u32)
             .build()
             .unwrap(),
     ]
@@ -66,11 +66,47 @@ pub fn to_sflag(dev_type: LinuxDeviceType) -> SFlag {
     }
 }
 
-pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
+/// Mount options of a spec mount split up by how they are applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountOptionConfig {
+    /// Mount flags passed to mount(2)
+    pub flags: MsFlags,
+    /// Options that are not mount flags and are passed on as mount data
+    pub data: String,
+    /// Propagation type that is applied with a separate mount call after the mount.
+    /// If multiple propagation types are specified, the last one wins.
+    pub propagation: MsFlags,
+    /// Whether the ids of the mount should be mapped into the user namespace
+    /// of the container
+    pub idmap: bool,
+}
+
+/// Returns the mounts of the spec whose ids are mapped into the user namespace of the
+/// container, in the order in which they appear in the spec
+pub fn idmapped_mounts(spec: &Spec) -> Vec<&Mount> {
+    spec.mounts()
+        .as_ref()
+        .map(|mounts| mounts.iter().filter(|m| parse_mount(m).idmap).collect())
+        .unwrap_or_default()
+}
+
+pub fn parse_mount(m: &Mount) -> MountOptionConfig {
     let mut flags = MsFlags::empty();
+    let mut propagation = MsFlags::empty();
+    let mut idmap = false;
     let mut data = Vec::new();
     if let Some(options) = &m.options() {
         for s in options {
+            if let Some(flag) = parse_propagation(s) {
+                propagation = flag;
+                continue;
+            }
+
+            if s == "idmap" {
+                idmap = true;
+                continue;
+            }
+
             if let Some((is_clear, flag)) = match s.as_str() {
                 "defaults" => Some((false, MsFlags::empty())),
                 "ro" => Some((false, MsFlags::MS_RDONLY)),
@@ -93,14 +129,6 @@ pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
                 "nodiratime" => Some((false, MsFlags::MS_NODIRATIME)),
                 "bind" => Some((false, MsFlags::MS_BIND)),
                 "rbind" => Some((false, MsFlags::MS_BIND | MsFlags::MS_REC)),
-                "unbindable" => Some((false, MsFlags::MS_UNBINDABLE)),
-                "runbindable" => Some((false, MsFlags::MS_UNBINDABLE | MsFlags::MS_REC)),
-                "private" => Some((true, MsFlags::MS_PRIVATE)),
-                "rprivate" => Some((true, MsFlags::MS_PRIVATE | MsFlags::MS_REC)),
-                "shared" => Some((true, MsFlags::MS_SHARED)),
-                "rshared" => Some((true, MsFlags::MS_SHARED | MsFlags::MS_REC)),
-                "slave" => Some((true, MsFlags::MS_SLAVE)),
-                "rslave" => Some((true, MsFlags::MS_SLAVE | MsFlags::MS_REC)),
                 "relatime" => Some((true, MsFlags::MS_RELATIME)),
                 "norelatime" => Some((true, MsFlags::MS_RELATIME)),
                 "strictatime" => Some((true, MsFlags::MS_STRICTATIME)),
@@ -117,7 +145,30 @@ pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
             };
         }
     }
-    (flags, data.join(","))
+    MountOptionConfig {
+        flags,
+        data: data.join(","),
+        propagation,
+        idmap,
+    }
+}
+
+// Propagation types can not be combined with other mount flags and are
+// applied with a separate mount call, so they are parsed on their own.
+fn parse_propagation(option: &str) -> Option<MsFlags> {
+    let flags = match option {
+        "unbindable" => MsFlags::MS_UNBINDABLE,
+        "runbindable" => MsFlags::MS_UNBINDABLE | MsFlags::MS_REC,
+        "private" => MsFlags::MS_PRIVATE,
+        "rprivate" => MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+        "shared" => MsFlags::MS_SHARED,
+        "rshared" => MsFlags::MS_SHARED | MsFlags::MS_REC,
+        "slave" => MsFlags::MS_SLAVE,
+        "rslave" => MsFlags::MS_SLAVE | MsFlags::MS_REC,
+        _ => return None,
+    };
+
+    Some(flags)
 }
 
 /// Find parent mount of rootfs in given mount infos
@@ -191,22 +242,26 @@ mod tests {
         assert_eq!(SFlag::S_IFIFO, to_sflag(LinuxDeviceType::P));
     }
 
+    fn flags_and_data(config: MountOptionConfig) -> (MsFlags, String) {
+        (config.flags, config.data)
+    }
+
     #[test]
     fn test_parse_mount() {
         assert_eq!(
             (MsFlags::empty(), "".to_string()),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/proc"))
                     .typ("proc")
                     .source(PathBuf::from("proc"))
                     .build()
                     .unwrap()
-            )
+            ))
         );
         assert_eq!(
             (MsFlags::MS_NOSUID, "mode=755,size=65536k".to_string()),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/dev"))
                     .typ("tmpfs")
@@ -219,14 +274,14 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
         );
         assert_eq!(
             (
                 MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
                 "newinstance,ptmxmode=0666,mode=0620,gid=5".to_string()
             ),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/dev/pts"))
                     .typ("devpts")
@@ -241,14 +296,14 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
         );
         assert_eq!(
             (
                 MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV,
                 "mode=1777,size=65536k".to_string()
             ),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/dev/shm"))
                     .typ("tmpfs")
@@ -262,14 +317,14 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
         );
         assert_eq!(
             (
                 MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV,
                 "".to_string()
             ),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/dev/mqueue"))
                     .typ("mqueue")
@@ -281,14 +336,14 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
         );
         assert_eq!(
             (
                 MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV | MsFlags::MS_RDONLY,
                 "".to_string()
             ),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/sys"))
                     .typ("sysfs")
@@ -301,14 +356,14 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
         );
         assert_eq!(
             (
                 MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV | MsFlags::MS_RDONLY,
                 "".to_string()
             ),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .destination(PathBuf::from("/sys/fs/cgroup"))
                     .typ("cgroup")
@@ -322,7 +377,7 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
         );
         // this case is just for coverage purpose
         assert_eq!(
@@ -335,10 +390,10 @@ mod tests {
                     | MsFlags::MS_NOATIME
                     | MsFlags::MS_NODIRATIME
                     | MsFlags::MS_BIND
-                    | MsFlags::MS_UNBINDABLE,
+                    | MsFlags::MS_REC,
                 "".to_string()
             ),
-            parse_mount(
+            flags_and_data(parse_mount(
                 &MountBuilder::default()
                     .options(vec![
                         "defaults".to_string(),
@@ -377,7 +432,82 @@ mod tests {
                     ])
                     .build()
                     .unwrap()
-            )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_mount_options() {
+        let config = parse_mount(
+            &MountBuilder::default()
+                .destination(PathBuf::from("/data"))
+                .typ("bind")
+                .source(PathBuf::from("/srv/data"))
+                .options(vec![
+                    "rbind".to_string(),
+                    "ro".to_string(),
+                    "rshared".to_string(),
+                    "idmap".to_string(),
+                    "uid=1000".to_string(),
+                    "mode=0755".to_string(),
+                ])
+                .build()
+                .unwrap(),
         );
+
+        assert_eq!(
+            config,
+            MountOptionConfig {
+                flags: MsFlags::MS_BIND | MsFlags::MS_REC | MsFlags::MS_RDONLY,
+                data: "uid=1000,mode=0755".to_string(),
+                propagation: MsFlags::MS_SHARED | MsFlags::MS_REC,
+                idmap: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_idmapped_mounts() -> Result<()> {
+        use oci_spec::runtime::SpecBuilder;
+
+        let mount = |destination: &str, options: &[&str]| {
+            MountBuilder::default()
+                .destination(PathBuf::from(destination))
+                .typ("bind")
+                .source(PathBuf::from("/tmp"))
+                .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+                .build()
+        };
+        let spec = SpecBuilder::default()
+            .mounts(vec![
+                mount("/first", &["rbind", "idmap"])?,
+                mount("/plain", &["rbind"])?,
+                mount("/second", &["idmap"])?,
+            ])
+            .build()?;
+
+        let destinations: Vec<&PathBuf> = idmapped_mounts(&spec)
+            .iter()
+            .map(|m| m.destination())
+            .collect();
+        assert_eq!(
+            destinations,
+            vec![&PathBuf::from("/first"), &PathBuf::from("/second")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mount_last_propagation_wins() {
+        let config = parse_mount(
+            &MountBuilder::default()
+                .options(vec!["rprivate".to_string(), "slave".to_string()])
+                .build()
+                .unwrap(),
+        );
+
+        assert_eq!(config.flags, MsFlags::empty());
+        assert_eq!(config.propagation, MsFlags::MS_SLAVE);
+        assert!(!config.idmap);
     }
 }
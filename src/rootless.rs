@@ -1,7 +1,9 @@
-use crate::{namespaces::Namespaces, utils};
+use crate::{annotations::Annotations, namespaces::Namespaces, utils};
 use anyhow::{bail, Context, Result};
 use nix::unistd::Pid;
-use oci_spec::runtime::{Linux, LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, Mount, Spec};
+use oci_spec::runtime::{
+    Linux, LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, Mount, Spec, User,
+};
 use std::path::Path;
 use std::process::Command;
 use std::{env, path::PathBuf};
@@ -30,7 +32,11 @@ impl<'a> Rootless<'a> {
 
         // If conditions requires us to use rootless, we must either create a new
         // user namespace or enter an exsiting.
-        if rootless_required() && user_namespace.is_none() {
+        let required = rootless_required()
+            || Annotations::new(spec)
+                .rootless()
+                .context("failed to determine if rootless is required")?;
+        if required && user_namespace.is_none() {
             bail!("rootless container requires valid user namespace definition");
         }
 
@@ -42,9 +48,15 @@ impl<'a> Rootless<'a> {
 
             validate(spec).context("The spec failed to comply to rootless requirement")?;
             let mut rootless = Rootless::from(linux);
-            if let Some((uid_binary, gid_binary)) = lookup_map_binaries(linux)? {
-                rootless.newuidmap = Some(uid_binary);
-                rootless.newgidmap = Some(gid_binary);
+            // a privileged user may map any id and writes the mappings without the
+            // helper binaries
+            if !rootless.privileged {
+                validate_subid_ranges(linux)
+                    .context("the id mappings are not allowed for the current user")?;
+                if let Some((uid_binary, gid_binary)) = lookup_map_binaries(linux)? {
+                    rootless.newuidmap = Some(uid_binary);
+                    rootless.newgidmap = Some(gid_binary);
+                }
             }
 
             Ok(Some(rootless))
@@ -62,6 +74,7 @@ impl<'a> Rootless<'a> {
                 &format!("/proc/{}/uid_map", target_pid),
                 uid_mappings,
                 self.newuidmap.as_deref(),
+                self.privileged,
             )
         } else {
             Ok(())
@@ -76,6 +89,7 @@ impl<'a> Rootless<'a> {
                 &format!("/proc/{}/gid_map", target_pid),
                 gid_mappings,
                 self.newgidmap.as_deref(),
+                self.privileged,
             );
         } else {
             Ok(())
@@ -133,12 +147,19 @@ fn validate(spec: &Spec) -> Result<()> {
         bail!("rootless containers require at least one gid mapping")
     }
 
+    validate_id_mappings(uid_mappings, "uid")?;
+    validate_id_mappings(gid_mappings, "gid")?;
+
     validate_mounts(
         spec.mounts().as_ref().context("no mounts in spec")?,
         uid_mappings,
         gid_mappings,
     )?;
 
+    if let Some(process) = spec.process() {
+        validate_process_user(process.user(), uid_mappings, gid_mappings)?;
+    }
+
     if let Some(additional_gids) = spec
         .process()
         .as_ref()
@@ -191,16 +212,152 @@ fn validate_mounts(
 }
 
 fn is_id_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
-    mappings
-        .iter()
-        .any(|m| id >= m.container_id() && id <= m.container_id() + m.size())
+    mappings.iter().any(|m| {
+        id >= m.container_id() && u64::from(id) < u64::from(m.container_id()) + u64::from(m.size())
+    })
+}
+
+/// Validates that the user and group of the container process are mapped in the
+/// user namespace, as the process could not switch to them otherwise
+fn validate_process_user(
+    user: &User,
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+) -> Result<()> {
+    if !is_id_mapped(user.uid(), uid_mappings) {
+        bail!(
+            "uid {} of the container process is not mapped in the user namespace",
+            user.uid()
+        );
+    }
+
+    if !is_id_mapped(user.gid(), gid_mappings) {
+        bail!(
+            "gid {} of the container process is not mapped in the user namespace",
+            user.gid()
+        );
+    }
+
+    Ok(())
+}
+
+/// A range of subordinate ids allocated to a user in /etc/subuid or /etc/subgid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SubIdRange {
+    start: u32,
+    count: u32,
+}
+
+impl SubIdRange {
+    fn contains(&self, id: u32, size: u32) -> bool {
+        let end = u64::from(id) + u64::from(size);
+        id >= self.start && end <= u64::from(self.start) + u64::from(self.count)
+    }
+}
+
+/// Parses the ranges of a subordinate id file that belong to the user. Entries
+/// have the form `name:start:count` where the name may also be the numeric id.
+fn parse_subid_ranges(content: &str, user_name: &str, user_id: u32) -> Vec<SubIdRange> {
+    let user_id = user_id.to_string();
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let (name, start, count) = (fields.next()?, fields.next()?, fields.next()?);
+            if name != user_name && name != user_id {
+                return None;
+            }
+
+            Some(SubIdRange {
+                start: start.parse().ok()?,
+                count: count.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Checks that every mapping which does not just map the id of the user itself is
+/// covered by the subordinate ids allocated to the user, as newuidmap/newgidmap
+/// will refuse to write the mapping otherwise
+fn check_subid_mappings(
+    mappings: &[LinuxIdMapping],
+    own_id: u32,
+    ranges: &[SubIdRange],
+    user_name: &str,
+    subid_file: &str,
+) -> Result<()> {
+    for mapping in mappings {
+        if mapping.host_id() == own_id && mapping.size() == 1 {
+            continue;
+        }
+
+        if ranges.is_empty() {
+            bail!(
+                "user {} has no range in {}; add an entry to {}",
+                user_name,
+                subid_file,
+                subid_file
+            );
+        }
+
+        if !ranges
+            .iter()
+            .any(|range| range.contains(mapping.host_id(), mapping.size()))
+        {
+            bail!(
+                "mapping of {} ids starting at host id {} is not within the ranges {:?} of user {} in {}",
+                mapping.size(),
+                mapping.host_id(),
+                ranges,
+                user_name,
+                subid_file
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_subid_ranges(linux: &Linux) -> Result<()> {
+    let uid = nix::unistd::getuid();
+    let gid = nix::unistd::getgid();
+    let user_name = nix::unistd::User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+        .unwrap_or_else(|| uid.to_string());
+
+    for (mappings, own_id, subid_file) in [
+        (linux.uid_mappings(), uid.as_raw(), "/etc/subuid"),
+        (linux.gid_mappings(), gid.as_raw(), "/etc/subgid"),
+    ] {
+        let mappings = match mappings {
+            Some(mappings) => mappings,
+            None => continue,
+        };
+        if mappings
+            .iter()
+            .all(|m| m.host_id() == own_id && m.size() == 1)
+        {
+            continue;
+        }
+
+        let content = std::fs::read_to_string(subid_file).unwrap_or_default();
+        let ranges = parse_subid_ranges(&content, &user_name, uid.as_raw());
+        check_subid_mappings(mappings, own_id, &ranges, &user_name, subid_file)?;
+    }
+
+    Ok(())
 }
 
 /// Looks up the location of the newuidmap and newgidmap binaries which
 /// are required to write multiple user/group mappings
 pub fn lookup_map_binaries(spec: &Linux) -> Result<Option<(PathBuf, PathBuf)>> {
     if let Some(uid_mappings) = spec.uid_mappings() {
-        if uid_mappings.len() == 1 && uid_mappings.len() == 1 {
+        let gid_mappings = spec.gid_mappings().as_ref().map_or(0, |m| m.len());
+        if uid_mappings.len() <= 1 && gid_mappings <= 1 {
             return Ok(None);
         }
 
@@ -220,8 +377,8 @@ fn lookup_map_binary(binary: &str) -> Result<Option<PathBuf>> {
     let paths = env::var("PATH")?;
     Ok(paths
         .split_terminator(':')
-        .find(|p| PathBuf::from(p).join(binary).exists())
-        .map(PathBuf::from))
+        .map(|p| PathBuf::from(p).join(binary))
+        .find(|p| p.exists()))
 }
 
 fn write_id_mapping(
@@ -229,21 +386,318 @@ fn write_id_mapping(
     map_file: &str,
     mappings: &[LinuxIdMapping],
     map_binary: Option<&Path>,
+    privileged: bool,
 ) -> Result<()> {
-    let mappings: Vec<String> = mappings
-        .iter()
-        .map(|m| format!("{} {} {}", m.container_id(), m.host_id(), m.size()))
-        .collect();
     log::debug!("Write ID mapping: {:?}", mappings);
-    if mappings.len() == 1 {
-        utils::write_file(map_file, mappings.first().unwrap())?;
+    // An unprivileged process is only allowed to map its own id, so anything beyond
+    // that has to be written by the setuid newuidmap/newgidmap helpers, which check
+    // the ranges against /etc/subuid and /etc/subgid.
+    // A privileged process can write all mappings itself. The kernel only accepts
+    // a single write to the map file, so all of them are written at once.
+    if mappings.len() == 1 || privileged {
+        utils::write_file(map_file, format_id_mappings(mappings))?;
     } else {
-        Command::new(map_binary.unwrap())
-            .arg(pid.to_string())
-            .args(mappings)
+        let map_binary = map_binary.with_context(|| {
+            format!(
+                "multiple id mappings require a helper to write {}",
+                map_file
+            )
+        })?;
+        let output = Command::new(map_binary)
+            .args(map_binary_args(pid, mappings))
             .output()
             .with_context(|| format!("failed to execute {:?}", map_binary))?;
+        if !output.status.success() {
+            bail!(
+                "{:?} failed with {}: {}",
+                map_binary,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
     }
 
     Ok(())
 }
+
+/// Formats the mappings as expected by /proc/<pid>/uid_map and gid_map, one
+/// mapping per line
+fn format_id_mappings(mappings: &[LinuxIdMapping]) -> String {
+    mappings
+        .iter()
+        .map(|m| format!("{} {} {}\n", m.container_id(), m.host_id(), m.size()))
+        .collect()
+}
+
+/// Validates that the id mappings are not empty and that neither the container
+/// nor the host ranges overlap, which the kernel would reject
+fn validate_id_mappings(mappings: &[LinuxIdMapping], kind: &str) -> Result<()> {
+    for (i, mapping) in mappings.iter().enumerate() {
+        if mapping.size() == 0 {
+            bail!("{} mapping {:?} has a size of zero", kind, mapping);
+        }
+
+        for other in &mappings[..i] {
+            if ranges_overlap(
+                mapping.container_id(),
+                other.container_id(),
+                mapping.size(),
+                other.size(),
+            ) {
+                bail!(
+                    "container ranges of {} mappings {:?} and {:?} overlap",
+                    kind,
+                    other,
+                    mapping
+                );
+            }
+            if ranges_overlap(
+                mapping.host_id(),
+                other.host_id(),
+                mapping.size(),
+                other.size(),
+            ) {
+                bail!(
+                    "host ranges of {} mappings {:?} and {:?} overlap",
+                    kind,
+                    other,
+                    mapping
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn ranges_overlap(start: u32, other_start: u32, size: u32, other_size: u32) -> bool {
+    let (start, other_start) = (u64::from(start), u64::from(other_start));
+    start < other_start + u64::from(other_size) && other_start < start + u64::from(size)
+}
+
+/// Builds the arguments of newuidmap/newgidmap, which expect the pid followed by a
+/// container id, host id and size triple for each mapping
+fn map_binary_args(pid: Pid, mappings: &[LinuxIdMapping]) -> Vec<String> {
+    let mut args = vec![pid.to_string()];
+    for mapping in mappings {
+        args.push(mapping.container_id().to_string());
+        args.push(mapping.host_id().to_string());
+        args.push(mapping.size().to_string());
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{LinuxIdMappingBuilder, UserBuilder};
+
+    fn mapping(container_id: u32, host_id: u32, size: u32) -> LinuxIdMapping {
+        LinuxIdMappingBuilder::default()
+            .container_id(container_id)
+            .host_id(host_id)
+            .size(size)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_is_id_mapped() {
+        let mappings = vec![mapping(0, 1000, 1), mapping(1, 100000, 1000)];
+        assert!(is_id_mapped(0, &mappings));
+        assert!(is_id_mapped(1, &mappings));
+        assert!(is_id_mapped(1000, &mappings));
+        assert!(!is_id_mapped(1001, &mappings));
+        assert!(!is_id_mapped(65534, &mappings));
+        assert!(!is_id_mapped(0, &[]));
+        assert!(is_id_mapped(u32::MAX, &[mapping(u32::MAX, 0, 1)]));
+    }
+
+    #[test]
+    fn test_validate_process_user() -> Result<()> {
+        let uid_mappings = vec![mapping(0, 1000, 1), mapping(1, 100000, 1000)];
+        let gid_mappings = vec![mapping(0, 1000, 1)];
+
+        let user = UserBuilder::default().uid(0u32).gid(0u32).build()?;
+        assert!(validate_process_user(&user, &uid_mappings, &gid_mappings).is_ok());
+        let user = UserBuilder::default().uid(500u32).gid(0u32).build()?;
+        assert!(validate_process_user(&user, &uid_mappings, &gid_mappings).is_ok());
+
+        let user = UserBuilder::default().uid(1001u32).gid(0u32).build()?;
+        assert!(validate_process_user(&user, &uid_mappings, &gid_mappings).is_err());
+        let user = UserBuilder::default().uid(0u32).gid(1u32).build()?;
+        assert!(validate_process_user(&user, &uid_mappings, &gid_mappings).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_binary_args() {
+        let mappings = vec![mapping(0, 1000, 1), mapping(1, 100000, 65536)];
+        assert_eq!(
+            map_binary_args(Pid::from_raw(42), &mappings),
+            vec!["42", "0", "1000", "1", "1", "100000", "65536"]
+        );
+    }
+
+    #[test]
+    fn test_map_binary_args_single_mapping() {
+        assert_eq!(
+            map_binary_args(Pid::from_raw(42), &[mapping(0, 1000, 1)]),
+            vec!["42", "0", "1000", "1"]
+        );
+    }
+
+    #[test]
+    fn test_multiple_mappings_require_binary() {
+        let mappings = vec![mapping(0, 1000, 1), mapping(1, 100000, 65536)];
+        assert!(write_id_mapping(
+            Pid::from_raw(42),
+            "/proc/42/uid_map",
+            &mappings,
+            None,
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_format_id_mappings() {
+        assert_eq!(format_id_mappings(&[mapping(0, 1000, 1)]), "0 1000 1\n");
+        assert_eq!(
+            format_id_mappings(&[mapping(0, 1000, 1), mapping(1, 100000, 65536)]),
+            "0 1000 1\n1 100000 65536\n"
+        );
+        assert_eq!(format_id_mappings(&[]), "");
+    }
+
+    #[test]
+    fn test_privileged_writes_multiple_mappings() -> Result<()> {
+        let tmp = crate::utils::create_temp_dir("test_privileged_writes_multiple_mappings")?;
+        let map_file = tmp.join("uid_map");
+        let mappings = vec![mapping(0, 1000, 1), mapping(1, 100000, 65536)];
+        write_id_mapping(
+            Pid::from_raw(42),
+            map_file.to_str().unwrap(),
+            &mappings,
+            None,
+            true,
+        )?;
+        assert_eq!(
+            std::fs::read_to_string(&map_file)?,
+            "0 1000 1\n1 100000 65536\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_id_mappings() {
+        assert!(validate_id_mappings(&[mapping(0, 1000, 1)], "uid").is_ok());
+        assert!(
+            validate_id_mappings(&[mapping(0, 1000, 1), mapping(1, 100000, 65536)], "uid").is_ok()
+        );
+        // adjacent ranges do not overlap
+        assert!(
+            validate_id_mappings(&[mapping(0, 1000, 10), mapping(10, 1010, 10)], "uid").is_ok()
+        );
+        // overlapping container ranges
+        assert!(
+            validate_id_mappings(&[mapping(0, 1000, 10), mapping(5, 2000, 10)], "uid").is_err()
+        );
+        // overlapping host ranges
+        assert!(
+            validate_id_mappings(&[mapping(0, 1000, 10), mapping(10, 1005, 10)], "gid").is_err()
+        );
+        // a range containing another one
+        assert!(
+            validate_id_mappings(&[mapping(0, 1000, 100), mapping(50, 5000, 1)], "gid").is_err()
+        );
+        assert!(validate_id_mappings(&[mapping(0, 1000, 0)], "uid").is_err());
+        assert!(validate_id_mappings(
+            &[mapping(u32::MAX, 1000, 1), mapping(0, 0, u32::MAX)],
+            "uid"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_parse_subid_ranges() {
+        let content = "# comment\n\
+            alice:100000:65536\n\
+            bob:165536:65536\n\
+            1000:231072:1000\n\
+            alice:invalid:1\n\
+            alice:300000:10\n";
+
+        assert_eq!(
+            parse_subid_ranges(content, "alice", 1000),
+            vec![
+                SubIdRange {
+                    start: 100000,
+                    count: 65536
+                },
+                SubIdRange {
+                    start: 231072,
+                    count: 1000
+                },
+                SubIdRange {
+                    start: 300000,
+                    count: 10
+                },
+            ]
+        );
+        assert!(parse_subid_ranges(content, "carol", 1001).is_empty());
+    }
+
+    #[test]
+    fn test_check_subid_mappings() {
+        let ranges = [SubIdRange {
+            start: 100000,
+            count: 65536,
+        }];
+
+        // the own id can always be mapped
+        assert!(
+            check_subid_mappings(&[mapping(0, 1000, 1)], 1000, &[], "alice", "/etc/subuid").is_ok()
+        );
+        assert!(check_subid_mappings(
+            &[mapping(0, 1000, 1), mapping(1, 100000, 65536)],
+            1000,
+            &ranges,
+            "alice",
+            "/etc/subuid"
+        )
+        .is_ok());
+
+        let err = check_subid_mappings(
+            &[mapping(1, 100000, 65536)],
+            1000,
+            &[],
+            "alice",
+            "/etc/subuid",
+        )
+        .unwrap_err();
+        assert!(
+            format!("{}", err).contains("add an entry to /etc/subuid"),
+            "{}",
+            err
+        );
+
+        // the range is one id too small
+        assert!(check_subid_mappings(
+            &[mapping(1, 100000, 65537)],
+            1000,
+            &ranges,
+            "alice",
+            "/etc/subuid"
+        )
+        .is_err());
+        assert!(check_subid_mappings(
+            &[mapping(1, 99999, 10)],
+            1000,
+            &ranges,
+            "alice",
+            "/etc/subuid"
+        )
+        .is_err());
+    }
+}
@@ -5,6 +5,7 @@ use nix::errno::Errno;
 use oci_spec::runtime::Arch;
 use oci_spec::runtime::LinuxSeccomp;
 use oci_spec::runtime::LinuxSeccompAction;
+use oci_spec::runtime::LinuxSeccompArg;
 use oci_spec::runtime::LinuxSeccompOperator;
 use seccomp::scmp_compare::*;
 use seccomp::*;
@@ -231,6 +232,35 @@ fn translate_arch(arch: Arch) -> scmp_arch {
     }
 }
 
+// Translates the argument matchers of a syscall entry into the rules added to
+// the filter. Not clear why but if there are multiple arg attached to one
+// syscall rule, we have to add them seperatly. add_rule will return EINVAL.
+// runc does the same but doesn't explain why.
+fn translate_rules(
+    action: u32,
+    syscall_number: i32,
+    args: Option<&[LinuxSeccompArg]>,
+) -> Result<Vec<Rule>> {
+    let args = match args {
+        Some(args) if !args.is_empty() => args,
+        _ => return Ok(vec![Rule::new(action, syscall_number)]),
+    };
+
+    args.iter()
+        .map(|arg| {
+            let mut rule = Rule::new(action, syscall_number);
+            let cmp = Compare::new(arg.index() as u32)
+                .op(translate_op(arg.op()))
+                .datum_a(arg.value())
+                .datum_b(arg.value_two().unwrap_or(0))
+                .build()
+                .context("Failed to build a seccomp compare rule")?;
+            rule.add_comparator(cmp);
+            Ok(rule)
+        })
+        .collect()
+}
+
 fn check_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
     // We don't support notify as default action. After the seccomp filter is
     // created with notify, the container process will have to communicate the
@@ -261,6 +291,15 @@ fn check_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
     Ok(())
 }
 
+/// Checks if any of the syscalls is handled by a seccomp agent
+pub fn is_notify(seccomp: &LinuxSeccomp) -> bool {
+    seccomp
+        .syscalls()
+        .iter()
+        .flatten()
+        .any(|syscall| syscall.action() == LinuxSeccompAction::ScmpActNotify)
+}
+
 pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
     if seccomp.flags().is_some() {
         // runc did not support this, so let's skip it for now.
@@ -322,37 +361,15 @@ pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
                         continue;
                     }
                 };
-                // Not clear why but if there are multiple arg attached to one
-                // syscall rule, we have to add them seperatly. add_rule will
-                // return EINVAL. runc does the same but doesn't explain why.
-                match syscall.args() {
-                    Some(args) => {
-                        for arg in args {
-                            let mut rule = Rule::new(action, syscall_number);
-                            let cmp = Compare::new(arg.index() as u32)
-                                .op(translate_op(arg.op()))
-                                .datum_a(arg.value())
-                                .datum_b(arg.value_two().unwrap_or(0))
-                                .build()
-                                .context("Failed to build a seccomp compare rule")?;
-                            rule.add_comparator(cmp);
-                            ctx.add_rule(&rule).with_context(|| {
-                                format!(
-                                    "failed to add seccomp rule: {:?}. Syscall: {:?}",
-                                    &rule, name,
-                                )
-                            })?;
-                        }
-                    }
-                    None => {
-                        let rule = Rule::new(action, syscall_number);
-                        ctx.add_rule(&rule).with_context(|| {
-                            format!(
-                                "failed to add seccomp rule: {:?}. Syscall: {:?}",
-                                &rule, name,
-                            )
-                        })?;
-                    }
+                let rules = translate_rules(action, syscall_number, syscall.args().as_deref())
+                    .with_context(|| format!("failed to translate seccomp rules for {:?}", name))?;
+                for rule in rules {
+                    ctx.add_rule(&rule).with_context(|| {
+                        format!(
+                            "failed to add seccomp rule: {:?}. Syscall: {:?}",
+                            &rule, name,
+                        )
+                    })?;
                 }
             }
         }
@@ -364,13 +381,7 @@ pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
     // Ref: https://man7.org/linux/man-pages/man2/seccomp.2.html
     ctx.load().context("failed to load seccomp context")?;
 
-    let is_seccomp_notify = seccomp
-        .syscalls()
-        .iter()
-        .flatten()
-        .any(|syscall| syscall.action() == LinuxSeccompAction::ScmpActNotify);
-
-    let fd = if is_seccomp_notify {
+    let fd = if is_notify(seccomp) {
         ctx.notify_fd().context("failed to get seccomp notify fd")?
     } else {
         None
@@ -385,7 +396,7 @@ mod tests {
     use crate::utils::test_utils;
     use anyhow::Result;
     use oci_spec::runtime::Arch;
-    use oci_spec::runtime::{LinuxSeccompBuilder, LinuxSyscallBuilder};
+    use oci_spec::runtime::{LinuxSeccompArgBuilder, LinuxSeccompBuilder, LinuxSyscallBuilder};
     use serial_test::serial;
     use std::path;
 
@@ -437,6 +448,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_translate_rules_without_args() -> Result<()> {
+        let nr = translate_syscall("getcwd")?;
+        let rules = translate_rules(SCMP_ACT_ALLOW, nr, None)?;
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].action, SCMP_ACT_ALLOW);
+        assert_eq!(rules[0].syscall_nr, nr);
+        assert!(rules[0].comparators.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_translate_rules_with_args() -> Result<()> {
+        let nr = translate_syscall("personality")?;
+        let args = vec![
+            LinuxSeccompArgBuilder::default()
+                .index(0usize)
+                .value(0u64)
+                .op(LinuxSeccompOperator::ScmpCmpEq)
+                .build()?,
+            LinuxSeccompArgBuilder::default()
+                .index(1usize)
+                .value(0xffu64)
+                .value_two(0x08u64)
+                .op(LinuxSeccompOperator::ScmpCmpMaskedEq)
+                .build()?,
+        ];
+        let action = translate_action(LinuxSeccompAction::ScmpActErrno, Some(libc::EPERM as u32));
+        let rules = translate_rules(action, nr, Some(args.as_slice()))?;
+
+        // every arg ends up in a rule of its own
+        assert_eq!(rules.len(), 2);
+        for rule in &rules {
+            assert_eq!(rule.action, SCMP_ACT_ERRNO(libc::EPERM as u32));
+            assert_eq!(rule.syscall_nr, nr);
+            assert_eq!(rule.comparators.len(), 1);
+        }
+
+        let eq = rules[0].comparators[0];
+        assert_eq!(eq.arg, 0);
+        assert_eq!(eq.op as u32, SCMP_CMP_EQ as u32);
+        assert_eq!(eq.datum_a, 0);
+        assert_eq!(eq.datum_b, 0);
+
+        let masked_eq = rules[1].comparators[0];
+        assert_eq!(masked_eq.arg, 1);
+        assert_eq!(masked_eq.op as u32, SCMP_CMP_MASKED_EQ as u32);
+        assert_eq!(masked_eq.datum_a, 0xff);
+        assert_eq!(masked_eq.datum_b, 0x08);
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_moby() -> Result<()> {
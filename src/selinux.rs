@@ -0,0 +1,68 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+use crate::utils;
+
+const SELINUX_ENFORCE_PATH: &str = "/sys/fs/selinux/enforce";
+const PROC_EXEC_ATTR_PATH: &str = "/proc/self/attr/exec";
+
+/// Checks if SELinux has been enabled on the system. The enforce file only
+/// exists if selinuxfs is mounted.
+pub fn is_enabled() -> bool {
+    is_enabled_at(Path::new(SELINUX_ENFORCE_PATH))
+}
+
+fn is_enabled_at(path: &Path) -> bool {
+    path.exists()
+}
+
+/// Sets the SELinux label the container process is executed with. Whether SELinux is
+/// enabled has to be detected by the runtime on the host, since selinuxfs is not visible
+/// anymore once the init process has entered the rootfs of the container. A requested
+/// label that cannot be applied is an error.
+pub fn apply_label(label: &str, enabled: bool) -> Result<()> {
+    if label.is_empty() {
+        return Ok(());
+    }
+
+    if !enabled {
+        bail!(
+            "selinux label {} is requested, but selinux is not enabled on this system",
+            label
+        );
+    }
+
+    let path = Path::new(PROC_EXEC_ATTR_PATH);
+    utils::ensure_procfs(path)?;
+    utils::write_file(path, label).with_context(|| format!("failed to set exec label {}", label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use std::fs;
+
+    #[test]
+    fn test_is_enabled_at() -> Result<()> {
+        let tmp = create_temp_dir("test_selinux_is_enabled_at")?;
+        let enforce = tmp.join("enforce");
+        assert!(!is_enabled_at(&enforce));
+
+        fs::write(&enforce, "1")?;
+        assert!(is_enabled_at(&enforce));
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_empty_label() {
+        assert!(apply_label("", false).is_ok());
+        assert!(apply_label("", true).is_ok());
+    }
+
+    #[test]
+    fn test_apply_label_without_selinux() {
+        let err = apply_label("system_u:system_r:container_t:s0", false).unwrap_err();
+        assert!(err.to_string().contains("not enabled"), "{}", err);
+    }
+}
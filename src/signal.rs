@@ -120,8 +120,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_conversion_from_string_is_case_insensitive() {
+        assert_eq!(SIGTERM, Signal::try_from("sigterm").unwrap().into_raw());
+        assert_eq!(SIGTERM, Signal::try_from("term").unwrap().into_raw());
+    }
+
     #[test]
     fn test_conversion_from_string_should_be_failed() {
         assert!(Signal::try_from("invalid").is_err())
     }
+
+    #[test]
+    fn test_conversion_from_invalid_number_or_prefix_should_be_failed() {
+        assert!(Signal::try_from("0").is_err());
+        assert!(Signal::try_from("32").is_err());
+        assert!(Signal::try_from("SIG").is_err());
+    }
 }
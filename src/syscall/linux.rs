@@ -1,11 +1,13 @@
 //! Implements Command trait for Linux systems
-use std::ffi::{CStr, OsStr};
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::symlink;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::sync::Arc;
 use std::{any::Any, mem, path::Path, ptr};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use caps::{errors::CapsError, CapSet, Capability, CapsHashSet};
 use libc::{c_char, uid_t};
 use nix::{
@@ -23,6 +25,25 @@ use oci_spec::runtime::LinuxRlimit;
 use super::Syscall;
 use crate::capabilities;
 
+// The new mount API syscalls share the same number on all architectures
+const SYS_OPEN_TREE: libc::c_long = 428;
+const SYS_MOVE_MOUNT: libc::c_long = 429;
+const SYS_MOUNT_SETATTR: libc::c_long = 442;
+
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+const MOUNT_ATTR_IDMAP: u64 = 0x0010_0000;
+
+// struct mount_attr from linux/mount.h
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
 /// Empty structure to implement Command trait for
 #[derive(Clone)]
 pub struct LinuxSyscall;
@@ -52,7 +73,8 @@ impl Syscall for LinuxSyscall {
     /// Function to set given path as root path inside process
     fn pivot_rootfs(&self, path: &Path) -> Result<()> {
         // open the path as directory and read only
-        let newroot = open(path, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
+        let newroot = open(path, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())
+            .with_context(|| format!("failed to open new root {:?}", path))?;
 
         // make the given path as the root directory for the container
         // see https://man7.org/linux/man-pages/man2/pivot_root.2.html, specially the notes
@@ -62,7 +84,7 @@ impl Syscall for LinuxSyscall {
         // this path. This is done, as otherwise, we will need to create a separate temporary directory under the new root path
         // so we can move the original root there, and then unmount that. This way saves the creation of the temporary
         // directory to put original root directory.
-        pivot_root(path, path)?;
+        pivot_root(path, path).with_context(|| format!("failed to pivot root to {:?}", path))?;
 
         // Make the original root directory rslave to avoid propagating unmount event to the host mount namespace.
         // We should use MS_SLAVE not MS_PRIVATE according to https://github.com/opencontainers/runc/pull/1500.
@@ -72,15 +94,18 @@ impl Syscall for LinuxSyscall {
             None::<&str>,
             MsFlags::MS_SLAVE | MsFlags::MS_REC,
             None::<&str>,
-        )?;
+        )
+        .context("failed to make the old root rslave")?;
 
         // Unmount the original root directory which was stacked on top of new root directory
         // MNT_DETACH makes the mount point unavailable to new accesses, but waits till the original mount point
         // to be free of activity to actually unmount
         // see https://man7.org/linux/man-pages/man2/umount2.2.html for more information
-        umount2("/", MntFlags::MNT_DETACH)?;
+        umount2("/", MntFlags::MNT_DETACH).context("failed to unmount the old root")?;
         // Change directory to root
         fchdir(newroot)?;
+        // The fd would otherwise leak the new root into the container process
+        unistd::close(newroot)?;
         Ok(())
     }
 
@@ -216,10 +241,28 @@ impl Syscall for LinuxSyscall {
         flags: MsFlags,
         data: Option<&str>,
     ) -> Result<()> {
-        match mount(source, target, fstype, flags, data) {
-            Ok(_) => Ok(()),
-            Err(e) => bail!("Failed to mount {:?}", e),
-        }
+        // keep the errno as the source of the error so that callers can inspect it
+        mount(source, target, fstype, flags, data)
+            .with_context(|| format!("Failed to mount {:?}", target))
+    }
+
+    /// Attaches a detached mount tree created by open_idmapped_tree to target
+    fn mount_idmapped(&self, tree: RawFd, target: &Path) -> Result<()> {
+        let target = CString::new(target.as_os_str().as_bytes())?;
+        let empty = CString::default();
+        let res = unsafe {
+            libc::syscall(
+                SYS_MOVE_MOUNT,
+                tree,
+                empty.as_ptr(),
+                libc::AT_FDCWD,
+                target.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            )
+        };
+        Errno::result(res).context("failed to move idmapped mount")?;
+
+        Ok(())
     }
 
     fn symlink(&self, original: &Path, link: &Path) -> Result<()> {
@@ -243,3 +286,44 @@ impl Syscall for LinuxSyscall {
         }
     }
 }
+
+/// Creates a detached bind mount of source with the ids mapped through the user namespace
+/// referred to by userns. This requires CAP_SYS_ADMIN in the user namespace of the host,
+/// so it has to be done by the runtime rather than by the container init process, which
+/// attaches the returned mount tree with mount_idmapped.
+// see https://man7.org/linux/man-pages/man2/mount_setattr.2.html for more information
+pub fn open_idmapped_tree(source: &Path, userns: RawFd, recursive: bool) -> Result<RawFd> {
+    let recursive_flag = if recursive { AT_RECURSIVE } else { 0 };
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let empty = CString::default();
+
+    let tree = unsafe {
+        libc::syscall(
+            SYS_OPEN_TREE,
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint | recursive_flag,
+        )
+    };
+    let tree = unsafe { File::from_raw_fd(Errno::result(tree)? as RawFd) };
+
+    let attr = MountAttr {
+        attr_set: MOUNT_ATTR_IDMAP,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: userns as u64,
+    };
+    let res = unsafe {
+        libc::syscall(
+            SYS_MOUNT_SETATTR,
+            tree.as_raw_fd(),
+            empty.as_ptr(),
+            libc::AT_EMPTY_PATH as libc::c_uint | recursive_flag,
+            &attr as *const MountAttr,
+            mem::size_of::<MountAttr>(),
+        )
+    };
+    Errno::result(res).context("failed to set idmap attribute")?;
+
+    Ok(tree.into_raw_fd())
+}
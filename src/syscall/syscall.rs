@@ -1,7 +1,7 @@
 //! An interface trait so that rest of Youki can call
 //! necessary functions without having to worry about their
 //! implementation details
-use std::{any::Any, ffi::OsStr, path::Path, sync::Arc};
+use std::{any::Any, ffi::OsStr, os::unix::io::RawFd, path::Path, sync::Arc};
 
 use anyhow::Result;
 use caps::{errors::CapsError, CapSet, CapsHashSet};
@@ -37,6 +37,7 @@ pub trait Syscall {
         flags: MsFlags,
         data: Option<&str>,
     ) -> Result<()>;
+    fn mount_idmapped(&self, tree: RawFd, target: &Path) -> Result<()>;
     fn symlink(&self, original: &Path, link: &Path) -> Result<()>;
     fn mknod(&self, path: &Path, kind: SFlag, perm: Mode, dev: u64) -> Result<()>;
     fn chown(&self, path: &Path, owner: Option<Uid>, group: Option<Gid>) -> Result<()>;
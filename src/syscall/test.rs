@@ -2,6 +2,7 @@ use std::{
     any::Any,
     cell::RefCell,
     ffi::{OsStr, OsString},
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -27,6 +28,12 @@ pub struct MountArgs {
     pub data: Option<String>,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IdmappedMountArgs {
+    pub tree: RawFd,
+    pub target: PathBuf,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct MknodArgs {
     pub path: PathBuf,
@@ -48,9 +55,12 @@ pub struct TestHelperSyscall {
     unshare_args: RefCell<Vec<CloneFlags>>,
     set_capability_args: RefCell<Vec<(CapSet, CapsHashSet)>>,
     mount_args: RefCell<Vec<MountArgs>>,
+    idmapped_mount_args: RefCell<Vec<IdmappedMountArgs>>,
     symlink_args: RefCell<Vec<(PathBuf, PathBuf)>>,
     mknod_args: RefCell<Vec<MknodArgs>>,
     chown_args: RefCell<Vec<ChownArgs>>,
+    pivot_rootfs_args: RefCell<Vec<PathBuf>>,
+    chroot_args: RefCell<Vec<PathBuf>>,
 }
 
 impl Default for TestHelperSyscall {
@@ -60,9 +70,12 @@ impl Default for TestHelperSyscall {
             unshare_args: RefCell::new(vec![]),
             set_capability_args: RefCell::new(vec![]),
             mount_args: RefCell::new(vec![]),
+            idmapped_mount_args: RefCell::new(vec![]),
             symlink_args: RefCell::new(vec![]),
             mknod_args: RefCell::new(vec![]),
             chown_args: RefCell::new(vec![]),
+            pivot_rootfs_args: RefCell::new(vec![]),
+            chroot_args: RefCell::new(vec![]),
         }
     }
 }
@@ -72,8 +85,9 @@ impl Syscall for TestHelperSyscall {
         self
     }
 
-    fn pivot_rootfs(&self, _path: &Path) -> anyhow::Result<()> {
-        unimplemented!()
+    fn pivot_rootfs(&self, path: &Path) -> anyhow::Result<()> {
+        self.pivot_rootfs_args.borrow_mut().push(path.to_path_buf());
+        Ok(())
     }
 
     fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> anyhow::Result<()> {
@@ -109,8 +123,9 @@ impl Syscall for TestHelperSyscall {
         Some(OsString::from("youki").into())
     }
 
-    fn chroot(&self, _: &Path) -> anyhow::Result<()> {
-        todo!()
+    fn chroot(&self, path: &Path) -> anyhow::Result<()> {
+        self.chroot_args.borrow_mut().push(path.to_path_buf());
+        Ok(())
     }
 
     fn mount(
@@ -131,6 +146,16 @@ impl Syscall for TestHelperSyscall {
         Ok(())
     }
 
+    fn mount_idmapped(&self, tree: RawFd, target: &Path) -> anyhow::Result<()> {
+        self.idmapped_mount_args
+            .borrow_mut()
+            .push(IdmappedMountArgs {
+                tree,
+                target: target.to_path_buf(),
+            });
+        Ok(())
+    }
+
     fn symlink(&self, original: &Path, link: &Path) -> anyhow::Result<()> {
         self.symlink_args
             .borrow_mut()
@@ -174,6 +199,10 @@ impl TestHelperSyscall {
         self.mount_args.borrow_mut().clone()
     }
 
+    pub fn get_idmapped_mount_args(&self) -> Vec<IdmappedMountArgs> {
+        self.idmapped_mount_args.borrow_mut().clone()
+    }
+
     pub fn get_symlink_args(&self) -> Vec<(PathBuf, PathBuf)> {
         self.symlink_args.borrow_mut().clone()
     }
@@ -185,4 +214,12 @@ impl TestHelperSyscall {
     pub fn get_chown_args(&self) -> Vec<ChownArgs> {
         self.chown_args.borrow_mut().clone()
     }
+
+    pub fn get_pivot_rootfs_args(&self) -> Vec<PathBuf> {
+        self.pivot_rootfs_args.borrow_mut().clone()
+    }
+
+    pub fn get_chroot_args(&self) -> Vec<PathBuf> {
+        self.chroot_args.borrow_mut().clone()
+    }
 }
@@ -1,9 +1,10 @@
 //! tty (teletype) for user-system interaction
 
+use std::convert::TryFrom;
 use std::os::unix::fs::symlink;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::RawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use anyhow::{bail, Result};
@@ -11,7 +12,8 @@ use nix::errno::Errno;
 use nix::sys::socket;
 use nix::sys::uio;
 use nix::unistd::dup2;
-use nix::unistd::{close, setsid};
+use nix::unistd::{close, setsid, ttyname};
+use oci_spec::runtime::Box as ConsoleSize;
 
 const STDIN: i32 = 0;
 const STDOUT: i32 = 1;
@@ -26,10 +28,12 @@ pub fn setup_console_socket(
     let linked = container_dir.join(socket_name);
     symlink(console_socket_path, &linked)?;
 
+    // the socket is only needed until the pty master has been sent and must not
+    // be inherited by the container process
     let mut csocketfd = socket::socket(
         socket::AddressFamily::Unix,
         socket::SockType::Stream,
-        socket::SockFlag::empty(),
+        socket::SockFlag::SOCK_CLOEXEC,
         None,
     )?;
     csocketfd = match socket::connect(
@@ -37,6 +41,7 @@ pub fn setup_console_socket(
         &socket::SockAddr::Unix(socket::UnixAddr::new(socket_name)?),
     ) {
         Err(errno) => {
+            let _ = close(csocketfd);
             if !matches!(errno, Errno::ENOENT) {
                 bail!("failed to open {}", socket_name);
             }
@@ -47,31 +52,81 @@ pub fn setup_console_socket(
     Ok(csocketfd)
 }
 
-pub fn setup_console(console_fd: &RawFd) -> Result<()> {
+/// Checks that a console socket is provided exactly when the container process
+/// requests a terminal
+pub fn validate_console_socket(terminal: bool, console_socket: Option<&Path>) -> Result<()> {
+    match (terminal, console_socket) {
+        (true, None) => bail!("process.terminal is set, but no console socket was provided"),
+        (false, Some(path)) => bail!(
+            "console socket {:?} was provided, but process.terminal is not set",
+            path
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Builds the window size of the pty from the console size in the spec
+fn winsize(console_size: &ConsoleSize) -> Result<libc::winsize> {
+    if console_size.height() == 0 || console_size.width() == 0 {
+        bail!(
+            "console size must not be zero, got {}x{}",
+            console_size.width(),
+            console_size.height()
+        );
+    }
+
+    Ok(libc::winsize {
+        ws_row: u16::try_from(console_size.height()).context("console height is too large")?,
+        ws_col: u16::try_from(console_size.width()).context("console width is too large")?,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    })
+}
+
+/// Allocates a pty, sends its master over the console socket and makes the
+/// slave the controlling terminal and stdio of the process. Returns the path of
+/// the slave, which is bind mounted to /dev/console in the container.
+pub fn setup_console(console_fd: &RawFd, console_size: Option<&ConsoleSize>) -> Result<PathBuf> {
     // You can also access pty master, but it is better to use the API.
     // ref. https://github.com/containerd/containerd/blob/261c107ffc4ff681bc73988f64e3f60c32233b37/vendor/github.com/containerd/go-runc/console.go#L139-L154
     let openpty_result =
         nix::pty::openpty(None, None).context("could not create pseudo terminal")?;
-    let pty_name: &[u8] = b"/dev/ptmx";
-    let iov = [uio::IoVec::from_slice(pty_name)];
-    let fds = [openpty_result.master];
-    let cmsg = socket::ControlMessage::ScmRights(&fds);
-    socket::sendmsg(
-        console_fd.as_raw_fd(),
-        &iov,
-        &[cmsg],
-        socket::MsgFlags::empty(),
-        None,
-    )
-    .context("failed to send pty master")?;
+    if let Some(console_size) = console_size {
+        let winsize = winsize(console_size)?;
+        if unsafe { libc::ioctl(openpty_result.slave, libc::TIOCSWINSZ, &winsize) } < 0 {
+            bail!(
+                "could not set console size: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    send_pty_master(console_fd.as_raw_fd(), openpty_result.master)?;
+    // the master is owned by the receiver of the console socket from now on
+    close(openpty_result.master).context("could not close pty master")?;
 
     setsid()?;
     if unsafe { libc::ioctl(openpty_result.slave, libc::TIOCSCTTY) } < 0 {
         log::warn!("could not TIOCSCTTY");
     };
     let slave = openpty_result.slave;
+    let console_path = ttyname(slave).context("could not get the name of the pty")?;
     connect_stdio(&slave, &slave, &slave).context("could not dup tty to stderr")?;
+    if slave > STDERR {
+        close(slave).context("could not close pty slave")?;
+    }
     close(console_fd.as_raw_fd()).context("could not close console socket")?;
+    Ok(console_path)
+}
+
+/// Sends the pty master over the console socket as SCM_RIGHTS ancillary data,
+/// with the name of the pty as the message payload
+fn send_pty_master(console_fd: RawFd, master: RawFd) -> Result<()> {
+    let pty_name: &[u8] = b"/dev/ptmx";
+    let iov = [uio::IoVec::from_slice(pty_name)];
+    let fds = [master];
+    let cmsg = socket::ControlMessage::ScmRights(&fds);
+    socket::sendmsg(console_fd, &iov, &[cmsg], socket::MsgFlags::empty(), None)
+        .context("failed to send pty master")?;
     Ok(())
 }
 
@@ -93,6 +148,7 @@ mod tests {
     use std::os::unix::net::UnixListener;
     use std::path::PathBuf;
 
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
     use serial_test::serial;
 
     use crate::utils::{create_temp_dir, TempDir};
@@ -119,7 +175,10 @@ mod tests {
         assert!(lis.is_ok());
         let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
         assert!(fd.is_ok());
-        assert_ne!(fd.unwrap().as_raw_fd(), -1);
+        let fd = fd.unwrap();
+        assert_ne!(fd.as_raw_fd(), -1);
+        let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).unwrap());
+        assert!(flags.contains(FdFlag::FD_CLOEXEC));
     }
 
     #[test]
@@ -154,7 +213,77 @@ mod tests {
         let lis = UnixListener::bind(Path::join(&testdir, "console-socket"));
         assert!(lis.is_ok());
         let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
-        let status = setup_console(&fd.unwrap());
+        let status = setup_console(&fd.unwrap(), None);
         assert!(status.is_ok());
     }
+
+    #[test]
+    fn test_validate_console_socket() {
+        let socket = Path::new("/run/console.sock");
+        assert!(validate_console_socket(true, Some(socket)).is_ok());
+        assert!(validate_console_socket(false, None).is_ok());
+        assert!(validate_console_socket(true, None).is_err());
+        assert!(validate_console_socket(false, Some(socket)).is_err());
+    }
+
+    #[test]
+    fn test_send_pty_master() -> Result<()> {
+        let (sender, receiver) = socket::socketpair(
+            socket::AddressFamily::Unix,
+            socket::SockType::Stream,
+            None,
+            socket::SockFlag::SOCK_CLOEXEC,
+        )?;
+        let file = File::open("/dev/null")?;
+
+        send_pty_master(sender, file.as_raw_fd())?;
+
+        let mut buf = [0u8; 64];
+        let iov = [uio::IoVec::from_mut_slice(&mut buf)];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let msg = socket::recvmsg(
+            receiver,
+            &iov,
+            Some(&mut cmsg_buf),
+            socket::MsgFlags::empty(),
+        )?;
+        let fds: Vec<RawFd> = msg
+            .cmsgs()
+            .filter_map(|cmsg| match cmsg {
+                socket::ControlMessageOwned::ScmRights(fds) => Some(fds),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(&buf[..msg.bytes], b"/dev/ptmx");
+        assert_eq!(fds.len(), 1);
+        // the received fd is a new descriptor for the same file
+        assert_ne!(fds[0], file.as_raw_fd());
+        assert!(nix::sys::stat::fstat(fds[0]).is_ok());
+
+        for fd in fds.into_iter().chain([sender, receiver]) {
+            close(fd)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_winsize() -> Result<()> {
+        use oci_spec::runtime::BoxBuilder;
+
+        let size = BoxBuilder::default().height(24u64).width(80u64).build()?;
+        let winsize = winsize(&size)?;
+        assert_eq!(winsize.ws_row, 24);
+        assert_eq!(winsize.ws_col, 80);
+
+        let empty = BoxBuilder::default().height(0u64).width(80u64).build()?;
+        assert!(winsize(&empty).is_err());
+
+        let huge = BoxBuilder::default()
+            .height(24u64)
+            .width(1u64 << 20)
+            .build()?;
+        assert!(winsize(&huge).is_err());
+        Ok(())
+    }
 }
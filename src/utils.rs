@@ -4,6 +4,7 @@ use anyhow::Context;
 use anyhow::{bail, Result};
 use nix::sys::stat::Mode;
 use nix::sys::statfs;
+use nix::sys::wait::WaitStatus;
 use nix::unistd;
 use std::collections::HashMap;
 use std::ffi::CString;
@@ -14,6 +15,7 @@ use std::os::linux::fs::MetadataExt;
 use std::os::unix::fs::DirBuilderExt;
 use std::os::unix::prelude::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub trait PathBufExt {
     fn as_in_container(&self) -> Result<PathBuf>;
@@ -77,11 +79,101 @@ pub fn do_exec(path: impl AsRef<Path>, args: &[String]) -> Result<()> {
     Ok(())
 }
 
+/// Resolves the executable the same way execvp does. A name that contains a
+/// slash is used as is, otherwise the directories of `path_var` are searched
+/// in order for an executable file with that name.
+pub fn get_executable_path(name: &str, path_var: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    path_var
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.st_mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Converts the wait status of a terminated process into an exit code. A process
+/// killed by a signal is reported the way shells do, as 128 + signal. Returns None
+/// if the process has not terminated.
+pub fn exit_code(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_, code) => Some(code),
+        WaitStatus::Signaled(_, signal, _) => Some(128 + signal as i32),
+        _ => None,
+    }
+}
+
+/// Parses a human readable size into a number of bytes. SI suffixes (k, M, G, T)
+/// are powers of 1000 and binary suffixes (Ki, Mi, Gi, Ti) powers of 1024. Both
+/// -1 and max denote unlimited and are returned as -1.
+pub fn parse_size(size: &str) -> Result<i64> {
+    let size = size.trim();
+    if size == "-1" || size.eq_ignore_ascii_case("max") {
+        return Ok(-1);
+    }
+
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: i64 = number
+        .parse()
+        .with_context(|| format!("invalid size {:?}", size))?;
+
+    let multiplier: i64 = match unit {
+        "" => 1,
+        "k" | "K" => 1000,
+        "M" => 1000_i64.pow(2),
+        "G" => 1000_i64.pow(3),
+        "T" => 1000_i64.pow(4),
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        "Ti" => 1 << 40,
+        _ => bail!("invalid unit {:?} in size {:?}", unit, size),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("size {:?} is too large", size))
+}
+
 /// If None, it will generate a default path for cgroups.
-pub fn get_cgroup_path(cgroups_path: &Option<PathBuf>, container_id: &str) -> PathBuf {
+pub fn get_cgroup_path(
+    cgroups_path: &Option<PathBuf>,
+    container_id: &str,
+    use_systemd: bool,
+) -> Result<PathBuf> {
+    match cgroups_path {
+        // systemd cgroups paths have the form slice:prefix:name and are
+        // resolved by the systemd cgroup manager
+        Some(cpath) if use_systemd => Ok(cpath.clone()),
+        _ => cgroups::common::resolve_cgroup_path(cgroups_path.as_deref(), container_id),
+    }
+}
+
+/// Returns the cgroup path of a container whose state does not contain the cgroup
+/// path. Such containers have been created before the cgroup path was resolved by
+/// get_cgroup_path, when cgroups were placed relative to the cgroup root and the
+/// container id was used if no path was specified.
+pub fn get_legacy_cgroup_path(
+    cgroups_path: &Option<PathBuf>,
+    container_id: &str,
+    use_systemd: bool,
+) -> Result<PathBuf> {
     match cgroups_path {
-        Some(cpath) => cpath.clone(),
-        None => PathBuf::from(container_id),
+        _ if use_systemd => get_cgroup_path(cgroups_path, container_id, use_systemd),
+        Some(cpath) => Ok(cpath.clone()),
+        None => Ok(PathBuf::from(container_id)),
     }
 }
 
@@ -265,8 +357,14 @@ impl Deref for TempDir {
     }
 }
 
+static TEMP_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a temporary directory named after the test. The name is made unique by
+/// appending the pid and a counter, so tests using the same name do not collide.
 pub fn create_temp_dir(test_name: &str) -> Result<TempDir> {
-    let dir = TempDir::new(std::env::temp_dir().join(test_name))?;
+    let id = TEMP_DIR_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir_name = format!("{}-{}-{}", test_name, std::process::id(), id);
+    let dir = TempDir::new(std::env::temp_dir().join(dir_name))?;
     Ok(dir)
 }
 
@@ -342,13 +440,36 @@ mod tests {
     fn test_get_cgroup_path() {
         let cid = "sample_container_id";
         assert_eq!(
-            get_cgroup_path(&None, cid),
-            PathBuf::from("sample_container_id")
+            get_cgroup_path(&None, cid, false).unwrap(),
+            PathBuf::from("/youki/sample_container_id")
         );
         assert_eq!(
-            get_cgroup_path(&Some(PathBuf::from("/youki")), cid),
+            get_cgroup_path(&Some(PathBuf::from("/youki")), cid, false).unwrap(),
             PathBuf::from("/youki")
         );
+        assert_eq!(
+            get_cgroup_path(&Some(PathBuf::from("machine.slice:youki:id")), cid, true).unwrap(),
+            PathBuf::from("machine.slice:youki:id")
+        );
+        assert!(get_cgroup_path(&Some(PathBuf::from("../host")), cid, false).is_err());
+    }
+
+    #[test]
+    fn test_get_legacy_cgroup_path() -> Result<()> {
+        let cid = "sample_container_id";
+        assert_eq!(
+            get_legacy_cgroup_path(&None, cid, false)?,
+            PathBuf::from("sample_container_id")
+        );
+        assert_eq!(
+            get_legacy_cgroup_path(&Some(PathBuf::from("pod/container")), cid, false)?,
+            PathBuf::from("pod/container")
+        );
+        assert_eq!(
+            get_legacy_cgroup_path(&None, cid, true)?,
+            PathBuf::from("/youki/sample_container_id")
+        );
+        Ok(())
     }
     #[test]
     fn test_parse_env() -> Result<()> {
@@ -365,6 +486,144 @@ mod tests {
 
         Ok(())
     }
+    #[test]
+    fn test_get_executable_path_absolute() {
+        assert_eq!(
+            get_executable_path("/bin/sh", ""),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert_eq!(
+            get_executable_path("./run.sh", "/usr/bin"),
+            Some(PathBuf::from("./run.sh"))
+        );
+    }
+
+    #[test]
+    fn test_get_executable_path_from_path_var() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let rootfs = create_temp_dir("test_get_executable_path_from_path_var")?;
+        let bin = rootfs.join("bin");
+        let usr_bin = rootfs.join("usr/bin");
+        fs::create_dir_all(&bin)?;
+        fs::create_dir_all(&usr_bin)?;
+        // not executable, so it must be skipped
+        fs::write(bin.join("sh"), "")?;
+        fs::write(usr_bin.join("sh"), "")?;
+        fs::set_permissions(usr_bin.join("sh"), fs::Permissions::from_mode(0o755))?;
+
+        let path_var = format!("{}::{}", bin.display(), usr_bin.display());
+        assert_eq!(
+            get_executable_path("sh", &path_var),
+            Some(usr_bin.join("sh"))
+        );
+        assert_eq!(get_executable_path("missing", &path_var), None);
+        assert_eq!(get_executable_path("sh", ""), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_code() {
+        use nix::sys::signal::Signal;
+        use nix::unistd::Pid;
+
+        let pid = Pid::from_raw(42);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 0)), Some(0));
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 42)), Some(42));
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGKILL, false)),
+            Some(137)
+        );
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGTERM, true)),
+            Some(143)
+        );
+        assert_eq!(exit_code(WaitStatus::Stopped(pid, Signal::SIGSTOP)), None);
+        assert_eq!(exit_code(WaitStatus::StillAlive), None);
+    }
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("0")?, 0);
+        assert_eq!(parse_size("1024")?, 1024);
+        assert_eq!(parse_size(" 1024 ")?, 1024);
+        assert_eq!(parse_size("4k")?, 4000);
+        assert_eq!(parse_size("4K")?, 4000);
+        assert_eq!(parse_size("512M")?, 512_000_000);
+        assert_eq!(parse_size("2G")?, 2_000_000_000);
+        assert_eq!(parse_size("1T")?, 1_000_000_000_000);
+        assert_eq!(parse_size("4Ki")?, 4096);
+        assert_eq!(parse_size("512Mi")?, 512 * 1024 * 1024);
+        assert_eq!(parse_size("2Gi")?, 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1Ti")?, 1024 * 1024 * 1024 * 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size_unlimited() -> Result<()> {
+        assert_eq!(parse_size("-1")?, -1);
+        assert_eq!(parse_size("max")?, -1);
+        assert_eq!(parse_size("MAX")?, -1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("12X").is_err());
+        assert!(parse_size("12kb").is_err());
+        assert!(parse_size("12m").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("-2").is_err());
+        assert!(parse_size("maximum").is_err());
+        assert!(parse_size("9999999999Ti").is_err());
+    }
+
+    #[test]
+    fn test_temp_dir_removed_on_drop() -> Result<()> {
+        let path = {
+            let tmp = create_temp_dir("test_temp_dir_removed_on_drop")?;
+            fs::write(tmp.join("file"), "content")?;
+            fs::create_dir(tmp.join("dir"))?;
+            assert!(tmp.path().is_dir());
+            tmp.path().to_path_buf()
+        };
+
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_temp_dir_unique() -> Result<()> {
+        let first = create_temp_dir("test_create_temp_dir_unique")?;
+        let second = create_temp_dir("test_create_temp_dir_unique")?;
+
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().is_dir());
+        assert!(second.path().is_dir());
+        assert!(first
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("test_create_temp_dir_unique"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_temp_dir_removed_on_panic() -> Result<()> {
+        let path = std::env::temp_dir().join("test_temp_dir_removed_on_panic");
+        let result = std::panic::catch_unwind(|| {
+            let _tmp = TempDir::new(&path).unwrap();
+            panic!("test failed mid-way");
+        });
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+        Ok(())
+    }
+
     #[test]
     fn test_secure_join() {
         assert_eq!(